@@ -0,0 +1,9 @@
+-- When a meeting's attendance code stops being accepted
+ALTER TABLE meetings ADD code_expires DATETIME NOT NULL DEFAULT 0;
+-- Existing meetings get the default window of 120 minutes, the default
+-- of the `meeting_code_minutes` setting
+UPDATE meetings SET code_expires = datetime(happened_at, '+120 minutes');
+-- Manual override of the code window set by the host
+-- NULL follows the window, 1 is forced open and 0 is forced closed
+ALTER TABLE meetings ADD code_open BOOLEAN;
+ALTER TABLE events ADD code_open BOOLEAN;
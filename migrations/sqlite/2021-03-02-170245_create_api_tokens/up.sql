@@ -0,0 +1,13 @@
+-- Personal tokens used to authenticate to the API
+CREATE TABLE api_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- ID of the user the token acts as
+    user_id INTEGER NOT NULL,
+    -- Name given to the token by the user
+    name TEXT NOT NULL,
+    -- SHA-256 hash of the token, the token itself is never stored
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used DATETIME,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+)
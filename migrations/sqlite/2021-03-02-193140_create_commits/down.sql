@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE commits;
+DROP TABLE repo_syncs;
\ No newline at end of file
@@ -0,0 +1,25 @@
+-- Local cache of the commits in project repositories
+-- Kept up to date by the commit sync worker
+CREATE TABLE commits (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- URL of the repository such as https://github.com/rcos/observatory
+    repo TEXT NOT NULL,
+    sha TEXT NOT NULL,
+    author_name TEXT NOT NULL,
+    author_email TEXT NOT NULL,
+    -- Username of the author on the forge if the forge knows who they are
+    author_login TEXT,
+    message TEXT NOT NULL,
+    -- Link to the commit on the forge
+    url TEXT NOT NULL,
+    committed_at DATETIME NOT NULL,
+    UNIQUE (repo, sha)
+);
+
+-- When each repository was last synced
+CREATE TABLE repo_syncs (
+    repo TEXT PRIMARY KEY NOT NULL,
+    -- ETag of the last response so unchanged repos cost nothing to check
+    etag TEXT,
+    synced_at DATETIME NOT NULL
+)
@@ -0,0 +1,12 @@
+-- Emails, logins and names that a user's commits are made under
+CREATE TABLE commit_identities (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    -- One of 'email', 'login' or 'name'
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL COLLATE NOCASE,
+    -- Only verified identities are used to attribute commits
+    verified BOOLEAN NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    UNIQUE (kind, value)
+)
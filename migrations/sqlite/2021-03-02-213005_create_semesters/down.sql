@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE enrollments;
+DROP TABLE semesters;
\ No newline at end of file
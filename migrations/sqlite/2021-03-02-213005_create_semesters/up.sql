@@ -0,0 +1,27 @@
+-- Semesters that projects, groups, meetings and events belong to
+CREATE TABLE semesters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- Name of the semester like 'Spring 2021'
+    name TEXT NOT NULL UNIQUE,
+    -- First and last day of the semester
+    start DATE NOT NULL,
+    end DATE NOT NULL
+);
+
+-- Users taking part in a semester
+CREATE TABLE enrollments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    semester_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    FOREIGN KEY (semester_id) REFERENCES semesters (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    UNIQUE (semester_id, user_id)
+);
+
+-- Everything that existed before semesters has no semester
+ALTER TABLE projects ADD semester_id INTEGER REFERENCES semesters (id);
+ALTER TABLE groups ADD semester_id INTEGER REFERENCES semesters (id);
+ALTER TABLE meetings ADD semester_id INTEGER REFERENCES semesters (id);
+ALTER TABLE events ADD semester_id INTEGER REFERENCES semesters (id);
+-- Groups from past semesters are archived and can't hold meetings
+ALTER TABLE groups ADD archived BOOLEAN NOT NULL DEFAULT 0;
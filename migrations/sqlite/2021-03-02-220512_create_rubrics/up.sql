@@ -0,0 +1,14 @@
+-- How grades are computed in each semester
+CREATE TABLE rubrics (
+    semester_id INTEGER PRIMARY KEY NOT NULL,
+    -- Relative weights of each part of the grade
+    small_group_weight INTEGER NOT NULL,
+    large_group_weight INTEGER NOT NULL,
+    event_weight INTEGER NOT NULL,
+    commit_weight INTEGER NOT NULL,
+    -- Commits needed for full credit
+    commits_needed INTEGER NOT NULL,
+    -- Minimum percentage for each grade like 'A:90, B:80, F:0'
+    thresholds TEXT NOT NULL,
+    FOREIGN KEY (semester_id) REFERENCES semesters (id)
+);
@@ -0,0 +1,4 @@
+-- Whether the user has verified their email
+ALTER TABLE users ADD verified boolean NOT NULL DEFAULT 0;
+-- Everyone from before verification existed is trusted
+UPDATE users SET verified = 1;
@@ -0,0 +1,14 @@
+-- Logged in browsers, the session cookie holds the token
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- ID of the user that is logged in
+    user_id INTEGER NOT NULL,
+    -- SHA-256 hash of the token, the token itself is never stored
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- IP address of the last request, if it is known
+    ip TEXT,
+    user_agent TEXT NOT NULL DEFAULT '',
+    FOREIGN KEY (user_id) REFERENCES users (id)
+)
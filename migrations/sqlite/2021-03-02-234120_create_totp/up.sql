@@ -0,0 +1,21 @@
+-- TOTP secrets for two-factor authentication, one per user
+CREATE TABLE totp_secrets (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    -- Base32 secret shared with the authenticator app
+    secret TEXT NOT NULL,
+    -- The secret is only used once a code from it has been confirmed
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    -- The last time step a code was accepted for, so codes can't be reused
+    last_step BIGINT NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+-- Single use codes for when the authenticator is lost
+CREATE TABLE recovery_codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    -- SHA-256 hash of the code
+    code_hash TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
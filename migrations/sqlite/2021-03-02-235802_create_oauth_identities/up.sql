@@ -0,0 +1,18 @@
+-- Accounts at OAuth and OpenID Connect providers that users log in with
+CREATE TABLE oauth_identities (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    -- Name of the provider in the `oauth_providers` setting
+    provider TEXT NOT NULL,
+    -- The provider's ID of the account, which never changes
+    subject TEXT NOT NULL,
+    -- User name at the provider such as the GitHub username
+    login TEXT,
+    created_at DATETIME NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    UNIQUE (provider, subject),
+    UNIQUE (provider, user_id)
+);
+
+-- Whether the handle was verified by logging in with GitHub
+ALTER TABLE users ADD handle_verified boolean NOT NULL DEFAULT 0;
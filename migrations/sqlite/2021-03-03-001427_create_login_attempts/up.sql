@@ -0,0 +1,12 @@
+-- Failed logins, used to slow down password guessing
+CREATE TABLE login_attempts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- The email that was tried in lowercase, whether or not it has an account
+    email TEXT NOT NULL,
+    -- IP address the attempt came from, if it is known
+    ip TEXT,
+    attempted_at DATETIME NOT NULL
+);
+
+CREATE INDEX login_attempts_email ON login_attempts (email);
+CREATE INDEX login_attempts_ip ON login_attempts (ip);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+-- The old hashes can't be turned back into bytes so everyone with a
+-- password has to reset it
+ALTER TABLE users ADD salt TEXT NOT NULL DEFAULT '';
+UPDATE users SET password_hash = '';
@@ -0,0 +1,33 @@
+-- Passwords are stored as PHC strings that hold the algorithm, its
+-- parameters and the salt along with the hash, so the salt column goes.
+-- SQLite can't drop columns so the table is made again without it.
+CREATE TABLE new_users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    real_name TEXT NOT NULL,
+    handle TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL UNIQUE,
+    -- PHC string of the user's password, empty if they can't log in with one
+    password_hash TEXT NOT NULL,
+    bio TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT 1,
+    joined_on DATETIME NOT NULL DEFAULT (datetime('now','localtime')),
+    tier INTEGER NOT NULL DEFAULT 0,
+    mmost TEXT NOT NULL UNIQUE,
+    former boolean NOT NULL DEFAULT 0,
+    extrn boolean NOT NULL DEFAULT 0,
+    verified boolean NOT NULL DEFAULT 0,
+    handle_verified boolean NOT NULL DEFAULT 0
+);
+
+-- Existing PBKDF2 hashes keep working with their salt and hash in hex,
+-- since SQLite has no base64. They are replaced when the user logs in.
+INSERT INTO new_users (id, real_name, handle, email, password_hash, bio, active, joined_on, tier, mmost, former, extrn, verified, handle_verified)
+SELECT id, real_name, handle, email,
+    CASE WHEN password_hash = '' THEN ''
+    ELSE '$pbkdf2-sha512$i=100000$' || lower(hex(salt)) || '$' || lower(hex(password_hash))
+    END,
+    bio, active, joined_on, tier, mmost, former, extrn, verified, handle_verified
+FROM users;
+
+DROP TABLE users;
+ALTER TABLE new_users RENAME TO users;
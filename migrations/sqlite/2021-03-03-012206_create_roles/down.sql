@@ -0,0 +1,44 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE new_enrollments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    semester_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    FOREIGN KEY (semester_id) REFERENCES semesters (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    UNIQUE (semester_id, user_id)
+);
+INSERT INTO new_enrollments (id, semester_id, user_id)
+SELECT id, semester_id, user_id FROM enrollments;
+DROP TABLE enrollments;
+ALTER TABLE new_enrollments RENAME TO enrollments;
+
+CREATE TABLE new_users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    real_name TEXT NOT NULL,
+    handle TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    bio TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT 1,
+    joined_on DATETIME NOT NULL DEFAULT (datetime('now','localtime')),
+    tier INTEGER NOT NULL DEFAULT 0,
+    mmost TEXT NOT NULL UNIQUE,
+    former boolean NOT NULL DEFAULT 0,
+    extrn boolean NOT NULL DEFAULT 0,
+    verified boolean NOT NULL DEFAULT 0,
+    handle_verified boolean NOT NULL DEFAULT 0
+);
+
+INSERT INTO new_users (id, real_name, handle, email, password_hash, bio, active, joined_on, tier, mmost, former, extrn, verified, handle_verified)
+SELECT id, real_name, handle, email, password_hash, bio, active, joined_on,
+    CASE role
+        WHEN 'admin' THEN 3
+        WHEN 'coordinator' THEN 2
+        WHEN 'mentor' THEN 1
+        ELSE 0
+    END,
+    mmost, former, extrn, verified, handle_verified
+FROM users;
+
+DROP TABLE users;
+ALTER TABLE new_users RENAME TO users;
@@ -0,0 +1,39 @@
+-- Users have a named role instead of a privilege tier, see `roles.rs`.
+-- SQLite can't drop columns so the table is made again without the tier.
+CREATE TABLE new_users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    real_name TEXT NOT NULL,
+    handle TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    bio TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT 1,
+    joined_on DATETIME NOT NULL DEFAULT (datetime('now','localtime')),
+    -- The role the user has now
+    -- student, external, mentor, coordinator or admin
+    role TEXT NOT NULL DEFAULT 'student',
+    mmost TEXT NOT NULL UNIQUE,
+    former boolean NOT NULL DEFAULT 0,
+    extrn boolean NOT NULL DEFAULT 0,
+    verified boolean NOT NULL DEFAULT 0,
+    handle_verified boolean NOT NULL DEFAULT 0
+);
+
+INSERT INTO new_users (id, real_name, handle, email, password_hash, bio, active, joined_on, role, mmost, former, extrn, verified, handle_verified)
+SELECT id, real_name, handle, email, password_hash, bio, active, joined_on,
+    CASE
+        WHEN tier >= 3 THEN 'admin'
+        WHEN tier = 2 THEN 'coordinator'
+        WHEN tier = 1 THEN 'mentor'
+        WHEN extrn THEN 'external'
+        ELSE 'student'
+    END,
+    mmost, former, extrn, verified, handle_verified
+FROM users;
+
+DROP TABLE users;
+ALTER TABLE new_users RENAME TO users;
+
+-- The role a user is given for a semester, NULL to keep the one they have.
+-- It becomes their role when the semester is rolled over to.
+ALTER TABLE enrollments ADD role TEXT;
@@ -0,0 +1,19 @@
+-- Every change made to the data, see `src/audit`
+CREATE TABLE audit_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- ID of the user that made the change, NULL if they weren't logged in
+    actor_id INTEGER,
+    -- What was done like `create`, `update` or `delete`
+    action TEXT NOT NULL,
+    -- What kind of thing was changed like `user` or `project`
+    target_type TEXT NOT NULL,
+    target_id INTEGER,
+    -- JSON of the fields that changed as they were before and after
+    old_values TEXT,
+    new_values TEXT,
+    happened_at DATETIME NOT NULL,
+    FOREIGN KEY (actor_id) REFERENCES users (id)
+);
+
+CREATE INDEX audit_events_actor ON audit_events (actor_id);
+CREATE INDEX audit_events_target ON audit_events (target_type, target_id);
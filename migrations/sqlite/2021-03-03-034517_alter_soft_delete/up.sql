@@ -0,0 +1,7 @@
+-- When something was moved to the trash, NULL if it wasn't
+-- Deleted things are hidden and purged after a while, see `src/trash`
+ALTER TABLE users ADD deleted_at DATETIME;
+ALTER TABLE projects ADD deleted_at DATETIME;
+ALTER TABLE groups ADD deleted_at DATETIME;
+ALTER TABLE events ADD deleted_at DATETIME;
+ALTER TABLE news ADD deleted_at DATETIME;
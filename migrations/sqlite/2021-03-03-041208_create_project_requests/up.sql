@@ -0,0 +1,22 @@
+-- How users can join a project, see `Joining` in `src/projects/models.rs`
+-- open, request or closed
+ALTER TABLE projects ADD joining TEXT NOT NULL DEFAULT 'request';
+
+-- Requests to join projects and invites from their owners
+CREATE TABLE project_requests (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    project_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    -- Whether the project invited the user rather than the user asking to join
+    invite BOOLEAN NOT NULL,
+    -- pending, accepted or declined
+    status TEXT NOT NULL DEFAULT 'pending',
+    created_at DATETIME NOT NULL,
+    -- When it was accepted or declined
+    answered_at DATETIME,
+    FOREIGN KEY (project_id) REFERENCES projects (id),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX project_requests_project ON project_requests (project_id);
+CREATE INDEX project_requests_user ON project_requests (user_id);
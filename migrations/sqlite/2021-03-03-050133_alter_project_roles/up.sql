@@ -0,0 +1,7 @@
+-- The role of each member of a project
+-- lead, developer or mentor
+ALTER TABLE relation_project_user ADD role TEXT NOT NULL DEFAULT 'developer';
+
+-- Owners lead their projects
+UPDATE relation_project_user SET role = 'lead'
+WHERE user_id = (SELECT owner_id FROM projects WHERE projects.id = project_id);
@@ -0,0 +1,20 @@
+-- Events that repeat every week, see `src/calendar/recurrence.rs`
+-- Each occurrence is its own row in `events` with its own attendance code.
+CREATE TABLE event_series (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- Times of the first occurrence, the others happen at the same time of day
+    start DATETIME NOT NULL,
+    end DATETIME NOT NULL,
+    -- Days of the week it repeats on like `MO,WE`
+    weekdays TEXT NOT NULL,
+    -- The last day it can happen on
+    until DATE NOT NULL,
+    -- Days it doesn't happen on like `2021-03-10,2021-03-17`
+    except_dates TEXT NOT NULL DEFAULT ''
+);
+
+ALTER TABLE events ADD series_id INTEGER REFERENCES event_series (id);
+-- When the series has the occurrence start, kept when the occurrence is moved
+ALTER TABLE events ADD original_start DATETIME;
+-- Whether the occurrence was changed apart from the rest of the series
+ALTER TABLE events ADD detached BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+-- Groups meet every week on a day like MO at a time
+ALTER TABLE groups ADD meeting_day TEXT;
+ALTER TABLE groups ADD meeting_time TIME;
+
+-- Meetings are scheduled ahead of time where the group meets
+ALTER TABLE meetings ADD location TEXT;
+-- Cancelled meetings excuse everyone from them
+ALTER TABLE meetings ADD cancelled BOOLEAN NOT NULL DEFAULT 0;
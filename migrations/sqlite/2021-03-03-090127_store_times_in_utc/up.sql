@@ -0,0 +1,20 @@
+-- Users can pick the time zone times are shown to them in
+ALTER TABLE users ADD time_zone TEXT;
+
+-- Times were stored in the server's time zone and are now stored in UTC
+UPDATE api_tokens SET created_at = datetime(created_at, 'utc'), last_used = datetime(last_used, 'utc');
+UPDATE audit_events SET happened_at = datetime(happened_at, 'utc');
+UPDATE commits SET committed_at = datetime(committed_at, 'utc');
+UPDATE event_series SET start = datetime(start, 'utc'), "end" = datetime("end", 'utc');
+UPDATE events SET start = datetime(start, 'utc'), "end" = datetime("end", 'utc'), deleted_at = datetime(deleted_at, 'utc'), original_start = datetime(original_start, 'utc');
+UPDATE groups SET deleted_at = datetime(deleted_at, 'utc');
+UPDATE login_attempts SET attempted_at = datetime(attempted_at, 'utc');
+UPDATE meetings SET happened_at = datetime(happened_at, 'utc'), code_expires = datetime(code_expires, 'utc');
+UPDATE news SET happened_at = datetime(happened_at, 'utc'), deleted_at = datetime(deleted_at, 'utc');
+UPDATE oauth_identities SET created_at = datetime(created_at, 'utc');
+UPDATE project_requests SET created_at = datetime(created_at, 'utc'), answered_at = datetime(answered_at, 'utc');
+UPDATE projects SET deleted_at = datetime(deleted_at, 'utc');
+UPDATE repo_syncs SET synced_at = datetime(synced_at, 'utc');
+UPDATE sessions SET created_at = datetime(created_at, 'utc'), last_seen = datetime(last_seen, 'utc');
+UPDATE totp_secrets SET created_at = datetime(created_at, 'utc');
+UPDATE users SET joined_on = datetime(joined_on, 'utc'), deleted_at = datetime(deleted_at, 'utc');
@@ -0,0 +1,14 @@
+-- Secret links to subscribe to a user's or a group's calendar
+CREATE TABLE calendar_feeds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- ID of the user the feed belongs to
+    user_id INTEGER NOT NULL,
+    -- ID of the group for a group feed, NULL for the user's own feed
+    group_id INTEGER,
+    -- SHA-256 hash of the token, the token itself is never stored
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used DATETIME,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (group_id) REFERENCES groups (id)
+)
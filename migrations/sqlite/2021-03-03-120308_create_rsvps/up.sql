@@ -0,0 +1,16 @@
+-- Answers to whether users are coming to an event
+CREATE TABLE rsvps (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    event_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    -- One of `going`, `maybe` or `not_going`
+    status TEXT NOT NULL,
+    -- When the answer last changed, the waitlist goes in this order
+    responded_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (event_id, user_id),
+    FOREIGN KEY (event_id) REFERENCES events (id),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+-- How many can go to the event, the rest are on the waitlist
+ALTER TABLE events ADD capacity INTEGER;
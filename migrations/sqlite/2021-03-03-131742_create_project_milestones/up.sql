@@ -0,0 +1,10 @@
+-- Dates a project plans to reach a goal by, shown in the calendar feeds
+CREATE TABLE project_milestones (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    project_id INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT,
+    -- When it is due in UTC
+    due_at DATETIME NOT NULL,
+    FOREIGN KEY (project_id) REFERENCES projects (id)
+)
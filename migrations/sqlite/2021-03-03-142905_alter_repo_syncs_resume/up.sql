@@ -0,0 +1,2 @@
+-- The page to continue from when the last sync stopped at the page limit
+ALTER TABLE repo_syncs ADD resume_url TEXT;
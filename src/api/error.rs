@@ -0,0 +1,88 @@
+//! Errors returned by the API
+//!
+//! The API never redirects or renders an HTML page. Anything that goes wrong
+//! is returned as a JSON object with the HTTP status and a short error
+//! string, for example `{"status": 404, "error": "Not Found"}`.
+//! Form errors use the same strings as the `e` parameter of the website.
+
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket_contrib::json::{Json, JsonError};
+
+use crate::templates::FormError;
+
+/// Result type of the API handlers
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// An error returned by the API
+#[derive(Debug)]
+pub struct ApiError {
+    /// HTTP status of the response
+    pub status: Status,
+    /// Short description of what went wrong
+    pub error: String,
+}
+
+impl ApiError {
+    pub fn new<T: Into<String>>(status: Status, error: T) -> Self {
+        Self {
+            status,
+            error: error.into(),
+        }
+    }
+}
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        Self::new(status, status.reason)
+    }
+}
+
+impl From<FormError> for ApiError {
+    fn from(e: FormError) -> Self {
+        let status = match e {
+            FormError::EmailExists
+            | FormError::GitExists
+            | FormError::MmostExists
+            | FormError::TakenName
+            | FormError::IdentityExists
+            | FormError::AccountLinked
+            | FormError::UsedCode => Status::Conflict,
+            FormError::Unverified => Status::Forbidden,
+            FormError::Locked => Status::TooManyRequests,
+            _ => Status::BadRequest,
+        };
+        Self::new(status, e.to_string())
+    }
+}
+
+impl<'a> From<JsonError<'a>> for ApiError {
+    fn from(e: JsonError<'a>) -> Self {
+        match e {
+            JsonError::Io(_) => Self::from(Status::BadRequest),
+            JsonError::Parse(_, e) => Self::new(Status::BadRequest, e.to_string()),
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        Response::build_from(
+            Json(serde_json::json!({
+                "status": self.status.code,
+                "error": self.error,
+            }))
+            .respond_to(req)?,
+        )
+        .status(self.status)
+        .ok()
+    }
+}
+
+/// Is this a request to the API?
+///
+/// Used by the catchers to respond with JSON instead of a page.
+pub fn is_api(req: &Request) -> bool {
+    req.uri().path().starts_with("/api/")
+}
@@ -0,0 +1,222 @@
+//! HTTP handlers for managing API tokens
+
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::http::Status;
+use rocket::request::Form;
+use rocket::response::Redirect;
+use rocket::State;
+
+use crate::audit;
+use crate::guards::*;
+use crate::models::User;
+use crate::roles::Permission;
+use crate::settings::Settings;
+use crate::tz::{self, Tz};
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::templates::*;
+
+/// GET handler for `/users/<uid>/tokens`
+///
+/// Lists the API tokens of a user.
+///
+/// Restricted to Admins and the user themselves. Admins can revoke the
+/// tokens of others but only users can create their own.
+#[get("/users/<uid>/tokens")]
+pub fn tokens(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+) -> Result<TokensTemplate, Status> {
+    tokens_page(&*conn, l.0, &settings.site_zone(), uid, None)
+}
+
+/// The name of a new token
+///
+/// Used to parse the incoming form in `tokens_post`
+#[derive(FromForm)]
+pub struct TokenForm {
+    name: String,
+}
+
+/// POST handler for `/users/<uid>/tokens`
+///
+/// Creates a new API token for the user and shows it.
+/// This is the only time the token can be seen.
+///
+/// Restricted to the user themselves, since a token acts as its user.
+#[post("/users/<uid>/tokens", data = "<form>")]
+pub fn tokens_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+    form: Form<TokenForm>,
+) -> Result<TokensTemplate, Status> {
+    if l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    let mut tname = form.into_inner().name;
+    tname.truncate(50);
+
+    let token = gen_token();
+    use crate::schema::api_tokens::dsl::*;
+    insert_into(api_tokens)
+        .values(&NewApiToken {
+            user_id: uid,
+            name: tname,
+            token_hash: hash_token(&token),
+            created_at: chrono::offset::Utc::now().naive_utc(),
+        })
+        .execute(&*conn)
+        .expect("Failed to insert API token into database");
+    let t: ApiToken = api_tokens
+        .filter(token_hash.eq(hash_token(&token)))
+        .first(&*conn)
+        .expect("Failed to get API token from database");
+    audit::created(&*conn, Some(&l.0), "token", t.id, &t);
+
+    audit_log!("User {} [{}] has created an API token", l.0.id, l.0.email);
+
+    tokens_page(&*conn, l.0, &settings.site_zone(), uid, Some(token))
+}
+
+/// DELETE handler for `/users/<uid>/tokens/<tid>`
+///
+/// Revokes an API token.
+///
+/// Restricted to Admins and the user themselves.
+#[delete("/users/<uid>/tokens/<tid>")]
+pub fn token_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    uid: i32,
+    tid: i32,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    use crate::schema::api_tokens::dsl::*;
+    let old: Option<ApiToken> = api_tokens
+        .filter(id.eq(tid).and(user_id.eq(uid)))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get API token from database");
+    delete(api_tokens.filter(id.eq(tid).and(user_id.eq(uid))))
+        .execute(&*conn)
+        .expect("Failed to delete API token from database");
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "token", tid, &old);
+    }
+
+    audit_log!(
+        "User {} [{}] has revoked API token {} of User {}",
+        l.0.id,
+        l.0.email,
+        tid,
+        uid
+    );
+
+    Ok(Redirect::to(format!("/users/{}/tokens", uid)))
+}
+
+fn tokens_page(
+    conn: &SqliteConnection,
+    l: User,
+    site: &Tz,
+    uid: i32,
+    new_token: Option<String>,
+) -> Result<TokensTemplate, Status> {
+    if !l.can(Permission::ManageUsers) && l.id != uid {
+        return Err(DENIED);
+    }
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .first(conn)
+            .optional()
+            .expect("Failed to get user from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    Ok(TokensTemplate {
+        own: l.id == uid,
+        zone: tz::of(Some(&l), site),
+        logged_in: Some(l),
+        tokens: ApiToken::belonging_to(&user)
+            .load(conn)
+            .expect("Failed to get API tokens from database"),
+        user,
+        new_token,
+    })
+}
+
+//# Helper Functions
+
+/// Generate a new random API token
+///
+/// Tokens start with `obs_` so they are easy to recognise if one leaks.
+pub fn gen_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate API token");
+    format!(
+        "obs_{}",
+        base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// The hash of a token as it is stored in the database
+pub fn hash_token(token: &str) -> String {
+    digest::digest(&digest::SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Revoke every API token of a user
+///
+/// Done with their sessions whenever their password or role changes.
+pub fn revoke_tokens(conn: &SqliteConnection, uid: i32) {
+    use crate::schema::api_tokens::dsl::*;
+    delete(api_tokens.filter(user_id.eq(uid)))
+        .execute(conn)
+        .expect("Failed to delete API tokens from database");
+}
+
+/// Find the user an API token belongs to
+///
+/// Also records that the token has been used.
+pub fn token_user(conn: &SqliteConnection, token: &str) -> Option<User> {
+    use crate::schema::api_tokens::dsl::*;
+
+    let t: ApiToken = api_tokens
+        .filter(token_hash.eq(hash_token(token)))
+        .first(conn)
+        .optional()
+        .expect("Failed to get API token from database")?;
+
+    update(api_tokens.find(t.id))
+        .set(last_used.eq(chrono::offset::Utc::now().naive_utc()))
+        .execute(conn)
+        .expect("Failed to update API token in database");
+
+    use crate::schema::users::dsl::*;
+    users
+        .find(t.user_id)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get user from database")
+}
@@ -0,0 +1,17 @@
+//! The JSON API and the tokens used to access it
+//!
+//! Scripts authenticate by sending one of their user's API tokens in an
+//! `Authorization: Bearer <token>` header. The normal guards accept these
+//! tokens so permissions are checked the same way as on the website.
+//!
+//! ## Routes
+//! - `/users/<uid>/tokens`
+//! - `/users/<uid>/tokens/<tid>`
+//! - `/api/v1/...` see the `v1` module
+
+pub mod error;
+pub mod handlers;
+pub mod models;
+pub mod v1;
+
+mod templates;
@@ -0,0 +1,42 @@
+//! Models for API tokens
+//!
+//! API tokens are stored in the `api_tokens` table where each row is a
+//! token belonging to a user. Only a hash of the token is stored.
+
+use chrono::NaiveDateTime;
+
+use crate::models::User;
+use crate::schema::*;
+
+/// Models an API token in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+pub struct ApiToken {
+    /// ID of the token
+    pub id: i32,
+    /// ID of the user the token acts as
+    pub user_id: i32,
+    /// Name given to the token by the user
+    pub name: String,
+    /// SHA-256 hash of the token
+    #[serde(skip)]
+    pub token_hash: String,
+    /// When the token was created
+    pub created_at: NaiveDateTime,
+    /// When the token was last used, if ever
+    pub last_used: Option<NaiveDateTime>,
+}
+
+/// Used to create a new API token in the database
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "api_tokens"]
+pub struct NewApiToken {
+    /// ID of the user the token acts as
+    pub user_id: i32,
+    /// Name given to the token by the user
+    pub name: String,
+    /// SHA-256 hash of the token
+    pub token_hash: String,
+    /// When the token was created
+    pub created_at: NaiveDateTime,
+}
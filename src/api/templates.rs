@@ -0,0 +1,29 @@
+//! HTML templates for API tokens
+
+use super::models::ApiToken;
+use crate::models::User;
+#[allow(unused_imports)]
+use crate::templates::{filters, OptUser};
+use crate::tz::Tz;
+
+/// API tokens page template
+///
+/// HTML File: `api/tokens.html`
+///
+/// Lists a user's API tokens and, for their own, has the form to create
+/// a new one.
+#[derive(Template)]
+#[template(path = "api/tokens.html")]
+pub struct TokensTemplate {
+    pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
+    pub user: User,
+    pub tokens: Vec<ApiToken>,
+    /// Are these the logged in user's own tokens?
+    pub own: bool,
+    /// A token that was just created
+    ///
+    /// This is the only time the token is ever shown.
+    pub new_token: Option<String>,
+}
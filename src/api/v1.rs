@@ -0,0 +1,1033 @@
+//! Version 1 of the JSON API
+//!
+//! Mounted at `/api/v1`. Every route needs a logged in user, either through
+//! an API token or the normal login cookie, and has the same permissions
+//! as the matching page on the website.
+//!
+//! Created resources are returned with `201 Created`, edited resources are
+//! returned as they were saved, and deletes respond with `204 No Content`.
+//! Deleted users, projects, groups, events and news go to the trash, see
+//! `crate::trash`.
+//! Errors are returned as JSON, see `ApiError`.
+//! Times are returned in UTC like `2021-03-01T21:00:00Z`. Times sent with
+//! an offset are converted from it, those without one are in the user's
+//! time zone, see `crate::tz`.
+//! Users only come with an email if it is their own or the token's user
+//! can see the details of other users.
+//!
+//! ## Routes
+//! - `/me`
+//! - `/users`, `/users/<uid>`
+//! - `/projects`, `/projects/<pid>`, `/projects/<pid>/members`
+//! - `/groups`, `/groups/<gid>`
+//! - `/groups/<gid>/meetings`, `/groups/<gid>/meetings/<mid>`
+//! - `/events`, `/events/<eid>`
+//! - `/news`, `/news/<nid>`
+//! - `/attendances`, `/attendances/pending`, `/attendances/<aid>`
+
+use diesel::prelude::*;
+use diesel::{insert_into, update};
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::State;
+use rocket_contrib::json::{Json, JsonError};
+
+use crate::attend::code::attendance_code;
+use crate::attend::handlers::{attended, can_review, review, submit_attendance};
+use crate::audit;
+use crate::auth::crypto::hash_password;
+use crate::calendar::handlers::update_event;
+use crate::groups::handlers::*;
+use crate::guards::*;
+use crate::models::*;
+use crate::projects::handlers::*;
+use crate::roles::{Permission, Role};
+use crate::settings::Settings;
+use crate::templates::{is_reserved, FormError};
+use crate::trash::handlers::move_to_trash;
+use crate::trash::models::Kind;
+use crate::tz;
+use crate::users::handlers::*;
+use crate::ObservDbConn;
+
+use super::error::*;
+
+// Diesel with SQLite can't return the row it just inserted
+no_arg_sql_function!(
+    last_insert_rowid,
+    diesel::sql_types::Integer,
+    "The ID of the last row inserted on this connection"
+);
+
+pub fn last_id(conn: &SqliteConnection) -> i32 {
+    diesel::select(last_insert_rowid)
+        .get_result(conn)
+        .expect("Failed to get ID of new row from database")
+}
+
+fn created<T>(url: String, body: T) -> Created<Json<T>> {
+    Created(format!("/api/v1{}", url), Some(Json(body)))
+}
+
+fn forbidden() -> ApiError {
+    ApiError::from(DENIED)
+}
+
+fn not_found() -> ApiError {
+    ApiError::from(Status::NotFound)
+}
+
+/// Leave out the emails of other users unless the viewer can see them
+fn hide_emails(viewer: &User, mut found: Vec<User>) -> Vec<User> {
+    if !viewer.can(Permission::ViewUsers) {
+        for u in found.iter_mut().filter(|u| u.id != viewer.id) {
+            u.email = String::new();
+        }
+    }
+    found
+}
+
+fn hide_email(viewer: &User, user: User) -> User {
+    hide_emails(viewer, vec![user]).remove(0)
+}
+
+//# Users
+
+/// GET handler for `/api/v1/me`
+///
+/// The user the request is authenticated as.
+#[get("/me")]
+pub fn api_me(l: UserGuard) -> Json<User> {
+    Json(l.0)
+}
+
+/// GET handler for `/api/v1/users?<s>&<a>`
+///
+/// Emails of other users are only included for Mentors and up.
+#[get("/users?<s>&<a>")]
+pub fn api_users(
+    conn: ObservDbConn,
+    l: UserGuard,
+    s: Option<String>,
+    a: Option<bool>,
+) -> Json<Vec<User>> {
+    Json(hide_emails(&l.0, filter_users(&*conn, s, a)))
+}
+
+/// GET handler for `/api/v1/users/<uid>`
+///
+/// Emails of other users are only included for Mentors and up.
+#[get("/users/<uid>")]
+pub fn api_user(conn: ObservDbConn, l: UserGuard, uid: i32) -> ApiResult<Json<User>> {
+    Ok(Json(hide_email(&l.0, find_user(&*conn, uid)?)))
+}
+
+/// POST handler for `/api/v1/users`
+///
+/// Restricted to Admins.
+#[post("/users", data = "<body>")]
+pub fn api_user_new(
+    conn: ObservDbConn,
+    l: Can<can::ManageUsers>,
+    body: Result<Json<NewUser>, JsonError>,
+) -> ApiResult<Created<Json<User>>> {
+    let mut newuser = body?.into_inner();
+    if newuser.password_hash.is_empty() {
+        return Err(FormError::Password.into());
+    }
+    newuser.password_hash = hash_password(newuser.password_hash);
+
+    let user = create_user(&*conn, newuser)?;
+    audit::created(&*conn, Some(&l.0), "user", user.id, &user);
+
+    audit_log!(
+        "User {} [{}] has created User {} through the API",
+        l.0.id,
+        l.0.email,
+        user.id
+    );
+
+    Ok(created(format!("/users/{}", user.id), user))
+}
+
+/// Changes that can be made to a user, fields that are left out aren't changed
+#[derive(Debug, Deserialize)]
+pub struct EditUser {
+    pub real_name: Option<String>,
+    pub handle: Option<String>,
+    /// The new plain text password
+    pub password: Option<String>,
+    pub bio: Option<String>,
+    pub email: Option<String>,
+    pub role: Option<Role>,
+    pub active: Option<bool>,
+    pub mmost: Option<String>,
+    pub former: Option<bool>,
+    pub extrn: Option<bool>,
+}
+
+impl EditUser {
+    /// The user with the changes made
+    fn apply(self, u: User) -> NewUser {
+        NewUser {
+            real_name: self.real_name.unwrap_or(u.real_name),
+            handle: self.handle.unwrap_or(u.handle),
+            // An empty password keeps the old one, see `save_user`
+            password_hash: self.password.unwrap_or_default(),
+            bio: self.bio.unwrap_or(u.bio),
+            email: self.email.unwrap_or(u.email),
+            role: self.role.unwrap_or(u.role),
+            active: self.active.unwrap_or(u.active),
+            mmost: self.mmost.unwrap_or(u.mmost),
+            former: self.former.unwrap_or(u.former),
+            extrn: self.extrn.unwrap_or(u.extrn),
+        }
+    }
+}
+
+/// PUT handler for `/api/v1/users/<uid>`
+///
+/// Fields that are left out aren't changed.
+///
+/// Restricted to Admins and the user themselves.
+#[put("/users/<uid>", data = "<body>")]
+pub fn api_user_edit(
+    conn: ObservDbConn,
+    l: UserGuard,
+    uid: i32,
+    body: Result<Json<EditUser>, JsonError>,
+) -> ApiResult<Json<User>> {
+    let user = find_user(&*conn, uid)?;
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(forbidden());
+    }
+
+    save_user(&*conn, &l.0, uid, body?.into_inner().apply(user))?;
+    Ok(Json(find_user(&*conn, uid)?))
+}
+
+/// DELETE handler for `/api/v1/users/<uid>`
+///
+/// Restricted to Admins.
+#[delete("/users/<uid>")]
+pub fn api_user_delete(
+    conn: ObservDbConn,
+    l: Can<can::ManageUsers>,
+    uid: i32,
+) -> ApiResult<Status> {
+    let user = find_user(&*conn, uid)?;
+    // The admin user can never be deleted
+    if uid == 0 {
+        return Err(forbidden());
+    }
+
+    move_to_trash(&*conn, Kind::User, uid);
+    audit::deleted(&*conn, Some(&l.0), "user", uid, &user);
+
+    audit_log!(
+        "User {} [{}] has deleted User {} through the API",
+        l.0.id,
+        l.0.email,
+        uid
+    );
+
+    Ok(Status::NoContent)
+}
+
+fn find_user(conn: &SqliteConnection, uid: i32) -> ApiResult<User> {
+    use crate::schema::users::dsl::*;
+    users
+        .find(uid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get user from database")
+        .ok_or_else(not_found)
+}
+
+//# Projects
+
+/// GET handler for `/api/v1/projects?<s>&<a>`
+#[get("/projects?<s>&<a>")]
+pub fn api_projects(
+    conn: ObservDbConn,
+    _l: UserGuard,
+    s: Option<String>,
+    a: Option<bool>,
+) -> Json<Vec<Project>> {
+    Json(filter_projects(&*conn, s, a))
+}
+
+/// GET handler for `/api/v1/projects/<pid>`
+#[get("/projects/<pid>")]
+pub fn api_project(conn: ObservDbConn, _l: UserGuard, pid: i32) -> ApiResult<Json<Project>> {
+    Ok(Json(find_project(&*conn, pid)?))
+}
+
+/// GET handler for `/api/v1/projects/<pid>/members`
+#[get("/projects/<pid>/members")]
+pub fn api_project_members(
+    conn: ObservDbConn,
+    l: UserGuard,
+    pid: i32,
+) -> ApiResult<Json<Vec<User>>> {
+    let p = find_project(&*conn, pid)?;
+    Ok(Json(hide_emails(&l.0, project_users(&*conn, &p))))
+}
+
+/// POST handler for `/api/v1/projects`
+///
+/// The new project is owned by the user who created it.
+#[post("/projects", data = "<body>")]
+pub fn api_project_new(
+    conn: ObservDbConn,
+    l: UserGuard,
+    body: Result<Json<NewProject>, JsonError>,
+) -> ApiResult<Created<Json<Project>>> {
+    let p = create_project(&*conn, &l.0, body?.into_inner())?;
+    audit::created(&*conn, Some(&l.0), "project", p.id, &p);
+    Ok(created(format!("/projects/{}", p.id), p))
+}
+
+/// PUT handler for `/api/v1/projects/<pid>`
+///
+/// Restricted to Admins and the project owner and leads.
+#[put("/projects/<pid>", data = "<body>")]
+pub fn api_project_edit(
+    conn: ObservDbConn,
+    l: UserGuard,
+    pid: i32,
+    body: Result<Json<NewProject>, JsonError>,
+) -> ApiResult<Json<Project>> {
+    let p = find_project(&*conn, pid)?;
+    if !can_edit_project(&*conn, &l.0, &p) {
+        return Err(forbidden());
+    }
+
+    update_project(&*conn, pid, body?.into_inner())?;
+    let new = find_project(&*conn, pid)?;
+    audit::updated(&*conn, Some(&l.0), "project", pid, &p, &new);
+    Ok(Json(new))
+}
+
+/// DELETE handler for `/api/v1/projects/<pid>`
+///
+/// Restricted to Admins and the project owner.
+#[delete("/projects/<pid>")]
+pub fn api_project_delete(conn: ObservDbConn, l: UserGuard, pid: i32) -> ApiResult<Status> {
+    let p = find_project(&*conn, pid)?;
+    if !l.0.can(Permission::EditAnyProject) && l.0.id != p.owner_id {
+        return Err(forbidden());
+    }
+
+    move_to_trash(&*conn, Kind::Project, pid);
+    audit::deleted(&*conn, Some(&l.0), "project", pid, &p);
+    Ok(Status::NoContent)
+}
+
+fn find_project(conn: &SqliteConnection, pid: i32) -> ApiResult<Project> {
+    use crate::schema::projects::dsl::*;
+    projects
+        .find(pid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get project from database")
+        .ok_or_else(not_found)
+}
+
+//# Groups
+
+/// GET handler for `/api/v1/groups`
+///
+/// Restricted to Mentors.
+#[get("/groups")]
+pub fn api_groups(conn: ObservDbConn, _l: Can<can::RunMeetings>) -> Json<Vec<Group>> {
+    use crate::schema::groups::dsl::*;
+    Json(
+        groups
+            .filter(deleted_at.is_null())
+            .load(&*conn)
+            .expect("Failed to get groups from database"),
+    )
+}
+
+/// GET handler for `/api/v1/groups/<gid>`
+#[get("/groups/<gid>")]
+pub fn api_group(conn: ObservDbConn, _l: UserGuard, gid: i32) -> ApiResult<Json<Group>> {
+    Ok(Json(find_group(&*conn, gid)?))
+}
+
+/// GET handler for `/api/v1/groups/<gid>/members`
+#[get("/groups/<gid>/members")]
+pub fn api_group_members(conn: ObservDbConn, l: UserGuard, gid: i32) -> ApiResult<Json<Vec<User>>> {
+    let g = find_group(&*conn, gid)?;
+    Ok(Json(hide_emails(&l.0, group_users(&*conn, &g))))
+}
+
+/// POST handler for `/api/v1/groups`
+///
+/// The meetings of its schedule are created for the coming weeks.
+///
+/// Restricted to Admins.
+#[post("/groups", data = "<body>")]
+pub fn api_group_new(
+    conn: ObservDbConn,
+    l: Can<can::ManageGroup>,
+    settings: State<Settings>,
+    body: Result<Json<NewGroup>, JsonError>,
+) -> ApiResult<Created<Json<Group>>> {
+    let newgroup = body?.into_inner();
+    let gid = create_group(&*conn, &newgroup)?;
+
+    audit_log!(
+        "User {} [{}] has created Group {} \'{}\'",
+        l.0.id,
+        l.0.email,
+        gid,
+        newgroup.name
+    );
+
+    let g = find_group(&*conn, gid)?;
+    audit::created(&*conn, Some(&l.0), "group", gid, &g);
+    let now = chrono::offset::Utc::now().naive_utc();
+    for m in schedule_meetings(&*conn, &g, &settings, now) {
+        audit::created(&*conn, Some(&l.0), "meeting", m.id, &m);
+    }
+    Ok(created(format!("/groups/{}", gid), g))
+}
+
+/// Changes that can be made to a group, fields that are left out aren't changed
+#[derive(Debug, Deserialize)]
+pub struct EditGroup {
+    pub name: Option<String>,
+    pub owner_id: Option<i32>,
+    pub location: Option<String>,
+    pub semester_id: Option<i32>,
+    pub meeting_day: Option<String>,
+    pub meeting_time: Option<String>,
+}
+
+impl EditGroup {
+    /// The group with the changes made
+    fn apply(self, g: Group) -> NewGroup {
+        let time = g.meeting_time.map(|t| t.format("%T").to_string());
+        NewGroup {
+            name: self.name.unwrap_or(g.name),
+            owner_id: self.owner_id.unwrap_or(g.owner_id),
+            location: self.location.or(g.location),
+            semester_id: self.semester_id.or(g.semester_id),
+            meeting_day: self.meeting_day.or(g.meeting_day),
+            meeting_time: self.meeting_time.or(time),
+        }
+    }
+}
+
+/// PUT handler for `/api/v1/groups/<gid>`
+///
+/// Fields that are left out aren't changed.
+/// Meetings that haven't happened yet are moved to the new schedule.
+///
+/// Restricted to Admins and the group mentor.
+/// Only Admins can change the mentor of a group.
+#[put("/groups/<gid>", data = "<body>")]
+pub fn api_group_edit(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    settings: State<Settings>,
+    gid: i32,
+    body: Result<Json<EditGroup>, JsonError>,
+) -> ApiResult<Json<Group>> {
+    let g = find_group(&*conn, gid)?;
+    if !l.0.can(Permission::ManageGroup) && l.0.id != g.owner_id {
+        return Err(forbidden());
+    }
+
+    let mut editgroup = body?.into_inner().apply(g.clone());
+    is_reserved(&editgroup.name)?;
+    editgroup.fix_schedule()?;
+    if !l.0.can(Permission::ManageGroup) {
+        editgroup.owner_id = g.owner_id;
+    }
+
+    use crate::schema::groups::dsl::*;
+    update(groups.find(gid))
+        .set(&editgroup)
+        .execute(&*conn)
+        .expect("Failed to update group in the database");
+
+    let new = find_group(&*conn, gid)?;
+    audit::updated(&*conn, Some(&l.0), "group", gid, &g, &new);
+    let now = chrono::offset::Utc::now().naive_utc();
+    for m in schedule_meetings(&*conn, &new, &settings, now) {
+        audit::created(&*conn, Some(&l.0), "meeting", m.id, &m);
+    }
+    Ok(Json(new))
+}
+
+/// DELETE handler for `/api/v1/groups/<gid>`
+///
+/// Restricted to Admins.
+#[delete("/groups/<gid>")]
+pub fn api_group_delete(
+    conn: ObservDbConn,
+    l: Can<can::ManageGroup>,
+    gid: i32,
+) -> ApiResult<Status> {
+    let g = find_group(&*conn, gid)?;
+    // The Large Group always has to exist
+    if gid == 0 {
+        return Err(forbidden());
+    }
+
+    move_to_trash(&*conn, Kind::Group, gid);
+    audit::deleted(&*conn, Some(&l.0), "group", gid, &g);
+
+    audit_log!(
+        "User {} [{}] has deleted Group {} through the API",
+        l.0.id,
+        l.0.email,
+        gid
+    );
+
+    Ok(Status::NoContent)
+}
+
+fn find_group(conn: &SqliteConnection, gid: i32) -> ApiResult<Group> {
+    use crate::schema::groups::dsl::*;
+    groups
+        .find(gid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get group from database")
+        .ok_or_else(not_found)
+}
+
+//# Meetings
+
+/// GET handler for `/api/v1/groups/<gid>/meetings`
+///
+/// Restricted to Mentors.
+#[get("/groups/<gid>/meetings")]
+pub fn api_meetings(
+    conn: ObservDbConn,
+    _l: Can<can::RunMeetings>,
+    gid: i32,
+) -> ApiResult<Json<Vec<Meeting>>> {
+    let g = find_group(&*conn, gid)?;
+    Ok(Json(
+        Meeting::belonging_to(&g)
+            .load(&*conn)
+            .expect("Failed to get meetings from database"),
+    ))
+}
+
+/// GET handler for `/api/v1/groups/<gid>/meetings/<mid>`
+///
+/// Restricted to Mentors.
+#[get("/groups/<gid>/meetings/<mid>")]
+pub fn api_meeting(
+    conn: ObservDbConn,
+    _l: Can<can::RunMeetings>,
+    gid: i32,
+    mid: i32,
+) -> ApiResult<Json<Meeting>> {
+    Ok(Json(find_meeting(&*conn, gid, mid)?))
+}
+
+/// GET handler for `/api/v1/groups/<gid>/meetings/<mid>/attendees`
+///
+/// The users whose attendance at the meeting was approved.
+///
+/// Restricted to Mentors.
+#[get("/groups/<gid>/meetings/<mid>/attendees")]
+pub fn api_meeting_attendees(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    gid: i32,
+    mid: i32,
+) -> ApiResult<Json<Vec<User>>> {
+    let m = find_meeting(&*conn, gid, mid)?;
+    Ok(Json(hide_emails(&l.0, meeting_users(&*conn, &m))))
+}
+
+/// POST handler for `/api/v1/groups/<gid>/meetings`
+///
+/// Records a new meeting starting now with a fresh attendance code.
+///
+/// Archived groups can't have new meetings.
+///
+/// Restricted to Admins, the group mentor and members of small groups.
+#[post("/groups/<gid>/meetings")]
+pub fn api_meeting_new(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    settings: State<Settings>,
+    gid: i32,
+) -> ApiResult<Created<Json<Meeting>>> {
+    let g = find_group(&*conn, gid)?;
+    if !can_host(&*conn, &l.0, &g) {
+        return Err(forbidden());
+    }
+    if g.archived {
+        return Err(Status::Conflict.into());
+    }
+
+    audit_log!(
+        "User {} [{}] has generated an attendance code for Group {}",
+        l.0.id,
+        l.0.email,
+        gid
+    );
+
+    let m = create_meeting(&*conn, &g, &l.0, &settings);
+    audit::created(&*conn, Some(&l.0), "meeting", m.id, &m);
+    Ok(created(format!("/groups/{}/meetings/{}", gid, m.id), m))
+}
+
+/// Changes that can be made to a meeting
+#[derive(Debug, Deserialize)]
+pub struct EditMeeting {
+    /// Manual override of the code window, `null` follows the window
+    pub code_open: Option<bool>,
+}
+
+/// PUT handler for `/api/v1/groups/<gid>/meetings/<mid>`
+///
+/// Restricted to Admins, the group mentor and the meeting host.
+#[put("/groups/<gid>/meetings/<mid>", data = "<body>")]
+pub fn api_meeting_edit(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    gid: i32,
+    mid: i32,
+    body: Result<Json<EditMeeting>, JsonError>,
+) -> ApiResult<Json<Meeting>> {
+    let g = find_group(&*conn, gid)?;
+    let m = find_meeting(&*conn, gid, mid)?;
+    if !l.0.can(Permission::ManageGroup) && l.0.id != g.owner_id && l.0.id != m.hosted_by {
+        return Err(forbidden());
+    }
+
+    let state = body?.into_inner().code_open;
+    use crate::schema::meetings::dsl::*;
+    update(meetings.find(mid))
+        .set(code_open.eq(state))
+        .execute(&*conn)
+        .expect("Failed to update meeting in database");
+
+    audit_log!(
+        "User {} [{}] has set the code of Meeting {} to {:?}",
+        l.0.id,
+        l.0.email,
+        mid,
+        state
+    );
+
+    let new = find_meeting(&*conn, gid, mid)?;
+    audit::updated(&*conn, Some(&l.0), "meeting", mid, &m, &new);
+    Ok(Json(new))
+}
+
+/// DELETE handler for `/api/v1/groups/<gid>/meetings/<mid>`
+///
+/// Deletes a meeting along with its attendances.
+///
+/// Restricted to Admins and the group mentor.
+#[delete("/groups/<gid>/meetings/<mid>")]
+pub fn api_meeting_delete(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    gid: i32,
+    mid: i32,
+) -> ApiResult<Status> {
+    let g = find_group(&*conn, gid)?;
+    let m = find_meeting(&*conn, gid, mid)?;
+    if !l.0.can(Permission::ManageGroup) && l.0.id != g.owner_id {
+        return Err(forbidden());
+    }
+
+    delete_meeting(&*conn, mid);
+    audit::deleted(&*conn, Some(&l.0), "meeting", mid, &m);
+
+    audit_log!(
+        "User {} [{}] has deleted Meeting {} of Group {}",
+        l.0.id,
+        l.0.email,
+        mid,
+        gid
+    );
+
+    Ok(Status::NoContent)
+}
+
+fn find_meeting(conn: &SqliteConnection, gid: i32, mid: i32) -> ApiResult<Meeting> {
+    use crate::schema::meetings::dsl::*;
+    meetings
+        .find(mid)
+        .filter(group_id.eq(gid))
+        .first(conn)
+        .optional()
+        .expect("Failed to get meeting from database")
+        .ok_or_else(not_found)
+}
+
+//# Events
+
+/// GET handler for `/api/v1/events`
+#[get("/events")]
+pub fn api_events(conn: ObservDbConn, _l: UserGuard) -> Json<Vec<Event>> {
+    use crate::schema::events::dsl::*;
+    Json(
+        events
+            .filter(deleted_at.is_null())
+            .order(start.asc())
+            .load(&*conn)
+            .expect("Failed to get events"),
+    )
+}
+
+/// GET handler for `/api/v1/events/<eid>`
+#[get("/events/<eid>")]
+pub fn api_event(conn: ObservDbConn, _l: UserGuard, eid: i32) -> ApiResult<Json<Event>> {
+    Ok(Json(find_event(&*conn, eid)?))
+}
+
+/// POST handler for `/api/v1/events`
+///
+/// The attendance code is always generated.
+///
+/// Restricted to Admins.
+#[post("/events", data = "<body>")]
+pub fn api_event_new(
+    conn: ObservDbConn,
+    l: Can<can::CreateEvent>,
+    settings: State<Settings>,
+    body: Result<Json<NewEvent>, JsonError>,
+) -> ApiResult<Created<Json<Event>>> {
+    let mut newevent = body?.into_inner();
+    newevent
+        .fix_times(&tz::of(Some(&l.0), &settings.site_zone()))
+        .ok_or(FormError::InvalidDate)?;
+    is_reserved(&newevent.title)?;
+    newevent.check_capacity()?;
+    newevent.code = attendance_code(&*conn);
+    newevent.find_semester(&*conn, &settings.site_zone());
+
+    audit_log!(
+        "User {} [{}] has generated an attendance code for Event \'{}\'",
+        l.0.id,
+        l.0.email,
+        newevent.title
+    );
+
+    use crate::schema::events::dsl::*;
+    insert_into(events)
+        .values(&newevent)
+        .execute(&*conn)
+        .expect("Failed to add event to database");
+
+    let evt = find_event(&*conn, last_id(&*conn))?;
+    audit::created(&*conn, Some(&l.0), "event", evt.id, &evt);
+    Ok(created(format!("/events/{}", evt.id), evt))
+}
+
+/// PUT handler for `/api/v1/events/<eid>`
+///
+/// The attendance code can't be changed.
+///
+/// Restricted to Admins and the event host.
+#[put("/events/<eid>", data = "<body>")]
+pub fn api_event_edit(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    eid: i32,
+    body: Result<Json<NewEvent>, JsonError>,
+) -> ApiResult<Json<Event>> {
+    let evt = find_event(&*conn, eid)?;
+    if !l.0.can(Permission::EditAnyEvent) && l.0.id != evt.hosted_by {
+        return Err(forbidden());
+    }
+
+    let mut editevent = body?.into_inner();
+    editevent
+        .fix_times(&tz::of(Some(&l.0), &settings.site_zone()))
+        .ok_or(FormError::InvalidDate)?;
+    is_reserved(&editevent.title)?;
+    editevent.check_capacity()?;
+    editevent.code = evt.code.clone();
+
+    update_event(&*conn, &evt, &editevent);
+
+    let new = find_event(&*conn, eid)?;
+    audit::updated(&*conn, Some(&l.0), "event", eid, &evt, &new);
+    Ok(Json(new))
+}
+
+/// DELETE handler for `/api/v1/events/<eid>`
+///
+/// Deletes an event along with its attendances.
+///
+/// Restricted to Admins.
+#[delete("/events/<eid>")]
+pub fn api_event_delete(
+    conn: ObservDbConn,
+    l: Can<can::EditAnyEvent>,
+    eid: i32,
+) -> ApiResult<Status> {
+    let evt = find_event(&*conn, eid)?;
+    move_to_trash(&*conn, Kind::Event, eid);
+    audit::deleted(&*conn, Some(&l.0), "event", eid, &evt);
+
+    audit_log!(
+        "User {} [{}] has deleted Event {} through the API",
+        l.0.id,
+        l.0.email,
+        eid
+    );
+
+    Ok(Status::NoContent)
+}
+
+fn find_event(conn: &SqliteConnection, eid: i32) -> ApiResult<Event> {
+    use crate::schema::events::dsl::*;
+    events
+        .find(eid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get event from database")
+        .ok_or_else(not_found)
+}
+
+//# News
+
+/// GET handler for `/api/v1/news`
+#[get("/news")]
+pub fn api_news(conn: ObservDbConn, _l: UserGuard) -> Json<Vec<NewsStory>> {
+    use crate::schema::news::dsl::*;
+    Json(
+        news.filter(deleted_at.is_null())
+            .order(happened_at.desc())
+            .load(&*conn)
+            .expect("Failed to get news from database"),
+    )
+}
+
+/// GET handler for `/api/v1/news/<nid>`
+#[get("/news/<nid>")]
+pub fn api_story(conn: ObservDbConn, _l: UserGuard, nid: i32) -> ApiResult<Json<NewsStory>> {
+    Ok(Json(find_story(&*conn, nid)?))
+}
+
+/// POST handler for `/api/v1/news`
+///
+/// Restricted to Admins.
+#[post("/news", data = "<body>")]
+pub fn api_story_new(
+    conn: ObservDbConn,
+    l: Can<can::ManageNews>,
+    settings: State<Settings>,
+    body: Result<Json<NewNewsStory>, JsonError>,
+) -> ApiResult<Created<Json<NewsStory>>> {
+    let mut newstory = body?.into_inner();
+    newstory
+        .fix_times(&tz::of(Some(&l.0), &settings.site_zone()))
+        .ok_or(FormError::InvalidDate)?;
+    is_reserved(&newstory.title)?;
+
+    use crate::schema::news::dsl::*;
+    insert_into(news)
+        .values(&newstory)
+        .execute(&*conn)
+        .expect("Failed to insert news story into database");
+
+    let story = find_story(&*conn, last_id(&*conn))?;
+    audit::created(&*conn, Some(&l.0), "news", story.id, &story);
+    Ok(created(format!("/news/{}", story.id), story))
+}
+
+/// PUT handler for `/api/v1/news/<nid>`
+///
+/// Restricted to Admins.
+#[put("/news/<nid>", data = "<body>")]
+pub fn api_story_edit(
+    conn: ObservDbConn,
+    l: Can<can::ManageNews>,
+    settings: State<Settings>,
+    nid: i32,
+    body: Result<Json<NewNewsStory>, JsonError>,
+) -> ApiResult<Json<NewsStory>> {
+    let old = find_story(&*conn, nid)?;
+
+    let mut editstory = body?.into_inner();
+    editstory
+        .fix_times(&tz::of(Some(&l.0), &settings.site_zone()))
+        .ok_or(FormError::InvalidDate)?;
+    is_reserved(&editstory.title)?;
+
+    use crate::schema::news::dsl::*;
+    update(news.find(nid))
+        .set(&editstory)
+        .execute(&*conn)
+        .expect("Failed to update news story in database");
+
+    let story = find_story(&*conn, nid)?;
+    audit::updated(&*conn, Some(&l.0), "news", nid, &old, &story);
+    Ok(Json(story))
+}
+
+/// DELETE handler for `/api/v1/news/<nid>`
+///
+/// Restricted to Admins.
+#[delete("/news/<nid>")]
+pub fn api_story_delete(
+    conn: ObservDbConn,
+    l: Can<can::ManageNews>,
+    nid: i32,
+) -> ApiResult<Status> {
+    let old = find_story(&*conn, nid)?;
+    move_to_trash(&*conn, Kind::News, nid);
+    audit::deleted(&*conn, Some(&l.0), "news", nid, &old);
+    Ok(Status::NoContent)
+}
+
+fn find_story(conn: &SqliteConnection, nid: i32) -> ApiResult<NewsStory> {
+    use crate::schema::news::dsl::*;
+    news.find(nid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get news story from database")
+        .ok_or_else(not_found)
+}
+
+//# Attendances
+
+/// GET handler for `/api/v1/attendances?<user>`
+///
+/// The attendances of a user, by default the logged in user.
+///
+/// Only Mentors can see the attendances of other users.
+#[get("/attendances?<user>")]
+pub fn api_attendances(
+    conn: ObservDbConn,
+    l: UserGuard,
+    user: Option<i32>,
+) -> ApiResult<Json<Vec<Attendance>>> {
+    let uid = user.unwrap_or(l.0.id);
+    if !l.0.can(Permission::ViewUsers) && l.0.id != uid {
+        return Err(forbidden());
+    }
+
+    use crate::schema::attendances::dsl::*;
+    Ok(Json(
+        attendances
+            .filter(user_id.eq(uid))
+            .order(id.asc())
+            .load(&*conn)
+            .expect("Failed to get attendances from database"),
+    ))
+}
+
+/// GET handler for `/api/v1/attendances/pending`
+///
+/// The attendances waiting for approval that the user can review.
+///
+/// Restricted to Mentors.
+#[get("/attendances/pending")]
+pub fn api_attendances_pending(
+    conn: ObservDbConn,
+    l: Can<can::ReviewAttendance>,
+) -> Json<Vec<Attendance>> {
+    use crate::schema::attendances::dsl::*;
+    Json(
+        attendances
+            .filter(approved.eq(false))
+            .order(id.asc())
+            .load::<Attendance>(&*conn)
+            .expect("Failed to get attendances from database")
+            .into_iter()
+            .filter(|a| can_review(&*conn, &l.0, &*attended(&*conn, a)))
+            .collect(),
+    )
+}
+
+/// GET handler for `/api/v1/attendances/<aid>`
+///
+/// Restricted to the user who attended and those who can review it.
+#[get("/attendances/<aid>")]
+pub fn api_attendance(conn: ObservDbConn, l: UserGuard, aid: i32) -> ApiResult<Json<Attendance>> {
+    let a = find_attendance(&*conn, aid)?;
+    if l.0.id != a.user_id && !can_review(&*conn, &l.0, &*attended(&*conn, &a)) {
+        return Err(forbidden());
+    }
+    Ok(Json(a))
+}
+
+/// An attendance code being submitted
+#[derive(Debug, Deserialize)]
+pub struct SubmitCode {
+    pub code: String,
+}
+
+/// POST handler for `/api/v1/attendances`
+///
+/// Submits an attendance code for the logged in user.
+#[post("/attendances", data = "<body>")]
+pub fn api_attendance_new(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    body: Result<Json<SubmitCode>, JsonError>,
+) -> ApiResult<Created<Json<Attendance>>> {
+    let a = submit_attendance(
+        &*conn,
+        &l.0,
+        &body?.into_inner().code,
+        settings.code_rotation_seconds,
+    )?;
+    Ok(created(format!("/attendances/{}", a.id), a))
+}
+
+/// PUT handler for `/api/v1/attendances/<aid>`
+///
+/// Approves an attendance.
+///
+/// Restricted to those who can review the attendance.
+#[put("/attendances/<aid>")]
+pub fn api_attendance_approve(
+    conn: ObservDbConn,
+    l: Can<can::ReviewAttendance>,
+    aid: i32,
+) -> ApiResult<Json<Attendance>> {
+    review(&*conn, &l.0, aid, true)?;
+    Ok(Json(find_attendance(&*conn, aid)?))
+}
+
+/// DELETE handler for `/api/v1/attendances/<aid>`
+///
+/// Rejects an attendance, removing it.
+///
+/// Restricted to those who can review the attendance.
+#[delete("/attendances/<aid>")]
+pub fn api_attendance_reject(
+    conn: ObservDbConn,
+    l: Can<can::ReviewAttendance>,
+    aid: i32,
+) -> ApiResult<Status> {
+    review(&*conn, &l.0, aid, false)?;
+    Ok(Status::NoContent)
+}
+
+fn find_attendance(conn: &SqliteConnection, aid: i32) -> ApiResult<Attendance> {
+    use crate::schema::attendances::dsl::*;
+    attendances
+        .find(aid)
+        .first(conn)
+        .optional()
+        .expect("Failed to get attendance from database")
+        .ok_or_else(not_found)
+}
@@ -1,8 +1,12 @@
 //! Attendance code generation and verification functions
 
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use ring::hmac;
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
 
 use crate::models::Attendable;
 use crate::models::Event;
@@ -12,43 +16,194 @@ use crate::models::Meeting;
 ///
 /// Takes a reference to the database connection and the code you want
 /// to verify and returns the event that the code corresponds to if it exists.
-pub fn verify_code(conn: &SqliteConnection, vcode: &str) -> Option<Box<dyn Attendable>> {
+///
+/// `rotation` is how many seconds a rotating code lasts. While codes
+/// rotate only the rotating codes shown in presenter mode are accepted,
+/// so a code seen once can't be used later. With a `rotation` of 0 the
+/// normal codes are accepted instead.
+pub fn verify_code(
+    conn: &SqliteConnection,
+    vcode: &str,
+    rotation: i64,
+) -> Option<Box<dyn Attendable>> {
+    if rotation > 0 {
+        let now = chrono::offset::Utc::now().naive_utc();
+        verify_rotating_code(conn, vcode, rotation, now)
+    } else {
+        lookup_code(conn, vcode)
+    }
+}
+
+/// Find the event or meeting that uses exactly this attendance code
+fn lookup_code(conn: &SqliteConnection, vcode: &str) -> Option<Box<dyn Attendable>> {
     if let Some(e) = {
         use crate::schema::events::dsl::*;
         events
-            .filter(code.eq(vcode.to_lowercase()))
+            .filter(code.eq(vcode.to_lowercase()).and(deleted_at.is_null()))
             .first::<Event>(conn)
             .optional()
             .expect("Failed to get events from database")
     } {
         Some(Box::new(e))
-    } else if let Some(m) = {
+    } else {
         use crate::schema::meetings::dsl::*;
         meetings
             .filter(code.eq(vcode.to_lowercase()))
             .first::<Meeting>(conn)
             .optional()
             .expect("Failed to get meetings from database")
-    } {
-        Some(Box::new(m))
-    } else {
-        None
+            .filter(|m| !in_trashed_group(conn, m))
+            .map(|m| Box::new(m) as Box<dyn Attendable>)
     }
 }
 
+/// Check if an attendance code is currently being accepted
+///
+/// The host's manual override wins if there is one, otherwise the code is
+/// open between `time()` and `closes_at()`.
+pub fn code_is_open(m: &dyn Attendable, now: NaiveDateTime) -> bool {
+    match m.code_open() {
+        Some(open) => open,
+        None => m.time() <= now && now <= m.closes_at(),
+    }
+}
+
+/// Check if attendance submitted now is late
+///
+/// Codes stop being accepted when their window closes, so this only
+/// happens when the host opens a code again afterwards. Late attendance
+/// has to be approved by the host.
+pub fn code_is_late(m: &dyn Attendable, now: NaiveDateTime) -> bool {
+    now > m.closes_at()
+}
+
 /// Generate a **unique** attendance code
 ///
 /// Takes a reference to the database connection and returns a
 /// **unique** attendance code that has not been used before.
 pub fn attendance_code(conn: &SqliteConnection) -> String {
     let code = gen_code();
-    if verify_code(conn, &code).is_some() {
+    if code_taken(conn, &code) {
         attendance_code(conn)
     } else {
         code
     }
 }
 
+/// Check if any event or meeting uses the code
+///
+/// Unlike `lookup_code` this includes the trash so a code is still unique
+/// when what uses it is restored.
+fn code_taken(conn: &SqliteConnection, vcode: &str) -> bool {
+    use diesel::dsl::{exists, select};
+    let vcode = vcode.to_lowercase();
+    let in_events = {
+        use crate::schema::events::dsl::*;
+        select(exists(events.filter(code.eq(&vcode))))
+            .get_result(conn)
+            .expect("Failed to get events from database")
+    };
+    in_events || {
+        use crate::schema::meetings::dsl::*;
+        select(exists(meetings.filter(code.eq(&vcode))))
+            .get_result(conn)
+            .expect("Failed to get meetings from database")
+    }
+}
+
+/// Check a code against the rotating codes of everything open right now
+///
+/// The code from the previous rotation is also accepted so that someone
+/// typing in a code just as it changes is not turned away.
+pub fn verify_rotating_code(
+    conn: &SqliteConnection,
+    vcode: &str,
+    rotation: i64,
+    now: NaiveDateTime,
+) -> Option<Box<dyn Attendable>> {
+    let vcode = vcode.to_lowercase();
+    let step = rotation_step(now, rotation);
+    let matches = |m: &dyn Attendable| {
+        rotating_code(&m.code(), step) == vcode || rotating_code(&m.code(), step - 1) == vcode
+    };
+
+    let open_events: Vec<Event> = {
+        use crate::schema::events::dsl::*;
+        events
+            .filter(
+                code_open
+                    .eq(true)
+                    .or(code_open.is_null().and(start.le(now)).and(end.ge(now)))
+                    .and(deleted_at.is_null()),
+            )
+            .load(conn)
+            .expect("Failed to get events from database")
+    };
+    if let Some(e) = open_events.into_iter().find(|e| matches(e)) {
+        return Some(Box::new(e));
+    }
+
+    let open_meetings: Vec<Meeting> = {
+        use crate::schema::meetings::dsl::*;
+        meetings
+            .filter(
+                code_open
+                    .eq(true)
+                    .or(code_open
+                        .is_null()
+                        .and(happened_at.le(now))
+                        .and(code_expires.ge(now)))
+                    .and(cancelled.eq(false)),
+            )
+            .load(conn)
+            .expect("Failed to get meetings from database")
+    };
+    open_meetings
+        .into_iter()
+        .find(|m| matches(m) && !in_trashed_group(conn, m))
+        .map(|m| Box::new(m) as Box<dyn Attendable>)
+}
+
+/// Is the meeting's group in the trash? Their codes aren't accepted.
+fn in_trashed_group(conn: &SqliteConnection, m: &Meeting) -> bool {
+    use crate::schema::groups::dsl::*;
+    groups
+        .find(m.group_id)
+        .filter(deleted_at.is_not_null())
+        .count()
+        .get_result::<i64>(conn)
+        .expect("Failed to get group from database")
+        > 0
+}
+
+/// Which rotation a point in time falls into
+pub fn rotation_step(now: NaiveDateTime, rotation: i64) -> i64 {
+    now.timestamp() / rotation.max(1)
+}
+
+/// Seconds left until the rotating codes change
+pub fn rotation_remaining(now: NaiveDateTime, rotation: i64) -> i64 {
+    let rotation = rotation.max(1);
+    rotation - now.timestamp() % rotation
+}
+
+/// Derive the rotating code of an attendance code
+///
+/// The rotating code is an HMAC of the rotation step keyed with the real
+/// attendance code, so it can't be used to work out the real code or the
+/// codes of any other rotation.
+pub fn rotating_code(base: &str, step: i64) -> String {
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, base.as_bytes());
+    hmac::sign(&key, &step.to_be_bytes())
+        .as_ref()
+        .iter()
+        .take(6)
+        .map(|b| CHARS[*b as usize % CHARS.len()] as char)
+        .collect()
+}
+
 /// Generate an attendance code
 ///
 /// This function generates a random 6 digit alphanumeric string.
@@ -64,3 +219,45 @@ pub fn gen_code() -> String {
         .collect::<String>()
         .to_lowercase()
 }
+
+/// Form used by hosts to manually open or close an attendance code
+#[derive(FromForm)]
+pub struct CodeStateForm {
+    pub state: CodeState,
+}
+
+/// The manual state of an attendance code
+///
+/// Stored in the database as a nullable boolean.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeState {
+    /// Follow the normal window
+    Auto,
+    /// Accept the code no matter the time
+    Open,
+    /// Never accept the code
+    Closed,
+}
+
+impl From<CodeState> for Option<bool> {
+    fn from(s: CodeState) -> Self {
+        match s {
+            CodeState::Auto => None,
+            CodeState::Open => Some(true),
+            CodeState::Closed => Some(false),
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for CodeState {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, &'v RawStr> {
+        match form_value.as_str() {
+            "auto" => Ok(CodeState::Auto),
+            "open" => Ok(CodeState::Open),
+            "closed" => Ok(CodeState::Closed),
+            _ => Err(form_value),
+        }
+    }
+}
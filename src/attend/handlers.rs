@@ -1,14 +1,21 @@
 //! HTTP handlers for attendance codes
 
-use diesel::insert_into;
 use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
 
-use rocket::request::Form;
+use rocket::http::{RawStr, Status};
+use rocket::request::{Form, FormItems, FromForm};
 use rocket::response::Redirect;
+use rocket::State;
 
+use crate::audit;
 use crate::guards::*;
-use crate::models::RelationGroupUser;
+use crate::models::{Attendable, Event, Group, Meeting, RelationGroupUser, User};
+use crate::roles::Permission;
+use crate::settings::Settings;
+use crate::templates::BigTemplate;
 use crate::templates::FormError;
+use crate::tz;
 use crate::ObservDbConn;
 
 use super::code::*;
@@ -16,11 +23,15 @@ use super::models::*;
 use super::templates::*;
 
 /// GET handler for `/attend`
-#[get("/attend?<e>")]
-pub fn attend(l: UserGuard, e: Option<FormError>) -> AttendTemplate {
+///
+/// The code can be pre-filled with `code`, this is used by the QR codes
+/// shown in presenter mode.
+#[get("/attend?<e>&<code>")]
+pub fn attend(l: UserGuard, e: Option<FormError>, code: Option<String>) -> AttendTemplate {
     AttendTemplate {
         logged_in: Some(l.0),
         error: e,
+        code,
     }
 }
 
@@ -39,61 +50,373 @@ pub struct AttendCode {
 /// and redirects to `/`.
 /// Otherwise redirects back to `/attend`.
 #[post("/attend", data = "<code>")]
-pub fn attend_post(conn: ObservDbConn, l: UserGuard, code: Form<AttendCode>) -> Redirect {
-    if let Some(m) = verify_code(&*conn, &code.code) {
-        let (mid, eid, gid) = if m.is_event() {
-            (None, Some(m.id()), None)
-        } else {
-            (Some(m.id()), None, m.group_id())
-        };
+pub fn attend_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    code: Form<AttendCode>,
+) -> Redirect {
+    match submit_attendance(&*conn, &l.0, &code.code, settings.code_rotation_seconds) {
+        Ok(_) => Redirect::to("/dashboard"),
+        Err(e) => Redirect::to(format!("/attend?e={}", e)),
+    }
+}
+
+/// Submit an attendance code for a user
+///
+/// Checks that the code is valid and open, and that the user is allowed to
+/// attend and hasn't already. Users have to verify their email first.
+/// Returns the new attendance.
+pub fn submit_attendance(
+    conn: &SqliteConnection,
+    user: &User,
+    vcode: &str,
+    rotation: i64,
+) -> Result<Attendance, FormError> {
+    if !user.verified {
+        return Err(FormError::Unverified);
+    }
+    let m = verify_code(conn, vcode, rotation).ok_or(FormError::InvalidCode)?;
+    let (mid, eid, gid) = if m.is_event() {
+        (None, Some(m.id()), None)
+    } else {
+        (Some(m.id()), None, m.group_id())
+    };
+
+    let user_in_group = if !m.is_event() {
+        use crate::schema::relation_group_user::dsl::*;
+        relation_group_user
+            .filter(group_id.eq(gid.unwrap()).and(user_id.eq(user.id)))
+            .first::<RelationGroupUser>(conn)
+            .optional()
+            .expect("Failed to get relations from database")
+            .is_some()
+    } else {
+        false
+    };
+
+    use crate::schema::attendances::dsl::*;
+    let user_attended = if m.is_event() {
+        attendances
+            .filter(event_id.eq(eid).and(user_id.eq(user.id)))
+            .first::<Attendance>(conn)
+            .optional()
+            .expect("Failed to get attendances from database")
+            .is_some()
+    } else {
+        attendances
+            .filter(meeting_id.eq(mid).and(user_id.eq(user.id)))
+            .first::<Attendance>(conn)
+            .optional()
+            .expect("Failed to get attendances from database")
+            .is_some()
+    };
 
-        let user_in_group = if !m.is_event() {
-            use crate::schema::relation_group_user::dsl::*;
-            relation_group_user
-                .filter(group_id.eq(gid.unwrap()).and(user_id.eq(l.0.id)))
-                .first::<RelationGroupUser>(&*conn)
-                .optional()
-                .expect("Failed to get relations from database")
-                .is_some()
-        } else {
-            false
+    let now = chrono::offset::Utc::now().naive_utc();
+
+    if user_attended {
+        Err(FormError::UsedCode)
+    } else if !m.is_event() && !user_in_group {
+        Err(FormError::InvalidCode)
+    } else if !code_is_open(&*m, now) {
+        Err(FormError::ExpiredCode)
+    } else {
+        let newattend = NewAttendance {
+            user_id: user.id,
+            is_event: m.is_event(),
+            meeting_id: mid,
+            event_id: eid,
+            approved: !code_is_late(&*m, now),
         };
+        insert_into(attendances)
+            .values(&newattend)
+            .execute(conn)
+            .expect("Failed to insert attendance into database");
 
-        use crate::schema::attendances::dsl::*;
-        let user_attended = if m.is_event() {
-            attendances
-                .filter(event_id.eq(eid).and(user_id.eq(l.0.id)))
-                .first::<Attendance>(&*conn)
-                .optional()
-                .expect("Failed to get attendances from database")
-                .is_some()
-        } else {
-            attendances
-                .filter(meeting_id.eq(mid).and(user_id.eq(l.0.id)))
-                .first::<Attendance>(&*conn)
-                .optional()
-                .expect("Failed to get attendances from database")
-                .is_some()
+        let a: Attendance = attendances
+            .filter(user_id.eq(user.id))
+            .order(id.desc())
+            .first(conn)
+            .expect("Failed to get attendance from database");
+        audit::created(conn, Some(user), "attendance", a.id, &a);
+        Ok(a)
+    }
+}
+
+/// GET handler for `/attend/pending`
+///
+/// The queue of attendances waiting to be approved.
+/// Mentors see the attendances for the groups they own and the meetings
+/// and events they host, Admins see everything. This is `can_review` done
+/// in the queries so the queue doesn't look up each attendance.
+#[get("/attend/pending")]
+pub fn attend_pending(
+    conn: ObservDbConn,
+    l: Can<can::ReviewAttendance>,
+    settings: State<Settings>,
+) -> PendingTemplate {
+    use crate::schema::{attendances, events, groups, meetings, users};
+    let uid = l.0.id;
+    let everything = l.0.can(Permission::ReviewAnyAttendance);
+
+    // Attendances of users that have since been deleted are skipped
+    let mut meeting_query = attendances::table
+        .inner_join(users::table)
+        .inner_join(meetings::table)
+        .filter(attendances::approved.eq(false))
+        .into_boxed();
+    if !everything {
+        let owned = groups::table
+            .filter(groups::owner_id.eq(uid))
+            .select(groups::id);
+        meeting_query = meeting_query.filter(
+            meetings::hosted_by
+                .eq(uid)
+                .or(meetings::group_id.eq_any(owned)),
+        );
+    }
+    let mut event_query = attendances::table
+        .inner_join(users::table)
+        .inner_join(events::table)
+        .filter(attendances::approved.eq(false))
+        .into_boxed();
+    if !everything {
+        event_query = event_query.filter(events::hosted_by.eq(uid));
+    }
+
+    let mut pending: Vec<PendingAttendance> = meeting_query
+        .load::<(Attendance, User, Meeting)>(&*conn)
+        .expect("Failed to get attendances from database")
+        .into_iter()
+        .map(|(attendance, user, m)| PendingAttendance {
+            attendance,
+            user,
+            attended: Box::new(m),
+        })
+        .collect();
+    pending.extend(
+        event_query
+            .load::<(Attendance, User, Event)>(&*conn)
+            .expect("Failed to get attendances from database")
+            .into_iter()
+            .map(|(attendance, user, e)| PendingAttendance {
+                attendance,
+                user,
+                attended: Box::new(e),
+            }),
+    );
+    pending.sort_by_key(|p| p.attendance.id);
+
+    PendingTemplate {
+        pending,
+        zone: tz::of(Some(&l.0), &settings.site_zone()),
+        logged_in: Some(l.0),
+    }
+}
+
+/// A selection of attendances from the approval queue
+///
+/// Used to parse the incoming form in `attend_pending_post`.
+/// Checkboxes all share the name `ids` so this can't be derived.
+pub struct ReviewForm {
+    /// Approve them if true, reject them if false
+    approve: bool,
+    /// IDs of the selected attendances
+    ids: Vec<i32>,
+}
+
+impl<'f> FromForm<'f> for ReviewForm {
+    type Error = &'f RawStr;
+
+    fn from_form(items: &mut FormItems<'f>, _strict: bool) -> Result<Self, Self::Error> {
+        let mut form = ReviewForm {
+            approve: false,
+            ids: Vec::new(),
         };
+        for item in items {
+            match item.key.as_str() {
+                "action" => form.approve = item.value == "approve",
+                "ids" => form.ids.push(item.value.parse().map_err(|_| item.value)?),
+                _ => (),
+            }
+        }
+        Ok(form)
+    }
+}
+
+/// POST handler for `/attend/pending`
+///
+/// Approves or rejects all the selected attendances at once.
+/// Anything the user is not allowed to review is skipped.
+#[post("/attend/pending", data = "<form>")]
+pub fn attend_pending_post(
+    conn: ObservDbConn,
+    l: Can<can::ReviewAttendance>,
+    form: Form<ReviewForm>,
+) -> Redirect {
+    let form = form.into_inner();
+    for aid in form.ids {
+        // Anything that can't be reviewed is skipped
+        review(&*conn, &l.0, aid, form.approve).ok();
+    }
+    Redirect::to("/attend/pending")
+}
+
+/// PUT handler for `/attend/<aid>`
+///
+/// Approves a single attendance.
+#[put("/attend/<aid>")]
+pub fn attend_approve(
+    conn: ObservDbConn,
+    l: Can<can::ReviewAttendance>,
+    aid: i32,
+) -> Result<Redirect, Status> {
+    review(&*conn, &l.0, aid, true)?;
+    Ok(Redirect::to("/attend/pending"))
+}
+
+/// DELETE handler for `/attend/<aid>`
+///
+/// Rejects a single attendance, removing it from the database.
+#[delete("/attend/<aid>")]
+pub fn attend_reject(
+    conn: ObservDbConn,
+    l: Can<can::ReviewAttendance>,
+    aid: i32,
+) -> Result<Redirect, Status> {
+    review(&*conn, &l.0, aid, false)?;
+    Ok(Redirect::to("/attend/pending"))
+}
+
+/// Approve or reject an attendance
+///
+/// Approving marks the attendance as approved, rejecting deletes it.
+pub fn review(
+    conn: &SqliteConnection,
+    reviewer: &User,
+    aid: i32,
+    approve: bool,
+) -> Result<(), Status> {
+    use crate::schema::attendances::dsl::*;
+
+    let a: Attendance = attendances
+        .find(aid)
+        .first(conn)
+        .optional()
+        .expect("Failed to get attendance from database")
+        .ok_or(Status::NotFound)?;
+
+    if !can_review(conn, reviewer, &*attended(conn, &a)) {
+        return Err(DENIED);
+    }
+
+    if approve {
+        update(attendances.find(aid))
+            .set(approved.eq(true))
+            .execute(conn)
+            .expect("Failed to update attendance in database");
+        let new: Attendance = attendances
+            .find(aid)
+            .first(conn)
+            .expect("Failed to get attendance from database");
+        audit::updated(conn, Some(reviewer), "attendance", aid, &a, &new);
+    } else {
+        delete(attendances.find(aid))
+            .execute(conn)
+            .expect("Failed to delete attendance from database");
+        audit::deleted(conn, Some(reviewer), "attendance", aid, &a);
+    }
+
+    audit_log!(
+        "User {} [{}] has {} Attendance {} of User {}",
+        reviewer.id,
+        reviewer.email,
+        if approve { "approved" } else { "rejected" },
+        aid,
+        a.user_id
+    );
 
-        if !user_attended && (m.is_event() || (!m.is_event() && user_in_group)) {
-            let newattend = NewAttendance {
-                user_id: l.0.id,
-                is_event: m.is_event(),
-                meeting_id: mid,
-                event_id: eid,
-            };
-            insert_into(attendances)
-                .values(&newattend)
-                .execute(&*conn)
-                .expect("Failed to insert attendance into database");
-            Redirect::to("/dashboard")
-        } else if user_attended {
-            Redirect::to(format!("/attend?e={}", FormError::UsedCode))
-        } else {
-            Redirect::to(format!("/attend?e={}", FormError::InvalidCode))
+    Ok(())
+}
+
+/// Can this user approve or reject attendance for something?
+///
+/// Admins can review anything, hosts can review their own meetings and
+/// events, and group mentors can review the meetings of their group.
+pub fn can_review(conn: &SqliteConnection, reviewer: &User, attended: &dyn Attendable) -> bool {
+    if reviewer.can(Permission::ReviewAnyAttendance) || reviewer.id == attended.owner_id() {
+        return true;
+    }
+
+    match attended.group_id() {
+        Some(gid) => {
+            use crate::schema::groups::dsl::*;
+            groups
+                .find(gid)
+                .first::<Group>(conn)
+                .map(|g| g.owner_id == reviewer.id)
+                .unwrap_or(false)
         }
+        None => false,
+    }
+}
+
+/// Get the meeting or event an attendance is for
+pub fn attended(conn: &SqliteConnection, a: &Attendance) -> Box<dyn Attendable> {
+    if a.is_event {
+        use crate::schema::events::dsl::*;
+        Box::new(
+            events
+                .find(a.event_id.unwrap())
+                .first::<Event>(conn)
+                .expect("Failed to load event from database"),
+        )
     } else {
-        Redirect::to(format!("/attend?e={}", FormError::InvalidCode))
+        use crate::schema::meetings::dsl::*;
+        Box::new(
+            meetings
+                .find(a.meeting_id.unwrap())
+                .first::<Meeting>(conn)
+                .expect("Failed to load meeting from database"),
+        )
     }
 }
+
+/// Presenter mode for a meeting or event
+///
+/// Shows the current rotating attendance code along with a QR code that
+/// links to `/attend` with the code filled in.
+/// The page reloads itself when the code rotates. If codes don't rotate
+/// the normal code is shown instead.
+pub fn present(logged_in: User, m: &dyn Attendable, rotation: i64, site: &SiteUrl) -> BigTemplate {
+    let now = chrono::offset::Utc::now().naive_utc();
+    let (text, refresh) = if rotation > 0 {
+        (
+            rotating_code(&m.code(), rotation_step(now, rotation)),
+            Some(rotation_remaining(now, rotation)),
+        )
+    } else {
+        (m.code(), None)
+    };
+    let url = format!("{}/attend?code={}", site.0, text);
+
+    BigTemplate {
+        logged_in: Some(logged_in),
+        qr: Some(qr_data_uri(&url)),
+        refresh,
+        text,
+    }
+}
+
+/// Render some text as a QR code SVG image in a `data:` URI
+pub fn qr_data_uri(text: &str) -> String {
+    use qrcode::render::svg;
+    use qrcode::QrCode;
+
+    let svg = QrCode::new(text.as_bytes())
+        .expect("Failed to create QR code")
+        .render::<svg::Color>()
+        .min_dimensions(300, 300)
+        .build();
+    format!("data:image/svg+xml;base64,{}", base64::encode(svg))
+}
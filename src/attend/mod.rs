@@ -2,6 +2,8 @@
 //!
 //! ## Routes
 //! - `/attend`
+//! - `/attend/pending`
+//! - `/attend/<aid>`
 
 pub mod code;
 pub mod handlers;
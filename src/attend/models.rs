@@ -22,6 +22,10 @@ pub struct Attendance {
     pub meeting_id: Option<i32>,
     /// If `is_event` is true this will be the event they attended
     pub event_id: Option<i32>,
+    /// Has the attendance been approved by the host?
+    ///
+    /// Only approved attendances count towards a user's grade.
+    pub approved: bool,
 }
 
 /// Used to create a new attendance in the database
@@ -36,4 +40,24 @@ pub struct NewAttendance {
     pub meeting_id: Option<i32>,
     /// If `is_event` is true this will be the event they attended
     pub event_id: Option<i32>,
+    /// Has the attendance been approved by the host?
+    ///
+    /// Only approved attendances count towards a user's grade.
+    pub approved: bool,
+}
+
+use crate::models::Attendable;
+
+/// An attendance that is waiting to be approved
+///
+/// This is not stored in the database, it just bundles together
+/// everything the approval queue needs to show about an attendance.
+#[derive(Debug)]
+pub struct PendingAttendance {
+    /// The attendance itself
+    pub attendance: Attendance,
+    /// The user who submitted the attendance
+    pub user: User,
+    /// The meeting or event that was attended
+    pub attended: Box<dyn Attendable>,
 }
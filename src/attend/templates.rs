@@ -13,4 +13,25 @@ use crate::templates::{filters, FormError, OptUser};
 pub struct AttendTemplate {
     pub logged_in: OptUser,
     pub error: Option<FormError>,
+    /// Code to pre-fill the form with
+    pub code: Option<String>,
+}
+
+use super::models::PendingAttendance;
+#[allow(unused_imports)]
+use crate::models::Attendable;
+use crate::tz::Tz;
+
+/// Approval queue template
+///
+/// HTML File: `pending.html`
+///
+/// The page that lists the attendances waiting for approval.
+#[derive(Template)]
+#[template(path = "pending.html")]
+pub struct PendingTemplate {
+    pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
+    pub pending: Vec<PendingAttendance>,
 }
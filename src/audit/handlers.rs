@@ -0,0 +1,276 @@
+//! HTTP handlers for the audit trail
+
+use chrono::{Duration, NaiveDate};
+use diesel::insert_into;
+use diesel::prelude::*;
+use rocket::http::uri::Uri;
+use rocket::request::LenientForm;
+use rocket::State;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::guards::*;
+use crate::models::User;
+use crate::settings::Settings;
+use crate::tz::{self, Tz};
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::templates::*;
+
+/// How many events the page shows at once
+const PAGE_SIZE: i64 = 200;
+
+/// GET handler for `/audit`
+///
+/// Searches the audit trail, see `AuditFilter`.
+///
+/// Restricted to those that can view the logs.
+#[get("/audit?<filter..>")]
+pub fn audit(
+    conn: ObservDbConn,
+    l: Can<can::ViewLogs>,
+    settings: State<Settings>,
+    filter: LenientForm<AuditFilter>,
+) -> AuditTemplate {
+    let filter = filter.into_inner().clean();
+    let zone = tz::of(Some(&l.0), &settings.site_zone());
+    let mut events = find_events(&*conn, &filter, &zone, Some(PAGE_SIZE + 1));
+    let more = events.len() as i64 > PAGE_SIZE;
+    events.truncate(PAGE_SIZE as usize);
+
+    let distinct = |column| {
+        use crate::schema::audit_events::dsl::*;
+        match column {
+            "action" => audit_events
+                .select(action)
+                .distinct()
+                .order(action)
+                .load(&*conn),
+            _ => audit_events
+                .select(target_type)
+                .distinct()
+                .order(target_type)
+                .load(&*conn),
+        }
+        .expect("Failed to get audit events from database")
+    };
+
+    AuditTemplate {
+        logged_in: Some(l.0),
+        zone,
+        events,
+        actions: distinct("action"),
+        targets: distinct("target_type"),
+        more,
+        query: query_string(&filter),
+        filter,
+    }
+}
+
+/// GET handler for `/audit.json`
+///
+/// Exports every event matching a search as JSON, newest first.
+///
+/// Restricted to those that can view the logs.
+#[get("/audit.json?<filter..>")]
+pub fn audit_json(
+    conn: ObservDbConn,
+    l: Can<can::ViewLogs>,
+    settings: State<Settings>,
+    filter: LenientForm<AuditFilter>,
+) -> Json<Vec<AuditEvent>> {
+    Json(
+        find_events(
+            &*conn,
+            &filter.into_inner().clean(),
+            &tz::of(Some(&l.0), &settings.site_zone()),
+            None,
+        )
+        .into_iter()
+        .map(|(e, _)| e)
+        .collect(),
+    )
+}
+
+//# Helper Functions
+
+/// Record that something was created
+pub fn created<T: Serialize>(
+    conn: &SqliteConnection,
+    actor: Option<&User>,
+    target: &str,
+    id: i32,
+    new: &T,
+) {
+    record(conn, actor, "create", target, Some(id), None, snapshot(new));
+}
+
+/// Record that something was changed
+pub fn updated<T: Serialize, U: Serialize>(
+    conn: &SqliteConnection,
+    actor: Option<&User>,
+    target: &str,
+    id: i32,
+    old: &T,
+    new: &U,
+) {
+    record(
+        conn,
+        actor,
+        "update",
+        target,
+        Some(id),
+        snapshot(old),
+        snapshot(new),
+    );
+}
+
+/// Record that something was deleted
+pub fn deleted<T: Serialize>(
+    conn: &SqliteConnection,
+    actor: Option<&User>,
+    target: &str,
+    id: i32,
+    old: &T,
+) {
+    record(conn, actor, "delete", target, Some(id), snapshot(old), None);
+}
+
+/// Record a user being added to or removed from something like a group
+pub fn member(
+    conn: &SqliteConnection,
+    actor: Option<&User>,
+    target: &str,
+    id: i32,
+    uid: i32,
+    added: bool,
+) {
+    let member = Some(serde_json::json!({ "user_id": uid }));
+    if added {
+        record(conn, actor, "add-member", target, Some(id), None, member);
+    } else {
+        record(conn, actor, "remove-member", target, Some(id), member, None);
+    }
+}
+
+/// Record a change in the audit trail
+///
+/// When there are both old and new values only the fields that differ
+/// are kept.
+pub fn record(
+    conn: &SqliteConnection,
+    actor: Option<&User>,
+    action: &str,
+    target: &str,
+    id: Option<i32>,
+    old: Option<Value>,
+    new: Option<Value>,
+) {
+    use crate::schema::audit_events::dsl::audit_events;
+
+    let (old, new) = match (old, new) {
+        (Some(Value::Object(mut o)), Some(Value::Object(mut n))) => {
+            let same: Vec<String> = o
+                .iter()
+                .filter(|(k, v)| n.get(*k) == Some(v))
+                .map(|(k, _)| k.clone())
+                .collect();
+            for k in same {
+                o.remove(&k);
+                n.remove(&k);
+            }
+            (Some(Value::Object(o)), Some(Value::Object(n)))
+        }
+        other => other,
+    };
+
+    insert_into(audit_events)
+        .values(&NewAuditEvent {
+            actor_id: actor.map(|a| a.id),
+            action: action.to_string(),
+            target_type: target.to_string(),
+            target_id: id,
+            old_values: old.map(|v| v.to_string()),
+            new_values: new.map(|v| v.to_string()),
+            happened_at: chrono::offset::Utc::now().naive_utc(),
+        })
+        .execute(conn)
+        .expect("Failed to insert audit event into database");
+}
+
+/// The JSON of something as it is before or after a change
+pub fn snapshot<T: Serialize>(t: &T) -> Option<Value> {
+    serde_json::to_value(t).ok()
+}
+
+/// The events matching a search with the users that made them, newest first
+///
+/// The days searched from and to are in the time zone `tz`.
+pub fn find_events(
+    conn: &SqliteConnection,
+    filter: &AuditFilter,
+    tz: &Tz,
+    limit: Option<i64>,
+) -> Vec<(AuditEvent, Option<User>)> {
+    use crate::schema::audit_events::dsl::*;
+    use crate::schema::users;
+
+    let date = |d: &Option<String>, days: i64| {
+        d.as_deref()
+            .and_then(|d| NaiveDate::parse_from_str(d, "%F").ok())
+            .map(|d| tz.to_utc(&(d + Duration::days(days)).and_hms(0, 0, 0)))
+    };
+
+    let mut query = audit_events.left_join(users::table).into_boxed();
+    if let Some(a) = filter.actor {
+        query = query.filter(actor_id.eq(a));
+    }
+    if let Some(a) = &filter.action {
+        query = query.filter(action.eq(a));
+    }
+    if let Some(t) = &filter.target {
+        query = query.filter(target_type.eq(t));
+    }
+    if let Some(t) = filter.tid {
+        query = query.filter(target_id.eq(t));
+    }
+    if let Some(from) = date(&filter.from, 0) {
+        query = query.filter(happened_at.ge(from));
+    }
+    if let Some(to) = date(&filter.to, 1) {
+        query = query.filter(happened_at.lt(to));
+    }
+    if let Some(l) = limit {
+        query = query.limit(l);
+    }
+
+    query
+        .order((happened_at.desc(), id.desc()))
+        .load(conn)
+        .expect("Failed to get audit events from database")
+}
+
+/// The query string of a search, without the `?`
+fn query_string(filter: &AuditFilter) -> String {
+    let text = [
+        ("action", &filter.action),
+        ("target", &filter.target),
+        ("from", &filter.from),
+        ("to", &filter.to),
+    ];
+    let ids = [("actor", filter.actor), ("tid", filter.tid)];
+
+    text.iter()
+        .filter_map(|(k, v)| {
+            v.as_ref()
+                .map(|v| format!("{}={}", k, Uri::percent_encode(v)))
+        })
+        .chain(
+            ids.iter()
+                .filter_map(|(k, v)| v.map(|v| format!("{}={}", k, v))),
+        )
+        .collect::<Vec<_>>()
+        .join("&")
+}
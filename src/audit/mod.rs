@@ -0,0 +1,21 @@
+//! Audit trail
+//!
+//! Every change made to the data is recorded in the `audit_events` table
+//! with who made it, what they did, what they did it to and the fields
+//! that changed as they were before and after. Handlers record their
+//! changes with `handlers::created`, `handlers::updated`,
+//! `handlers::deleted`, `handlers::member` or `handlers::record` for
+//! anything else.
+//!
+//! Users that can view the logs can search the trail and export it as JSON.
+//!
+//! ## Routes
+//! - `/audit`
+//! - `/audit.json`
+
+pub mod handlers;
+pub mod models;
+
+mod templates;
+
+pub use handlers::{created, deleted, member, record, snapshot, updated};
@@ -0,0 +1,119 @@
+//! Models for the audit trail
+//!
+//! The old and new values are stored as JSON objects holding only the
+//! fields that changed.
+
+use chrono::NaiveDateTime;
+use serde::Serializer;
+use serde_json::Value;
+
+use crate::models::User;
+use crate::schema::*;
+
+/// Models an audit event in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User, foreign_key = "actor_id")]
+pub struct AuditEvent {
+    /// ID of the event
+    pub id: i32,
+    /// ID of the user that made the change, if they were logged in
+    pub actor_id: Option<i32>,
+    /// What was done like `create`, `update` or `delete`
+    pub action: String,
+    /// What kind of thing was changed like `user` or `project`
+    pub target_type: String,
+    /// ID of what was changed
+    pub target_id: Option<i32>,
+    /// JSON of the fields that changed as they were before
+    #[serde(serialize_with = "as_json")]
+    pub old_values: Option<String>,
+    /// JSON of the fields that changed as they are after
+    #[serde(serialize_with = "as_json")]
+    pub new_values: Option<String>,
+    /// When the change was made
+    pub happened_at: NaiveDateTime,
+}
+
+impl AuditEvent {
+    /// The fields that changed with their old and new values
+    pub fn changes(&self) -> Vec<(String, String, String)> {
+        let parse = |v: &Option<String>| {
+            v.as_deref()
+                .and_then(|v| serde_json::from_str(v).ok())
+                .unwrap_or(Value::Null)
+        };
+        let (old, new) = (parse(&self.old_values), parse(&self.new_values));
+        let show = |v: Option<&Value>| match v {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(v) => v.to_string(),
+        };
+
+        let mut fields: Vec<&String> = old
+            .as_object()
+            .into_iter()
+            .chain(new.as_object())
+            .flat_map(|o| o.keys())
+            .collect();
+        fields.sort();
+        fields.dedup();
+        fields
+            .into_iter()
+            .map(|f| (f.clone(), show(old.get(f)), show(new.get(f))))
+            .collect()
+    }
+}
+
+/// Used to record a new audit event in the database
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent {
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Option<i32>,
+    pub old_values: Option<String>,
+    pub new_values: Option<String>,
+    pub happened_at: NaiveDateTime,
+}
+
+/// What to search the audit trail for
+///
+/// Used to parse the query of `/audit` and `/audit.json`
+#[derive(Debug, Default, Clone, FromForm)]
+pub struct AuditFilter {
+    /// ID of the user that made the changes
+    pub actor: Option<i32>,
+    pub action: Option<String>,
+    /// The kind of thing that was changed
+    pub target: Option<String>,
+    /// ID of the thing that was changed
+    pub tid: Option<i32>,
+    /// Only changes on or after this date like `2021-03-01`
+    pub from: Option<String>,
+    /// Only changes on or before this date
+    pub to: Option<String>,
+}
+
+impl AuditFilter {
+    /// Treat empty fields as not being searched for
+    pub fn clean(mut self) -> Self {
+        for f in &mut [
+            &mut self.action,
+            &mut self.target,
+            &mut self.from,
+            &mut self.to,
+        ] {
+            if f.as_deref().map_or(false, |s| s.trim().is_empty()) {
+                **f = None;
+            }
+        }
+        self
+    }
+}
+
+/// Serialize the stored JSON as JSON instead of as a string
+fn as_json<S: Serializer>(v: &Option<String>, s: S) -> Result<S::Ok, S::Error> {
+    let v: Option<Value> = v.as_deref().and_then(|v| serde_json::from_str(v).ok());
+    serde::Serialize::serialize(&v, s)
+}
@@ -0,0 +1,43 @@
+//! HTML templates for the audit trail
+
+use super::models::{AuditEvent, AuditFilter};
+use crate::models::User;
+#[allow(unused_imports)]
+use crate::templates::{filters, OptUser};
+use crate::tz::Tz;
+
+/// Audit trail page template
+///
+/// HTML File: `audit/audit.html`
+///
+/// Lists the changes matching a search, newest first.
+#[derive(Template)]
+#[template(path = "audit/audit.html")]
+pub struct AuditTemplate {
+    pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
+    /// The events with the user that made them
+    pub events: Vec<(AuditEvent, Option<User>)>,
+    pub filter: AuditFilter,
+    /// Every action in the trail
+    pub actions: Vec<String>,
+    /// Every kind of thing in the trail
+    pub targets: Vec<String>,
+    /// Whether there are more events than are shown
+    pub more: bool,
+    /// Query string of the search for links like the export
+    pub query: String,
+}
+
+impl AuditTemplate {
+    /// Is this the action being searched for?
+    pub fn is_action(&self, a: &str) -> bool {
+        self.filter.action.as_deref() == Some(a)
+    }
+
+    /// Is this the kind of thing being searched for?
+    pub fn is_target(&self, t: &str) -> bool {
+        self.filter.target.as_deref() == Some(t)
+    }
+}
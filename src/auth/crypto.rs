@@ -1,106 +1,108 @@
 //! User authentication cryptography
 //!
-//! This module handles the encryption and verification of user passwords.
-//! Uses the [`ring`](https://crates.io/crates/ring) library to do encryption.
+//! This module handles the hashing and verification of user passwords.
+//!
+//! Passwords are hashed with Argon2id using the
+//! [`rust-argon2`](https://crates.io/crates/rust-argon2) library and stored
+//! as [PHC strings](https://github.com/P-H-C/phc-string-format/blob/master/phc-sf-spec.md)
+//! that hold the algorithm, its parameters and the salt along with the hash.
+//!
+//! Passwords from before were hashed with PBKDF2-HMAC-SHA512 using
+//! [`ring`](https://crates.io/crates/ring) and are stored like
+//! `$pbkdf2-sha512$i=100000$<salt>$<hash>` with the salt and hash in hex.
+//! They still verify, and `needs_rehash` tells when a hash should be
+//! replaced by one made with the current parameters.
 
-use std::num::NonZeroU32;
+use argon2::{Config, ThreadMode, Variant, Version};
+use ring::pbkdf2;
 use ring::rand::{SecureRandom, SystemRandom};
-use ring::{digest, pbkdf2};
-use rocket::http::RawStr;
-use rocket::request::FromFormValue;
-
-const CRE_LEN: usize = digest::SHA512_256_OUTPUT_LEN;
-static ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA512;
-
-/// A newtype struct wrapping around a `String`
-/// that is used by a number of cryptography functions
-/// in order to be compatible with SQLite.
-/// The type itself exists so that it is not possible to accidentally
-/// use it as if it were a string, providing far more safety.
-#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, DieselNewType)]
-pub struct UnsafeBinaryString(String);
+use std::num::NonZeroU32;
 
-impl UnsafeBinaryString {
-    pub fn as_bytes(&self) -> &[u8] {
-        self.0.as_bytes()
-    }
+/// Memory used by Argon2id in KiB
+const ARGON2_MEMORY: u32 = 19 * 1024;
+/// Passes Argon2id makes over the memory
+const ARGON2_ITERATIONS: u32 = 2;
+const SALT_LEN: usize = 16;
+const HASH_LEN: u32 = 32;
 
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+fn argon2_config<'a>() -> Config<'a> {
+    Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: ARGON2_MEMORY,
+        time_cost: ARGON2_ITERATIONS,
+        lanes: 1,
+        thread_mode: ThreadMode::Sequential,
+        secret: &[],
+        ad: &[],
+        hash_length: HASH_LEN,
     }
 }
 
-impl Default for UnsafeBinaryString {
-    fn default() -> Self {
-        Self(String::new())
-    }
-}
+/// Hash a password and return it as a PHC string
+pub fn hash_password<T: AsRef<str>>(pass: T) -> String {
+    // Generate the password salt
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    rng.fill(&mut salt).expect("Failed to generate salt");
 
-impl<T> From<T> for UnsafeBinaryString
-where
-    T: AsRef<[u8]>,
-{
-    fn from(f: T) -> Self {
-        unsafe { Self(String::from_utf8_unchecked(f.as_ref().to_vec())) }
-    }
+    argon2::hash_encoded(pass.as_ref().as_bytes(), &salt, &argon2_config())
+        .expect("Failed to hash password")
 }
 
-impl<'v> FromFormValue<'v> for UnsafeBinaryString {
-    type Error = &'v RawStr;
-
-    fn from_form_value(form_value: &'v RawStr) -> Result<Self, &'v RawStr> {
-        Ok(Self(form_value.to_string()))
+/// Verify that a password is correct
+///
+/// Takes a password and the PHC string of a hashed password and
+/// verifies that the password is correct. Hashes in a format that
+/// isn't known, like the empty one of the Admin, never match.
+///
+/// You should never directly compare two hashed passwords.
+pub fn verify_password<T: AsRef<str>>(pass: T, hash: &str) -> bool {
+    let pass = pass.as_ref().as_bytes();
+    if hash.starts_with("$argon2") {
+        argon2::verify_encoded(hash, pass).unwrap_or(false)
+    } else if let Some(rest) = hash.strip_prefix("$pbkdf2-sha512$") {
+        verify_pbkdf2(rest, pass).unwrap_or(false)
+    } else {
+        false
     }
 }
 
-impl AsRef<str> for UnsafeBinaryString {
-    fn as_ref(&self) -> &str {
-        &*self.0
-    }
+/// Whether a hash was made with something other than the current
+/// algorithm and parameters and should be replaced
+pub fn needs_rehash(hash: &str) -> bool {
+    let current = format!(
+        "$argon2id$v=19$m={},t={},p=1$",
+        ARGON2_MEMORY, ARGON2_ITERATIONS
+    );
+    !hash.is_empty() && !hash.starts_with(&current)
 }
 
-/// Encrypt a password and return a `HashedPassword` struct
-/// which can be used to get the hash and salt directly.
-pub fn hash_password<T: AsRef<str>>(pass: T) -> (UnsafeBinaryString, UnsafeBinaryString) {
-    // Generate the password salt
-    let rng = SystemRandom::new();
-    let mut salt = [0u8; CRE_LEN];
-    rng.fill(&mut salt).unwrap();
-
-    // Derive the password hash
-    let mut out = [0u8; CRE_LEN];
-    pbkdf2::derive(
-        ALGORITHM,
-        NonZeroU32::new(100_000).unwrap(),
-        &salt as &[u8],
-        pass.as_ref().as_bytes(),
-        &mut out,
-    );
+/// Verify the `i=<iterations>$<salt>$<hash>` of a PBKDF2 hash
+fn verify_pbkdf2(phc: &str, pass: &[u8]) -> Option<bool> {
+    let mut parts = phc.split('$');
+    let iterations = parts.next()?.strip_prefix("i=")?.parse().ok()?;
+    let salt = hex_decode(parts.next()?)?;
+    let hash = hex_decode(parts.next()?)?;
 
-    // Return a HashedPassword struct
-    (
-        UnsafeBinaryString::from(out),
-        UnsafeBinaryString::from(salt),
+    Some(
+        pbkdf2::verify(
+            pbkdf2::PBKDF2_HMAC_SHA512,
+            NonZeroU32::new(iterations)?,
+            &salt,
+            pass,
+            &hash,
+        )
+        .is_ok(),
     )
 }
 
-/// Verify that a password is correct
-///
-/// Takes a password and the hash and salt of an encrypted password and
-/// verifies that the password is correct.
-///
-/// You should never directly compare two hashed passwords.
-pub fn verify_password(
-    pass: String,
-    compare_to: UnsafeBinaryString,
-    salt: UnsafeBinaryString,
-) -> bool {
-    pbkdf2::verify(
-        ALGORITHM,
-        NonZeroU32::new(100_000).unwrap(),
-        salt.as_bytes(),
-        pass.as_bytes(),
-        compare_to.as_bytes(),
-    )
-    .is_ok()
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
 }
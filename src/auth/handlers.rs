@@ -1,19 +1,29 @@
 //! HTTP handlers for authentication
 
-use diesel::insert_into;
 use diesel::prelude::*;
-use rocket::http::{Cookie, Cookies};
+use diesel::update;
+use rocket::http::uri::Uri;
 use rocket::request::Form;
 use rocket::response::Redirect;
+use rocket::State;
 
+use crate::api::handlers::revoke_tokens;
+use crate::audit;
 use crate::guards::*;
-use crate::models::NewRelationGroupUser;
+use crate::mailer::Outbox;
 use crate::models::{NewUser, User};
-use crate::templates::{is_reserved, FormError};
+use crate::roles::Role;
+use crate::sessions::handlers::revoke_sessions;
+use crate::settings::Settings;
+use crate::templates::FormError;
+use crate::totp::handlers::{totp_enabled, totp_missing};
+use crate::users::handlers::create_user;
 use crate::ObservDbConn;
 
 use super::crypto::*;
 use super::templates::*;
+use super::throttle::*;
+use super::tokens::*;
 
 /// GET handler for `/signup`
 #[get("/signup?<e>")]
@@ -39,35 +49,35 @@ pub struct SignUpForm {
 
 impl From<SignUpForm> for NewUser {
     fn from(f: SignUpForm) -> Self {
-        let mut newuser = Self::default();
-
-        newuser.email = f.email;
-        newuser.real_name = f.real_name;
-        newuser.handle = f.handle;
-        newuser.mmost = f.mmost;
-
-        let (pass, salt) = hash_password(f.password);
-        newuser.salt = salt;
-        newuser.password_hash = pass;
-
-        newuser.tier = 0;
-        newuser.active = true;
-
-        newuser.former = false;
-        newuser.extrn = false;
-
-        newuser
+        Self {
+            email: f.email,
+            real_name: f.real_name,
+            handle: f.handle,
+            mmost: f.mmost,
+            password_hash: hash_password(f.password),
+            role: Role::Student,
+            active: true,
+            former: false,
+            extrn: false,
+            ..Self::default()
+        }
     }
 }
 
 /// POST handler for `/signup`
 ///
 /// Creates a new user in the database with the information provided by the
-/// POSTed form and logs them in.
+/// POSTed form, logs them in and emails them a link to verify their email.
 ///
 /// If all goes well then it redirects to `/` otherwise back to the same page.
 #[post("/signup", data = "<form>")]
-pub fn signup_post(conn: ObservDbConn, mut cookies: Cookies, form: Form<SignUpForm>) -> Redirect {
+pub fn signup_post(
+    conn: ObservDbConn,
+    mut session: SessionCookie,
+    outbox: State<Outbox>,
+    key: State<TokenKey>,
+    form: Form<SignUpForm>,
+) -> Redirect {
     let mut form = form.into_inner();
     form.handle.truncate(39);
     form.mmost.truncate(22);
@@ -76,84 +86,43 @@ pub fn signup_post(conn: ObservDbConn, mut cookies: Cookies, form: Form<SignUpFo
         return Redirect::to(format!("/signup?e={}", FormError::PasswordMismatch));
     }
 
-    let newuser = NewUser::from(form);
-
-    use crate::schema::users::dsl::*;
-
-    if let Err(e) = is_reserved(&*newuser.handle) {
-        return Redirect::to(format!("/signup?e={}", e));
-    }
-
-    // Check if user's email is already signed up
-    if users
-        .filter(&email.eq(&*newuser.email))
-        .first::<User>(&*conn)
-        .optional()
-        .expect("Failed to get user from database")
-        .is_some()
-    {
-        return Redirect::to(format!("/signup?e={}", FormError::EmailExists));
-    }
-
-    // Check if user's github is already signed up
-    if users
-        .filter(&handle.eq(&*newuser.handle))
-        .first::<User>(&*conn)
-        .optional()
-        .expect("Failed to get user from database")
-        .is_some()
-    {
-        return Redirect::to(format!("/signup?e={}", FormError::GitExists));
-    }
+    let user = match create_user(&*conn, NewUser::from(form)) {
+        Ok(user) => user,
+        Err(e) => return Redirect::to(format!("/signup?e={}", e)),
+    };
 
-    // Check if user's mattermost is already signed up
-    if users
-        .filter(&mmost.eq(&*newuser.mmost))
-        .first::<User>(&*conn)
-        .optional()
-        .expect("Failed to get user from database")
-        .is_some()
-    {
-        return Redirect::to(format!("/signup?e={}", FormError::MmostExists));
-    }
-
-    // Insert the new user into the database
-    insert_into(users)
-        .values(&newuser)
-        .execute(&*conn)
-        .expect("Failed to add user to database");
-
-    let user: User = users
-        .filter(&email.eq(&*newuser.email))
-        .first(&*conn)
-        .expect("Failed to get user from database");
-    {
-        use crate::schema::relation_group_user::dsl::*;
-        insert_into(relation_group_user)
-            .values(&NewRelationGroupUser {
-                group_id: 0,
-                user_id: user.id,
-            })
-            .execute(&*conn)
-            .expect("Failed to insert new relation into database");
-    }
-
-    cookies.add_private(Cookie::new("user_id", format!("{}", user.id)));
+    session.start(&*conn, &user);
 
+    audit::created(&*conn, Some(&user), "user", user.id, &user);
     audit_log!(
         "User {} [{}] has registered for an account",
         user.id,
         user.email
     );
 
+    send_verification(&outbox, &key, &user);
+
     Redirect::to(format!("/users/{}", user.id))
 }
 
 /// GET handler for `/login`
-#[get("/login?<e>")]
-pub fn login(l: MaybeLoggedIn, e: Option<FormError>) -> LogInTemplate {
+///
+/// Also offers to log in with each of the OAuth providers.
+#[get("/login?<to>&<e>")]
+pub fn login(
+    l: MaybeLoggedIn,
+    settings: State<Settings>,
+    to: Option<String>,
+    e: Option<FormError>,
+) -> LogInTemplate {
     LogInTemplate {
         logged_in: l.user(),
+        providers: settings
+            .oauth_providers
+            .iter()
+            .map(|p| (p.id.clone(), p.name.clone()))
+            .collect(),
+        to: Uri::percent_encode(to.as_deref().unwrap_or("/")).to_string(),
         error: e,
     }
 }
@@ -170,11 +139,18 @@ pub struct LogInForm {
 /// POST handler for `/login`
 ///
 /// This handler attempts to verify the creditionals POSTed to it and then
-/// on succes redirects to `/` otherwise back to the same page.
+/// on succes logs them in with `finish_login` otherwise redirects back to
+/// the same page.
+///
+/// A wrong email and a wrong password get the same error so that it can't
+/// be used to find out who has an account. Too many failures have to wait
+/// before trying again, see `throttle`.
 #[post("/login?<to>", data = "<creds>")]
 pub fn login_post(
     conn: ObservDbConn,
-    mut cookies: Cookies,
+    mut session: SessionCookie,
+    settings: State<Settings>,
+    client: ClientInfo,
     creds: Form<LogInForm>,
     to: Option<String>,
 ) -> Redirect {
@@ -183,28 +159,334 @@ pub fn login_post(
     let creds = creds.into_inner();
 
     let to = to.unwrap_or_else(|| String::from("/"));
+    // Re-encode it so that a query in it survives the round trip
+    let back = Uri::percent_encode(&to);
+
+    let ip = client.ip.as_deref();
+    if locked_until(&*conn, &settings, &creds.email, ip, now()).is_some() {
+        return Redirect::to(format!("/login?to={}&e={}", back, FormError::Locked));
+    }
+
+    let user = users
+        .filter(email.eq(&creds.email).and(deleted_at.is_null()))
+        .first::<User>(&*conn)
+        .optional()
+        .expect("Failed to get user from database");
+
+    // Verify the password
+    let valid = match &user {
+        Some(u) => verify_password(&creds.password, &u.password_hash),
+        None => {
+            // Take as long as checking a password would
+            // so the time doesn't give away who has an account
+            hash_password(&creds.password);
+            false
+        }
+    };
+
+    match user {
+        Some(user) if valid => {
+            if needs_rehash(&user.password_hash) {
+                rehash_password(&*conn, &user, &creds.password);
+            }
+            finish_login(&*conn, &mut session, &settings, &user, to)
+        }
+        _ => {
+            record_failure(&*conn, &creds.email, ip, now());
+            Redirect::to(format!("/login?to={}&e={}", back, FormError::Credentials))
+        }
+    }
+}
+
+/// GET handler for `/logout`
+///
+/// Ends the session and removes the cookie.
+#[get("/logout")]
+pub fn logout(conn: ObservDbConn, mut session: SessionCookie) -> Redirect {
+    session.end(&*conn);
+    Redirect::to("/")
+}
+
+/// GET handler for `/forgot`
+#[get("/forgot?<sent>&<e>")]
+pub fn forgot(l: MaybeLoggedIn, sent: Option<bool>, e: Option<FormError>) -> ForgotTemplate {
+    ForgotTemplate {
+        logged_in: l.user(),
+        sent: sent.unwrap_or(false),
+        error: e,
+    }
+}
+
+/// The email of a forgotten account
+///
+/// Used to parse the incoming form in `forgot_post`
+#[derive(FromForm)]
+pub struct ForgotForm {
+    email: String,
+}
+
+/// POST handler for `/forgot`
+///
+/// Emails a password reset link to the user with the POSTed email.
+/// Always says the link was sent so that it can't be used to find out
+/// who has an account.
+#[post("/forgot", data = "<form>")]
+pub fn forgot_post(
+    conn: ObservDbConn,
+    outbox: State<Outbox>,
+    key: State<TokenKey>,
+    form: Form<ForgotForm>,
+) -> Redirect {
+    use crate::schema::users::dsl::*;
 
-    // If we find the user
     if let Some(user) = users
-        .filter(&email.eq(creds.email))
+        .filter(email.eq(form.email.trim()).and(deleted_at.is_null()))
         .first::<User>(&*conn)
         .optional()
         .expect("Failed to get user from database")
     {
-        // Verify the password
-        if verify_password(creds.password, user.password_hash, user.salt) {
-            cookies.add_private(Cookie::new("user_id", format!("{}", user.id)));
-            Redirect::to(to)
-        } else {
-            Redirect::to(format!("/login?to={}&e={}", to, FormError::Password))
+        let token = key.sign(Purpose::Reset, &user, now());
+        outbox.send_links(&user.email, "Reset your Observatory password", |site| {
+            format!(
+                "Hi {},\n\n\
+                 Someone asked to reset the password of your Observatory account. \
+                 If it was you, set a new password within the next hour here:\n\n\
+                 {}/reset?token={}\n\n\
+                 If it wasn't you then you can ignore this email.\n",
+                user.real_name, site, token
+            )
+        });
+
+        audit_log!(
+            "User {} [{}] has asked for a password reset",
+            user.id,
+            user.email
+        );
+    }
+
+    Redirect::to("/forgot?sent=true")
+}
+
+/// GET handler for `/reset`
+///
+/// Shows the form to set a new password if the token from the reset email
+/// is valid, otherwise redirects back to `/forgot`.
+#[get("/reset?<token>&<e>")]
+pub fn reset(
+    conn: ObservDbConn,
+    l: MaybeLoggedIn,
+    key: State<TokenKey>,
+    token: String,
+    e: Option<FormError>,
+) -> Result<ResetTemplate, Redirect> {
+    if key.verify(&*conn, Purpose::Reset, &token, now()).is_none() {
+        return Err(Redirect::to(format!(
+            "/forgot?e={}",
+            FormError::InvalidToken
+        )));
+    }
+
+    Ok(ResetTemplate {
+        logged_in: l.user(),
+        token,
+        error: e,
+    })
+}
+
+/// A new password
+///
+/// Used to parse the incoming form in `reset_post`
+#[derive(FromForm)]
+pub struct ResetForm {
+    token: String,
+    password: String,
+    password_repeat: String,
+}
+
+/// POST handler for `/reset`
+///
+/// Sets the user's new password and logs out all of their sessions.
+/// The link came to their email so this verifies it as well.
+#[post("/reset", data = "<form>")]
+pub fn reset_post(conn: ObservDbConn, key: State<TokenKey>, form: Form<ResetForm>) -> Redirect {
+    use crate::schema::users::dsl::*;
+
+    let form = form.into_inner();
+    let user = match key.verify(&*conn, Purpose::Reset, &form.token, now()) {
+        Some(user) => user,
+        None => return Redirect::to(format!("/forgot?e={}", FormError::InvalidToken)),
+    };
+    if form.password != form.password_repeat {
+        // The token was just checked so it is safe to put in the URL
+        return Redirect::to(format!(
+            "/reset?token={}&e={}",
+            form.token,
+            FormError::PasswordMismatch
+        ));
+    }
+
+    update(users.find(user.id))
+        .set((
+            password_hash.eq(hash_password(form.password)),
+            verified.eq(true),
+        ))
+        .execute(&*conn)
+        .expect("Failed to update user in database");
+    revoke_sessions(&*conn, user.id);
+    revoke_tokens(&*conn, user.id);
+
+    audit::record(
+        &*conn,
+        Some(&user),
+        "reset-password",
+        "user",
+        Some(user.id),
+        None,
+        None,
+    );
+    audit_log!("User {} [{}] has reset their password", user.id, user.email);
+
+    Redirect::to("/login")
+}
+
+/// GET handler for `/verify`
+///
+/// Verifies the user's email with the token from a verification email.
+/// Without a token it offers to send a new link.
+#[get("/verify?<token>&<sent>")]
+pub fn verify(
+    conn: ObservDbConn,
+    l: MaybeLoggedIn,
+    key: State<TokenKey>,
+    token: Option<String>,
+    sent: Option<bool>,
+) -> VerifyTemplate {
+    let mut logged_in = l.user();
+    let mut verified = logged_in.as_ref().map_or(false, |u| u.verified);
+    let mut error = None;
+
+    if let Some(token) = token {
+        match key.verify(&*conn, Purpose::Verify, &token, now()) {
+            Some(user) => {
+                {
+                    use crate::schema::users::dsl::*;
+                    update(users.find(user.id))
+                        .set(verified.eq(true))
+                        .execute(&*conn)
+                        .expect("Failed to update user in database");
+                }
+
+                audit::updated(
+                    &*conn,
+                    Some(&user),
+                    "user",
+                    user.id,
+                    &user,
+                    &User {
+                        verified: true,
+                        ..user.clone()
+                    },
+                );
+                audit_log!("User {} [{}] has verified their email", user.id, user.email);
+
+                verified = true;
+                if let Some(u) = logged_in.as_mut().filter(|u| u.id == user.id) {
+                    u.verified = true;
+                }
+            }
+            None => error = Some(FormError::InvalidToken),
         }
+    }
+
+    VerifyTemplate {
+        logged_in,
+        verified,
+        sent: sent.unwrap_or(false),
+        error,
+    }
+}
+
+/// POST handler for `/verify`
+///
+/// Emails the logged in user a new verification link.
+#[post("/verify")]
+pub fn verify_post(l: UserGuard, outbox: State<Outbox>, key: State<TokenKey>) -> Redirect {
+    if !l.0.verified {
+        send_verification(&outbox, &key, &l.0);
+    }
+    Redirect::to("/verify?sent=true")
+}
+
+//# Helper Functions
+
+/// The current time that tokens and login attempts are checked against
+pub fn now() -> chrono::NaiveDateTime {
+    chrono::offset::Utc::now().naive_utc()
+}
+
+/// Log in a user whose password or linked account checked out
+///
+/// Redirects to `to` once logged in. Users with two-factor authentication
+/// go on to `/login/totp` instead, and users that are required to have it
+/// but don't are sent to set it up.
+pub fn finish_login(
+    conn: &SqliteConnection,
+    session: &mut SessionCookie,
+    settings: &Settings,
+    user: &User,
+    to: String,
+) -> Redirect {
+    if totp_enabled(conn, user.id) {
+        session.start_second_step(user);
+        Redirect::to(format!("/login/totp?to={}", Uri::percent_encode(&to)))
+    } else if totp_missing(conn, settings, user) {
+        clear_failures(conn, &user.email);
+        session.start(conn, user);
+        Redirect::to(format!("/users/{}/totp", user.id))
     } else {
-        Redirect::to(format!("/login?to={}&e={}", to, FormError::Email))
+        clear_failures(conn, &user.email);
+        session.start(conn, user);
+        Redirect::to(to)
     }
 }
 
-#[get("/logout")]
-pub fn logout(mut cookies: Cookies) -> Redirect {
-    cookies.remove_private(Cookie::named("user_id"));
-    Redirect::to("/")
+/// Email a user a link to verify their email
+pub fn send_verification(outbox: &Outbox, key: &TokenKey, user: &User) {
+    let token = key.sign(Purpose::Verify, user, now());
+    outbox.send_links(&user.email, "Verify your Observatory email", |site| {
+        format!(
+            "Hi {},\n\n\
+             Please verify the email of your Observatory account within \
+             the next 3 days by opening this link:\n\n\
+             {}/verify?token={}\n",
+            user.real_name, site, token
+        )
+    });
+}
+
+/// Replace an old hash of a user's password with one made the current way
+///
+/// Only possible while the plain text password is known, so it is done
+/// when the user logs in.
+fn rehash_password(conn: &SqliteConnection, user: &User, password: &str) {
+    use crate::schema::users::dsl::*;
+    update(users.find(user.id))
+        .set(password_hash.eq(hash_password(password)))
+        .execute(conn)
+        .expect("Failed to update user in database");
+
+    audit::record(
+        conn,
+        Some(user),
+        "rehash-password",
+        "user",
+        Some(user.id),
+        None,
+        None,
+    );
+    audit_log!(
+        "User {} [{}] has had their password hash upgraded",
+        user.id,
+        user.email
+    );
 }
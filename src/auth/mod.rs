@@ -1,15 +1,22 @@
 //! User authentication
 //!
 //! Handles user singup and login as well as the crypto-related
-//! tasks of authentication.
+//! tasks of authentication. Forgotten passwords are reset and emails are
+//! verified through emailed links with signed tokens. Failed logins are
+//! throttled, see `throttle`.
 //!
 //! This module has no models.
 //!
 //! ## Routes
 //! - `/login`
 //! - `/signup`
+//! - `/forgot`
+//! - `/reset`
+//! - `/verify`
 
 pub mod crypto;
 pub mod handlers;
+pub mod throttle;
+pub mod tokens;
 
 mod templates;
@@ -25,5 +25,53 @@ pub struct SignUpTemplate {
 #[template(path = "auth/login.html")]
 pub struct LogInTemplate {
     pub logged_in: OptUser,
+    /// ID and name of each OAuth provider
+    pub providers: Vec<(String, String)>,
+    /// Where to go after logging in, percent encoded
+    pub to: String,
+    pub error: Option<FormError>,
+}
+
+/// Forgotten Password page template
+///
+/// HTML File: `auth/forgot.html`
+///
+/// Page that asks for an email to send a password reset link to
+#[derive(Template)]
+#[template(path = "auth/forgot.html")]
+pub struct ForgotTemplate {
+    pub logged_in: OptUser,
+    /// If a link was just sent
+    pub sent: bool,
+    pub error: Option<FormError>,
+}
+
+/// Reset Password page template
+///
+/// HTML File: `auth/reset.html`
+///
+/// Page that shows the form to set a new password
+#[derive(Template)]
+#[template(path = "auth/reset.html")]
+pub struct ResetTemplate {
+    pub logged_in: OptUser,
+    /// The token from the reset link
+    pub token: String,
+    pub error: Option<FormError>,
+}
+
+/// Verify Email page template
+///
+/// HTML File: `auth/verify.html`
+///
+/// Page that says if an email was verified and can send a new link
+#[derive(Template)]
+#[template(path = "auth/verify.html")]
+pub struct VerifyTemplate {
+    pub logged_in: OptUser,
+    /// If the email is verified
+    pub verified: bool,
+    /// If a new link was just sent
+    pub sent: bool,
     pub error: Option<FormError>,
 }
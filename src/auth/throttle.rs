@@ -0,0 +1,124 @@
+//! Slowing down password guessing
+//!
+//! Every failed login is recorded in the `login_attempts` table and the
+//! audit trail, whether or not the email has an account. Once an email has
+//! had too many failures from any IP address, or an IP address has had too
+//! many failures across every email, it has to wait before trying again.
+//! The wait doubles with every failure after that up to
+//! `login_lockout_minutes`.
+//!
+//! The limit on an email counts failures from every IP address so guessing
+//! from many of them doesn't get around it. Since anyone can make an email
+//! wait this way, the wait starts short and never gets longer than the
+//! lockout, rather than locking the account until someone unlocks it.
+//!
+//! Logging in clears the failures of the email but not of the IP address,
+//! so logging in to your own account doesn't reset the limit on guessing
+//! the passwords of others. Failures are forgotten after a day.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::{delete, insert_into};
+
+use crate::audit;
+use crate::settings::Settings;
+
+/// How many hours failures are remembered for
+const WINDOW_HOURS: i64 = 24;
+
+/// When an email can try to log in again from an IP address, if it has to wait
+pub fn locked_until(
+    conn: &SqliteConnection,
+    settings: &Settings,
+    email: &str,
+    ip: Option<&str>,
+    now: NaiveDateTime,
+) -> Option<NaiveDateTime> {
+    use crate::schema::login_attempts::dsl;
+
+    let since = now - Duration::hours(WINDOW_HOURS);
+    // Takes the times of the failures with the latest first
+    let failures = |times: Vec<NaiveDateTime>, allowed| {
+        let wait = backoff(settings, times.len() as i64, allowed)?;
+        Some(times.first().copied().unwrap_or(now) + wait)
+    };
+
+    let by_email = dsl::login_attempts
+        .filter(dsl::attempted_at.gt(since))
+        .filter(dsl::email.eq(key(email)))
+        .select(dsl::attempted_at)
+        .order(dsl::attempted_at.desc())
+        .load(conn)
+        .expect("Failed to get login attempts from database");
+    let by_ip = ip.map(|ip| {
+        dsl::login_attempts
+            .filter(dsl::attempted_at.gt(since))
+            .filter(dsl::ip.eq(ip))
+            .select(dsl::attempted_at)
+            .order(dsl::attempted_at.desc())
+            .load(conn)
+            .expect("Failed to get login attempts from database")
+    });
+
+    let email_until = failures(by_email, settings.login_attempts);
+    let ip_until = by_ip.and_then(|q| failures(q, settings.login_ip_attempts));
+    email_until.max(ip_until).filter(|until| *until > now)
+}
+
+/// Record a failed login and write it to the audit log
+pub fn record_failure(conn: &SqliteConnection, email: &str, ip: Option<&str>, now: NaiveDateTime) {
+    use crate::schema::login_attempts::dsl;
+
+    delete(dsl::login_attempts.filter(dsl::attempted_at.lt(now - Duration::hours(WINDOW_HOURS))))
+        .execute(conn)
+        .expect("Failed to delete login attempts from database");
+    insert_into(dsl::login_attempts)
+        .values((
+            dsl::email.eq(key(email)),
+            dsl::ip.eq(ip),
+            dsl::attempted_at.eq(now),
+        ))
+        .execute(conn)
+        .expect("Failed to insert login attempt into database");
+
+    audit::record(
+        conn,
+        None,
+        "failed-login",
+        "login",
+        None,
+        None,
+        Some(serde_json::json!({ "email": email, "ip": ip })),
+    );
+    audit_log!(
+        "Failed login for {} from {}",
+        email,
+        ip.unwrap_or("an unknown IP address")
+    );
+}
+
+/// Forget the failed logins of an email after it logs in
+pub fn clear_failures(conn: &SqliteConnection, email: &str) {
+    use crate::schema::login_attempts::dsl;
+
+    delete(dsl::login_attempts.filter(dsl::email.eq(key(email))))
+        .execute(conn)
+        .expect("Failed to delete login attempts from database");
+}
+
+/// How long to wait after the last failure, if at all
+fn backoff(settings: &Settings, failures: i64, allowed: i64) -> Option<Duration> {
+    if failures < allowed {
+        return None;
+    }
+    let lockout = settings.login_lockout_minutes * 60;
+    let seconds = (allowed..failures).fold(settings.login_backoff_seconds, |s, _| {
+        s.saturating_mul(2).min(lockout)
+    });
+    Some(Duration::seconds(seconds.min(lockout)))
+}
+
+/// Emails are compared ignoring case and spaces
+fn key(email: &str) -> String {
+    email.trim().to_lowercase()
+}
@@ -0,0 +1,114 @@
+//! Signed tokens for emailed links
+//!
+//! Password reset and email verification links carry a token of the form
+//! `<user id>.<expiry>.<signature>`. The HMAC signature also covers what the
+//! token is for and part of the user, the password hash for a reset and the
+//! email for a verification, so a reset token stops working once it has been
+//! used and a verification token stops working if the email changes.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use log::*;
+use ring::hmac;
+use ring::rand::SystemRandom;
+
+use crate::models::User;
+
+/// What a token can be used for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Purpose {
+    /// Setting a new password
+    Reset,
+    /// Verifying the user's email
+    Verify,
+}
+
+impl Purpose {
+    /// How long a token is valid for after it is made
+    pub fn lifetime(self) -> Duration {
+        match self {
+            Purpose::Reset => Duration::hours(1),
+            Purpose::Verify => Duration::days(3),
+        }
+    }
+
+    /// The signed message for a token
+    fn message(self, user: &User, expires: i64) -> Vec<u8> {
+        let (name, bound) = match self {
+            Purpose::Reset => ("reset", user.password_hash.as_bytes()),
+            Purpose::Verify => ("verify", user.email.as_bytes()),
+        };
+        let mut msg = format!("{}.{}.{}.", name, user.id, expires).into_bytes();
+        msg.extend_from_slice(bound);
+        msg
+    }
+}
+
+/// The key that tokens are signed with
+///
+/// Managed by Rocket so it can be accessed from a handler
+/// using `State<TokenKey>`.
+pub struct TokenKey(hmac::Key);
+
+impl TokenKey {
+    /// Create the key from the `token_secret` setting
+    ///
+    /// If there is no secret a random key is generated instead.
+    pub fn new(secret: Option<&str>) -> Self {
+        match secret {
+            Some(s) => Self(hmac::Key::new(hmac::HMAC_SHA256, s.as_bytes())),
+            None => {
+                warn!("No token_secret is set, emailed links will stop working on restart");
+                Self(
+                    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                        .expect("Failed to generate token key"),
+                )
+            }
+        }
+    }
+
+    /// Make a token for a user that expires after the purpose's lifetime
+    pub fn sign(&self, purpose: Purpose, user: &User, now: NaiveDateTime) -> String {
+        let expires = (now + purpose.lifetime()).timestamp();
+        let tag = hmac::sign(&self.0, &purpose.message(user, expires));
+        format!(
+            "{}.{}.{}",
+            user.id,
+            expires,
+            base64::encode_config(tag.as_ref(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Check a token and get the user it was made for
+    ///
+    /// Returns `None` if the token is malformed, forged, expired
+    /// or no longer matches the user.
+    pub fn verify(
+        &self,
+        conn: &SqliteConnection,
+        purpose: Purpose,
+        token: &str,
+        now: NaiveDateTime,
+    ) -> Option<User> {
+        let mut parts = token.splitn(3, '.');
+        let uid: i32 = parts.next()?.parse().ok()?;
+        let expires: i64 = parts.next()?.parse().ok()?;
+        let tag = base64::decode_config(parts.next()?, base64::URL_SAFE_NO_PAD).ok()?;
+        if now.timestamp() > expires {
+            return None;
+        }
+
+        let user: User = {
+            use crate::schema::users::dsl::*;
+            users
+                .find(uid)
+                .filter(deleted_at.is_null())
+                .first(conn)
+                .optional()
+                .expect("Failed to get user from database")?
+        };
+
+        hmac::verify(&self.0, &purpose.message(&user, expires), &tag).ok()?;
+        Some(user)
+    }
+}
@@ -1,21 +1,34 @@
 //! HTTP handlers for the calendar
 
+use chrono::offset::{TimeZone, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
 use diesel::{delete, insert_into, update};
 use rocket::http::Status;
 use rocket::request::Form;
 use rocket::response::Redirect;
+use rocket::State;
 
 use rocket::http::ContentType;
 use rocket::response::Content;
 use rocket_contrib::json::Json;
 
-use crate::attend::code::attendance_code;
+use crate::api::v1::last_id;
+use crate::attend::code::{attendance_code, code_is_late, code_is_open, CodeStateForm};
+use crate::attend::handlers::present;
+use crate::audit;
+use crate::grades::handlers::csv_field;
 use crate::guards::*;
+use crate::roles::Permission;
 
 use super::models::*;
+use super::recurrence::{weekday_key, Recurrence};
 use super::templates::*;
-use crate::templates::{is_reserved, FormError};
+use crate::settings::Settings;
+use crate::templates::{is_reserved, BigTemplate, FormError};
+use crate::trash::handlers::move_to_trash;
+use crate::trash::models::Kind;
+use crate::tz::{self, Tz};
 use crate::ObservDbConn;
 
 /// GET handler for `/calendar`
@@ -23,12 +36,19 @@ use crate::ObservDbConn;
 /// Returns the main calendar page which either shows the FullCalendar view
 /// or a plain HTML list if JS is off.
 #[get("/calendar")]
-pub fn calendar(conn: ObservDbConn, l: MaybeLoggedIn) -> CalendarTemplate {
+pub fn calendar(
+    conn: ObservDbConn,
+    l: MaybeLoggedIn,
+    settings: State<Settings>,
+) -> CalendarTemplate {
     use crate::schema::events::dsl::*;
 
+    let logged_in = l.user();
     CalendarTemplate {
-        logged_in: l.user(),
+        zone: tz::of(logged_in.as_ref(), &settings.site_zone()),
+        logged_in,
         events: events
+            .filter(deleted_at.is_null())
             .order(start.asc())
             .load(&conn.0)
             .expect("Failed to get events"),
@@ -44,6 +64,7 @@ pub fn calendar_json(conn: ObservDbConn) -> Json<Vec<Event>> {
 
     Json(
         events
+            .filter(deleted_at.is_null())
             .order(start.asc())
             .load(&*conn)
             .expect("Failed to get events"),
@@ -52,47 +73,16 @@ pub fn calendar_json(conn: ObservDbConn) -> Json<Vec<Event>> {
 
 /// GET handler for `/calendar.ics`
 ///
-/// An ICalendar version of
+/// An ICalendar version of the calendar.
+///
+/// Events that repeat are exported as one event with an `RRULE`, with
+/// `EXDATE` for the days they don't happen on and `RECURRENCE-ID` for the
+/// occurrences that were changed apart from the series.
 #[get("/calendar.ics")]
-pub fn calendar_ics(conn: ObservDbConn) -> Content<String> {
-    use icalendar;
-    use icalendar::Component;
-
+pub fn calendar_ics(conn: ObservDbConn, settings: State<Settings>) -> Content<String> {
     let mut ical = icalendar::Calendar::new();
-
-    use crate::schema::events::dsl::*;
-    use chrono::offset::{Local, TimeZone, Utc};
-
-    for evt in events
-        .order(start.asc())
-        .load::<Event>(&*conn)
-        .expect("Failed to get events")
-    {
-        ical.push(
-            icalendar::Event::new()
-                .summary(&evt.title)
-                .description(&evt.description.unwrap_or_else(|| String::new()))
-                .class(icalendar::Class::Public)
-                // Ensure that everything converts to UTC and offsets are correct
-                .starts(
-                    Utc.from_utc_datetime(
-                        &Local.from_local_datetime(&evt.start).unwrap().naive_utc(),
-                    ),
-                )
-                .ends(
-                    Utc.from_utc_datetime(
-                        &Local.from_local_datetime(&evt.end).unwrap().naive_utc(),
-                    ),
-                )
-                .append_property(
-                    icalendar::Property::new(
-                        "LOCATION",
-                        &evt.location.unwrap_or_else(|| String::new()),
-                    )
-                    .done(),
-                )
-                .done(),
-        );
+    for e in events_ical(&*conn, &settings.site_zone()) {
+        ical.push(e);
     }
 
     Content(ContentType::Calendar, ical.to_string())
@@ -102,22 +92,165 @@ pub fn calendar_ics(conn: ObservDbConn) -> Content<String> {
 ///
 /// A single calendar event's page with information on the event.
 #[get("/calendar/<eid>")]
-pub fn event(conn: ObservDbConn, l: MaybeLoggedIn, eid: i32) -> Option<EventTemplate> {
+pub fn event(
+    conn: ObservDbConn,
+    l: MaybeLoggedIn,
+    settings: State<Settings>,
+    eid: i32,
+) -> Option<EventTemplate> {
     use crate::schema::events::dsl::*;
 
-    let evt = events
+    let evt: Event = events
         .find(eid)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .optional()
         .expect("Failed to get event")?;
 
+    let now = chrono::offset::Utc::now().naive_utc();
+    let logged_in = l.user();
+    let guests = guest_list(&*conn, &evt);
+    let uid = logged_in.as_ref().map(|u| u.id).unwrap_or_default();
+
     Some(EventTemplate {
-        logged_in: l.user(),
+        series: evt.series_id.and_then(|sid| find_series(&*conn, sid)),
         users: event_users(&*conn, &evt),
+        code_open_now: code_is_open(&evt, now),
+        code_late_now: code_is_late(&evt, now),
+        rotating: settings.rotating_codes(),
+        rsvp: guests.status_of(uid),
+        waitlist_place: guests.waitlist_place(uid),
+        rsvp_open: now < evt.end,
+        guests,
+        zone: tz::of(logged_in.as_ref(), &settings.site_zone()),
+        logged_in,
         event: evt,
     })
 }
 
+/// PUT handler for `/calendar/<eid>/rsvp`
+///
+/// Answers whether the user is coming to an event.
+/// Those going past the capacity are put on the waitlist.
+///
+/// Closes once the event is over.
+#[put("/calendar/<eid>/rsvp", data = "<form>")]
+pub fn event_rsvp_put(
+    conn: ObservDbConn,
+    l: UserGuard,
+    eid: i32,
+    form: Form<RsvpForm>,
+) -> Result<Redirect, Status> {
+    let evt: Event = {
+        use crate::schema::events::dsl::*;
+        events
+            .find(eid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get event from database")
+            .ok_or(Status::NotFound)?
+    };
+    let now = chrono::offset::Utc::now().naive_utc();
+    if now >= evt.end {
+        return Err(DENIED);
+    }
+
+    let answer = form.into_inner().status;
+    use crate::schema::rsvps::dsl::*;
+    let old: Option<Rsvp> = Rsvp::belonging_to(&evt)
+        .filter(user_id.eq(l.0.id))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get RSVP from database");
+    match &old {
+        // Answering the same again keeps their place in line
+        Some(o) if o.status == answer => return Ok(Redirect::to(format!("/calendar/{}", eid))),
+        Some(o) => {
+            update(rsvps.find(o.id))
+                .set((status.eq(answer), responded_at.eq(now)))
+                .execute(&*conn)
+                .expect("Failed to update RSVP in database");
+        }
+        None => {
+            insert_into(rsvps)
+                .values(&NewRsvp {
+                    event_id: eid,
+                    user_id: l.0.id,
+                    status: answer,
+                    responded_at: now,
+                })
+                .execute(&*conn)
+                .expect("Failed to add RSVP to database");
+        }
+    }
+    let new: Rsvp = Rsvp::belonging_to(&evt)
+        .filter(user_id.eq(l.0.id))
+        .first(&*conn)
+        .expect("Failed to get RSVP from database");
+    match old {
+        Some(o) => audit::updated(&*conn, Some(&l.0), "rsvp", new.id, &o, &new),
+        None => audit::created(&*conn, Some(&l.0), "rsvp", new.id, &new),
+    }
+
+    audit_log!(
+        "User {} [{}] has answered {} to Event {}",
+        l.0.id,
+        l.0.email,
+        answer,
+        eid
+    );
+
+    Ok(Redirect::to(format!("/calendar/{}", eid)))
+}
+
+/// GET handler for `/calendar/<eid>/rsvps.csv`
+///
+/// Everyone who answered the RSVP of an event as a CSV file, with whether
+/// their attendance was approved.
+///
+/// Restricted to Mentors and the event host.
+#[get("/calendar/<eid>/rsvps.csv")]
+pub fn event_rsvps_csv(
+    conn: ObservDbConn,
+    l: UserGuard,
+    eid: i32,
+) -> Result<Content<String>, Status> {
+    let evt: Event = {
+        use crate::schema::events::dsl::*;
+        events
+            .find(eid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get event from database")
+            .ok_or(Status::NotFound)?
+    };
+    if !(l.0.can(Permission::RunMeetings) || l.0.id == evt.hosted_by) {
+        return Err(DENIED);
+    }
+
+    let guests = guest_list(&*conn, &evt);
+    let mut csv = String::from("handle,name,email,rsvp,waitlist,responded_at,attended\n");
+    for g in guests.all() {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            csv_field(&g.user.handle),
+            csv_field(&g.user.real_name),
+            csv_field(&g.user.email),
+            g.rsvp.status.key(),
+            guests
+                .waitlist_place(g.user.id)
+                .map(|p| p.to_string())
+                .unwrap_or_default(),
+            g.rsvp.responded_at.format("%FT%TZ"),
+            g.attended,
+        ));
+    }
+
+    Ok(Content(ContentType::CSV, csv))
+}
+
 /// GET handler for `/calendar/<eid>/edit`
 ///
 /// The page to edit a calendar event.
@@ -127,6 +260,7 @@ pub fn event(conn: ObservDbConn, l: MaybeLoggedIn, eid: i32) -> Option<EventTemp
 pub fn event_edit(
     conn: ObservDbConn,
     l: UserGuard,
+    settings: State<Settings>,
     eid: i32,
     e: Option<FormError>,
 ) -> Result<EditEventTemplate, Status> {
@@ -137,31 +271,37 @@ pub fn event_edit(
 
     let host_id: i32 = events
         .find(eid)
+        .filter(crate::schema::events::deleted_at.is_null())
         .select(hosted_by)
         .first(&*conn)
         .expect("Failed to get event code");
 
-    if l.tier > 1 || l.id == host_id {
+    if l.can(Permission::EditAnyEvent) || l.id == host_id {
+        let evt: Event = if let Some(e) = events
+            .find(eid)
+            .filter(crate::schema::events::deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get event from database")
+        {
+            e
+        } else {
+            // Return early
+            return Err(Status::NotFound);
+        };
         Ok(EditEventTemplate {
+            zone: tz::of(Some(&l), &settings.site_zone()),
             logged_in: Some(l),
-            event: if let Some(e) = events
-                .find(eid)
-                .first(&*conn)
-                .optional()
-                .expect("Failed to get event from database")
-            {
-                e
-            } else {
-                // Return early
-                return Err(Status::NotFound);
-            },
+            series: evt.series_id.and_then(|sid| find_series(&*conn, sid)),
+            event: evt,
             all_users: users
+                .filter(crate::schema::users::deleted_at.is_null())
                 .load(&*conn)
                 .expect("Failed to get users from database"),
             error: e,
         })
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -169,19 +309,26 @@ pub fn event_edit(
 ///
 /// Changes the calendar event. For use with `editevent`.
 ///
+/// Occurrences of a series are changed on their own unless `whole_series`
+/// is set, then every occurrence and how the series repeats is changed.
+/// Giving an event that doesn't repeat an `until` makes it repeat.
+///
 /// Restricted to Admins and the event owner.
-#[put("/calendar/<eid>", data = "<editevent>")]
+#[put("/calendar/<eid>", data = "<form>")]
 pub fn event_edit_put(
     conn: ObservDbConn,
     l: UserGuard,
+    settings: State<Settings>,
     eid: i32,
-    editevent: Form<NewEvent>,
+    form: Form<EventForm>,
 ) -> Result<Redirect, Status> {
     let l = l.0;
+    let site = settings.site_zone();
 
     use crate::schema::events::dsl::*;
-    let mut editevent = editevent.into_inner();
-    if editevent.fix_times().is_none() {
+    let form = form.into_inner();
+    let mut editevent = form.event();
+    if editevent.fix_times(&tz::of(Some(&l), &site)).is_none() {
         return Ok(Redirect::to(format!(
             "/calendar/{}/edit?e={}",
             eid,
@@ -191,46 +338,188 @@ pub fn event_edit_put(
     if let Err(e) = is_reserved(&editevent.title) {
         return Ok(Redirect::to(format!("/calendar/{}/edit?e={}", eid, e)));
     }
-    let (atcode, host_id): (String, i32) = events
+    if let Err(e) = editevent.check_capacity() {
+        return Ok(Redirect::to(format!("/calendar/{}/edit?e={}", eid, e)));
+    }
+    let old: Event = events
         .find(eid)
-        .select((code, hosted_by))
+        .filter(deleted_at.is_null())
         .first(&*conn)
-        .expect("Failed to get event code");
-    editevent.code = atcode;
+        .expect("Failed to get event from database");
+    editevent.code = old.code.clone();
+
+    if !(l.can(Permission::EditAnyEvent) || l.id == old.hosted_by) {
+        return Err(DENIED);
+    }
+
+    let series = old.series_id.and_then(|sid| find_series(&*conn, sid));
+    let repeat = match (&series, form.whole_series) {
+        (Some(s), true) => Some(local_date(&s.start, &site)),
+        (None, _) => {
+            Some(local_date(&old.start, &site).min(local_date(&start_of(&editevent), &site)))
+        }
+        (Some(_), false) => None,
+    };
+    let recurrence = match repeat.map(|first| form.recurrence(first)) {
+        Some(Err(e)) => return Ok(Redirect::to(format!("/calendar/{}/edit?e={}", eid, e))),
+        Some(Ok(r)) => r,
+        None => None,
+    };
+
+    match (series, recurrence) {
+        (Some(s), Some(r)) => {
+            let changed = save_series(&*conn, Some(s.id), &editevent, &r, &site);
+            audit_series(&*conn, &l, changed);
+        }
+        (None, Some(r)) => {
+            let sid = new_series(&*conn, &editevent, &r, &site);
+            update(events.find(eid))
+                .set((series_id.eq(sid), original_start.eq(old.start)))
+                .execute(&*conn)
+                .expect("Failed to update event in database");
+            let changed = save_series(&*conn, Some(sid), &editevent, &r, &site);
+            audit_series(&*conn, &l, changed);
+        }
+        (_, None) => {
+            update_event(&*conn, &old, &editevent);
+            let new: Event = events
+                .find(eid)
+                .first(&*conn)
+                .expect("Failed to get event from database");
+            audit::updated(&*conn, Some(&l), "event", eid, &old, &new);
+        }
+    }
+
+    Ok(Redirect::to("/calendar"))
+}
+
+/// GET handler for `/calendar/<eid>/present`
+///
+/// Presenter mode showing the rotating attendance code of an event.
+///
+/// Restricted to Mentors and the event host.
+#[get("/calendar/<eid>/present")]
+pub fn event_present(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    site: SiteUrl,
+    eid: i32,
+) -> Result<BigTemplate, Status> {
+    use crate::schema::events::dsl::*;
 
-    if l.tier > 1 || l.id == host_id {
+    let evt: Event = events
+        .find(eid)
+        .filter(deleted_at.is_null())
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get event from database")
+        .ok_or(Status::NotFound)?;
+
+    if l.0.can(Permission::RunMeetings) || l.0.id == evt.hosted_by {
+        Ok(present(l.0, &evt, settings.code_rotation_seconds, &site))
+    } else {
+        Err(DENIED)
+    }
+}
+
+/// PUT handler for `/calendar/<eid>/code`
+///
+/// Manually opens or closes the attendance code of an event.
+///
+/// Restricted to Admins and the event host.
+#[put("/calendar/<eid>/code", data = "<form>")]
+pub fn event_code_put(
+    conn: ObservDbConn,
+    l: UserGuard,
+    eid: i32,
+    form: Form<CodeStateForm>,
+) -> Result<Redirect, Status> {
+    use crate::schema::events::dsl::*;
+
+    let old: Event = events
+        .find(eid)
+        .filter(deleted_at.is_null())
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get event from database")
+        .ok_or(Status::NotFound)?;
+
+    if l.0.can(Permission::EditAnyEvent) || l.0.id == old.hosted_by {
+        let state = form.into_inner().state;
         update(events.find(eid))
-            .set(&editevent)
+            .set(code_open.eq(Option::<bool>::from(state)))
             .execute(&*conn)
             .expect("Failed to update event in database");
 
-        Ok(Redirect::to("/calendar"))
+        audit_log!(
+            "User {} [{}] has set the code of Event {} to {:?}",
+            l.0.id,
+            l.0.email,
+            eid,
+            state
+        );
+        let new: Event = events
+            .find(eid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .expect("Failed to get event from database");
+        audit::updated(&*conn, Some(&l.0), "event", eid, &old, &new);
+
+        Ok(Redirect::to(format!("/calendar/{}", eid)))
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
 /// DELETE handler for `/calendar/<eid>
 ///
-/// Deletes an event from the calendar and database.
+/// Moves an event to the trash, its attendances are kept in case it is restored.
 ///
 /// Restricted to Admins.
 #[delete("/calendar/<eid>")]
-pub fn event_delete(conn: ObservDbConn, _l: AdminGuard, eid: i32) -> Redirect {
+pub fn event_delete(conn: ObservDbConn, l: Can<can::EditAnyEvent>, eid: i32) -> Redirect {
+    let old: Option<Event> = {
+        use crate::schema::events::dsl::*;
+        events
+            .find(eid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get event from database")
+    };
+    move_to_trash(&*conn, Kind::Event, eid);
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "event", eid, &old);
+    }
+    Redirect::to("/calendar")
+}
+
+/// Deletes an event for good along with the attendances for it
+///
+/// Only used to purge the trash, see `crate::trash`.
+pub fn delete_event(conn: &SqliteConnection, eid: i32) {
     // Delete the attendances relations
     {
         use crate::schema::attendances::dsl::*;
         delete(attendances.filter(is_event.eq(true).and(event_id.eq(eid))))
-            .execute(&*conn)
+            .execute(conn)
             .expect("Failed to delete attendances from database");
     }
 
+    // Delete the RSVPs
+    {
+        use crate::schema::rsvps::dsl::*;
+        delete(rsvps.filter(event_id.eq(eid)))
+            .execute(conn)
+            .expect("Failed to delete RSVPs from database");
+    }
+
     // Delete the event
     use crate::schema::events::dsl::*;
     delete(events.find(eid))
-        .execute(&*conn)
+        .execute(conn)
         .expect("Failed to delete event from database");
-    Redirect::to("/calendar")
 }
 
 /// GET handler for `/calendar/new`
@@ -239,11 +528,16 @@ pub fn event_delete(conn: ObservDbConn, _l: AdminGuard, eid: i32) -> Redirect {
 ///
 /// Restricted to Admins.
 #[get("/calendar/new?<e>")]
-pub fn event_new(conn: ObservDbConn, admin: AdminGuard, e: Option<FormError>) -> NewEventTemplate {
+pub fn event_new(
+    conn: ObservDbConn,
+    admin: Can<can::CreateEvent>,
+    e: Option<FormError>,
+) -> NewEventTemplate {
     use crate::schema::users::dsl::*;
     NewEventTemplate {
         logged_in: Some(admin.0),
         all_users: users
+            .filter(deleted_at.is_null())
             .load(&*conn)
             .expect("Failed to get users from database"),
         error: e,
@@ -254,52 +548,457 @@ pub fn event_new(conn: ObservDbConn, admin: AdminGuard, e: Option<FormError>) ->
 ///
 /// Creates the new calendar event. For use with `newevent`.
 ///
+/// Events that repeat have every occurrence created at once, each with
+/// its own attendance code.
+///
 /// Restricted to Admins.
-#[post("/calendar/new", data = "<newevent>")]
+#[post("/calendar/new", data = "<form>")]
 pub fn event_new_post(
     conn: ObservDbConn,
-    _admin: AdminGuard,
-    newevent: Form<NewEvent>,
+    admin: Can<can::CreateEvent>,
+    settings: State<Settings>,
+    form: Form<EventForm>,
 ) -> Redirect {
     use crate::schema::events::dsl::*;
 
-    let mut newevent = newevent.into_inner();
-    if newevent.fix_times().is_none() {
+    let site = settings.site_zone();
+    let form = form.into_inner();
+    let mut newevent = form.event();
+    if newevent.fix_times(&tz::of(Some(&admin.0), &site)).is_none() {
         return Redirect::to(format!("/calendar/new?e={}", FormError::InvalidDate));
     }
     if let Err(e) = is_reserved(&newevent.title) {
         return Redirect::to(format!("/calendar/new?e={}", e));
     }
-    newevent.code = attendance_code(&*conn);
+    if let Err(e) = newevent.check_capacity() {
+        return Redirect::to(format!("/calendar/new?e={}", e));
+    }
+    let recurrence = match form.recurrence(local_date(&start_of(&newevent), &site)) {
+        Ok(r) => r,
+        Err(e) => return Redirect::to(format!("/calendar/new?e={}", e)),
+    };
 
     audit_log!(
         "User {} [{}] has generated an attendance code for Event \'{}\'",
-        _admin.0.id,
-        _admin.0.email,
+        admin.0.id,
+        admin.0.email,
         newevent.title
     );
 
+    if let Some(r) = recurrence {
+        let changed = save_series(&*conn, None, &newevent, &r, &site);
+        audit_series(&*conn, &admin.0, changed);
+        return Redirect::to("/calendar");
+    }
+
+    newevent.code = attendance_code(&*conn);
+    newevent.find_semester(&*conn, &site);
+
     insert_into(events)
         .values(&newevent)
         .execute(&*conn)
         .expect("Failed to add event to database");
 
+    let evt: Event = events
+        .find(last_id(&*conn))
+        .first(&*conn)
+        .expect("Failed to get event from database");
+    audit::created(&*conn, Some(&admin.0), "event", evt.id, &evt);
+
     Redirect::to("/calendar")
 }
 
 use crate::models::{Attendance, User};
-/// Returns a list of users who attended a given event
-fn event_users(conn: &SqliteConnection, event: &Event) -> Vec<User> {
+/// Returns a list of users whose attendance at a given event was approved
+pub fn event_users(conn: &SqliteConnection, event: &Event) -> Vec<User> {
+    use crate::schema::attendances::dsl::approved;
     Attendance::belonging_to(event)
+        .filter(approved.eq(true))
         .load::<Attendance>(conn)
         .expect("Failed to get relations from database")
         .iter()
-        .map(|r| {
+        .filter_map(|r| {
             use crate::schema::users::dsl::*;
             users
                 .find(r.user_id)
+                .filter(deleted_at.is_null())
                 .first(conn)
+                .optional()
                 .expect("Failed to get user from database")
         })
         .collect()
 }
+
+/// Who answered the RSVP of an event and whether they came
+pub fn guest_list(conn: &SqliteConnection, event: &Event) -> GuestList {
+    use crate::schema::rsvps::dsl::*;
+    let attended: Vec<i32> = event_users(conn, event).iter().map(|u| u.id).collect();
+    let guests = Rsvp::belonging_to(event)
+        .order((responded_at.asc(), id.asc()))
+        .load::<Rsvp>(conn)
+        .expect("Failed to get RSVPs from database")
+        .into_iter()
+        .filter_map(|r| {
+            use crate::schema::users::dsl::*;
+            let user: User = users
+                .find(r.user_id)
+                .filter(deleted_at.is_null())
+                .first(conn)
+                .optional()
+                .expect("Failed to get user from database")?;
+            Some(Guest {
+                attended: attended.contains(&user.id),
+                user,
+                rsvp: r,
+            })
+        })
+        .collect();
+    GuestList::new(guests, event.capacity)
+}
+
+/// Saves changes to an event that were made to it alone
+///
+/// An occurrence of a series is marked as changed apart from the series.
+pub fn update_event(conn: &SqliteConnection, old: &Event, editevent: &NewEvent) {
+    use crate::schema::events::dsl::*;
+    update(events.find(old.id))
+        .set((editevent, detached.eq(old.series_id.is_some())))
+        .execute(conn)
+        .expect("Failed to update event in database");
+    // Changesets skip `None`, so taking away the capacity is done on its own
+    if editevent.capacity.is_none() {
+        update(events.find(old.id))
+            .set(capacity.eq(None::<i32>))
+            .execute(conn)
+            .expect("Failed to update event in database");
+    }
+}
+
+/// The start of an event with fixed times
+fn start_of(evt: &NewEvent) -> NaiveDateTime {
+    smart_time_parse(&evt.start).expect("Times of event are not fixed")
+}
+
+/// The day a time in UTC is on in the site's time zone `site`
+///
+/// Series repeat on the days and at the time of day of the site.
+fn local_date(t: &NaiveDateTime, site: &Tz) -> NaiveDate {
+    site.to_local(t).date()
+}
+
+/// The day an occurrence of a series is on in the site's time zone,
+/// where the series has it even if it was moved
+fn day_of(e: &Event, site: &Tz) -> NaiveDate {
+    local_date(&e.original_start.unwrap_or(e.start), site)
+}
+
+/// When the occurrence of a series on a day in the site's time zone starts
+fn occurrence_at(series: &EventSeries, day: NaiveDate, site: &Tz) -> NaiveDateTime {
+    site.to_utc(&day.and_time(site.to_local(&series.start).time()))
+}
+
+/// A series of events
+pub fn find_series(conn: &SqliteConnection, sid: i32) -> Option<EventSeries> {
+    use crate::schema::event_series::dsl::*;
+    event_series
+        .find(sid)
+        .first(conn)
+        .optional()
+        .expect("Failed to get series from database")
+}
+
+/// Every series of events
+pub fn all_series(conn: &SqliteConnection) -> Vec<EventSeries> {
+    use crate::schema::event_series::dsl::*;
+    event_series
+        .load(conn)
+        .expect("Failed to get series from database")
+}
+
+/// Adds a series without any occurrences, returning its ID
+///
+/// `save_series` creates the occurrences.
+fn new_series(conn: &SqliteConnection, evt: &NewEvent, r: &Recurrence, site: &Tz) -> i32 {
+    use crate::schema::event_series::dsl::*;
+    insert_into(event_series)
+        .values(&series_times(
+            evt,
+            r,
+            local_date(&start_of(evt), site),
+            site,
+        ))
+        .execute(conn)
+        .expect("Failed to add series to database");
+    last_id(conn)
+}
+
+/// How a series repeats with the time of day of `evt`, starting on the
+/// first day it repeats on from `from`
+fn series_times(evt: &NewEvent, r: &Recurrence, from: NaiveDate, site: &Tz) -> NewEventSeries {
+    let evt_start = start_of(evt);
+    let length = smart_time_parse(&evt.end).expect("Times of event are not fixed") - evt_start;
+    let first = r.days(from).first().copied().unwrap_or(from);
+    let first = site.to_utc(&first.and_time(site.to_local(&evt_start).time()));
+    NewEventSeries {
+        start: first,
+        end: first + length,
+        weekdays: r.weekdays_key(),
+        until: r.until,
+        except_dates: r.except_key(),
+    }
+}
+
+/// The occurrences of a series that were created, changed and moved to
+/// the trash when it was saved, as they were before and after
+pub struct SeriesChanges {
+    pub created: Vec<Event>,
+    pub updated: Vec<(Event, Event)>,
+    pub trashed: Vec<Event>,
+}
+
+/// Creates or changes a series of events so it repeats like `r` with the
+/// details and time of day of `evt`
+///
+/// Occurrences are created for the days that don't have one yet, each with
+/// its own attendance code, and the occurrences on days the series no
+/// longer happens on are moved to the trash.
+/// Occurrences in the trash aren't made again.
+/// Every other occurrence gets the details and times of `evt`.
+/// Series repeat in the site's time zone `site`.
+pub fn save_series(
+    conn: &SqliteConnection,
+    sid: Option<i32>,
+    evt: &NewEvent,
+    r: &Recurrence,
+    site: &Tz,
+) -> SeriesChanges {
+    let sid = match sid {
+        Some(sid) => {
+            let old = find_series(conn, sid).expect("Failed to get series from database");
+            use crate::schema::event_series::dsl::*;
+            update(event_series.find(sid))
+                .set(&series_times(evt, r, local_date(&old.start, site), site))
+                .execute(conn)
+                .expect("Failed to update series in database");
+            sid
+        }
+        None => new_series(conn, evt, r, site),
+    };
+    let series = find_series(conn, sid).expect("Failed to get series from database");
+    let length = series.end - series.start;
+    let dates = r.dates(local_date(&series.start, site));
+
+    use crate::schema::events::dsl::*;
+    let existing: Vec<Event> = events
+        .filter(series_id.eq(sid))
+        .load(conn)
+        .expect("Failed to get events from database");
+
+    let mut changes = SeriesChanges {
+        created: Vec::new(),
+        updated: Vec::new(),
+        trashed: Vec::new(),
+    };
+    for old in existing.iter().filter(|e| e.deleted_at.is_none()) {
+        if !dates.contains(&day_of(old, site)) {
+            move_to_trash(conn, Kind::Event, old.id);
+            changes.trashed.push(old.clone());
+            continue;
+        }
+        let at = occurrence_at(&series, day_of(old, site), site);
+        update(events.find(old.id))
+            .set((
+                title.eq(&evt.title),
+                description.eq(&evt.description),
+                hosted_by.eq(evt.hosted_by),
+                location.eq(&evt.location),
+                color.eq(&evt.color),
+                capacity.eq(evt.capacity),
+                start.eq(at),
+                end.eq(at + length),
+                original_start.eq(at),
+                detached.eq(false),
+            ))
+            .execute(conn)
+            .expect("Failed to update event in database");
+        let new: Event = events
+            .find(old.id)
+            .first(conn)
+            .expect("Failed to get event from database");
+        if &new != old {
+            changes.updated.push((old.clone(), new));
+        }
+    }
+
+    for day in dates
+        .iter()
+        .filter(|d| !existing.iter().any(|e| day_of(e, site) == **d))
+    {
+        let at = occurrence_at(&series, *day, site);
+        let mut occurrence = NewEvent {
+            start: at.format("%F %R").to_string(),
+            end: (at + length).format("%F %R").to_string(),
+            code: attendance_code(conn),
+            ..evt.clone()
+        };
+        occurrence.find_semester(conn, site);
+        insert_into(events)
+            .values((&occurrence, series_id.eq(sid), original_start.eq(at)))
+            .execute(conn)
+            .expect("Failed to add event to database");
+        changes.created.push(
+            events
+                .find(last_id(conn))
+                .first(conn)
+                .expect("Failed to get event from database"),
+        );
+    }
+
+    changes
+}
+
+/// Records the changes made by `save_series` in the audit trail
+fn audit_series(conn: &SqliteConnection, actor: &User, changes: SeriesChanges) {
+    for e in &changes.created {
+        audit::created(conn, Some(actor), "event", e.id, e);
+    }
+    for (old, new) in &changes.updated {
+        audit::updated(conn, Some(actor), "event", old.id, old, new);
+    }
+    for e in &changes.trashed {
+        audit::deleted(conn, Some(actor), "event", e.id, e);
+    }
+}
+
+/// Every event on the calendar for the ICalendar export
+///
+/// Used by `calendar_ics` and the calendar feeds, see `crate::feeds`.
+/// Series repeat in the site's time zone `site`.
+pub fn events_ical(conn: &SqliteConnection, site: &Tz) -> Vec<icalendar::Event> {
+    use crate::schema::events::dsl::*;
+
+    let all: Vec<Event> = events
+        .filter(deleted_at.is_null())
+        .order(start.asc())
+        .load(conn)
+        .expect("Failed to get events");
+
+    let (singles, occurrences): (Vec<Event>, Vec<Event>) =
+        all.into_iter().partition(|e| e.series_id.is_none());
+    let mut exported: Vec<icalendar::Event> = singles
+        .iter()
+        .map(|evt| ical_event(evt, &format!("event-{}@observatory", evt.id)))
+        .collect();
+    for series in all_series(conn) {
+        let occ: Vec<&Event> = occurrences
+            .iter()
+            .filter(|e| e.series_id == Some(series.id))
+            .collect();
+        exported.extend(series_ical(&series, &occ, site));
+    }
+    exported
+}
+
+/// A time stored in UTC for the ICalendar export
+pub fn to_utc(t: &NaiveDateTime) -> DateTime<Utc> {
+    Utc.from_utc_datetime(t)
+}
+
+/// An event for the ICalendar export
+fn ical_event(evt: &Event, id: &str) -> icalendar::Event {
+    use icalendar::Component;
+
+    icalendar::Event::new()
+        .uid(id)
+        .summary(&evt.title)
+        .description(evt.description.as_deref().unwrap_or_default())
+        .class(icalendar::Class::Public)
+        // Ensure that everything converts to UTC and offsets are correct
+        .starts(to_utc(&evt.start))
+        .ends(to_utc(&evt.end))
+        .append_property(
+            icalendar::Property::new("LOCATION", evt.location.as_deref().unwrap_or_default())
+                .done(),
+        )
+        .done()
+}
+
+/// A series of events for the ICalendar export
+///
+/// The `RRULE` is in UTC so its times don't change with daylight saving
+/// time. The occurrences that daylight saving time moves are exported as
+/// changed occurrences along with the ones that were changed on their own.
+fn series_ical(series: &EventSeries, occurrences: &[&Event], site: &Tz) -> Vec<icalendar::Event> {
+    use chrono::Duration;
+    use icalendar::Component;
+
+    let uid = format!("series-{}@observatory", series.id);
+    let r = series.recurrence();
+    let local_start = site.to_local(&series.start);
+    let days = r.days(local_start.date());
+    let (in_rule, moved): (Vec<&Event>, Vec<&Event>) = occurrences
+        .iter()
+        .partition(|e| days.contains(&day_of(e, site)));
+
+    // The details of the series are those of an occurrence that wasn't changed
+    let main = match in_rule
+        .iter()
+        .find(|e| !e.detached)
+        .or_else(|| in_rule.first())
+    {
+        Some(e) => *e,
+        None => return Vec::new(),
+    };
+
+    // Each day of the rule at the UTC time of the first occurrence
+    let first = to_utc(&series.start);
+    let offset = local_start - series.start;
+    let instance = |d: NaiveDate| Utc.from_utc_datetime(&(d.and_time(local_start.time()) - offset));
+    let shift = first.date().naive_utc() - local_start.date();
+    let length = series.end - series.start;
+
+    let byday = r
+        .weekdays
+        .iter()
+        .map(|w| {
+            let d = local_start.date() + Duration::days(i64::from(w.num_days_from_monday()))
+                - Duration::days(i64::from(local_start.weekday().num_days_from_monday()))
+                + shift;
+            weekday_key(d.weekday())
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut master = ical_event(main, &uid);
+    master.starts(first).ends(first + length).add_property(
+        "RRULE",
+        &format!(
+            "FREQ=WEEKLY;BYDAY={};UNTIL={}",
+            byday,
+            instance(r.until).format("%Y%m%dT%H%M%SZ")
+        ),
+    );
+    for d in days
+        .iter()
+        .filter(|d| !in_rule.iter().any(|e| day_of(e, site) == **d))
+    {
+        master.add_multi_property("EXDATE", &instance(*d).format("%Y%m%dT%H%M%SZ").to_string());
+    }
+
+    let mut exported = vec![master.done()];
+    for e in in_rule {
+        let at = instance(day_of(e, site));
+        if e.detached || to_utc(&e.start) != at || e.end - e.start != length {
+            exported.push(
+                ical_event(e, &uid)
+                    .add_property("RECURRENCE-ID", &at.format("%Y%m%dT%H%M%SZ").to_string())
+                    .done(),
+            );
+        }
+    }
+    // Occurrences on days the series no longer repeats on stand alone
+    for e in moved {
+        exported.push(ical_event(e, &format!("event-{}@observatory", e.id)));
+    }
+    exported
+}
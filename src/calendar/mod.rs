@@ -9,8 +9,13 @@
 //! - `/calendar/new`
 //! - `/calendar/<eid>`
 //! - `/calendar/<eid>/edit`
+//! - `/calendar/<eid>/code`
+//! - `/calendar/<eid>/present`
+//! - `/calendar/<eid>/rsvp`
+//! - `/calendar/<eid>/rsvps.csv`
 
 pub mod handlers;
 pub mod models;
+pub mod recurrence;
 
 mod templates;
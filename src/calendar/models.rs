@@ -2,10 +2,22 @@
 //!
 //! Calendar events are stored in the `calendar` table where each row
 //! is an event.
-use chrono::naive::NaiveDateTime;
+//! Events that repeat have an `EventSeries` with each occurrence being
+//! an event, see `super::recurrence`.
+use chrono::naive::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, Datelike, Weekday};
+use diesel::sql_types::Text;
+use diesel::SqliteConnection;
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
+use std::fmt;
 
-use crate::models::Attendable;
+use super::recurrence::{parse_dates, parse_weekdays, Recurrence};
+use crate::models::{Attendable, User};
 use crate::schema::*;
+use crate::semesters::handlers::semester_at;
+use crate::templates::FormError;
+use crate::tz::Tz;
 
 /// A calendar Event
 ///
@@ -14,9 +26,11 @@ use crate::schema::*;
 pub struct Event {
     /// ID of the event
     pub id: i32,
-    /// Time the event starts
+    /// Time the event starts in UTC
+    #[serde(serialize_with = "crate::tz::serialize_utc")]
     pub start: NaiveDateTime,
-    /// Time the event ends
+    /// Time the event ends in UTC
+    #[serde(serialize_with = "crate::tz::serialize_utc")]
     pub end: NaiveDateTime,
     /// Title of the event
     pub title: String,
@@ -30,6 +44,26 @@ pub struct Event {
     pub code: String,
     /// Optional color to display the event on the calendar
     pub color: Option<String>,
+    /// Manual override of the code window, `None` follows the event times
+    pub code_open: Option<bool>,
+    /// Semester the event is part of
+    pub semester_id: Option<i32>,
+    /// When the event was moved to the trash, see `crate::trash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<NaiveDateTime>,
+    /// Series the event is an occurrence of if it repeats
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<i32>,
+    /// When the series has the occurrence start, kept if it is moved
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::tz::serialize_utc_opt"
+    )]
+    pub original_start: Option<NaiveDateTime>,
+    /// Was the occurrence changed apart from the rest of the series
+    pub detached: bool,
+    /// How many can go, the rest are put on the waitlist (optional)
+    pub capacity: Option<i32>,
 }
 
 // Implement the Attendable trait for an Event.
@@ -37,16 +71,22 @@ impl Attendable for Event {
     fn id(&self) -> i32 {
         self.id
     }
-    fn name(&self) -> String {
+    fn name(&self, zone: Tz) -> String {
         format!(
             "Event ({}) on: {}",
             self.title.clone(),
-            self.start.format("%b. %-d, at %l:%M %p").to_string()
+            zone.to_local(&self.start).format("%b. %-d, at %l:%M %p")
         )
     }
     fn time(&self) -> NaiveDateTime {
         self.start
     }
+    fn closes_at(&self) -> NaiveDateTime {
+        self.end
+    }
+    fn code_open(&self) -> Option<bool> {
+        self.code_open
+    }
     fn code(&self) -> String {
         self.code.clone()
     }
@@ -62,8 +102,9 @@ impl Attendable for Event {
 }
 
 /// Used to create a new event in the database
-#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset)]
+#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset, Deserialize)]
 #[table_name = "events"]
+#[serde(default)]
 pub struct NewEvent {
     /// Title of the event
     pub title: String,
@@ -81,17 +122,325 @@ pub struct NewEvent {
     pub code: String,
     /// Optional color to display the event on the calendar
     pub color: Option<String>,
+    /// Semester the event is part of
+    pub semester_id: Option<i32>,
+    /// How many can go, the rest are put on the waitlist (optional)
+    pub capacity: Option<i32>,
 }
 
 impl NewEvent {
-    /// Verifies that the start and end times are valid
-    pub fn fix_times(&mut self) -> Option<()> {
+    /// Verifies that the start and end times are valid and puts them in UTC
+    ///
+    /// Times without an offset are in the time zone `tz`.
+    pub fn fix_times(&mut self, tz: &Tz) -> Option<()> {
         // Set the start and end in self
-        self.start = smart_time_parse(&self.start)?.format("%F %R").to_string();
-        self.end = smart_time_parse(&self.end)?.format("%F %R").to_string();
+        self.start = parse_time(&self.start, tz)?.format("%F %R").to_string();
+        self.end = parse_time(&self.end, tz)?.format("%F %R").to_string();
         // If it got this far then return a Some
         Some(())
     }
+
+    /// Verifies that at least one person can go if there is a capacity
+    pub fn check_capacity(&self) -> Result<(), FormError> {
+        match self.capacity {
+            Some(c) if c < 1 => Err(FormError::Capacity),
+            _ => Ok(()),
+        }
+    }
+
+    /// Puts the event in the semester it starts in, unless it already has one
+    ///
+    /// The times must already be fixed by `fix_times`.
+    pub fn find_semester(&mut self, conn: &SqliteConnection, site: &Tz) {
+        if self.semester_id.is_none() {
+            self.semester_id = smart_time_parse(&self.start)
+                .and_then(|t| semester_at(conn, t, site))
+                .map(|s| s.id);
+        }
+    }
+}
+
+/// A series of events that repeats every week
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Serialize)]
+#[table_name = "event_series"]
+pub struct EventSeries {
+    pub id: i32,
+    /// Time the first occurrence starts, the others start at the same time of day
+    pub start: NaiveDateTime,
+    /// Time the first occurrence ends
+    pub end: NaiveDateTime,
+    /// Days of the week it repeats on like `MO,WE`
+    pub weekdays: String,
+    /// The last day it can happen on
+    pub until: NaiveDate,
+    /// Days it doesn't happen on like `2021-03-10,2021-03-17`
+    pub except_dates: String,
+}
+
+impl EventSeries {
+    /// How the series repeats
+    pub fn recurrence(&self) -> Recurrence {
+        Recurrence {
+            weekdays: parse_weekdays(&self.weekdays).unwrap_or_default(),
+            until: self.until,
+            except: parse_dates(&self.except_dates).unwrap_or_default(),
+        }
+    }
+
+    /// The days of the week it repeats on like `Mon, Wed`
+    pub fn day_names(&self) -> String {
+        self.recurrence()
+            .weekdays
+            .iter()
+            .map(|w| format!("{:?}", w))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Used to create or change a series of events
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[table_name = "event_series"]
+pub struct NewEventSeries {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub weekdays: String,
+    pub until: NaiveDate,
+    pub except_dates: String,
+}
+
+/// The form to create or edit an event
+///
+/// It has the fields of `NewEvent` and how the event repeats.
+/// Events repeat when `until` is given.
+#[derive(Debug, Default, Clone, FromForm)]
+pub struct EventForm {
+    pub title: String,
+    pub start: String,
+    pub end: String,
+    pub description: Option<String>,
+    pub hosted_by: i32,
+    pub location: Option<String>,
+    pub code: String,
+    pub color: Option<String>,
+    pub semester_id: Option<i32>,
+    pub capacity: Option<i32>,
+    /// The days of the week it repeats on,
+    /// the day it starts on if none are picked
+    pub mo: bool,
+    pub tu: bool,
+    pub we: bool,
+    pub th: bool,
+    pub fr: bool,
+    pub sa: bool,
+    pub su: bool,
+    /// The last day it repeats on like `2021-05-01`
+    pub until: Option<String>,
+    /// Days it doesn't happen on separated by commas
+    pub except: Option<String>,
+    /// When editing an occurrence, change the whole series instead
+    pub whole_series: bool,
+}
+
+impl EventForm {
+    /// The event without how it repeats
+    pub fn event(&self) -> NewEvent {
+        NewEvent {
+            title: self.title.clone(),
+            start: self.start.clone(),
+            end: self.end.clone(),
+            description: self.description.clone(),
+            hosted_by: self.hosted_by,
+            location: self.location.clone(),
+            code: self.code.clone(),
+            color: self.color.clone(),
+            semester_id: self.semester_id,
+            capacity: self.capacity,
+        }
+    }
+
+    /// How the event repeats starting on `first`, `None` if it doesn't
+    pub fn recurrence(&self, first: NaiveDate) -> Result<Option<Recurrence>, FormError> {
+        let until = match self.until.as_deref().map(str::trim) {
+            Some(u) if !u.is_empty() => {
+                NaiveDate::parse_from_str(u, "%F").map_err(|_| FormError::InvalidDate)?
+            }
+            _ => return Ok(None),
+        };
+
+        let picked = [
+            (self.mo, Weekday::Mon),
+            (self.tu, Weekday::Tue),
+            (self.we, Weekday::Wed),
+            (self.th, Weekday::Thu),
+            (self.fr, Weekday::Fri),
+            (self.sa, Weekday::Sat),
+            (self.su, Weekday::Sun),
+        ];
+        let mut weekdays: Vec<Weekday> = picked
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, w)| *w)
+            .collect();
+        if weekdays.is_empty() {
+            weekdays.push(first.weekday());
+        }
+
+        let recurrence = Recurrence {
+            weekdays,
+            until,
+            except: parse_dates(self.except.as_deref().unwrap_or_default())
+                .ok_or(FormError::InvalidDate)?,
+        };
+        if recurrence.dates(first).is_empty() {
+            return Err(FormError::InvalidDate);
+        }
+        Ok(Some(recurrence))
+    }
+}
+
+/// A user's answer to whether they are coming to an event
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(Event)]
+#[belongs_to(User)]
+pub struct Rsvp {
+    pub id: i32,
+    /// ID of the event
+    pub event_id: i32,
+    /// ID of the user who answered
+    pub user_id: i32,
+    /// Whether they are coming
+    pub status: RsvpStatus,
+    /// When the answer last changed in UTC, the waitlist goes in this order
+    #[serde(serialize_with = "crate::tz::serialize_utc")]
+    pub responded_at: NaiveDateTime,
+}
+
+/// Used to answer the RSVP of an event
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "rsvps"]
+pub struct NewRsvp {
+    pub event_id: i32,
+    pub user_id: i32,
+    pub status: RsvpStatus,
+    pub responded_at: NaiveDateTime,
+}
+
+/// Whether a user is coming to an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[sql_type = "Text"]
+#[serde(rename_all = "snake_case")]
+pub enum RsvpStatus {
+    Going,
+    Maybe,
+    NotGoing,
+}
+
+text_enum!(RsvpStatus {
+    Going = "going",
+    Maybe = "maybe",
+    NotGoing = "not_going",
+});
+
+impl fmt::Display for RsvpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                RsvpStatus::Going => "Going",
+                RsvpStatus::Maybe => "Maybe",
+                RsvpStatus::NotGoing => "Not Going",
+            }
+        )
+    }
+}
+
+impl<'v> FromFormValue<'v> for RsvpStatus {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<RsvpStatus, &'v RawStr> {
+        form_value.parse().map_err(|_| form_value)
+    }
+}
+
+/// Used to parse the incoming form in `event_rsvp_put`
+#[derive(Debug, FromForm)]
+pub struct RsvpForm {
+    pub status: RsvpStatus,
+}
+
+/// Someone who answered the RSVP of an event
+#[derive(Debug, Clone)]
+pub struct Guest {
+    pub user: User,
+    pub rsvp: Rsvp,
+    /// Was their attendance at the event approved
+    pub attended: bool,
+}
+
+/// Who is coming to an event, worked out from the RSVPs
+///
+/// The first to say they are going get the spots and the rest wait in the
+/// order they answered, so a spot goes to the next in line when it opens.
+#[derive(Debug, Clone, Default)]
+pub struct GuestList {
+    /// Going and have a spot
+    pub going: Vec<Guest>,
+    /// Going but past the capacity, first in line first
+    pub waitlist: Vec<Guest>,
+    pub maybe: Vec<Guest>,
+    pub not_going: Vec<Guest>,
+}
+
+impl GuestList {
+    /// Sorts the guests by their answers, `guests` must be in the order
+    /// they answered
+    pub fn new(guests: Vec<Guest>, capacity: Option<i32>) -> Self {
+        let mut list = GuestList::default();
+        for g in guests {
+            match g.rsvp.status {
+                RsvpStatus::Going => match capacity {
+                    Some(c) if list.going.len() >= c as usize => list.waitlist.push(g),
+                    _ => list.going.push(g),
+                },
+                RsvpStatus::Maybe => list.maybe.push(g),
+                RsvpStatus::NotGoing => list.not_going.push(g),
+            }
+        }
+        list
+    }
+
+    /// Everyone in the list, going then waitlisted, maybe and not going
+    pub fn all(&self) -> Vec<&Guest> {
+        self.going
+            .iter()
+            .chain(self.waitlist.iter())
+            .chain(self.maybe.iter())
+            .chain(self.not_going.iter())
+            .collect()
+    }
+
+    /// What the user answered
+    pub fn status_of(&self, uid: i32) -> Option<RsvpStatus> {
+        self.all()
+            .into_iter()
+            .find(|g| g.user.id == uid)
+            .map(|g| g.rsvp.status)
+    }
+
+    /// Where the user is on the waitlist, starting at 1
+    pub fn waitlist_place(&self, uid: i32) -> Option<usize> {
+        self.waitlist
+            .iter()
+            .position(|g| g.user.id == uid)
+            .map(|p| p + 1)
+    }
+
+    /// Those with a spot whose attendance wasn't approved
+    pub fn no_shows(&self) -> Vec<&Guest> {
+        self.going.iter().filter(|g| !g.attended).collect()
+    }
 }
 
 // The array of possible valid strftime strings
@@ -119,3 +468,15 @@ pub fn smart_time_parse(timestr: &str) -> Option<NaiveDateTime> {
         // Find the first valid pair and stop
         .find_map(|e| e.ok())
 }
+
+/// Reads a time typed in by a user as UTC
+///
+/// Times with an offset like `2018-05-03T10:22:00-04:00` are converted
+/// from it, the others are in the time zone `tz` in any of the formats
+/// accepted by `smart_time_parse`.
+pub fn parse_time(timestr: &str, tz: &Tz) -> Option<NaiveDateTime> {
+    match DateTime::parse_from_rfc3339(timestr.trim()) {
+        Ok(t) => Some(t.naive_utc()),
+        Err(_) => smart_time_parse(timestr).map(|t| tz.to_utc(&t)),
+    }
+}
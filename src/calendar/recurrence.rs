@@ -0,0 +1,136 @@
+//! Events that repeat every week
+//!
+//! This is the part of the iCalendar `RRULE` that RCOS needs: a series
+//! happens every week on some days of the week until a date, skipping
+//! some dates. Each occurrence is its own `Event` so it can have its own
+//! attendance code and be changed apart from the rest of the series.
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+/// The most days after the first occurrence a series can go on for
+pub const MAX_DAYS: i64 = 366;
+
+/// How a series of events repeats
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recurrence {
+    /// Days of the week it happens on, Monday first
+    pub weekdays: Vec<Weekday>,
+    /// The last day it can happen on
+    pub until: NaiveDate,
+    /// Days it doesn't happen on
+    pub except: Vec<NaiveDate>,
+}
+
+impl Recurrence {
+    /// Every day from `from` until the end on one of the weekdays,
+    /// including the days it doesn't happen on
+    ///
+    /// These are the days the `RRULE` gives.
+    pub fn days(&self, from: NaiveDate) -> Vec<NaiveDate> {
+        let mut day = from;
+        let mut days = Vec::new();
+        while day <= self.until && day <= from + Duration::days(MAX_DAYS) {
+            if self.weekdays.contains(&day.weekday()) {
+                days.push(day);
+            }
+            day = day.succ();
+        }
+        days
+    }
+
+    /// The days from `from` that it happens on
+    pub fn dates(&self, from: NaiveDate) -> Vec<NaiveDate> {
+        self.days(from)
+            .into_iter()
+            .filter(|d| !self.except.contains(d))
+            .collect()
+    }
+
+    /// The weekdays like `MO,WE`
+    pub fn weekdays_key(&self) -> String {
+        self.weekdays
+            .iter()
+            .map(|w| weekday_key(*w))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// The days it doesn't happen on like `2021-03-10,2021-03-17`
+    pub fn except_key(&self) -> String {
+        self.except
+            .iter()
+            .map(|d| d.format("%F").to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Is it on this day of the week?
+    pub fn on(&self, w: Weekday) -> bool {
+        self.weekdays.contains(&w)
+    }
+}
+
+/// The two letter name of a weekday used by `RRULE` like `MO`
+pub fn weekday_key(w: Weekday) -> &'static str {
+    match w {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Reads weekdays like `MO,WE`, sorted with Monday first
+pub fn parse_weekdays(s: &str) -> Option<Vec<Weekday>> {
+    let mut days = s
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            WEEKDAYS
+                .iter()
+                .copied()
+                .find(|w| weekday_key(*w).eq_ignore_ascii_case(d))
+        })
+        .collect::<Option<Vec<Weekday>>>()?;
+    days.sort_by_key(|w| w.num_days_from_monday());
+    days.dedup();
+    Some(days)
+}
+
+/// Reads dates like `2021-03-10` separated by commas or spaces, sorted
+pub fn parse_dates(s: &str) -> Option<Vec<NaiveDate>> {
+    let mut dates = s
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|d| !d.is_empty())
+        .map(|d| NaiveDate::parse_from_str(d, "%F").ok())
+        .collect::<Option<Vec<NaiveDate>>>()?;
+    dates.sort();
+    dates.dedup();
+    Some(dates)
+}
+
+/// Every day of the week, Monday first
+pub const WEEKDAYS: &[Weekday] = &[
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// The weekdays as they are named in forms and their labels
+pub const WEEKDAY_FIELDS: &[(&str, &str)] = &[
+    ("mo", "Mon"),
+    ("tu", "Tue"),
+    ("we", "Wed"),
+    ("th", "Thu"),
+    ("fr", "Fri"),
+    ("sa", "Sat"),
+    ("su", "Sun"),
+];
@@ -6,6 +6,7 @@ use crate::templates::filters;
 use crate::templates::{FormError, OptUser};
 
 use crate::models::User;
+use crate::tz::Tz;
 
 /// Calendar page template
 ///
@@ -17,6 +18,8 @@ use crate::models::User;
 #[template(path = "calendar/calendar.html")]
 pub struct CalendarTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub events: Vec<Event>,
 }
 
@@ -29,8 +32,32 @@ pub struct CalendarTemplate {
 #[template(path = "calendar/event.html")]
 pub struct EventTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub event: Event,
+    /// The series the event is an occurrence of
+    pub series: Option<EventSeries>,
     pub users: Vec<User>,
+    /// Is the attendance code being accepted right now
+    pub code_open_now: bool,
+    /// Is attendance submitted right now late and has to be approved
+    pub code_late_now: bool,
+    /// Do attendance codes rotate, if so only presenter mode shows them
+    pub rotating: bool,
+    pub guests: GuestList,
+    /// What the logged in user answered to the RSVP
+    pub rsvp: Option<RsvpStatus>,
+    /// Where the logged in user is on the waitlist
+    pub waitlist_place: Option<usize>,
+    /// Can the RSVP still be answered, it closes when the event is over
+    pub rsvp_open: bool,
+}
+
+impl EventTemplate {
+    /// Did the logged in user answer the RSVP like `going`?
+    pub fn answered(&self, key: &str) -> bool {
+        self.rsvp.map(|s| s.key() == key).unwrap_or(false)
+    }
 }
 
 /// Template for creating a new Event
@@ -55,7 +82,21 @@ pub struct NewEventTemplate {
 #[template(path = "calendar/edit-event.html")]
 pub struct EditEventTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub event: Event,
+    /// The series the event is an occurrence of
+    pub series: Option<EventSeries>,
     pub all_users: Vec<User>,
     pub error: Option<FormError>,
 }
+
+impl EditEventTemplate {
+    /// Does the series repeat on the weekday like `MO`?
+    pub fn repeats_on(&self, day: &str) -> bool {
+        self.series
+            .as_ref()
+            .map(|s| s.weekdays.split(',').any(|w| w == day))
+            .unwrap_or(false)
+    }
+}
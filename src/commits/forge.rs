@@ -0,0 +1,315 @@
+//! Forges that repositories can be hosted on
+//!
+//! Each forge knows which repository URLs it hosts, where its API lists
+//! commits, and how to turn a page of that API into `NewCommit`s.
+//! Supported forges are GitHub, GitLab, Gitea/Forgejo and Bitbucket.
+//!
+//! GitLab and Gitea are often self-hosted so the hosts they run on are set
+//! with the `gitlab_hosts` and `gitea_hosts` settings.
+
+use chrono::{DateTime, NaiveDateTime};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::settings::Settings;
+
+use super::models::NewCommit;
+
+/// A repository URL split into its parts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repo {
+    /// Normalized URL like `https://github.com/owner/name`
+    ///
+    /// This is what commits are stored under in the database.
+    pub url: String,
+    /// The scheme and host like `https://github.com`
+    pub base: String,
+    /// The host like `github.com`
+    pub host: String,
+    /// The path of the repository like `owner/name`
+    pub path: String,
+}
+
+impl Repo {
+    /// Parse and normalize a repository URL
+    ///
+    /// The scheme is always `https`, even for `http` URLs, so a repository
+    /// has one URL and forge tokens are never sent in the clear. The host
+    /// is lowercased with any `www.` removed. A trailing `.git` or `/` is
+    /// dropped.
+    pub fn parse(url: &str) -> Option<Self> {
+        let re = Regex::new(r"^(?:https?://)?(?:www\.)?([^/\s]+)/([^\s]+?)(?:\.git)?/?$")
+            .expect("Failed to build regular expression");
+        let caps = re.captures(url.trim())?;
+        let host = caps[1].to_lowercase();
+        let path = caps[2].to_string();
+        if !path.contains('/') {
+            return None;
+        }
+        let base = format!("https://{}", host);
+        Some(Self {
+            url: format!("{}/{}", base, path),
+            base,
+            host,
+            path,
+        })
+    }
+
+    /// Number of segments in the path
+    fn depth(&self) -> usize {
+        self.path.split('/').count()
+    }
+}
+
+/// A site that hosts repositories
+pub trait Forge: Send {
+    /// Whether this forge hosts the repository
+    fn hosts(&self, repo: &Repo) -> bool;
+    /// URL of the first page of commits in the repository
+    fn commits_url(&self, repo: &Repo) -> String;
+    /// Value of the `Authorization` header for a token
+    fn auth_header(&self, token: &str) -> String {
+        format!("Bearer {}", token)
+    }
+    /// Parse a page of commits from the API
+    ///
+    /// Returns the commits and the URL of the next page, if there is one.
+    /// `link` is the `Link` header of the response.
+    fn parse_page(
+        &self,
+        repo: &Repo,
+        body: &Value,
+        link: Option<&str>,
+    ) -> Option<(Vec<NewCommit>, Option<String>)>;
+}
+
+/// Every supported forge configured from the settings
+pub fn forges(settings: &Settings) -> Vec<Box<dyn Forge>> {
+    vec![
+        Box::new(GitHub {
+            api_url: settings.github_api_url.clone(),
+        }),
+        Box::new(GitLab {
+            hosts: settings.gitlab_hosts.clone(),
+        }),
+        Box::new(Gitea {
+            hosts: settings.gitea_hosts.clone(),
+        }),
+        Box::new(Bitbucket {
+            api_url: String::from("https://api.bitbucket.org/2.0"),
+        }),
+    ]
+}
+
+/// Find the forge that hosts a repository URL
+pub fn find_forge<'a>(forges: &'a [Box<dyn Forge>], url: &str) -> Option<(&'a dyn Forge, Repo)> {
+    let repo = Repo::parse(url)?;
+    forges
+        .iter()
+        .find(|f| f.hosts(&repo))
+        .map(|f| (f.as_ref(), repo))
+}
+
+/// [GitHub](https://docs.github.com/en/rest/reference/repos#list-commits)
+pub struct GitHub {
+    /// Base URL of the API without a trailing slash
+    pub api_url: String,
+}
+
+impl Forge for GitHub {
+    fn hosts(&self, repo: &Repo) -> bool {
+        repo.host == "github.com" && repo.depth() == 2
+    }
+
+    fn commits_url(&self, repo: &Repo) -> String {
+        format!("{}/repos/{}/commits?per_page=100", self.api_url, repo.path)
+    }
+
+    fn auth_header(&self, token: &str) -> String {
+        format!("token {}", token)
+    }
+
+    fn parse_page(
+        &self,
+        repo: &Repo,
+        body: &Value,
+        link: Option<&str>,
+    ) -> Option<(Vec<NewCommit>, Option<String>)> {
+        let commits = body
+            .as_array()?
+            .iter()
+            .map(|c| {
+                let author = &c["commit"]["author"];
+                Some(NewCommit {
+                    repo: repo.url.clone(),
+                    sha: c["sha"].as_str()?.to_string(),
+                    author_name: string(&author["name"]),
+                    author_email: string(&author["email"]),
+                    author_login: c["author"]["login"].as_str().map(String::from),
+                    message: string(&c["commit"]["message"]),
+                    url: string(&c["html_url"]),
+                    committed_at: date(&author["date"])?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some((commits, link.and_then(next_link)))
+    }
+}
+
+/// [GitLab](https://docs.gitlab.com/ee/api/commits.html) and its
+/// self-hosted instances
+pub struct GitLab {
+    /// Hosts that run GitLab
+    pub hosts: Vec<String>,
+}
+
+impl Forge for GitLab {
+    fn hosts(&self, repo: &Repo) -> bool {
+        // GitLab allows nested groups so the path can be any depth
+        self.hosts.contains(&repo.host)
+    }
+
+    fn commits_url(&self, repo: &Repo) -> String {
+        format!(
+            "{}/api/v4/projects/{}/repository/commits?per_page=100",
+            repo.base,
+            repo.path.replace('/', "%2F")
+        )
+    }
+
+    fn parse_page(
+        &self,
+        repo: &Repo,
+        body: &Value,
+        link: Option<&str>,
+    ) -> Option<(Vec<NewCommit>, Option<String>)> {
+        let commits = body
+            .as_array()?
+            .iter()
+            .map(|c| {
+                Some(NewCommit {
+                    repo: repo.url.clone(),
+                    sha: c["id"].as_str()?.to_string(),
+                    author_name: string(&c["author_name"]),
+                    author_email: string(&c["author_email"]),
+                    // GitLab doesn't say which user made a commit
+                    author_login: None,
+                    message: string(&c["message"]),
+                    url: string(&c["web_url"]),
+                    committed_at: date(&c["authored_date"])?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some((commits, link.and_then(next_link)))
+    }
+}
+
+/// [Gitea](https://try.gitea.io/api/swagger#/repository/repoGetAllCommits)
+/// and Forgejo
+pub struct Gitea {
+    /// Hosts that run Gitea or Forgejo
+    pub hosts: Vec<String>,
+}
+
+impl Forge for Gitea {
+    fn hosts(&self, repo: &Repo) -> bool {
+        self.hosts.contains(&repo.host) && repo.depth() == 2
+    }
+
+    fn commits_url(&self, repo: &Repo) -> String {
+        format!(
+            "{}/api/v1/repos/{}/commits?limit=50&stat=false",
+            repo.base, repo.path
+        )
+    }
+
+    fn auth_header(&self, token: &str) -> String {
+        format!("token {}", token)
+    }
+
+    fn parse_page(
+        &self,
+        repo: &Repo,
+        body: &Value,
+        link: Option<&str>,
+    ) -> Option<(Vec<NewCommit>, Option<String>)> {
+        // Gitea copies the GitHub API closely
+        GitHub {
+            api_url: String::new(),
+        }
+        .parse_page(repo, body, link)
+    }
+}
+
+/// [Bitbucket](https://developer.atlassian.com/cloud/bitbucket/rest/api-group-commits/)
+pub struct Bitbucket {
+    /// Base URL of the API without a trailing slash
+    pub api_url: String,
+}
+
+impl Forge for Bitbucket {
+    fn hosts(&self, repo: &Repo) -> bool {
+        repo.host == "bitbucket.org" && repo.depth() == 2
+    }
+
+    fn commits_url(&self, repo: &Repo) -> String {
+        format!(
+            "{}/repositories/{}/commits?pagelen=100",
+            self.api_url, repo.path
+        )
+    }
+
+    fn parse_page(
+        &self,
+        repo: &Repo,
+        body: &Value,
+        _link: Option<&str>,
+    ) -> Option<(Vec<NewCommit>, Option<String>)> {
+        let commits = body["values"]
+            .as_array()?
+            .iter()
+            .map(|c| {
+                // The author is only given as `Name <email>`
+                let raw = c["author"]["raw"].as_str().unwrap_or_default();
+                let (name, email) = match raw.find('<') {
+                    Some(i) => (raw[..i].trim(), raw[i + 1..].trim_end_matches('>').trim()),
+                    None => (raw.trim(), ""),
+                };
+                Some(NewCommit {
+                    repo: repo.url.clone(),
+                    sha: c["hash"].as_str()?.to_string(),
+                    author_name: name.to_string(),
+                    author_email: email.to_string(),
+                    author_login: c["author"]["user"]["nickname"].as_str().map(String::from),
+                    message: string(&c["message"]),
+                    url: string(&c["links"]["html"]["href"]),
+                    committed_at: date(&c["date"])?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some((commits, body["next"].as_str().map(String::from)))
+    }
+}
+
+//# Helper Functions
+
+/// Get a JSON string or an empty string
+fn string(v: &Value) -> String {
+    v.as_str().unwrap_or_default().to_string()
+}
+
+/// Parse an RFC 3339 date from JSON into UTC
+fn date(v: &Value) -> Option<NaiveDateTime> {
+    Some(DateTime::parse_from_rfc3339(v.as_str()?).ok()?.naive_utc())
+}
+
+/// Get the URL of the next page from a `Link` header
+fn next_link(link: &str) -> Option<String> {
+    link.split(',')
+        .find(|part| part.contains("rel=\"next\""))
+        .and_then(|part| {
+            let start = part.find('<')? + 1;
+            let end = part.find('>')?;
+            Some(part[start..end].to_string())
+        })
+}
@@ -0,0 +1,367 @@
+//! HTTP handlers for commit identities and attribution
+//!
+//! A commit is attributed to a user when its author email, forge login or
+//! author name matches one of the user's verified identities. The user's
+//! account email and handle always count.
+
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use rocket::http::Status;
+use rocket::request::Form;
+use rocket::response::Redirect;
+
+use crate::audit;
+use crate::guards::*;
+use crate::models::{Project, User};
+use crate::roles::Permission;
+use crate::templates::FormError;
+use crate::ObservDbConn;
+
+use super::forge::Repo;
+use super::models::*;
+use super::templates::*;
+
+/// GET handler for `/users/<uid>/identities`
+///
+/// Lists the emails and aliases a user makes commits under.
+///
+/// Restricted to Mentors and the user themselves.
+#[get("/users/<uid>/identities?<e>")]
+pub fn identities(
+    conn: ObservDbConn,
+    l: UserGuard,
+    uid: i32,
+    e: Option<FormError>,
+) -> Result<IdentitiesTemplate, Status> {
+    if !l.0.can(Permission::VerifyIdentities) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get user from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    Ok(IdentitiesTemplate {
+        logged_in: Some(l.0),
+        identities: CommitIdentity::belonging_to(&user)
+            .load(&*conn)
+            .expect("Failed to get commit identities from database"),
+        user,
+        error: e,
+    })
+}
+
+/// A new commit identity
+///
+/// Used to parse the incoming form in `identities_post`
+/// and `unattributed_post`
+#[derive(FromForm)]
+pub struct IdentityForm {
+    user_id: Option<i32>,
+    kind: String,
+    value: String,
+}
+
+/// POST handler for `/users/<uid>/identities`
+///
+/// Adds an identity to the user. Identities added by Mentors are verified
+/// right away, otherwise they wait for a Mentor to verify them.
+///
+/// Restricted to Mentors and the user themselves.
+#[post("/users/<uid>/identities", data = "<form>")]
+pub fn identities_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    uid: i32,
+    form: Form<IdentityForm>,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::VerifyIdentities) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    let form = form.into_inner();
+    match add_identity(&*conn, &l.0, uid, &form.kind, &form.value) {
+        Ok(_) => Ok(Redirect::to(format!("/users/{}/identities", uid))),
+        Err(e) => Ok(Redirect::to(format!("/users/{}/identities?e={}", uid, e))),
+    }
+}
+
+/// PUT handler for `/users/<uid>/identities/<iid>`
+///
+/// Verifies an identity so it is used to attribute commits.
+#[put("/users/<uid>/identities/<iid>")]
+pub fn identity_verify(
+    conn: ObservDbConn,
+    l: Can<can::VerifyIdentities>,
+    uid: i32,
+    iid: i32,
+) -> Result<Redirect, Status> {
+    use crate::schema::commit_identities::dsl::*;
+
+    let old: CommitIdentity = commit_identities
+        .filter(id.eq(iid).and(user_id.eq(uid)))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get commit identity from database")
+        .ok_or(Status::NotFound)?;
+    update(commit_identities.find(iid))
+        .set(verified.eq(true))
+        .execute(&*conn)
+        .expect("Failed to update commit identity in database");
+    let new: CommitIdentity = commit_identities
+        .find(iid)
+        .first(&*conn)
+        .expect("Failed to get commit identity from database");
+    audit::updated(&*conn, Some(&l.0), "identity", iid, &old, &new);
+
+    audit_log!(
+        "User {} [{}] has verified commit identity {} of User {}",
+        l.0.id,
+        l.0.email,
+        iid,
+        uid
+    );
+
+    Ok(Redirect::to(format!("/users/{}/identities", uid)))
+}
+
+/// DELETE handler for `/users/<uid>/identities/<iid>`
+///
+/// Removes an identity from the user.
+///
+/// Restricted to Mentors and the user themselves.
+#[delete("/users/<uid>/identities/<iid>")]
+pub fn identity_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    uid: i32,
+    iid: i32,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::VerifyIdentities) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    use crate::schema::commit_identities::dsl::*;
+    let old: Option<CommitIdentity> = commit_identities
+        .filter(id.eq(iid).and(user_id.eq(uid)))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get commit identity from database");
+    delete(commit_identities.filter(id.eq(iid).and(user_id.eq(uid))))
+        .execute(&*conn)
+        .expect("Failed to delete commit identity from database");
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "identity", iid, &old);
+    }
+
+    audit_log!(
+        "User {} [{}] has removed commit identity {} of User {}",
+        l.0.id,
+        l.0.email,
+        iid,
+        uid
+    );
+
+    Ok(Redirect::to(format!("/users/{}/identities", uid)))
+}
+
+/// GET handler for `/commits/unattributed`
+///
+/// Lists the recent commits in each active project's repos that aren't
+/// attributed to any member of the project, so Mentors can see whose
+/// emails are missing and attribute them.
+#[get("/commits/unattributed?<e>")]
+pub fn unattributed(
+    conn: ObservDbConn,
+    l: Can<can::VerifyIdentities>,
+    e: Option<FormError>,
+) -> UnattributedTemplate {
+    use crate::projects::handlers::{project_repos, project_users};
+
+    let active_projects: Vec<Project> = {
+        use crate::schema::projects::dsl::*;
+        projects
+            .filter(active.eq(true).and(deleted_at.is_null()))
+            .order(name.asc())
+            .load(&*conn)
+            .expect("Failed to get projects from database")
+    };
+
+    let mut found = Vec::new();
+    for project in active_projects {
+        let members = project_users(&*conn, &project);
+        let ids: Vec<Identities> = members.iter().map(|m| Identities::of(&*conn, m)).collect();
+        let repos: Vec<String> = project_repos(&project)
+            .iter()
+            .filter_map(|r| Repo::parse(r))
+            .map(|r| r.url)
+            .collect();
+
+        let commits: Vec<Commit> = {
+            use crate::schema::commits::dsl::*;
+            commits
+                .filter(repo.eq_any(repos))
+                .order(committed_at.desc())
+                .limit(UNATTRIBUTED_LIMIT)
+                .load(&*conn)
+                .expect("Failed to get commits from database")
+        };
+
+        let missing: Vec<Commit> = commits
+            .into_iter()
+            .filter(|c| !ids.iter().any(|i| i.matches(c)))
+            .collect();
+        if !missing.is_empty() {
+            found.push(UnattributedProject {
+                project,
+                members,
+                commits: missing,
+            });
+        }
+    }
+
+    UnattributedTemplate {
+        logged_in: Some(l.0),
+        projects: found,
+        error: e,
+    }
+}
+
+/// POST handler for `/commits/unattributed`
+///
+/// Attributes commits by adding a verified identity to a user.
+#[post("/commits/unattributed", data = "<form>")]
+pub fn unattributed_post(
+    conn: ObservDbConn,
+    l: Can<can::VerifyIdentities>,
+    form: Form<IdentityForm>,
+) -> Redirect {
+    let form = form.into_inner();
+    let res = match form.user_id {
+        Some(uid) => add_identity(&*conn, &l.0, uid, &form.kind, &form.value),
+        None => Err(FormError::Other),
+    };
+    match res {
+        Ok(_) => Redirect::to("/commits/unattributed"),
+        Err(e) => Redirect::to(format!("/commits/unattributed?e={}", e)),
+    }
+}
+
+//# Helper Functions
+
+/// The most recent commits checked per project on the unattributed page
+const UNATTRIBUTED_LIMIT: i64 = 200;
+
+/// Add an identity to a user
+///
+/// Identities added by Mentors are verified, others are not.
+pub fn add_identity(
+    conn: &SqliteConnection,
+    editor: &User,
+    uid: i32,
+    ikind: &str,
+    ivalue: &str,
+) -> Result<CommitIdentity, FormError> {
+    use crate::schema::commit_identities::dsl::*;
+
+    let ivalue = ivalue.trim();
+    if !IDENTITY_KINDS.contains(&ikind) || ivalue.is_empty() {
+        return Err(FormError::Other);
+    }
+    if ikind == "email" && !ivalue.contains('@') {
+        return Err(FormError::Email);
+    }
+
+    // Values are compared case insensitively by the database
+    let taken = commit_identities
+        .filter(kind.eq(ikind).and(value.eq(ivalue)))
+        .count()
+        .get_result::<i64>(conn)
+        .expect("Failed to get commit identities from database");
+    if taken > 0 {
+        return Err(FormError::IdentityExists);
+    }
+
+    insert_into(commit_identities)
+        .values(&NewCommitIdentity {
+            user_id: uid,
+            kind: ikind.to_string(),
+            value: ivalue.to_string(),
+            verified: editor.can(Permission::VerifyIdentities),
+        })
+        .execute(conn)
+        .expect("Failed to insert commit identity into database");
+
+    audit_log!(
+        "User {} [{}] has added commit {} {} to User {}",
+        editor.id,
+        editor.email,
+        ikind,
+        ivalue,
+        uid
+    );
+
+    let identity: CommitIdentity = commit_identities
+        .filter(kind.eq(ikind).and(value.eq(ivalue)))
+        .first(conn)
+        .expect("Failed to get commit identity from database");
+    audit::created(conn, Some(editor), "identity", identity.id, &identity);
+    Ok(identity)
+}
+
+/// The identities that a user's commits are attributed by
+///
+/// Everything is lowercase so matching is case insensitive.
+pub struct Identities {
+    emails: Vec<String>,
+    logins: Vec<String>,
+    names: Vec<String>,
+}
+
+impl Identities {
+    /// Get the verified identities of a user along with their
+    /// account email and handle
+    pub fn of(conn: &SqliteConnection, user: &User) -> Self {
+        let mut ids = Self {
+            emails: vec![user.email.to_lowercase()],
+            logins: vec![user.handle.to_lowercase()],
+            names: Vec::new(),
+        };
+
+        let verified: Vec<CommitIdentity> = {
+            use crate::schema::commit_identities::dsl::*;
+            CommitIdentity::belonging_to(user)
+                .filter(verified.eq(true))
+                .load(conn)
+                .expect("Failed to get commit identities from database")
+        };
+        for i in verified {
+            let v = i.value.to_lowercase();
+            match i.kind.as_str() {
+                "email" => ids.emails.push(v),
+                "login" => ids.logins.push(v),
+                "name" => ids.names.push(v),
+                _ => (),
+            }
+        }
+
+        ids
+    }
+
+    /// Is the commit attributed to these identities?
+    pub fn matches(&self, c: &Commit) -> bool {
+        let has = |list: &[String], v: &str| !v.is_empty() && list.contains(&v.to_lowercase());
+
+        has(&self.emails, &c.author_email)
+            || c.author_login
+                .as_ref()
+                .map_or(false, |l| has(&self.logins, l))
+            || has(&self.names, &c.author_name)
+    }
+}
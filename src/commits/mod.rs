@@ -0,0 +1,24 @@
+//! Cache of the commits in project repositories
+//!
+//! Fetching commits from forges on every page view is slow, uses up the API
+//! rate limit, and breaks when the network is down. Instead a background
+//! worker periodically syncs the commits of every active project into the
+//! `commits` table and the handlers only ever read from there.
+//!
+//! See `forge` for the supported forges, `sync` for the worker and
+//! `Settings` for how to configure it.
+//!
+//! Commits are attributed to users by their emails and aliases,
+//! see `handlers`.
+//!
+//! ## Routes
+//! - `/users/<uid>/identities`
+//! - `/users/<uid>/identities/<iid>`
+//! - `/commits/unattributed`
+
+pub mod forge;
+pub mod handlers;
+pub mod models;
+pub mod sync;
+
+mod templates;
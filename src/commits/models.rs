@@ -0,0 +1,117 @@
+//! Models for cached commits
+//!
+//! Commits are stored in the `commits` table where each row is a commit in
+//! a repository. The `repo_syncs` table records when each repository was
+//! last synced. The `commit_identities` table holds the emails and aliases
+//! used to attribute commits to users.
+
+use chrono::NaiveDateTime;
+
+use crate::models::User;
+use crate::schema::*;
+
+/// Models a cached commit in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Serialize)]
+pub struct Commit {
+    /// ID of the commit in the cache
+    pub id: i32,
+    /// URL of the repository the commit is in
+    pub repo: String,
+    /// Hash of the commit
+    pub sha: String,
+    /// Name of the commit author
+    pub author_name: String,
+    /// Email of the commit author
+    pub author_email: String,
+    /// Username of the author on the forge, if it knows who they are
+    pub author_login: Option<String>,
+    /// Full commit message
+    pub message: String,
+    /// Link to the commit on the forge
+    pub url: String,
+    /// Time the commit was made
+    pub committed_at: NaiveDateTime,
+}
+
+impl Commit {
+    /// The first line of the commit message
+    pub fn summary(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+}
+
+/// Used to add a commit to the cache
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "commits"]
+pub struct NewCommit {
+    /// URL of the repository the commit is in
+    pub repo: String,
+    /// Hash of the commit
+    pub sha: String,
+    /// Name of the commit author
+    pub author_name: String,
+    /// Email of the commit author
+    pub author_email: String,
+    /// Username of the author on the forge, if it knows who they are
+    pub author_login: Option<String>,
+    /// Full commit message
+    pub message: String,
+    /// Link to the commit on the forge
+    pub url: String,
+    /// Time the commit was made
+    pub committed_at: NaiveDateTime,
+}
+
+/// Models the sync state of a repository in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Insertable, AsChangeset)]
+#[table_name = "repo_syncs"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct RepoSync {
+    /// URL of the repository
+    pub repo: String,
+    /// ETag of the last response from the forge
+    pub etag: Option<String>,
+    /// When the repository was last synced
+    pub synced_at: NaiveDateTime,
+    /// Page of older commits to continue from when the last sync
+    /// stopped at the page limit
+    pub resume_url: Option<String>,
+}
+
+/// The kinds of commit identity
+///
+/// - `email` matches the commit author's email
+/// - `login` matches the author's username on the forge
+/// - `name` matches the commit author's name
+pub const IDENTITY_KINDS: [&str; 3] = ["email", "login", "name"];
+
+/// Models an email or alias a user makes commits under
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+#[table_name = "commit_identities"]
+pub struct CommitIdentity {
+    /// ID of the identity
+    pub id: i32,
+    /// ID of the user the identity belongs to
+    pub user_id: i32,
+    /// What the value is, one of `IDENTITY_KINDS`
+    pub kind: String,
+    /// The email, login or name
+    pub value: String,
+    /// Only verified identities are used to attribute commits
+    pub verified: bool,
+}
+
+/// Used to add an identity to a user
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "commit_identities"]
+pub struct NewCommitIdentity {
+    /// ID of the user the identity belongs to
+    pub user_id: i32,
+    /// What the value is, one of `IDENTITY_KINDS`
+    pub kind: String,
+    /// The email, login or name
+    pub value: String,
+    /// Only verified identities are used to attribute commits
+    pub verified: bool,
+}
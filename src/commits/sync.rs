@@ -0,0 +1,355 @@
+//! Background sync of commits from forges
+//!
+//! The `Syncer` walks the repositories of every active project and stores
+//! any new commits in the `commits` table. See `forge` for the supported
+//! forges. It is careful with their APIs:
+//!
+//! - Requests send the `ETag` of the last response so unchanged repositories
+//!   come back as `304 Not Modified` and don't count against the rate limit.
+//! - Paging stops as soon as a commit that is already cached is seen.
+//! - At most `MAX_PAGES` pages are fetched per sync. When a repository has
+//!   more, the next sync picks up from the page it stopped at.
+//! - When a forge says the rate limit is used up the syncer pauses requests
+//!   to that host until it resets instead of hammering the API.
+//! - Requests time out after `TIMEOUT_SECONDS` so a forge that hangs can't
+//!   stall the sync.
+//!
+//! Errors are logged and never panic, a failed repository is just retried
+//! on the next sync.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use diesel::prelude::*;
+use diesel::{insert_or_ignore_into, replace_into};
+use http_req::request::Request;
+use http_req::response::Response;
+use http_req::uri::Uri;
+use log::{info, warn};
+
+use crate::models::Project;
+use crate::settings::Settings;
+
+use super::forge::*;
+use super::models::*;
+
+/// The most pages of commits fetched from a single repository per sync
+///
+/// With 100 commits a page a big repository takes a few syncs to fetch,
+/// 1000 commits each.
+const MAX_PAGES: usize = 10;
+
+/// How long to wait on a forge to connect or send the response
+const TIMEOUT_SECONDS: u64 = 30;
+
+/// The longest a forge can pause requests to it for
+const MAX_PAUSE_SECONDS: i64 = 24 * 60 * 60;
+
+/// Reasons syncing a repository can fail
+#[derive(Debug)]
+pub enum SyncError {
+    /// The URL is not a repository on a supported forge
+    Unsupported,
+    /// The request could not be made at all
+    Http(http_req::error::Error),
+    /// The forge responded with an unexpected status code
+    Status(u16),
+    /// The forge responded with something that wasn't the expected JSON
+    BadResponse,
+    /// The rate limit is used up until the given time
+    RateLimited(NaiveDateTime),
+    /// The database couldn't be read or written, often because it is busy
+    Database(diesel::result::Error),
+}
+
+impl From<http_req::error::Error> for SyncError {
+    fn from(e: http_req::error::Error) -> Self {
+        SyncError::Http(e)
+    }
+}
+
+impl From<diesel::result::Error> for SyncError {
+    fn from(e: diesel::result::Error) -> Self {
+        SyncError::Database(e)
+    }
+}
+
+/// Syncs commits from forge APIs into the database
+pub struct Syncer {
+    /// The forges repositories can be on
+    forges: Vec<Box<dyn Forge>>,
+    /// Tokens used to get a higher rate limit keyed by host
+    tokens: HashMap<String, String>,
+    /// No requests are made to a host before this time because of rate limiting
+    paused_until: HashMap<String, NaiveDateTime>,
+}
+
+impl Syncer {
+    /// Create a syncer from the site settings
+    pub fn new(settings: &Settings) -> Self {
+        let mut tokens = settings.forge_tokens.clone();
+        if let Some(t) = &settings.github_token {
+            tokens
+                .entry(String::from("github.com"))
+                .or_insert_with(|| t.clone());
+        }
+
+        Self {
+            forges: forges(settings),
+            tokens,
+            paused_until: HashMap::new(),
+        }
+    }
+
+    /// Sync the repositories of every active project
+    ///
+    /// Returns the number of new commits.
+    pub fn sync_all(&mut self, conn: &SqliteConnection) -> usize {
+        let active_projects = {
+            use crate::schema::projects::dsl::*;
+            projects
+                .filter(active.eq(true).and(deleted_at.is_null()))
+                .load::<Project>(conn)
+        };
+        let repos: HashSet<String> = match active_projects {
+            Ok(ps) => ps
+                .iter()
+                .filter_map(|p| serde_json::from_str::<Vec<String>>(&p.repos).ok())
+                .flatten()
+                .filter_map(|r| find_forge(&self.forges, &r).map(|(_, repo)| repo.url))
+                .collect(),
+            Err(e) => {
+                warn!("Failed to get projects to sync commits of: {:?}", e);
+                return 0;
+            }
+        };
+
+        let mut total = 0;
+        for repo in repos {
+            match self.sync_repo(conn, &repo) {
+                Ok(n) => total += n,
+                Err(SyncError::RateLimited(until)) => {
+                    warn!("Rate limited syncing commits of {} until {}", repo, until)
+                }
+                Err(e) => warn!("Failed to sync commits of {}: {:?}", repo, e),
+            }
+        }
+
+        if total > 0 {
+            info!("Synced {} new commits", total);
+        }
+        total
+    }
+
+    /// Sync the commits of a single repository
+    ///
+    /// Nothing is saved unless the whole sync succeeds so a repository is
+    /// never left with a gap in its commits.
+    ///
+    /// Returns the number of new commits.
+    pub fn sync_repo(
+        &mut self,
+        conn: &SqliteConnection,
+        repo_url: &str,
+    ) -> Result<usize, SyncError> {
+        let (forge, target) = find_forge(&self.forges, repo_url).ok_or(SyncError::Unsupported)?;
+        let auth = self.tokens.get(&target.host).map(|t| forge.auth_header(t));
+        let now = Utc::now().naive_utc();
+
+        let last: Option<RepoSync> = {
+            use crate::schema::repo_syncs::dsl::*;
+            repo_syncs.find(&target.url).first(conn).optional()?
+        };
+
+        let known: HashSet<String> = {
+            use crate::schema::commits::dsl::*;
+            commits
+                .filter(repo.eq(&target.url))
+                .select(sha)
+                .load(conn)?
+                .into_iter()
+                .collect()
+        };
+
+        let first = forge.commits_url(&target);
+        let api = origin(&first).map(str::to_lowercase);
+        let mut next = Some(first);
+        let mut resume = last.as_ref().and_then(|l| l.resume_url.clone());
+        let mut resuming = false;
+        let mut new_etag = None;
+        let mut new = Vec::new();
+
+        for page in 0..MAX_PAGES {
+            // New commits come first then the older ones left last time
+            let url = match (next.take(), resume.take()) {
+                (Some(u), r) => {
+                    resume = r;
+                    u
+                }
+                (None, Some(u)) => {
+                    resuming = true;
+                    u
+                }
+                (None, None) => break,
+            };
+            // Only the first page is cached by the forge
+            let if_none_match = if page == 0 {
+                last.as_ref().and_then(|l| l.etag.as_deref())
+            } else {
+                None
+            };
+
+            // Page URLs come from the response so the token is only sent
+            // back to the API it is for
+            let same_api = origin(&url).map(str::to_lowercase) == api;
+            let (res, body) = get(
+                &mut self.paused_until,
+                &target.host,
+                &url,
+                auth.as_deref().filter(|_| same_api),
+                if_none_match,
+            )?;
+            let status = u16::from(res.status_code());
+
+            if status == 304 {
+                continue;
+            }
+            if !res.status_code().is_success() {
+                return Err(SyncError::Status(status));
+            }
+
+            if page == 0 {
+                new_etag = res.headers().get("ETag").cloned();
+            }
+
+            let json: serde_json::Value =
+                serde_json::from_slice(&body).map_err(|_| SyncError::BadResponse)?;
+            let (page_commits, next_page) = forge
+                .parse_page(
+                    &target,
+                    &json,
+                    res.headers().get("Link").map(|l| l.as_str()),
+                )
+                .ok_or(SyncError::BadResponse)?;
+            next = next_page;
+
+            for nc in page_commits {
+                // Older pages shift when commits are pushed so they can
+                // repeat commits, which are ignored when saving
+                if !resuming && known.contains(&nc.sha) {
+                    next = None;
+                    break;
+                }
+                new.push(nc);
+            }
+        }
+
+        // Only move on to the new ETag once every page has been fetched,
+        // otherwise the next sync continues where this one stopped
+        let pending = next.or(resume);
+        if pending.is_some() {
+            new_etag = None;
+        }
+
+        let added = conn.transaction::<_, diesel::result::Error, _>(|| {
+            let added = {
+                use crate::schema::commits::dsl::*;
+                insert_or_ignore_into(commits).values(&new).execute(conn)?
+            };
+            {
+                use crate::schema::repo_syncs::dsl::*;
+                replace_into(repo_syncs)
+                    .values(&RepoSync {
+                        repo: target.url.clone(),
+                        etag: new_etag.or_else(|| last.and_then(|l| l.etag)),
+                        synced_at: now,
+                        resume_url: pending,
+                    })
+                    .execute(conn)?;
+            }
+            Ok(added)
+        })?;
+
+        Ok(added)
+    }
+}
+
+/// The scheme, host and port of a URL like `https://api.github.com`
+fn origin(url: &str) -> Option<&str> {
+    let start = url.find("://")? + 3;
+    let end = url[start..]
+        .find(|c| c == '/' || c == '?' || c == '#')
+        .map_or(url.len(), |i| start + i);
+    Some(&url[..end])
+}
+
+/// Make a GET request to a forge API
+///
+/// Also checks the rate limit headers and pauses requests to the host
+/// if needed.
+fn get(
+    paused_until: &mut HashMap<String, NaiveDateTime>,
+    host: &str,
+    url: &str,
+    auth: Option<&str>,
+    etag: Option<&str>,
+) -> Result<(Response, Vec<u8>), SyncError> {
+    if let Some(until) = paused_until.get(host).copied() {
+        if until > Utc::now().naive_utc() {
+            return Err(SyncError::RateLimited(until));
+        }
+        paused_until.remove(host);
+    }
+
+    let uri: Uri = url.parse()?;
+    let timeout = Some(std::time::Duration::from_secs(TIMEOUT_SECONDS));
+    let mut req = Request::new(&uri);
+    req.connect_timeout(timeout)
+        .read_timeout(timeout)
+        .timeout(timeout)
+        .header("User-Agent", "Observatory")
+        .header("Accept", "application/json");
+    if let Some(a) = auth {
+        req.header("Authorization", a);
+    }
+    if let Some(e) = etag {
+        req.header("If-None-Match", e);
+    }
+
+    let mut body = Vec::new();
+    let res = req.send(&mut body)?;
+
+    // GitHub uses the `X-` headers while GitLab doesn't
+    let now = Utc::now().naive_utc();
+    let headers = res.headers();
+    let header = |name: &str| {
+        headers
+            .get(&format!("X-{}", name))
+            .or_else(|| headers.get(name))
+            .and_then(|s| s.trim().parse::<i64>().ok())
+    };
+    let latest = now + chrono::Duration::seconds(MAX_PAUSE_SECONDS);
+    let retry_after = header("Retry-After")
+        .map(|s| now + chrono::Duration::seconds(s.max(0).min(MAX_PAUSE_SECONDS)));
+    let reset = if header("RateLimit-Remaining") == Some(0) {
+        // Without a reset time that makes sense wait out an hourly limit
+        let reset = header("RateLimit-Reset")
+            .and_then(|s| Utc.timestamp_opt(s, 0).single())
+            .map_or(now + chrono::Duration::hours(1), |t| t.naive_utc());
+        Some(reset.min(latest))
+    } else {
+        None
+    };
+
+    if let Some(until) = retry_after.or(reset) {
+        paused_until.insert(host.to_string(), until);
+        // A rate limited request fails, otherwise the response is fine
+        // and only the following requests wait
+        let status = u16::from(res.status_code());
+        if status == 403 || status == 429 {
+            return Err(SyncError::RateLimited(until));
+        }
+    }
+
+    Ok((res, body))
+}
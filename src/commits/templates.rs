@@ -0,0 +1,40 @@
+//! HTML templates for commit identities and attribution
+
+use super::models::{Commit, CommitIdentity};
+use crate::models::{Project, User};
+#[allow(unused_imports)]
+use crate::templates::{filters, FormError, OptUser};
+
+/// Commit identities page template
+///
+/// HTML File: `commits/identities.html`
+///
+/// Lists a user's commit emails and aliases and has the form to add one.
+#[derive(Template)]
+#[template(path = "commits/identities.html")]
+pub struct IdentitiesTemplate {
+    pub logged_in: OptUser,
+    pub user: User,
+    pub identities: Vec<CommitIdentity>,
+    pub error: Option<FormError>,
+}
+
+/// A project with commits that aren't attributed to its members
+pub struct UnattributedProject {
+    pub project: Project,
+    pub members: Vec<User>,
+    pub commits: Vec<Commit>,
+}
+
+/// Unattributed commits page template
+///
+/// HTML File: `commits/unattributed.html`
+///
+/// Lists the commits that aren't attributed to a member of their project.
+#[derive(Template)]
+#[template(path = "commits/unattributed.html")]
+pub struct UnattributedTemplate {
+    pub logged_in: OptUser,
+    pub projects: Vec<UnattributedProject>,
+    pub error: Option<FormError>,
+}
@@ -25,20 +25,10 @@ impl Fairing for DatabaseCreate {
     }
 
     fn on_launch(&self, rocket: &Rocket) {
-        // Get the database url from the config
-        let conn_url = rocket
-            .config()
-            .get_table("databases")
-            .unwrap()
-            .get("sqlite_observ")
-            .unwrap()
-            .get("url")
-            .unwrap()
-            .as_str()
-            .unwrap();
+        let conn_url = db_url(rocket);
 
         use diesel::prelude::*;
-        let conn = SqliteConnection::establish(conn_url)
+        let conn = SqliteConnection::establish(&conn_url)
             .expect("Failed to connect to database in DatabaseCreate");
 
         // Run the embedded migrations
@@ -61,24 +51,14 @@ impl Fairing for AdminCheck {
     }
 
     fn on_launch(&self, rocket: &Rocket) {
-        // Get the database url from the config
-        let conn_url = rocket
-            .config()
-            .get_table("databases")
-            .unwrap()
-            .get("sqlite_observ")
-            .unwrap()
-            .get("url")
-            .unwrap()
-            .as_str()
-            .unwrap();
+        let conn_url = db_url(rocket);
 
         // Import needed things
         use crate::models::{NewUser, User};
         use crate::schema::users::dsl::*;
         use diesel::prelude::*;
 
-        let conn = SqliteConnection::establish(conn_url)
+        let conn = SqliteConnection::establish(&conn_url)
             .expect("Failed to connect to database in AdminCheck");
 
         let admin: User = users
@@ -97,17 +77,14 @@ impl Fairing for AdminCheck {
                 pass
             );
 
-            let (phash, psalt) = hash_password(pass);
-
             // Needs to be a NewUser for set() so create it
             let nu = NewUser {
                 real_name: admin.real_name,
                 handle: admin.handle,
-                password_hash: phash,
-                salt: psalt,
+                password_hash: hash_password(pass),
                 bio: admin.bio,
                 email: admin.email,
-                tier: admin.tier,
+                role: admin.role,
                 active: admin.active,
                 mmost: admin.mmost,
                 former: admin.former,
@@ -123,6 +100,164 @@ impl Fairing for AdminCheck {
     }
 }
 
+/// Load Observatory's settings at attach
+///
+/// Reads the `Settings` from the Rocket config and puts them in
+/// Rocket's managed state so handlers can get them with `State<Settings>`.
+/// The `Outbox` and `TokenKey` made from the settings are managed as well.
+///
+/// Launching fails if the `time_zone` setting isn't a time zone.
+pub struct LoadSettings;
+
+impl Fairing for LoadSettings {
+    fn info(&self) -> Info {
+        Info {
+            name: "Load Observatory settings",
+            kind: Kind::Attach,
+        }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> std::result::Result<Rocket, Rocket> {
+        use crate::auth::tokens::TokenKey;
+        use crate::mailer::Outbox;
+        use crate::settings::Settings;
+        let settings = Settings::from_config(rocket.config());
+        let outbox = Outbox::from_settings(&settings);
+        let key = TokenKey::new(settings.token_secret.as_deref());
+        if crate::tz::zone(&settings.time_zone).is_none() {
+            log::error!("Unknown time zone {} in the settings", settings.time_zone);
+            return Err(rocket);
+        }
+        if settings.site_url.is_none() {
+            log::warn!("site_url isn't set so emails with links won't be sent");
+        }
+        Ok(rocket.manage(settings).manage(outbox).manage(key))
+    }
+}
+
+/// Start the background commit sync at launch
+///
+/// Spawns a thread with its own database connection that syncs the commits
+/// of every project every `commit_sync_minutes`. See `crate::commits::sync`.
+pub struct CommitSync;
+
+impl Fairing for CommitSync {
+    fn info(&self) -> Info {
+        Info {
+            name: "Background Commit Sync",
+            kind: Kind::Launch,
+        }
+    }
+
+    fn on_launch(&self, rocket: &Rocket) {
+        use crate::commits::sync::Syncer;
+        use crate::settings::Settings;
+        use std::time::Duration;
+
+        let settings = Settings::from_config(rocket.config());
+        if settings.commit_sync_minutes <= 0 {
+            return;
+        }
+
+        let conn_url = db_url(rocket);
+
+        std::thread::spawn(move || {
+            let conn = background_conn(&conn_url, "CommitSync");
+
+            let mut syncer = Syncer::new(&settings);
+            loop {
+                syncer.sync_all(&conn);
+                std::thread::sleep(Duration::from_secs(
+                    settings.commit_sync_minutes as u64 * 60,
+                ));
+            }
+        });
+    }
+}
+
+/// Start purging the trash at launch
+///
+/// Spawns a thread with its own database connection that purges anything
+/// that has been in the trash for longer than `trash_days` every hour.
+/// See `crate::trash`.
+pub struct TrashPurge;
+
+impl Fairing for TrashPurge {
+    fn info(&self) -> Info {
+        Info {
+            name: "Background Trash Purge",
+            kind: Kind::Launch,
+        }
+    }
+
+    fn on_launch(&self, rocket: &Rocket) {
+        use crate::settings::Settings;
+        use crate::trash::handlers::purge_expired;
+        use std::time::Duration;
+
+        let settings = Settings::from_config(rocket.config());
+        if settings.trash_days <= 0 {
+            return;
+        }
+
+        let conn_url = db_url(rocket);
+
+        std::thread::spawn(move || {
+            let conn = background_conn(&conn_url, "TrashPurge");
+
+            loop {
+                let now = chrono::offset::Utc::now().naive_utc();
+                let purged = purge_expired(&conn, settings.trash_days, now);
+                if purged > 0 {
+                    audit_log!("Purged {} things from the trash", purged);
+                }
+                std::thread::sleep(Duration::from_secs(60 * 60));
+            }
+        });
+    }
+}
+
+/// Create the meetings on group schedules ahead of time
+///
+/// Every hour the meetings for the coming weeks are created, see
+/// `crate::groups::handlers::schedule_meetings`.
+pub struct MeetingSchedule;
+
+impl Fairing for MeetingSchedule {
+    fn info(&self) -> Info {
+        Info {
+            name: "Background Meeting Scheduling",
+            kind: Kind::Launch,
+        }
+    }
+
+    fn on_launch(&self, rocket: &Rocket) {
+        use crate::groups::handlers::schedule_all_meetings;
+        use crate::settings::Settings;
+        use std::time::Duration;
+
+        let settings = Settings::from_config(rocket.config());
+        if settings.meeting_weeks_ahead <= 0 {
+            return;
+        }
+
+        let conn_url = db_url(rocket);
+
+        std::thread::spawn(move || {
+            let conn = background_conn(&conn_url, "MeetingSchedule");
+
+            loop {
+                let now = chrono::offset::Utc::now().naive_utc();
+                let created = schedule_all_meetings(&conn, &settings, now);
+                if created > 0 {
+                    audit_log!("Scheduled {} group meetings", created);
+                }
+                std::thread::sleep(Duration::from_secs(60 * 60));
+            }
+        });
+    }
+}
+
 /// Check for the config file at attach
 ///
 /// If there is no config file specified Rocket is going to fallback to defaults.
@@ -216,6 +351,34 @@ impl Fairing for ConfigWrite {
     }
 }
 
+/// The database url from the config
+fn db_url(rocket: &Rocket) -> String {
+    rocket
+        .config()
+        .get_table("databases")
+        .unwrap()
+        .get("sqlite_observ")
+        .unwrap()
+        .get("url")
+        .unwrap()
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Connects to the database for a background thread
+///
+/// The connection waits on the webserver instead of failing when the
+/// database is busy. `name` is the fairing, for the panic messages.
+fn background_conn(url: &str, name: &str) -> diesel::SqliteConnection {
+    use diesel::prelude::*;
+    let conn = SqliteConnection::establish(url)
+        .unwrap_or_else(|_| panic!("Failed to connect to database in {}", name));
+    conn.execute("PRAGMA busy_timeout = 5000")
+        .unwrap_or_else(|_| panic!("Failed to set busy timeout in {}", name));
+    conn
+}
+
 /// Generates a new secret key using Ring
 fn gen_secret() -> String {
     use base64::encode;
@@ -0,0 +1,362 @@
+//! HTTP handlers for calendar feeds
+
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use icalendar::Component;
+use rocket::http::{ContentType, Status};
+use rocket::request::Form;
+use rocket::response::{Content, Redirect};
+use rocket::State;
+
+use crate::api::handlers::{gen_token, hash_token};
+use crate::audit;
+use crate::calendar::handlers::{events_ical, to_utc};
+use crate::guards::*;
+use crate::models::{Group, Meeting, Project, RelationGroupUser, User};
+use crate::projects::handlers::project_milestones;
+use crate::roles::Permission;
+use crate::settings::Settings;
+use crate::tz::{self, Tz};
+use crate::users::handlers::{user_groups, user_projects};
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::templates::*;
+
+/// GET handler for `/users/<uid>/feeds`
+///
+/// Lists the calendar feeds of a user.
+///
+/// Restricted to Admins and the user themselves.
+#[get("/users/<uid>/feeds")]
+pub fn feeds(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+) -> Result<FeedsTemplate, Status> {
+    feeds_page(&*conn, l.0, &settings.site_zone(), uid, None)
+}
+
+/// The group of a new feed, `None` for the user's own feed
+///
+/// Used to parse the incoming form in `feeds_post`
+#[derive(FromForm)]
+pub struct FeedForm {
+    group_id: Option<i32>,
+}
+
+/// POST handler for `/users/<uid>/feeds`
+///
+/// Creates a new calendar feed for the user and shows its link.
+/// This is the only time the link can be seen.
+///
+/// Restricted to Admins and the user themselves.
+/// Group feeds are only for the groups the user can follow.
+#[post("/users/<uid>/feeds", data = "<form>")]
+pub fn feeds_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    site: SiteUrl,
+    settings: State<Settings>,
+    uid: i32,
+    form: Form<FeedForm>,
+) -> Result<FeedsTemplate, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+    let user = find_user(&*conn, uid).ok_or(Status::NotFound)?;
+
+    let gid = form.into_inner().group_id;
+    if let Some(gid) = gid {
+        let g = find_group(&*conn, gid).ok_or(Status::NotFound)?;
+        if !can_follow(&*conn, &user, &g) {
+            return Err(DENIED);
+        }
+    }
+
+    let token = gen_token();
+    use crate::schema::calendar_feeds::dsl::*;
+    insert_into(calendar_feeds)
+        .values(&NewCalendarFeed {
+            user_id: uid,
+            group_id: gid,
+            token_hash: hash_token(&token),
+            created_at: chrono::offset::Utc::now().naive_utc(),
+        })
+        .execute(&*conn)
+        .expect("Failed to insert calendar feed into database");
+    let f: CalendarFeed = calendar_feeds
+        .filter(token_hash.eq(hash_token(&token)))
+        .first(&*conn)
+        .expect("Failed to get calendar feed from database");
+    audit::created(&*conn, Some(&l.0), "feed", f.id, &f);
+
+    audit_log!(
+        "User {} [{}] has created a calendar feed for User {}",
+        l.0.id,
+        l.0.email,
+        uid
+    );
+
+    let url = match gid {
+        Some(g) => format!("{}/groups/{}/calendar.ics?token={}", site.0, g, token),
+        None => format!("{}/users/{}/calendar.ics?token={}", site.0, uid, token),
+    };
+    feeds_page(&*conn, l.0, &settings.site_zone(), uid, Some(url))
+}
+
+/// DELETE handler for `/users/<uid>/feeds/<fid>`
+///
+/// Revokes a calendar feed.
+///
+/// Restricted to Admins and the user themselves.
+#[delete("/users/<uid>/feeds/<fid>")]
+pub fn feed_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    uid: i32,
+    fid: i32,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    use crate::schema::calendar_feeds::dsl::*;
+    let old: Option<CalendarFeed> = calendar_feeds
+        .filter(id.eq(fid).and(user_id.eq(uid)))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get calendar feed from database");
+    delete(calendar_feeds.filter(id.eq(fid).and(user_id.eq(uid))))
+        .execute(&*conn)
+        .expect("Failed to delete calendar feed from database");
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "feed", fid, &old);
+    }
+
+    audit_log!(
+        "User {} [{}] has revoked calendar feed {} of User {}",
+        l.0.id,
+        l.0.email,
+        fid,
+        uid
+    );
+
+    Ok(Redirect::to(format!("/users/{}/feeds", uid)))
+}
+
+/// GET handler for `/users/<uid>/calendar.ics?<token>`
+///
+/// The user's own calendar feed with the events on the calendar, the
+/// meetings of their groups and the milestones of their projects.
+///
+/// Needs the token of one of the user's feeds instead of a login.
+#[get("/users/<uid>/calendar.ics?<token>")]
+pub fn user_calendar(
+    conn: ObservDbConn,
+    settings: State<Settings>,
+    uid: i32,
+    token: String,
+) -> Option<Content<String>> {
+    let user = feed_user(&*conn, &token, Some(uid), None)?;
+
+    let mut ical = icalendar::Calendar::new();
+    ical.name(&format!("RCOS Calendar for {}", user.real_name));
+    ical.extend(events_ical(&*conn, &settings.site_zone()));
+    for g in user_groups(&*conn, &user) {
+        ical.extend(meetings_ical(&*conn, &g));
+    }
+    for p in user_projects(&*conn, &user) {
+        ical.extend(milestones_ical(&*conn, &p));
+    }
+
+    Some(Content(ContentType::Calendar, ical.to_string()))
+}
+
+/// GET handler for `/groups/<gid>/calendar.ics?<token>`
+///
+/// The meetings of a group.
+///
+/// Needs the token of a feed of the group instead of a login, and the
+/// user it belongs to must still be able to follow the group.
+#[get("/groups/<gid>/calendar.ics?<token>")]
+pub fn group_calendar(conn: ObservDbConn, gid: i32, token: String) -> Option<Content<String>> {
+    let g = find_group(&*conn, gid)?;
+    let user = feed_user(&*conn, &token, None, Some(gid))?;
+    if !can_follow(&*conn, &user, &g) {
+        return None;
+    }
+
+    let mut ical = icalendar::Calendar::new();
+    ical.name(&format!("{} Meetings", g.name));
+    ical.extend(meetings_ical(&*conn, &g));
+
+    Some(Content(ContentType::Calendar, ical.to_string()))
+}
+
+fn feeds_page(
+    conn: &SqliteConnection,
+    l: User,
+    site: &Tz,
+    uid: i32,
+    new_url: Option<String>,
+) -> Result<FeedsTemplate, Status> {
+    if !l.can(Permission::ManageUsers) && l.id != uid {
+        return Err(DENIED);
+    }
+    let user = find_user(conn, uid).ok_or(Status::NotFound)?;
+
+    let feeds = CalendarFeed::belonging_to(&user)
+        .load::<CalendarFeed>(conn)
+        .expect("Failed to get calendar feeds from database")
+        .into_iter()
+        .map(|f| {
+            let g = f.group_id.and_then(|gid| find_group(conn, gid));
+            (f, g)
+        })
+        .collect();
+    let groups = {
+        use crate::schema::groups::dsl::*;
+        groups
+            .filter(deleted_at.is_null())
+            .order(name.asc())
+            .load::<Group>(conn)
+            .expect("Failed to get groups from database")
+            .into_iter()
+            .filter(|g| can_follow(conn, &user, g))
+            .collect()
+    };
+
+    Ok(FeedsTemplate {
+        zone: tz::of(Some(&l), site),
+        logged_in: Some(l),
+        user,
+        feeds,
+        groups,
+        new_url,
+    })
+}
+
+//# Helper Functions
+
+/// Can the user have a feed of the group?
+///
+/// Members and the mentor of a group can, as can those who manage groups.
+pub fn can_follow(conn: &SqliteConnection, user: &User, g: &Group) -> bool {
+    use crate::schema::relation_group_user::dsl::*;
+    user.can(Permission::ManageGroup)
+        || user.id == g.owner_id
+        || relation_group_user
+            .filter(group_id.eq(g.id).and(user_id.eq(user.id)))
+            .first::<RelationGroupUser>(conn)
+            .optional()
+            .expect("Failed to get relations from database")
+            .is_some()
+}
+
+/// Find the user a feed token belongs to
+///
+/// The feed must belong to `uid` if it is given. It must be the user's
+/// own feed when `gid` is `None`, or a feed of that group otherwise.
+/// Also records that the feed has been used.
+fn feed_user(
+    conn: &SqliteConnection,
+    token: &str,
+    uid: Option<i32>,
+    gid: Option<i32>,
+) -> Option<User> {
+    use crate::schema::calendar_feeds::dsl::*;
+
+    let mut query = calendar_feeds
+        .filter(token_hash.eq(hash_token(token)))
+        .into_boxed();
+    if let Some(uid) = uid {
+        query = query.filter(user_id.eq(uid));
+    }
+    query = match gid {
+        Some(gid) => query.filter(group_id.eq(gid)),
+        None => query.filter(group_id.is_null()),
+    };
+    let f: CalendarFeed = query
+        .first(conn)
+        .optional()
+        .expect("Failed to get calendar feed from database")?;
+
+    update(calendar_feeds.find(f.id))
+        .set(last_used.eq(chrono::offset::Utc::now().naive_utc()))
+        .execute(conn)
+        .expect("Failed to update calendar feed in database");
+
+    find_user(conn, f.user_id)
+}
+
+/// The meetings of a group for the ICalendar export
+///
+/// Cancelled meetings are kept so calendar apps show them as cancelled.
+fn meetings_ical(conn: &SqliteConnection, g: &Group) -> Vec<icalendar::Event> {
+    use crate::schema::meetings::dsl::*;
+    meetings
+        .filter(group_id.eq(g.id))
+        .order(happened_at.asc())
+        .load::<Meeting>(conn)
+        .expect("Failed to get meetings from database")
+        .iter()
+        .map(|m| {
+            let mut e = icalendar::Event::new();
+            e.uid(&format!("meeting-{}@observatory", m.id))
+                .summary(&format!("{} Meeting", g.name))
+                .class(icalendar::Class::Public)
+                .starts(to_utc(&m.happened_at))
+                // Meetings end when their attendance code stops being accepted
+                .ends(to_utc(&m.code_expires.max(m.happened_at)))
+                .append_property(
+                    icalendar::Property::new("LOCATION", m.location.as_deref().unwrap_or_default())
+                        .done(),
+                );
+            if m.cancelled {
+                e.status(icalendar::EventStatus::Cancelled);
+            }
+            e.done()
+        })
+        .collect()
+}
+
+/// The milestones of a project for the ICalendar export
+fn milestones_ical(conn: &SqliteConnection, p: &Project) -> Vec<icalendar::Event> {
+    project_milestones(conn, p)
+        .iter()
+        .map(|m| {
+            let mut e = icalendar::Event::new();
+            e.uid(&format!("milestone-{}@observatory", m.id))
+                .summary(&format!("{}: {}", p.name, m.title))
+                .class(icalendar::Class::Public)
+                .starts(to_utc(&m.due_at))
+                .ends(to_utc(&m.due_at));
+            if let Some(d) = &m.description {
+                e.description(d);
+            }
+            e.done()
+        })
+        .collect()
+}
+
+fn find_user(conn: &SqliteConnection, uid: i32) -> Option<User> {
+    use crate::schema::users::dsl::*;
+    users
+        .find(uid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get user from database")
+}
+
+fn find_group(conn: &SqliteConnection, gid: i32) -> Option<Group> {
+    use crate::schema::groups::dsl::*;
+    groups
+        .find(gid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get group from database")
+}
@@ -0,0 +1,22 @@
+//! Calendar feeds
+//!
+//! Secret links to ICalendar feeds that calendar apps can subscribe to
+//! without logging in. A user's own feed has the events on the calendar,
+//! the meetings of every group they are in, including the Large Group,
+//! and the milestones of every project they are on.
+//! A group feed has the meetings of one group.
+//!
+//! Each link has its own token. Only a hash of the token is stored, so a
+//! link can only be copied when it is made. Revoking a link stops it
+//! working, and group feeds stop working when the user leaves the group.
+//!
+//! ## Routes
+//! - `/users/<uid>/feeds`
+//! - `/users/<uid>/feeds/<fid>`
+//! - `/users/<uid>/calendar.ics?<token>`
+//! - `/groups/<gid>/calendar.ics?<token>`
+
+pub mod handlers;
+pub mod models;
+
+mod templates;
@@ -0,0 +1,42 @@
+//! Models for calendar feeds
+//!
+//! Feeds are stored in the `calendar_feeds` table where each row is a
+//! link belonging to a user. Only a hash of the token is stored.
+
+use chrono::NaiveDateTime;
+
+use crate::models::User;
+use crate::schema::*;
+
+/// Models a calendar feed in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+pub struct CalendarFeed {
+    /// ID of the feed
+    pub id: i32,
+    /// ID of the user the feed belongs to
+    pub user_id: i32,
+    /// ID of the group for a group feed, `None` for the user's own feed
+    pub group_id: Option<i32>,
+    /// SHA-256 hash of the token
+    #[serde(skip)]
+    pub token_hash: String,
+    /// When the feed was created
+    pub created_at: NaiveDateTime,
+    /// When the feed was last fetched, if ever
+    pub last_used: Option<NaiveDateTime>,
+}
+
+/// Used to create a new calendar feed in the database
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "calendar_feeds"]
+pub struct NewCalendarFeed {
+    /// ID of the user the feed belongs to
+    pub user_id: i32,
+    /// ID of the group for a group feed, `None` for the user's own feed
+    pub group_id: Option<i32>,
+    /// SHA-256 hash of the token
+    pub token_hash: String,
+    /// When the feed was created
+    pub created_at: NaiveDateTime,
+}
@@ -0,0 +1,29 @@
+//! HTML templates for calendar feeds
+
+use super::models::CalendarFeed;
+use crate::models::{Group, User};
+#[allow(unused_imports)]
+use crate::templates::{filters, OptUser};
+use crate::tz::Tz;
+
+/// Calendar feeds page template
+///
+/// HTML File: `feeds/feeds.html`
+///
+/// Lists a user's calendar feeds and has the form to create a new one.
+#[derive(Template)]
+#[template(path = "feeds/feeds.html")]
+pub struct FeedsTemplate {
+    pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
+    pub user: User,
+    /// The feeds with the group of each group feed
+    pub feeds: Vec<(CalendarFeed, Option<Group>)>,
+    /// The groups the user can have a feed of
+    pub groups: Vec<Group>,
+    /// The link to a feed that was just created
+    ///
+    /// This is the only time the link is ever shown.
+    pub new_url: Option<String>,
+}
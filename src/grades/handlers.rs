@@ -0,0 +1,334 @@
+//! HTTP handlers for grades
+//!
+//! A grade is made of four parts: attendance at small group meetings,
+//! attendance at large group meetings (group 0), attendance at events and
+//! commits. The semester's rubric weighs the parts into a percentage and
+//! turns that into a grade.
+
+use diesel::prelude::*;
+use diesel::replace_into;
+use rocket::http::{ContentType, Status};
+use rocket::request::Form;
+use rocket::response::{Content, Redirect};
+use rocket::State;
+
+use crate::audit;
+use crate::guards::*;
+use crate::models::{Attendance, Event, Meeting, Semester, User};
+use crate::semesters::handlers::{find_semester, semester_users};
+use crate::settings::Settings;
+use crate::templates::FormError;
+use crate::tz::Tz;
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::templates::*;
+
+/// GET handler for `/semesters/<sid>/grades`
+///
+/// Report of the grades of everyone enrolled in a semester along with the
+/// rubric. `sort` is the column to sort by.
+///
+/// Restricted to Admins.
+#[get("/semesters/<sid>/grades?<sort>&<desc>&<e>")]
+pub fn grade_report(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    settings: State<Settings>,
+    sid: i32,
+    sort: Option<String>,
+    desc: Option<bool>,
+    e: Option<FormError>,
+) -> Option<GradeReportTemplate> {
+    let semester = find_semester(&*conn, sid)?;
+    let rubric = rubric_for(&*conn, sid);
+    let sort = sort.unwrap_or_else(|| String::from("name"));
+    let desc = desc.unwrap_or(false);
+
+    Some(GradeReportTemplate {
+        logged_in: Some(l.0),
+        rows: grade_rows(
+            &*conn,
+            &semester,
+            &rubric,
+            &settings.site_zone(),
+            &sort,
+            desc,
+        ),
+        semester,
+        rubric,
+        sort,
+        desc,
+        error: e,
+    })
+}
+
+/// GET handler for `/semesters/<sid>/grades.csv`
+///
+/// The grade report as a CSV file.
+///
+/// Restricted to Admins.
+#[get("/semesters/<sid>/grades.csv")]
+pub fn grade_report_csv(
+    conn: ObservDbConn,
+    _l: Can<can::ManageSemesters>,
+    settings: State<Settings>,
+    sid: i32,
+) -> Option<Content<String>> {
+    let semester = find_semester(&*conn, sid)?;
+    let rubric = rubric_for(&*conn, sid);
+
+    let mut csv = String::from(
+        "handle,name,email,small_group_attended,small_group_needed,\
+         large_group_attended,large_group_needed,events_attended,events_needed,\
+         commits,commits_needed,score,grade\n",
+    );
+    let site = settings.site_zone();
+    for row in grade_rows(&*conn, &semester, &rubric, &site, "name", false) {
+        let g = &row.grade;
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{:.1},{}\n",
+            csv_field(&row.user.handle),
+            csv_field(&row.user.real_name),
+            csv_field(&row.user.email),
+            g.small_group.done,
+            g.small_group.needed,
+            g.large_group.done,
+            g.large_group.needed,
+            g.events.done,
+            g.events.needed,
+            g.commits.done,
+            g.commits.needed,
+            g.score,
+            csv_field(&g.letter),
+        ));
+    }
+
+    Some(Content(ContentType::CSV, csv))
+}
+
+/// PUT handler for `/semesters/<sid>/rubric`
+///
+/// Changes how grades are computed in a semester.
+///
+/// Restricted to Admins.
+#[put("/semesters/<sid>/rubric", data = "<form>")]
+pub fn rubric_put(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    sid: i32,
+    form: Form<RubricForm>,
+) -> Result<Redirect, Status> {
+    find_semester(&*conn, sid).ok_or(Status::NotFound)?;
+
+    let f = form.into_inner();
+    let rubric = Rubric {
+        semester_id: sid,
+        small_group_weight: f.small_group_weight,
+        large_group_weight: f.large_group_weight,
+        event_weight: f.event_weight,
+        commit_weight: f.commit_weight,
+        commits_needed: f.commits_needed,
+        thresholds: f.thresholds.trim().to_string(),
+    };
+    let weights = [
+        rubric.small_group_weight,
+        rubric.large_group_weight,
+        rubric.event_weight,
+        rubric.commit_weight,
+    ];
+    if weights.iter().any(|&w| w < 0) || rubric.commits_needed < 0 || rubric.thresholds().is_none()
+    {
+        return Ok(Redirect::to(format!(
+            "/semesters/{}/grades?e={}",
+            sid,
+            FormError::Other
+        )));
+    }
+
+    let old = rubric_for(&*conn, sid);
+    use crate::schema::rubrics::dsl::*;
+    replace_into(rubrics)
+        .values(&rubric)
+        .execute(&*conn)
+        .expect("Failed to save rubric to database");
+
+    audit_log!(
+        "User {} [{}] has changed the rubric of Semester {}",
+        l.0.id,
+        l.0.email,
+        sid
+    );
+    audit::updated(&*conn, Some(&l.0), "rubric", sid, &old, &rubric);
+
+    Ok(Redirect::to(format!("/semesters/{}/grades", sid)))
+}
+
+/// A changed rubric
+///
+/// Used to parse the incoming form in `rubric_put`
+#[derive(FromForm)]
+pub struct RubricForm {
+    small_group_weight: i32,
+    large_group_weight: i32,
+    event_weight: i32,
+    commit_weight: i32,
+    commits_needed: i32,
+    thresholds: String,
+}
+
+//# Helper Functions
+
+/// The rubric of a semester, or the default if it hasn't set one
+pub fn rubric_for(conn: &SqliteConnection, sid: i32) -> Rubric {
+    use crate::schema::rubrics::dsl::*;
+    rubrics
+        .find(sid)
+        .first(conn)
+        .optional()
+        .expect("Failed to get rubric from database")
+        .unwrap_or_else(|| Rubric::default_for(sid))
+}
+
+/// Compute a user's grade in a semester
+///
+/// Meetings, events and commits count when they happened during the
+/// semester. Only approved attendances count. Meetings count once they
+/// have happened, unless they were cancelled. The days of the semester
+/// are in the site's time zone `site`.
+pub fn compute_grade(
+    conn: &SqliteConnection,
+    user: &User,
+    semester: &Semester,
+    rubric: &Rubric,
+    site: &Tz,
+) -> Grade {
+    use crate::attend::handlers::attended;
+    use crate::users::handlers::{user_commits_count, user_groups};
+
+    let mut g = Grade::default();
+    let now = chrono::offset::Utc::now().naive_utc();
+
+    for group in user_groups(conn, user) {
+        let held = Meeting::belonging_to(&group)
+            .load::<Meeting>(conn)
+            .expect("Failed to get meetings from database")
+            .iter()
+            .filter(|m| {
+                semester.contains(m.happened_at, site) && m.happened_at <= now && !m.cancelled
+            })
+            .count();
+        if group.id == 0 {
+            g.large_group.needed += held;
+        } else {
+            g.small_group.needed += held;
+        }
+    }
+
+    g.events.needed = {
+        use crate::schema::events::dsl::*;
+        events
+            .filter(deleted_at.is_null())
+            .load::<Event>(conn)
+            .expect("Failed to get events from database")
+            .iter()
+            .filter(|e| semester.contains(e.start, site))
+            .count()
+    };
+
+    let approved: Vec<Attendance> = {
+        use crate::schema::attendances::dsl::*;
+        Attendance::belonging_to(user)
+            .filter(approved.eq(true))
+            .load(conn)
+            .expect("Failed to get attendances from database")
+    };
+    for a in approved.iter().map(|a| attended(conn, a)) {
+        if !semester.contains(a.time(), site) || a.cancelled() {
+            continue;
+        }
+        if a.is_event() {
+            g.events.done += 1;
+        } else if a.group_id() == Some(0) {
+            g.large_group.done += 1;
+        } else {
+            g.small_group.done += 1;
+        }
+    }
+
+    g.commits = Part {
+        done: user_commits_count(conn, user, Some(semester), site).unwrap_or(0),
+        needed: rubric.commits_needed.max(0) as usize,
+    };
+
+    g.score = rubric.score(&g);
+    g.letter = rubric.letter(g.score);
+    g
+}
+
+/// The grades of everyone enrolled in a semester
+///
+/// Sorted by `sort` which is one of `handle`, `small_group`, `large_group`,
+/// `events`, `commits` or `score`, anything else sorts by name.
+pub fn grade_rows(
+    conn: &SqliteConnection,
+    semester: &Semester,
+    rubric: &Rubric,
+    site: &Tz,
+    sort: &str,
+    desc: bool,
+) -> Vec<GradeRow> {
+    let mut rows: Vec<GradeRow> = semester_users(conn, semester)
+        .into_iter()
+        .map(|user| GradeRow {
+            grade: compute_grade(conn, &user, semester, rubric, site),
+            user,
+        })
+        .collect();
+
+    let ratio = |p: &Part| p.ratio().unwrap_or(0.0);
+    rows.sort_by(|a, b| {
+        let (ga, gb) = (&a.grade, &b.grade);
+        match sort {
+            "handle" => a
+                .user
+                .handle
+                .to_lowercase()
+                .cmp(&b.user.handle.to_lowercase()),
+            "small_group" => ratio(&ga.small_group)
+                .partial_cmp(&ratio(&gb.small_group))
+                .unwrap(),
+            "large_group" => ratio(&ga.large_group)
+                .partial_cmp(&ratio(&gb.large_group))
+                .unwrap(),
+            "events" => ratio(&ga.events).partial_cmp(&ratio(&gb.events)).unwrap(),
+            "commits" => ga.commits.done.cmp(&gb.commits.done),
+            "score" => ga.score.partial_cmp(&gb.score).unwrap(),
+            _ => a
+                .user
+                .real_name
+                .to_lowercase()
+                .cmp(&b.user.real_name.to_lowercase()),
+        }
+    });
+    if desc {
+        rows.reverse();
+    }
+    rows
+}
+
+/// Quote a CSV field if it needs to be
+///
+/// Fields that a spreadsheet would run as a formula are prefixed with `'`.
+pub fn csv_field(s: &str) -> String {
+    let s = if s.starts_with(|c| c == '=' || c == '+' || c == '-' || c == '@') {
+        format!("'{}", s)
+    } else {
+        s.to_string()
+    };
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s
+    }
+}
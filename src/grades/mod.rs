@@ -0,0 +1,13 @@
+//! Grades
+//!
+//! Grades are computed for each user in each semester from their attendance
+//! and commits using a rubric that Admins set per semester.
+//!
+//! ## Routes
+//! - `/semesters/<sid>/grades`
+//! - `/semesters/<sid>/grades.csv`
+//! - `/semesters/<sid>/rubric`
+
+pub mod handlers;
+pub mod models;
+pub mod templates;
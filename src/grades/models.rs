@@ -0,0 +1,152 @@
+//! Models for grading
+//!
+//! Each semester has a rubric in the `rubrics` table. Grades themselves are
+//! not stored, they are computed from attendance and commits when needed.
+
+use std::fmt;
+
+use crate::schema::*;
+
+/// Models how grades are computed in a semester
+#[derive(Debug, PartialEq, Clone, Queryable, Insertable, AsChangeset, Identifiable, Serialize)]
+#[primary_key(semester_id)]
+#[table_name = "rubrics"]
+pub struct Rubric {
+    /// ID of the semester the rubric is for
+    pub semester_id: i32,
+    /// Weight of attendance at small group meetings
+    pub small_group_weight: i32,
+    /// Weight of attendance at large group meetings
+    pub large_group_weight: i32,
+    /// Weight of attendance at events
+    pub event_weight: i32,
+    /// Weight of commits
+    pub commit_weight: i32,
+    /// Commits needed for full credit
+    pub commits_needed: i32,
+    /// Minimum percentage for each grade like `A:90, B:80, F:0`
+    ///
+    /// See `Rubric::thresholds`.
+    pub thresholds: String,
+}
+
+impl Rubric {
+    /// The rubric used by semesters that haven't set one
+    pub fn default_for(semester_id: i32) -> Self {
+        Self {
+            semester_id,
+            small_group_weight: 1,
+            large_group_weight: 1,
+            event_weight: 1,
+            commit_weight: 1,
+            commits_needed: 10,
+            thresholds: String::from("A:90, B:80, C:70, D:60, F:0"),
+        }
+    }
+
+    /// Parse the thresholds into grades and their minimum percentage
+    ///
+    /// Highest first. Returns `None` if they are malformed.
+    /// Pass/fail is just `Pass:70, Fail:0`.
+    pub fn thresholds(&self) -> Option<Vec<(String, i32)>> {
+        let mut t = self
+            .thresholds
+            .split(',')
+            .map(|part| {
+                let mut kv = part.splitn(2, ':');
+                let name = kv.next()?.trim();
+                let min = kv.next()?.trim().parse().ok()?;
+                if name.is_empty() {
+                    None
+                } else {
+                    Some((name.to_string(), min))
+                }
+            })
+            .collect::<Option<Vec<(String, i32)>>>()?;
+        if t.is_empty() {
+            return None;
+        }
+        t.sort_by(|a, b| b.1.cmp(&a.1));
+        Some(t)
+    }
+
+    /// The grade for a percentage
+    ///
+    /// Below every threshold is the lowest grade.
+    pub fn letter(&self, score: f64) -> String {
+        let t = self.thresholds().unwrap_or_default();
+        t.iter()
+            .find(|(_, min)| score >= f64::from(*min))
+            .or_else(|| t.last())
+            .map(|(name, _)| name.clone())
+            .unwrap_or_default()
+    }
+
+    /// The weighted percentage of a set of parts
+    ///
+    /// Parts with nothing needed are left out and the weights of the rest
+    /// are scaled up to make up for it.
+    pub fn score(&self, g: &Grade) -> f64 {
+        let (total, weights) = [
+            (self.small_group_weight, &g.small_group),
+            (self.large_group_weight, &g.large_group),
+            (self.event_weight, &g.events),
+            (self.commit_weight, &g.commits),
+        ]
+        .iter()
+        .filter_map(|(w, p)| p.ratio().map(|r| (f64::from(*w) * r, f64::from(*w))))
+        .fold((0.0, 0.0), |(t, ws), (v, w)| (t + v, ws + w));
+
+        if weights > 0.0 {
+            total / weights * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// One part of a grade
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Part {
+    /// How many were attended or made
+    pub done: usize,
+    /// How many were needed
+    pub needed: usize,
+}
+
+impl Part {
+    /// Fraction of the part that is done, capped at 1
+    ///
+    /// `None` if nothing was needed.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.needed == 0 {
+            None
+        } else {
+            Some((self.done as f64 / self.needed as f64).min(1.0))
+        }
+    }
+}
+
+// Shown like `3 / 4`
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} / {}", self.done, self.needed)
+    }
+}
+
+/// A user's computed grade in a semester
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Grade {
+    /// Attendance at small group meetings
+    pub small_group: Part,
+    /// Attendance at large group meetings
+    pub large_group: Part,
+    /// Attendance at events
+    pub events: Part,
+    /// Commits made
+    pub commits: Part,
+    /// Weighted percentage from the rubric
+    pub score: f64,
+    /// Grade from the rubric thresholds
+    pub letter: String,
+}
@@ -0,0 +1,43 @@
+//! HTML templates for grades
+
+use super::models::{Grade, Rubric};
+use crate::models::{Semester, User};
+#[allow(unused_imports)]
+use crate::templates::{filters, FormError, OptUser};
+
+/// A user and their grade in the report
+pub struct GradeRow {
+    pub user: User,
+    pub grade: Grade,
+}
+
+/// Grade report template
+///
+/// HTML File: `grades/report.html`
+///
+/// The grades of everyone in a semester with the form to change the rubric.
+#[derive(Template)]
+#[template(path = "grades/report.html")]
+pub struct GradeReportTemplate {
+    pub logged_in: OptUser,
+    pub semester: Semester,
+    pub rubric: Rubric,
+    pub rows: Vec<GradeRow>,
+    /// Column the rows are sorted by
+    pub sort: String,
+    /// Are the rows sorted in descending order?
+    pub desc: bool,
+    pub error: Option<FormError>,
+}
+
+impl GradeReportTemplate {
+    /// Link to sort by a column, clicking the sorted column flips the order
+    pub fn sort_link(&self, column: &str) -> String {
+        format!(
+            "/semesters/{}/grades?sort={}&desc={}",
+            self.semester.id,
+            column,
+            self.sort == column && !self.desc
+        )
+    }
+}
@@ -1,16 +1,30 @@
 //! HTTP handlers for the calendar
 
+use chrono::{Datelike, Duration, NaiveDateTime};
 use diesel::prelude::*;
 use diesel::{delete, insert_into, update};
 use rocket::http::Status;
 use rocket::request::Form;
 use rocket::response::Redirect;
+use rocket::State;
 use rocket_contrib::json::Json;
 
-use crate::attend::code::attendance_code;
+use crate::api::v1::last_id;
+use crate::attend::code::{attendance_code, code_is_late, code_is_open, CodeStateForm};
+use crate::attend::handlers::present;
 use crate::attend::models::*;
+use crate::audit;
 use crate::guards::*;
-use crate::templates::{is_reserved, FormError};
+use crate::roles::Permission;
+use crate::semesters::handlers::{
+    all_semesters, enroll_in, find_semester, semester_at, semester_select,
+};
+use crate::semesters::templates::SemesterSelect;
+use crate::settings::Settings;
+use crate::templates::{is_reserved, BigTemplate, FormError};
+use crate::trash::handlers::move_to_trash;
+use crate::trash::models::Kind;
+use crate::tz;
 use crate::ObservDbConn;
 
 use super::models::*;
@@ -18,38 +32,63 @@ use super::templates::*;
 
 /// GET handler for `/groups/<gid>`
 #[get("/groups/<gid>")]
-pub fn group(conn: ObservDbConn, l: UserGuard, gid: i32) -> Option<GroupTemplate> {
+pub fn group(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    gid: i32,
+) -> Option<GroupTemplate> {
     use crate::schema::groups::dsl::*;
 
     let g: Group = groups
         .find(gid)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .optional()
         .expect("Failed to get groups from database")?;
 
-    let m: Vec<Meeting> = Meeting::belonging_to(&g)
-        .load(&*conn)
-        .expect("Failed to get project's repos from database");
+    let now = chrono::offset::Utc::now().naive_utc();
+    let (upcoming, mut past): (Vec<Meeting>, Vec<Meeting>) = Meeting::belonging_to(&g)
+        .order(crate::schema::meetings::happened_at.asc())
+        .load::<Meeting>(&*conn)
+        .expect("Failed to get meetings from database")
+        .into_iter()
+        .partition(|m| m.happened_at > now);
+    past.reverse();
 
     Some(GroupTemplate {
+        zone: tz::of(Some(&l.0), &settings.site_zone()),
         logged_in: Some(l.0),
         users: group_users(&*conn, &g),
         group: g,
-        meetings: m,
+        meetings: past,
+        upcoming,
+        rotating: settings.rotating_codes(),
     })
 }
 
 /// GET handler for `/groups`
 ///
-/// Returns a list of groups
-#[get("/groups")]
-pub fn groups(conn: ObservDbConn, l: MentorGuard) -> GroupsListTemplate {
+/// Returns a list of groups in the selected semester
+#[get("/groups?<sem>")]
+pub fn groups(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    settings: State<Settings>,
+    sem: Option<String>,
+) -> GroupsListTemplate {
     use crate::schema::groups::dsl::*;
+    let semester = semester_select(&*conn, sem, &settings.site_zone());
     GroupsListTemplate {
         logged_in: Some(l.0),
         groups: groups
-            .load(&*conn)
-            .expect("Failed to get groups from database"),
+            .filter(deleted_at.is_null())
+            .load::<Group>(&*conn)
+            .expect("Failed to get groups from database")
+            .into_iter()
+            .filter(|g| semester.includes(g.semester_id))
+            .collect(),
+        semester,
     }
 }
 
@@ -57,10 +96,11 @@ pub fn groups(conn: ObservDbConn, l: MentorGuard) -> GroupsListTemplate {
 ///
 /// JSON endpoint that returns the list of groups as a single JSON array
 #[get("/groups.json")]
-pub fn groups_json(conn: ObservDbConn, _l: MentorGuard) -> Json<Vec<Group>> {
+pub fn groups_json(conn: ObservDbConn, _l: Can<can::RunMeetings>) -> Json<Vec<Group>> {
     use crate::schema::groups::dsl::*;
     Json(
         groups
+            .filter(deleted_at.is_null())
             .load(&*conn)
             .expect("Failed to get groups from database"),
     )
@@ -70,13 +110,20 @@ pub fn groups_json(conn: ObservDbConn, _l: MentorGuard) -> Json<Vec<Group>> {
 ///
 /// Creates a new group list and populates it with users
 #[get("/groups/new?<e>")]
-pub fn group_new(conn: ObservDbConn, l: AdminGuard, e: Option<FormError>) -> NewGroupTemplate {
+pub fn group_new(
+    conn: ObservDbConn,
+    l: Can<can::ManageGroup>,
+    settings: State<Settings>,
+    e: Option<FormError>,
+) -> NewGroupTemplate {
     use crate::schema::users::dsl::*;
     NewGroupTemplate {
         logged_in: Some(l.0),
         all_users: users
+            .filter(deleted_at.is_null())
             .load(&*conn)
             .expect("Failed to get users from database"),
+        semester: semester_select(&*conn, None, &settings.site_zone()),
         error: e,
     }
 }
@@ -85,46 +132,44 @@ pub fn group_new(conn: ObservDbConn, l: AdminGuard, e: Option<FormError>) -> New
 ///
 /// Creates a new group list. For use with `newgroup`.
 ///
+/// The meetings of its schedule are created for the coming weeks.
+///
 /// Restricted to Admins
 #[post("/groups/new", data = "<newgroup>")]
-pub fn group_new_post(conn: ObservDbConn, _l: AdminGuard, newgroup: Form<NewGroup>) -> Redirect {
+pub fn group_new_post(
+    conn: ObservDbConn,
+    l: Can<can::ManageGroup>,
+    settings: State<Settings>,
+    newgroup: Form<NewGroup>,
+) -> Redirect {
     let newgroup = newgroup.into_inner();
 
-    if let Err(e) = is_reserved(&newgroup.name) {
-        return Redirect::to(format!("/groups/new?e={}", e));
+    match create_group(&*conn, &newgroup) {
+        Ok(gid) => {
+            audit_log!(
+                "User {} [{}] has created Group {} \'{}\'",
+                l.0.id,
+                l.0.email,
+                gid,
+                newgroup.name
+            );
+            let g: Group = {
+                use crate::schema::groups::dsl::*;
+                groups
+                    .find(gid)
+                    .filter(deleted_at.is_null())
+                    .first(&*conn)
+                    .expect("Failed to get group from database")
+            };
+            audit::created(&*conn, Some(&l.0), "group", gid, &g);
+            let now = chrono::offset::Utc::now().naive_utc();
+            for m in schedule_meetings(&*conn, &g, &settings, now) {
+                audit::created(&*conn, Some(&l.0), "meeting", m.id, &m);
+            }
+            Redirect::to("/groups")
+        }
+        Err(e) => Redirect::to(format!("/groups/new?e={}", e)),
     }
-
-    use crate::schema::groups::dsl::*;
-    insert_into(groups)
-        .values(&newgroup)
-        .execute(&*conn)
-        .expect("Failed to insert group into database");
-
-    use crate::schema::groups::dsl::id;
-    let gid = groups
-        .filter(name.eq(&*newgroup.name).and(owner_id.eq(newgroup.owner_id)))
-        .select(id)
-        .first(&*conn)
-        .expect("Failed to get group from database");
-
-    use crate::schema::relation_group_user::dsl::*;
-    insert_into(relation_group_user)
-        .values(&NewRelationGroupUser {
-            group_id: gid,
-            user_id: newgroup.owner_id,
-        })
-        .execute(&*conn)
-        .expect("Failed to insert relation into database");
-
-    audit_log!(
-        "User {} [{}] has created Group {} \'{}\'",
-        _l.0.id,
-        _l.0.email,
-        gid,
-        newgroup.name
-    );
-
-    Redirect::to("/groups")
 }
 
 /// GET handler for `/groups/<gid>/meetings`
@@ -139,7 +184,8 @@ pub fn meetings(gid: i32) -> Redirect {
 #[get("/groups/<gid>/meetings/<mid>")]
 pub fn meeting_get(
     conn: ObservDbConn,
-    l: MentorGuard,
+    l: Can<can::RunMeetings>,
+    settings: State<Settings>,
     gid: i32,
     mid: i32,
 ) -> Option<MeetingTemplate> {
@@ -147,6 +193,7 @@ pub fn meeting_get(
         use crate::schema::groups::dsl::*;
         groups
             .find(gid)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .optional()
             .expect("Failed to get groups from database")?
@@ -162,11 +209,16 @@ pub fn meeting_get(
     };
 
     if m.group_id != gid {
-        return None;
+        None
     } else {
+        let now = chrono::offset::Utc::now().naive_utc();
         Some(MeetingTemplate {
+            zone: tz::of(Some(&l.0), &settings.site_zone()),
             logged_in: Some(l.0),
             users: meeting_users(&*conn, &m),
+            code_open_now: code_is_open(&m, now),
+            code_late_now: code_is_late(&m, now),
+            rotating: settings.rotating_codes(),
             group: g,
             meeting: m,
         })
@@ -175,7 +227,11 @@ pub fn meeting_get(
 
 /// GET handler for `/groups/<gid>/meetings.json`
 #[get("/groups/<gid>/meetings.json")]
-pub fn meetings_json(conn: ObservDbConn, _l: MentorGuard, gid: i32) -> Json<Vec<Meeting>> {
+pub fn meetings_json(
+    conn: ObservDbConn,
+    _l: Can<can::RunMeetings>,
+    gid: i32,
+) -> Json<Vec<Meeting>> {
     use crate::schema::meetings::dsl::*;
     Json(
         meetings
@@ -187,14 +243,17 @@ pub fn meetings_json(conn: ObservDbConn, _l: MentorGuard, gid: i32) -> Json<Vec<
 
 /// POST handler for `/groups/<gid>/meetings/new`
 ///
-/// Records a new meeting
-#[post("/groups/<gid>/meetings/new", data = "<newmeeting>")]
+/// Records an extra meeting starting now, hosted by whoever started it.
+/// The meetings on the group's schedule are created ahead of time.
+///
+/// Archived groups can't have new meetings.
+#[post("/groups/<gid>/meetings/new")]
 pub fn meeting_new_post(
     conn: ObservDbConn,
-    l: MentorGuard,
+    l: Can<can::RunMeetings>,
+    settings: State<Settings>,
     gid: i32,
-    newmeeting: Form<NewMeeting>,
-) -> Redirect {
+) -> Result<Redirect, Status> {
     use crate::schema::groups::dsl::*;
 
     audit_log!(
@@ -206,24 +265,167 @@ pub fn meeting_new_post(
 
     let g: Group = groups
         .find(gid)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get group from database");
 
-    if l.0.tier > 1
-        || l.0.id == g.owner_id
-        || (l.0.id > 0 && group_users(&*conn, &g).contains(&l.0) && g.id > 0)
-    {
-        use crate::schema::meetings::dsl::*;
-        let mut newmeeting = newmeeting.into_inner();
-        newmeeting.group_id = gid;
-        newmeeting.code = attendance_code(&*conn);
+    if g.archived {
+        return Err(Status::Conflict);
+    }
+    if can_host(&*conn, &l.0, &g) {
+        let m = create_meeting(&*conn, &g, &l.0, &settings);
+        audit::created(&*conn, Some(&l.0), "meeting", m.id, &m);
+    }
+    Ok(Redirect::to(format!("/groups/{}", gid)))
+}
+
+/// GET handler for `/groups/<gid>/meetings/<mid>/present`
+///
+/// Presenter mode showing the rotating attendance code of a meeting.
+#[get("/groups/<gid>/meetings/<mid>/present")]
+pub fn meeting_present(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    settings: State<Settings>,
+    site: SiteUrl,
+    gid: i32,
+    mid: i32,
+) -> Option<BigTemplate> {
+    use crate::schema::meetings::dsl::*;
+
+    let m: Meeting = meetings
+        .find(mid)
+        .filter(group_id.eq(gid))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get meeting from database")?;
+
+    Some(present(l.0, &m, settings.code_rotation_seconds, &site))
+}
 
-        insert_into(meetings)
-            .values(&newmeeting)
+/// PUT handler for `/groups/<gid>/meetings/<mid>/code`
+///
+/// Manually opens or closes the attendance code of a meeting.
+///
+/// Restricted to Admins, the group mentor and the meeting host.
+#[put("/groups/<gid>/meetings/<mid>/code", data = "<form>")]
+pub fn meeting_code_put(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    gid: i32,
+    mid: i32,
+    form: Form<CodeStateForm>,
+) -> Result<Redirect, Status> {
+    let g: Group = {
+        use crate::schema::groups::dsl::*;
+        groups
+            .find(gid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get group from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    use crate::schema::meetings::dsl::*;
+    let m: Meeting = meetings
+        .find(mid)
+        .filter(group_id.eq(gid))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get meeting from database")
+        .ok_or(Status::NotFound)?;
+
+    if l.0.can(Permission::ManageGroup) || l.0.id == g.owner_id || l.0.id == m.hosted_by {
+        let state = form.into_inner().state;
+        update(meetings.find(mid))
+            .set(code_open.eq(Option::<bool>::from(state)))
             .execute(&*conn)
-            .expect("Failed to insert meeting into database");
+            .expect("Failed to update meeting in database");
+
+        audit_log!(
+            "User {} [{}] has set the code of Meeting {} to {:?}",
+            l.0.id,
+            l.0.email,
+            mid,
+            state
+        );
+        let new: Meeting = meetings
+            .find(mid)
+            .first(&*conn)
+            .expect("Failed to get meeting from database");
+        audit::updated(&*conn, Some(&l.0), "meeting", mid, &m, &new);
+
+        Ok(Redirect::to(format!("/groups/{}/meetings/{}", gid, mid)))
+    } else {
+        Err(DENIED)
     }
-    Redirect::to(format!("/groups/{}", gid))
+}
+
+/// Whether a meeting is cancelled
+#[derive(FromForm)]
+pub struct CancelForm {
+    pub cancelled: bool,
+}
+
+/// PUT handler for `/groups/<gid>/meetings/<mid>/cancel`
+///
+/// Cancels a meeting, excusing everyone from it, or brings it back.
+///
+/// Restricted to Admins, the group mentor and the meeting host.
+#[put("/groups/<gid>/meetings/<mid>/cancel", data = "<form>")]
+pub fn meeting_cancel_put(
+    conn: ObservDbConn,
+    l: Can<can::RunMeetings>,
+    gid: i32,
+    mid: i32,
+    form: Form<CancelForm>,
+) -> Result<Redirect, Status> {
+    let g: Group = {
+        use crate::schema::groups::dsl::*;
+        groups
+            .find(gid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get group from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    use crate::schema::meetings::dsl::*;
+    let m: Meeting = meetings
+        .find(mid)
+        .filter(group_id.eq(gid))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get meeting from database")
+        .ok_or(Status::NotFound)?;
+
+    if !(l.0.can(Permission::ManageGroup) || l.0.id == g.owner_id || l.0.id == m.hosted_by) {
+        return Err(DENIED);
+    }
+
+    let state = form.into_inner().cancelled;
+    update(meetings.find(mid))
+        .set(cancelled.eq(state))
+        .execute(&*conn)
+        .expect("Failed to update meeting in database");
+
+    audit_log!(
+        "User {} [{}] has {} Meeting {} of Group {}",
+        l.0.id,
+        l.0.email,
+        if state { "cancelled" } else { "restored" },
+        mid,
+        gid
+    );
+    let new: Meeting = meetings
+        .find(mid)
+        .first(&*conn)
+        .expect("Failed to get meeting from database");
+    audit::updated(&*conn, Some(&l.0), "meeting", mid, &m, &new);
+
+    Ok(Redirect::to(format!("/groups/{}/meetings/{}", gid, mid)))
 }
 
 /// GET handler for `/groups/<gid>/members/add`
@@ -232,13 +434,14 @@ pub fn meeting_new_post(
 #[get("/groups/<gid>/members/add")]
 pub fn group_user_add(
     conn: ObservDbConn,
-    l: MentorGuard,
+    l: Can<can::RunMeetings>,
     gid: i32,
 ) -> Result<AddUserTemplate, Status> {
     let g: Group = {
         use crate::schema::groups::dsl::*;
         groups
             .find(gid)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .expect("Failed to get group from database")
     };
@@ -253,7 +456,7 @@ pub fn group_user_add(
 
     let gu = group_users(&*conn, &g);
 
-    if l.0.tier > 1 || g.owner_id == l.0.id {
+    if l.0.can(Permission::ManageGroup) || g.owner_id == l.0.id {
         Ok(AddUserTemplate {
             logged_in: Some(l.0),
             group: g,
@@ -264,7 +467,7 @@ pub fn group_user_add(
                 .collect(),
         })
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -280,7 +483,7 @@ pub struct AddUserForm {
 #[post("/groups/<gid>/members/add", data = "<form>")]
 pub fn group_user_add_post(
     conn: ObservDbConn,
-    l: MentorGuard,
+    l: Can<can::RunMeetings>,
     gid: i32,
     form: Form<AddUserForm>,
 ) -> Result<Redirect, Status> {
@@ -288,10 +491,11 @@ pub fn group_user_add_post(
 
     let g: Group = groups
         .find(gid)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get group from database");
 
-    if l.0.tier > 1 || g.owner_id == l.0.id {
+    if l.0.can(Permission::ManageGroup) || g.owner_id == l.0.id {
         use crate::schema::relation_group_user::dsl::*;
 
         if let Some(uid) = form.into_inner().uid {
@@ -302,6 +506,7 @@ pub fn group_user_add_post(
                 })
                 .execute(&*conn)
                 .expect("Failed to insert new relation into database");
+            enroll_in(&*conn, g.semester_id, uid);
 
             audit_log!(
                 "User {} [{}] has added User {} to Group {}",
@@ -310,13 +515,14 @@ pub fn group_user_add_post(
                 uid,
                 g.id
             );
+            audit::member(&*conn, Some(&l.0), "group", g.id, uid, true);
 
             Ok(Redirect::to(format!("/groups/{}", gid)))
         } else {
             Ok(Redirect::to("/"))
         }
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -326,7 +532,7 @@ pub fn group_user_add_post(
 #[delete("/groups/<gid>/members/<uid>")]
 pub fn group_user_delete(
     conn: ObservDbConn,
-    l: MentorGuard,
+    l: Can<can::RunMeetings>,
     gid: i32,
     uid: i32,
 ) -> Result<Redirect, Status> {
@@ -334,10 +540,11 @@ pub fn group_user_delete(
 
     let g: Group = groups
         .find(gid)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get group from database");
 
-    if l.0.tier > 1 || g.owner_id == l.0.id {
+    if l.0.can(Permission::ManageGroup) || g.owner_id == l.0.id {
         // Just return if this was the Large Group which users cannot be removed from
         if g.id == 0 {
             return Ok(Redirect::to(format!("/groups/{}", gid)));
@@ -366,10 +573,11 @@ pub fn group_user_delete(
                 .execute(&*conn)
                 .expect("Failed to removed user from group in database");
         }
+        audit::member(&*conn, Some(&l.0), "group", g.id, uid, false);
 
         Ok(Redirect::to(format!("/groups/{}", gid)))
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -379,7 +587,7 @@ pub fn group_user_delete(
 #[get("/groups/<gid>/edit?<e>")]
 pub fn group_edit(
     conn: ObservDbConn,
-    l: MentorGuard,
+    l: Can<can::RunMeetings>,
     gid: i32,
     e: Option<FormError>,
 ) -> Result<EditGroupTemplate, Status> {
@@ -388,30 +596,39 @@ pub fn group_edit(
 
     let g: Group = groups
         .find(gid)
+        .filter(crate::schema::groups::deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get group from database");
 
-    if l.0.tier > 1 || g.owner_id == l.0.id {
+    if l.0.can(Permission::ManageGroup) || g.owner_id == l.0.id {
         Ok(EditGroupTemplate {
             logged_in: Some(l.0),
+            semester: SemesterSelect {
+                semesters: all_semesters(&*conn),
+                selected: g.semester_id.and_then(|sid| find_semester(&*conn, sid)),
+            },
             group: g,
             all_users: users
+                .filter(crate::schema::users::deleted_at.is_null())
                 .load(&*conn)
                 .expect("Failed to get users from database"),
             error: e,
         })
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
 /// PUT handler for `/groups/<gid>`
 ///
 /// Updates the group owner
+///
+/// Meetings that haven't happened yet are moved to the new schedule.
 #[put("/groups/<gid>", data = "<editgroup>")]
 pub fn group_edit_put(
     conn: ObservDbConn,
-    l: MentorGuard,
+    l: Can<can::RunMeetings>,
+    settings: State<Settings>,
     editgroup: Form<NewGroup>,
     gid: i32,
 ) -> Result<Redirect, Status> {
@@ -421,24 +638,48 @@ pub fn group_edit_put(
 
     let g: Group = groups
         .find(gid)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get group from database");
 
-    if l.0.tier > 1 || g.owner_id == l.0.id {
+    if l.0.can(Permission::ManageGroup) || g.owner_id == l.0.id {
         if let Err(e) = is_reserved(&editgroup.name) {
             return Ok(Redirect::to(format!("/groups/{}/edit?e={}", gid, e)));
         }
+        if let Err(e) = editgroup.fix_schedule() {
+            return Ok(Redirect::to(format!("/groups/{}/edit?e={}", gid, e)));
+        }
 
-        if l.0.tier <= 1 {
+        if !l.0.can(Permission::ManageGroup) {
             editgroup.owner_id = l.0.id;
         }
         update(groups.find(gid))
             .set(&editgroup)
             .execute(&*conn)
             .expect("Failed to update group in the database");
+        // The changeset skips `None` so they are set separately to allow clearing them
+        update(groups.find(gid))
+            .set((
+                semester_id.eq(editgroup.semester_id),
+                meeting_day.eq(&editgroup.meeting_day),
+                meeting_time.eq(&editgroup.meeting_time),
+            ))
+            .execute(&*conn)
+            .expect("Failed to update group in the database");
+
+        let new: Group = groups
+            .find(gid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .expect("Failed to get group from database");
+        audit::updated(&*conn, Some(&l.0), "group", gid, &g, &new);
+        let now = chrono::offset::Utc::now().naive_utc();
+        for m in schedule_meetings(&*conn, &new, &settings, now) {
+            audit::created(&*conn, Some(&l.0), "meeting", m.id, &m);
+        }
         Ok(Redirect::to(format!("/groups/{}", gid)))
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -446,37 +687,255 @@ pub fn group_edit_put(
 ///
 /// Deletes a group from the database
 #[delete("/groups/<gid>")]
-pub fn group_delete(conn: ObservDbConn, _l: AdminGuard, gid: i32) -> Redirect {
+pub fn group_delete(conn: ObservDbConn, l: Can<can::ManageGroup>, gid: i32) -> Redirect {
+    let old: Option<Group> = {
+        use crate::schema::groups::dsl::*;
+        groups
+            .find(gid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get group from database")
+    };
+    move_to_trash(&*conn, Kind::Group, gid);
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "group", gid, &old);
+    }
+    Redirect::to("/groups")
+}
+
+/// Creates a new group and adds its mentor to it
+///
+/// Returns the ID of the new group.
+pub fn create_group(conn: &SqliteConnection, newgroup: &NewGroup) -> Result<i32, FormError> {
+    is_reserved(&newgroup.name)?;
+    let mut newgroup = newgroup.clone();
+    newgroup.fix_schedule()?;
+
+    use crate::schema::groups::dsl::*;
+    insert_into(groups)
+        .values(&newgroup)
+        .execute(conn)
+        .expect("Failed to insert group into database");
+
+    use crate::schema::groups::dsl::id;
+    let gid = groups
+        .filter(name.eq(&*newgroup.name).and(owner_id.eq(newgroup.owner_id)))
+        .select(id)
+        .first(conn)
+        .expect("Failed to get group from database");
+
+    use crate::schema::relation_group_user::dsl::*;
+    insert_into(relation_group_user)
+        .values(&NewRelationGroupUser {
+            group_id: gid,
+            user_id: newgroup.owner_id,
+        })
+        .execute(conn)
+        .expect("Failed to insert relation into database");
+    enroll_in(conn, newgroup.semester_id, newgroup.owner_id);
+
+    Ok(gid)
+}
+
+/// Deletes a group for good along with its members and meetings
+///
+/// Only used to purge the trash, see `crate::trash`.
+pub fn delete_group(conn: &SqliteConnection, gid: i32) {
     // Delete the user relations
     use crate::schema::relation_group_user::dsl::*;
     delete(relation_group_user.filter(group_id.eq(gid)))
-        .execute(&*conn)
+        .execute(conn)
         .expect("Failed to delete relations from database");
 
     // Delete the meetings
-    delete_meetings_for(&*conn, gid);
+    delete_meetings_for(conn, gid);
+
+    // Revoke the feeds of its meetings
+    {
+        use crate::schema::calendar_feeds::dsl::*;
+        delete(calendar_feeds.filter(group_id.eq(gid)))
+            .execute(conn)
+            .expect("Failed to delete calendar feeds from database");
+    }
 
     // Delete the group
     use crate::schema::groups::dsl::*;
     delete(groups.find(gid))
-        .execute(&*conn)
+        .execute(conn)
         .expect("Failed to delete group from database");
+}
 
-    Redirect::to("/groups")
+/// Can this user start a meeting of the group?
+///
+/// Admins and the group mentor can, as can any member of a small group.
+pub fn can_host(conn: &SqliteConnection, user: &User, g: &Group) -> bool {
+    user.can(Permission::ManageGroup)
+        || user.id == g.owner_id
+        || (user.id > 0 && group_users(conn, g).contains(user) && g.id > 0)
+}
+
+/// Records a new meeting of a group starting now with a fresh attendance code
+pub fn create_meeting(
+    conn: &SqliteConnection,
+    g: &Group,
+    host: &User,
+    settings: &Settings,
+) -> Meeting {
+    let now = chrono::offset::Utc::now().naive_utc();
+    insert_meeting(conn, g, host.id, now, settings)
+}
+
+/// Adds a meeting of a group at a time with a fresh attendance code
+///
+/// The meeting is held where the group meets and is part of the group's
+/// semester, or if the group doesn't have one the semester it happens in.
+fn insert_meeting(
+    conn: &SqliteConnection,
+    g: &Group,
+    host: i32,
+    at: NaiveDateTime,
+    settings: &Settings,
+) -> Meeting {
+    use crate::schema::meetings::dsl::*;
+    let newmeeting = NewMeeting {
+        happened_at: at,
+        code: attendance_code(conn),
+        group_id: g.id,
+        hosted_by: host,
+        code_expires: at + Duration::minutes(settings.meeting_code_minutes),
+        semester_id: g
+            .semester_id
+            .or_else(|| semester_at(conn, at, &settings.site_zone()).map(|s| s.id)),
+        location: g.location.clone(),
+    };
+
+    insert_into(meetings)
+        .values(&newmeeting)
+        .execute(conn)
+        .expect("Failed to insert meeting into database");
+
+    meetings
+        .find(last_id(conn))
+        .first(conn)
+        .expect("Failed to get meeting from database")
+}
+
+/// Creates the meetings on a group's schedule for the coming weeks
+///
+/// Every meeting from `now` until `meeting_weeks_ahead` weeks later that
+/// doesn't exist yet is created, staying within the group's semester if it
+/// has one. Meetings that haven't happened yet are moved to where the group
+/// meets and given to its mentor. Those no longer on the schedule are
+/// deleted unless someone already attended them.
+///
+/// Archived groups and groups in the trash don't have a schedule.
+///
+/// Returns the meetings that were created.
+pub fn schedule_meetings(
+    conn: &SqliteConnection,
+    g: &Group,
+    settings: &Settings,
+    now: NaiveDateTime,
+) -> Vec<Meeting> {
+    use crate::schema::meetings::dsl::*;
+
+    let schedule = if g.archived || g.deleted_at.is_some() {
+        None
+    } else {
+        g.schedule()
+    };
+    // Schedules are in the site's time zone
+    let site = settings.site_zone();
+    let on_schedule = |t: NaiveDateTime| {
+        let t = site.to_local(&t);
+        schedule.map_or(false, |(day, time)| t.weekday() == day && t.time() == time)
+    };
+
+    let upcoming: Vec<Meeting> = meetings
+        .filter(group_id.eq(g.id))
+        .filter(happened_at.gt(now))
+        .load(conn)
+        .expect("Failed to get meetings from database");
+    for m in upcoming.iter().filter(|m| !on_schedule(m.happened_at)) {
+        let attended = {
+            use crate::schema::attendances::dsl::*;
+            attendances
+                .filter(is_event.eq(false).and(meeting_id.eq(m.id)))
+                .count()
+                .get_result::<i64>(conn)
+                .expect("Failed to get attendances from database")
+        };
+        if attended == 0 {
+            delete_meeting(conn, m.id);
+        }
+    }
+    update(
+        meetings
+            .filter(group_id.eq(g.id))
+            .filter(happened_at.gt(now)),
+    )
+    .set((location.eq(&g.location), hosted_by.eq(g.owner_id)))
+    .execute(conn)
+    .expect("Failed to update meetings in database");
+
+    let (day, time) = match schedule {
+        Some(s) => s,
+        None => return Vec::new(),
+    };
+    let semester = g.semester_id.and_then(|sid| find_semester(conn, sid));
+    let today = site.to_local(&now).date();
+    (0..settings.meeting_weeks_ahead * 7)
+        .map(|d| today + Duration::days(d))
+        .filter(|d| d.weekday() == day)
+        .map(|d| site.to_utc(&d.and_time(time)))
+        .filter(|at| *at > now)
+        .filter(|at| semester.as_ref().map_or(true, |s| s.contains(*at, &site)))
+        .filter(|at| !upcoming.iter().any(|m| m.happened_at == *at))
+        .map(|at| insert_meeting(conn, g, g.owner_id, at, settings))
+        .collect()
+}
+
+/// Creates the coming meetings of every group with a schedule
+///
+/// Used by the `crate::fairings::MeetingSchedule` job. Returns how many
+/// meetings were created.
+pub fn schedule_all_meetings(
+    conn: &SqliteConnection,
+    settings: &Settings,
+    now: NaiveDateTime,
+) -> usize {
+    use crate::schema::groups::dsl::*;
+    let scheduled: Vec<Group> = groups
+        .filter(deleted_at.is_null())
+        .filter(meeting_day.is_not_null())
+        .load(conn)
+        .expect("Failed to get groups from database");
+
+    let mut created = 0;
+    for g in scheduled {
+        for m in schedule_meetings(conn, &g, settings, now) {
+            audit::created(conn, None, "meeting", m.id, &m);
+            created += 1;
+        }
+    }
+    created
 }
 
 /// Returns a list of users in a given group
 use crate::models::User;
-fn group_users(conn: &SqliteConnection, group: &Group) -> Vec<User> {
+pub fn group_users(conn: &SqliteConnection, group: &Group) -> Vec<User> {
     RelationGroupUser::belonging_to(group)
         .load::<RelationGroupUser>(conn)
         .expect("Failed to get relations from database")
         .iter()
-        .map(|r| {
+        .filter_map(|r| {
             use crate::schema::users::dsl::*;
             users
                 .find(r.user_id)
+                .filter(deleted_at.is_null())
                 .first(conn)
+                .optional()
                 .expect("Failed to get user from database")
         })
         .collect()
@@ -494,33 +953,42 @@ fn group_meetings(conn: &SqliteConnection, gid: i32) -> Vec<Meeting> {
 /// Deletes all the meetings for a group.
 /// Used only when a group is being deleted.
 fn delete_meetings_for(conn: &SqliteConnection, gid: i32) {
-    use crate::schema::meetings::dsl::*;
     for meeting in group_meetings(conn, gid) {
-        // Delete their attendances
-        {
-            use crate::schema::attendances::dsl::*;
-            delete(attendances.filter(is_event.eq(false).and(meeting_id.eq(meeting.id))))
-                .execute(conn)
-                .expect("Failed to delete attendance from database");
-        }
-        // Delete the meetings
-        delete(meetings.find(meeting.id))
+        delete_meeting(conn, meeting.id);
+    }
+}
+
+/// Deletes a meeting and the attendances for it
+pub fn delete_meeting(conn: &SqliteConnection, mid: i32) {
+    // Delete their attendances
+    {
+        use crate::schema::attendances::dsl::*;
+        delete(attendances.filter(is_event.eq(false).and(meeting_id.eq(mid))))
             .execute(conn)
-            .expect("Failed to delete meeting from database");
+            .expect("Failed to delete attendance from database");
     }
+    // Delete the meeting
+    use crate::schema::meetings::dsl::*;
+    delete(meetings.find(mid))
+        .execute(conn)
+        .expect("Failed to delete meeting from database");
 }
 
-/// Returns a list of users who attended a given meeting
-fn meeting_users(conn: &SqliteConnection, meeting: &Meeting) -> Vec<User> {
+/// Returns a list of users whose attendance at a given meeting was approved
+pub fn meeting_users(conn: &SqliteConnection, meeting: &Meeting) -> Vec<User> {
+    use crate::schema::attendances::dsl::approved;
     Attendance::belonging_to(meeting)
+        .filter(approved.eq(true))
         .load::<Attendance>(conn)
         .expect("Failed to get relations from database")
         .iter()
-        .map(|r| {
+        .filter_map(|r| {
             use crate::schema::users::dsl::*;
             users
                 .find(r.user_id)
+                .filter(deleted_at.is_null())
                 .first(conn)
+                .optional()
                 .expect("Failed to get user from database")
         })
         .collect()
@@ -15,6 +15,8 @@
 //! - `/groups/<gid>/meetings.json
 //! - `/groups/<gid>/meetings/new
 //! - '/groups/<gid>/meetings/<mid>
+//! - '/groups/<gid>/meetings/<mid>/code
+//! - '/groups/<gid>/meetings/<mid>/present
 
 pub mod handlers;
 pub mod models;
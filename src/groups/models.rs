@@ -2,12 +2,19 @@
 //!
 //! Groups are stored in the `groups` table where each row is a group member
 //! or a meeting
+//!
+//! Groups meet every week on the day and at the time of their schedule.
+//! Their meetings are created ahead of time, see
+//! `crate::groups::handlers::schedule_meetings`.
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Weekday};
 
+use crate::calendar::recurrence::{parse_weekdays, weekday_key};
 use crate::models::Attendable;
 use crate::models::User;
 use crate::schema::*;
+use crate::templates::FormError;
+use crate::tz::Tz;
 
 /// Models a group in the database
 #[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Serialize)]
@@ -20,11 +27,41 @@ pub struct Group {
     pub owner_id: i32,
     /// Meeting location
     pub location: Option<String>,
+    /// Semester the group is part of
+    pub semester_id: Option<i32>,
+    /// Archived groups are from past semesters and can't hold meetings
+    pub archived: bool,
+    /// When the group was moved to the trash, see `crate::trash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<NaiveDateTime>,
+    /// Day of the week the group meets on like `MO`
+    pub meeting_day: Option<String>,
+    /// Time of day the group meets at
+    pub meeting_time: Option<NaiveTime>,
+}
+
+impl Group {
+    /// The day and time the group meets every week, if it has a schedule
+    pub fn schedule(&self) -> Option<(Weekday, NaiveTime)> {
+        let day = parse_weekdays(self.meeting_day.as_deref()?)?;
+        Some((*day.first()?, self.meeting_time?))
+    }
+
+    /// The schedule like `Thursdays at 4:00 PM`
+    pub fn schedule_name(&self) -> Option<String> {
+        let (day, time) = self.schedule()?;
+        Some(format!(
+            "{}s at {}",
+            NaiveDate::from_isoywd(2021, 1, day).format("%A"),
+            time.format("%-l:%M %p")
+        ))
+    }
 }
 
 /// Used to create a new group in the database
-#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset)]
+#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset, Deserialize)]
 #[table_name = "groups"]
+#[serde(default)]
 pub struct NewGroup {
     /// Name of the group
     pub name: String,
@@ -32,6 +69,47 @@ pub struct NewGroup {
     pub owner_id: i32,
     /// Meeting location
     pub location: Option<String>,
+    /// Semester the group is part of
+    pub semester_id: Option<i32>,
+    /// Day of the week the group meets on like `MO`
+    pub meeting_day: Option<String>,
+    /// Time of day the group meets at like `16:00`
+    pub meeting_time: Option<String>,
+}
+
+impl NewGroup {
+    /// Checks the meeting schedule and puts it in the form it is stored in
+    ///
+    /// Empty fields are cleared, and the day and time have to be given
+    /// together.
+    pub fn fix_schedule(&mut self) -> Result<(), FormError> {
+        let blank = |s: &Option<String>| s.as_deref().map_or(true, |s| s.trim().is_empty());
+        if blank(&self.meeting_day) && blank(&self.meeting_time) {
+            self.meeting_day = None;
+            self.meeting_time = None;
+            return Ok(());
+        }
+
+        let day = self
+            .meeting_day
+            .as_deref()
+            .and_then(parse_weekdays)
+            .filter(|d| d.len() == 1)
+            .ok_or(FormError::InvalidDate)?;
+        let time = self
+            .meeting_time
+            .as_deref()
+            .map(str::trim)
+            .and_then(|t| {
+                NaiveTime::parse_from_str(t, "%R")
+                    .or_else(|_| NaiveTime::parse_from_str(t, "%T"))
+                    .ok()
+            })
+            .ok_or(FormError::InvalidDate)?;
+        self.meeting_day = Some(weekday_key(day[0]).to_string());
+        self.meeting_time = Some(time.format("%T").to_string());
+        Ok(())
+    }
 }
 
 /// Models a meeting in the database
@@ -40,7 +118,8 @@ pub struct NewGroup {
 pub struct Meeting {
     /// ID of the meeting
     pub id: i32,
-    /// Time of the meeting
+    /// Time of the meeting in UTC
+    #[serde(serialize_with = "crate::tz::serialize_utc")]
     pub happened_at: NaiveDateTime,
     /// Attendance code
     pub code: String,
@@ -48,21 +127,46 @@ pub struct Meeting {
     pub group_id: i32,
     /// ID of the mentor who hosted the meeting/event
     pub hosted_by: i32,
+    /// Time the attendance code stops being accepted in UTC
+    #[serde(serialize_with = "crate::tz::serialize_utc")]
+    pub code_expires: NaiveDateTime,
+    /// Manual override of the code window, `None` follows the window
+    pub code_open: Option<bool>,
+    /// Semester the meeting is part of
+    pub semester_id: Option<i32>,
+    /// Where the meeting is held
+    pub location: Option<String>,
+    /// Cancelled meetings excuse everyone from them
+    pub cancelled: bool,
 }
 
 impl Attendable for Meeting {
     fn id(&self) -> i32 {
         self.id
     }
-    fn name(&self) -> String {
+    fn name(&self, zone: Tz) -> String {
         format!(
             "Meeting on: {}",
-            self.happened_at.format("%b. %-d, at %l:%M %p").to_string()
+            zone.to_local(&self.happened_at)
+                .format("%b. %-d, at %l:%M %p")
         )
     }
     fn time(&self) -> NaiveDateTime {
         self.happened_at
     }
+    fn closes_at(&self) -> NaiveDateTime {
+        self.code_expires
+    }
+    fn code_open(&self) -> Option<bool> {
+        if self.cancelled {
+            Some(false)
+        } else {
+            self.code_open
+        }
+    }
+    fn cancelled(&self) -> bool {
+        self.cancelled
+    }
     fn code(&self) -> String {
         self.code.clone()
     }
@@ -85,13 +189,23 @@ impl Attendable for Meeting {
 }
 
 /// Used to create a new meeting in the database
-#[derive(Debug, Default, Clone, FromForm, Insertable)]
+#[derive(Debug, Clone, Insertable)]
 #[table_name = "meetings"]
 pub struct NewMeeting {
+    /// Time of the meeting
+    pub happened_at: NaiveDateTime,
     /// Attendance code
     pub code: String,
     /// ID of the group
     pub group_id: i32,
+    /// ID of the mentor who hosts the meeting
+    pub hosted_by: i32,
+    /// Time the attendance code stops being accepted
+    pub code_expires: NaiveDateTime,
+    /// Semester the meeting is part of
+    pub semester_id: Option<i32>,
+    /// Where the meeting is held
+    pub location: Option<String>,
 }
 
 /// Models the relationship of a user between different groups
@@ -9,6 +9,8 @@ use crate::templates::{FormError, OptUser};
 use crate::models::Attendable;
 
 use crate::models::User;
+use crate::semesters::templates::SemesterSelect;
+use crate::tz::Tz;
 
 /// Attendance page template
 ///
@@ -20,12 +22,18 @@ use crate::models::User;
 pub struct GroupTemplate {
     /// Login information for the group
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     /// Group that uses this template
     pub group: Group,
     /// Users this template is for
     pub users: Vec<User>,
-    /// Meetings this group has had
+    /// Meetings this group has had, newest first
     pub meetings: Vec<Meeting>,
+    /// Meetings on the group's schedule that haven't happened yet
+    pub upcoming: Vec<Meeting>,
+    /// Do attendance codes rotate, if so only presenter mode shows them
+    pub rotating: bool,
 }
 
 /// New Group page template
@@ -41,6 +49,8 @@ pub struct NewGroupTemplate {
     pub logged_in: OptUser,
     /// List of all users in group
     pub all_users: Vec<User>,
+    /// Semester the group is part of
+    pub semester: SemesterSelect,
     /// The user gave invalid input so we tell them
     pub error: Option<FormError>,
 }
@@ -59,10 +69,22 @@ pub struct EditGroupTemplate {
     pub group: Group,
     /// List of all users in group
     pub all_users: Vec<User>,
+    /// Semester the group is part of
+    pub semester: SemesterSelect,
     /// The user gave invalid input so we tell them
     pub error: Option<FormError>,
 }
 
+impl EditGroupTemplate {
+    /// Does the group meet on the weekday like `mo`?
+    pub fn meets_on(&self, day: &str) -> bool {
+        self.group
+            .meeting_day
+            .as_deref()
+            .map_or(false, |d| d.eq_ignore_ascii_case(day))
+    }
+}
+
 /// Groups List page template
 ///
 /// HTML file: `group/groups-list.html`
@@ -74,6 +96,8 @@ pub struct GroupsListTemplate {
     pub logged_in: OptUser,
     /// LOgin information for the group
     pub groups: Vec<Group>,
+    /// Semester the groups are shown for
+    pub semester: SemesterSelect,
 }
 
 /// Add User page template
@@ -99,10 +123,18 @@ pub struct AddUserTemplate {
 pub struct MeetingTemplate {
     /// Login information for the group
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     /// Group that contains this meeting
     pub group: Group,
     /// Users this template is for
     pub users: Vec<User>,
     /// Meeting that uses this template
     pub meeting: Meeting,
+    /// Is the attendance code being accepted right now
+    pub code_open_now: bool,
+    /// Is attendance submitted right now late and has to be approved
+    pub code_late_now: bool,
+    /// Do attendance codes rotate, if so only presenter mode shows them
+    pub rotating: bool,
 }
@@ -4,12 +4,17 @@
 //! and are mostly used to validate that the user is logged in and has
 //! permission to view the page they are trying to.
 
-use diesel::prelude::*;
-use rocket::http::Status;
+use std::marker::PhantomData;
+
+use diesel::SqliteConnection;
+use rocket::http::{Cookie, Cookies, SameSite, Status};
 use rocket::request::{self, FromRequest, Request};
-use rocket::Outcome;
+use rocket::{Outcome, State};
 
 use crate::models::User;
+pub use crate::roles::can;
+use crate::roles::RequiredPermission;
+use crate::settings::Settings;
 use crate::ObservDbConn;
 
 /// A user might be logged in
@@ -24,29 +29,45 @@ pub type MaybeLoggedIn = Option<UserGuard>;
 ///
 /// When using this guards and not `MaybeLoggedIn` the user *must* be
 /// logged in to access the page.
+///
+/// Browsers are logged in by the token of a session in the `session`
+/// cookie, see `crate::sessions`. Scripts can log in by sending one of the
+/// user's API tokens in an `Authorization: Bearer <token>` header instead.
 pub struct UserGuard(pub User);
 
 impl<'a, 'r> FromRequest<'a, 'r> for UserGuard {
     type Error = GuardError;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        if let Some(auth) = request.headers().get_one("Authorization") {
+            use crate::api::handlers::token_user;
+            let conn = request.guard::<ObservDbConn>().unwrap();
+            return match auth
+                .strip_prefix("Bearer ")
+                .and_then(|t| token_user(&*conn, t.trim()))
+            {
+                Some(u) => Outcome::Success(Self(u)),
+                None => Outcome::Failure((Status::Unauthorized, GuardError::InvalidToken)),
+            };
+        }
+
+        use crate::sessions::handlers::{session_user, SESSION_COOKIE};
         let mut cookies = request.cookies();
-        match cookies.get_private("user_id") {
-            Some(uid) => {
-                use crate::schema::users::dsl::*;
-                let conn = request.guard::<ObservDbConn>().unwrap();
-                match users
-                    .find(uid.value().parse::<i32>().unwrap())
-                    .first(&*conn)
-                {
-                    Ok(u) => Outcome::Success(Self(u)),
-                    Err(e) => Outcome::Failure((
-                        Status::InternalServerError,
-                        GuardError::DatabaseError(e),
-                    )),
-                }
+        let token = match cookies.get_private(SESSION_COOKIE) {
+            Some(c) => c.value().to_string(),
+            None => return Outcome::Failure((Status::Unauthorized, GuardError::NotLoggedIn)),
+        };
+
+        let conn = request.guard::<ObservDbConn>().unwrap();
+        let settings = request.guard::<State<Settings>>().unwrap();
+        let client = request.guard::<ClientInfo>().unwrap();
+        match session_user(&*conn, &token, &client, settings.session_days) {
+            Some(u) => Outcome::Success(Self(u)),
+            None => {
+                // The session expired or was revoked
+                cookies.remove_private(Cookie::named(SESSION_COOKIE));
+                Outcome::Failure((Status::Unauthorized, GuardError::NotLoggedIn))
             }
-            None => Outcome::Failure((Status::Unauthorized, GuardError::NotLoggedIn)),
         }
     }
 }
@@ -57,55 +78,210 @@ impl UserThroughOption for Option<UserGuard> {
     }
 }
 
-/// Guards page for Mentors
+/// The status for a logged in user who isn't allowed to do something
+///
+/// `Can` fails with this and handlers that check permissions themselves
+/// return it too, so every denial ends up at the same 403 page.
+pub const DENIED: Status = Status::Forbidden;
+
+/// Guards pages for users with a permission
 ///
-/// The user must be logged in **and** be of the Mentor privledge tier (>0)
-/// in order to access the page.
-pub struct MentorGuard(pub User);
+/// The user must be logged in **and** their role must have the permission
+/// named by `P`, one of the types in `can` like `Can<can::ManageNews>`.
+/// If their role requires two-factor authentication they must have set it up.
+pub struct Can<P: RequiredPermission>(pub User, PhantomData<P>);
 
-impl<'a, 'r> FromRequest<'a, 'r> for MentorGuard {
+impl<'a, 'r, P: RequiredPermission> FromRequest<'a, 'r> for Can<P> {
     type Error = GuardError;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
         let u = request.guard::<UserGuard>()?;
-        // 0 is normal user so greater than is mentors and admins
-        if u.0.tier > 0 {
-            Outcome::Success(Self(u.0))
+        if u.0.can(P::PERMISSION) {
+            check_totp(request, u.0).map(|u| Self(u, PhantomData))
         } else {
-            Outcome::Failure((Status::Forbidden, GuardError::NotMentor))
+            Outcome::Failure((DENIED, GuardError::MissingPermission))
         }
     }
 }
 
-impl UserThroughOption for Option<MentorGuard> {
+impl<P: RequiredPermission> UserThroughOption for Option<Can<P>> {
     fn user(self) -> Option<User> {
         self.map(|u| u.0)
     }
 }
 
-/// Guards page for Admins
+/// The public URL of the site
 ///
-/// The user must be logged in **and** be of the Admin privledge tier (>1)
-/// in order to access the page.
-pub struct AdminGuard(pub User);
+/// This is the `site_url` setting if it is set, otherwise it is guessed
+/// from the `Host` header of the request.
+/// Never fails, but it is up to the server configuration to make sure
+/// the `Host` header can be trusted. Don't use it for links in emails,
+/// see `Outbox::send_links`.
+pub struct SiteUrl(pub String);
 
-impl<'a, 'r> FromRequest<'a, 'r> for AdminGuard {
+impl<'a, 'r> FromRequest<'a, 'r> for SiteUrl {
     type Error = GuardError;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
-        let u = request.guard::<UserGuard>()?;
-        // 1 is mentors so greater than is admins
-        if u.0.tier > 1 {
-            Outcome::Success(Self(u.0))
-        } else {
-            Outcome::Failure((Status::Forbidden, GuardError::NotAdmin))
+        if let Outcome::Success(settings) = request.guard::<State<Settings>>() {
+            if let Some(url) = &settings.site_url {
+                return Outcome::Success(Self(url.clone()));
+            }
         }
+
+        let host = request.headers().get_one("Host").unwrap_or("localhost");
+        Outcome::Success(Self(format!("http://{}", host)))
     }
 }
 
-impl UserThroughOption for Option<AdminGuard> {
-    fn user(self) -> Option<User> {
-        self.map(|u| u.0)
+/// Marks a request that was forbidden because the user hasn't set up
+/// two-factor authentication that their role requires
+///
+/// Kept in the request's local cache so the 403 catcher can explain.
+pub struct TotpMissing(pub bool);
+
+/// Fail if the user's role requires two-factor authentication
+/// that they haven't set up
+fn check_totp(request: &Request, user: User) -> request::Outcome<User, GuardError> {
+    use crate::totp::handlers::totp_missing;
+    let conn = request.guard::<ObservDbConn>().unwrap();
+    let settings = request.guard::<State<Settings>>().unwrap();
+    if totp_missing(&*conn, &settings, &user) {
+        request.local_cache(|| TotpMissing(true));
+        Outcome::Failure((DENIED, GuardError::TotpMissing))
+    } else {
+        Outcome::Success(user)
+    }
+}
+
+/// Where a request came from
+///
+/// Recorded with sessions so users can tell them apart. Never fails.
+pub struct ClientInfo {
+    /// IP address of the client, if it is known
+    pub ip: Option<String>,
+    /// The `User-Agent` header, or empty if there isn't one
+    pub user_agent: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = GuardError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(Self {
+            ip: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .unwrap_or_default()
+                .to_string(),
+        })
+    }
+}
+
+/// Name of the cookie kept between the two steps of logging in
+const SECOND_STEP_COOKIE: &str = "second_step";
+
+/// Name of the cookie kept while the user logs in at an OAuth provider
+const OAUTH_COOKIE: &str = "oauth";
+
+/// The session cookie of a request
+///
+/// Used to log a user in or out of the browser making the request.
+/// Never fails.
+pub struct SessionCookie<'a> {
+    cookies: Cookies<'a>,
+    client: ClientInfo,
+    days: i64,
+}
+
+impl<'a> SessionCookie<'a> {
+    /// Start a new session for the user
+    pub fn start(&mut self, conn: &SqliteConnection, user: &User) {
+        use crate::sessions::handlers::start_session;
+        start_session(conn, &mut self.cookies, user, &self.client, self.days);
+    }
+
+    /// End the current session
+    pub fn end(&mut self, conn: &SqliteConnection) {
+        use crate::sessions::handlers::end_session;
+        end_session(conn, &mut self.cookies);
+    }
+
+    /// Remember that the user got their password right but still has to
+    /// enter a two-factor code, for the next 5 minutes
+    pub fn start_second_step(&mut self, user: &User) {
+        let expires = chrono::offset::Utc::now().timestamp() + 5 * 60;
+        self.cookies.add_private(Cookie::new(
+            SECOND_STEP_COOKIE,
+            format!("{}.{}", user.id, expires),
+        ));
+    }
+
+    /// The ID of the user that is between the two steps of logging in
+    pub fn second_step_user(&mut self) -> Option<i32> {
+        let c = self.cookies.get_private(SECOND_STEP_COOKIE)?;
+        let mut parts = c.value().splitn(2, '.');
+        let uid = parts.next()?.parse().ok()?;
+        let expires: i64 = parts.next()?.parse().ok()?;
+        if chrono::offset::Utc::now().timestamp() > expires {
+            return None;
+        }
+        Some(uid)
+    }
+
+    /// Forget the first step of logging in
+    pub fn end_second_step(&mut self) {
+        self.cookies
+            .remove_private(Cookie::named(SECOND_STEP_COOKIE));
+    }
+
+    /// Remember what is needed to finish logging in at an OAuth provider,
+    /// for the next 10 minutes
+    ///
+    /// `link` is the logged in user the account is being linked to.
+    pub fn start_oauth(&mut self, state: &str, verifier: &str, link: Option<i32>, to: &str) {
+        let link = link.map(|uid| uid.to_string()).unwrap_or_default();
+        self.cookies.add_private(
+            Cookie::build(
+                OAUTH_COOKIE,
+                format!("{}.{}.{}.{}", state, verifier, link, to),
+            )
+            .max_age(chrono::Duration::minutes(10))
+            // The provider redirects back from another site
+            .same_site(SameSite::Lax)
+            .finish(),
+        );
+    }
+
+    /// Finish logging in at an OAuth provider if `state` is the one it
+    /// was started with
+    ///
+    /// Returns the PKCE verifier, the user being linked and where to go next.
+    pub fn end_oauth(&mut self, state: &str) -> Option<(String, Option<i32>, String)> {
+        let c = self.cookies.get_private(OAUTH_COOKIE)?;
+        self.cookies.remove_private(Cookie::named(OAUTH_COOKIE));
+
+        let mut parts = c.value().splitn(4, '.');
+        if parts.next()? != state {
+            return None;
+        }
+        let verifier = parts.next()?.to_string();
+        let link = parts.next()?.parse().ok();
+        Some((verifier, link, parts.next()?.to_string()))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for SessionCookie<'a> {
+    type Error = GuardError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let settings = request.guard::<State<Settings>>().unwrap();
+        Outcome::Success(Self {
+            cookies: request.cookies(),
+            client: request.guard::<ClientInfo>().unwrap(),
+            days: settings.session_days,
+        })
     }
 }
 
@@ -115,9 +291,9 @@ impl UserThroughOption for Option<AdminGuard> {
 #[derive(Debug)]
 pub enum GuardError {
     NotLoggedIn,
-    NotMentor,
-    NotAdmin,
-    DatabaseError(diesel::result::Error),
+    MissingPermission,
+    InvalidToken,
+    TotpMissing,
 }
 
 /// Access a user through an Option<Guard>
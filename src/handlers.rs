@@ -6,22 +6,36 @@ use std::fs::read_to_string;
 use std::io::Cursor;
 use std::path::PathBuf;
 
-use rocket::http::ContentType;
+use rocket::http::{ContentType, Status};
 
 use rocket::response::{Redirect, Response};
-use rocket::Request;
+use rocket::{Request, State};
 
+use crate::api::error::{is_api, ApiError};
 use crate::guards::*;
+use crate::settings::Settings;
 use crate::templates::*;
+use crate::tz;
 use crate::ObservDbConn;
 
 // Load all the sub-module's handlers
+pub use crate::api::handlers::*;
+pub use crate::api::v1::*;
 pub use crate::attend::handlers::*;
+pub use crate::audit::handlers::*;
 pub use crate::auth::handlers::*;
 pub use crate::calendar::handlers::*;
+pub use crate::commits::handlers::*;
+pub use crate::feeds::handlers::*;
+pub use crate::grades::handlers::*;
 pub use crate::groups::handlers::*;
 pub use crate::news::handlers::*;
+pub use crate::oauth::handlers::*;
 pub use crate::projects::handlers::*;
+pub use crate::semesters::handlers::*;
+pub use crate::sessions::handlers::*;
+pub use crate::totp::handlers::*;
+pub use crate::trash::handlers::*;
 pub use crate::users::handlers::*;
 
 /// GET handler for `/`
@@ -35,7 +49,7 @@ pub fn index(conn: ObservDbConn, l: MaybeLoggedIn) -> IndexTemplate {
             use crate::news::models::NewsStory;
             use crate::schema::news::dsl::*;
             use diesel::prelude::*;
-            news.filter(announcement.eq(true))
+            news.filter(announcement.eq(true).and(deleted_at.is_null()))
                 .order(happened_at.desc())
                 .first::<NewsStory>(&*conn)
                 .optional()
@@ -50,20 +64,41 @@ pub fn big(l: MaybeLoggedIn, text: String) -> BigTemplate {
     BigTemplate {
         logged_in: l.user(),
         text,
+        qr: None,
+        refresh: None,
     }
 }
 
 /// GET handler for `/dashboard`
 ///
 /// The logged in user's dashboard showing their groups, projects, and attendance
-#[get("/dashboard")]
-pub fn dashboard(conn: ObservDbConn, l: UserGuard) -> DashboardTemplate {
+/// in the selected semester
+#[get("/dashboard?<sem>")]
+pub fn dashboard(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    sem: Option<String>,
+) -> DashboardTemplate {
+    use crate::projects::handlers::requests_for;
+    use crate::semesters::handlers::semester_select;
     use crate::users::handlers::{grade_summary, user_groups, user_projects};
+    let site = settings.site_zone();
+    let semester = semester_select(&*conn, sem, &site);
     DashboardTemplate {
-        summary: grade_summary(&*conn, &l.0),
-        projects: user_projects(&*conn, &l.0),
-        groups: user_groups(&*conn, &l.0),
+        summary: grade_summary(&*conn, &l.0, semester.selected.as_ref(), &site),
+        projects: user_projects(&*conn, &l.0)
+            .into_iter()
+            .filter(|p| semester.includes(p.semester_id))
+            .collect(),
+        groups: user_groups(&*conn, &l.0)
+            .into_iter()
+            .filter(|g| semester.includes(g.semester_id))
+            .collect(),
+        requests: requests_for(&*conn, &l.0),
+        zone: tz::of(Some(&l.0), &site),
         logged_in: Some(l.0),
+        semester,
     }
 }
 
@@ -79,7 +114,7 @@ pub fn sitemap() -> SitemapTemplate {
 #[get("/logs?<file>")]
 pub fn log_viewer(
     _conn: ObservDbConn,
-    l: AdminGuard,
+    l: Can<can::ViewLogs>,
     file: Option<String>,
 ) -> std::io::Result<LogViewerTemplate> {
     let log_dir = PathBuf::from(crate::LOG_DIR);
@@ -87,7 +122,7 @@ pub fn log_viewer(
         log_dir
             .join(file)
             .canonicalize()
-            .and_then(|path| read_to_string(path))
+            .and_then(read_to_string)
             .ok()
     });
     Ok(LogViewerTemplate {
@@ -147,13 +182,22 @@ pub fn favicon() -> Redirect {
 
 //# # Error Catchers
 
+// The catchers respond to the API with a JSON error instead
+
 /// Catch 401 errors
 ///
 /// Redirects the user to the login page when they try to go to a page that
-/// requires login.
+/// requires login. The whole URI is kept so they end up back where they were.
 #[catch(401)]
-pub fn catch_401(req: &Request) -> Redirect {
-    Redirect::to(format!("/login?to={}", req.uri().path()))
+pub fn catch_401(req: &Request) -> Result<Redirect, ApiError> {
+    use rocket::http::uri::Uri;
+    if is_api(req) {
+        return Err(Status::Unauthorized.into());
+    }
+    Ok(Redirect::to(format!(
+        "/login?to={}",
+        Uri::percent_encode(&req.uri().to_string())
+    )))
 }
 
 /// Catch 403 errors
@@ -161,20 +205,27 @@ pub fn catch_401(req: &Request) -> Redirect {
 /// A nice page for 403 errors when the user doesn't have access to the
 /// page they are trying to visit.
 #[catch(403)]
-pub fn catch_403(req: &Request) -> Error403Template {
+pub fn catch_403(req: &Request) -> Result<Error403Template, ApiError> {
+    if is_api(req) {
+        return Err(Status::Forbidden.into());
+    }
     let l = req.guard::<MaybeLoggedIn>().unwrap();
-    Error403Template {
+    Ok(Error403Template {
         logged_in: l.user(),
-    }
+        totp_missing: req.local_cache(|| TotpMissing(false)).0,
+    })
 }
 
 /// Catch 404 errors
 ///
 /// A nice page for 404 errors
 #[catch(404)]
-pub fn catch_404(req: &Request) -> Error404Template {
+pub fn catch_404(req: &Request) -> Result<Error404Template, ApiError> {
+    if is_api(req) {
+        return Err(Status::NotFound.into());
+    }
     let l = req.guard::<MaybeLoggedIn>().unwrap();
-    Error404Template {
+    Ok(Error404Template {
         logged_in: l.user(),
-    }
+    })
 }
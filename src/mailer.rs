@@ -0,0 +1,226 @@
+//! Sending email
+//!
+//! Emails are sent through a `Mailer`, which one is picked by the `mailer`
+//! setting:
+//!
+//! - `smtp` sends them through the SMTP server in `smtp_host`
+//! - `file` writes each one to a file in `mail_dir`
+//! - `stdout` prints them, this is the default
+//!
+//! The file and stdout mailers are meant for development and tests.
+//!
+//! The `Outbox` is managed by Rocket and can be accessed from a handler
+//! using `State<Outbox>`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use lettre::smtp::authentication::Credentials;
+use lettre::smtp::client::net::DEFAULT_TLS_PROTOCOLS;
+use lettre::{
+    ClientSecurity, ClientTlsParameters, EmailAddress, Envelope, SendableEmail, SmtpClient,
+    Transport,
+};
+use log::*;
+use native_tls::TlsConnector;
+
+use crate::settings::Settings;
+
+/// A plain text email
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// The whole message with its headers
+    pub fn message(&self) -> String {
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+             MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: 8bit\r\n\r\n{}\r\n",
+            header(&self.from),
+            header(&self.to),
+            header(&self.subject),
+            chrono::offset::Utc::now().to_rfc2822(),
+            self.body.replace("\r\n", "\n").replace('\n', "\r\n"),
+        )
+    }
+}
+
+/// Something that can send emails
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+/// Sends emails through an SMTP server
+///
+/// Port 465 uses TLS from the start, any other port has to support STARTTLS.
+pub struct SmtpMailer {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), String> {
+        let address = |a: &str| EmailAddress::new(bare_address(a)).map_err(|e| e.to_string());
+        let envelope = Envelope::new(Some(address(&email.from)?), vec![address(&email.to)?])
+            .map_err(|e| e.to_string())?;
+
+        let mut tls = TlsConnector::builder();
+        tls.min_protocol_version(Some(DEFAULT_TLS_PROTOCOLS[0]));
+        let tls =
+            ClientTlsParameters::new(self.host.clone(), tls.build().map_err(|e| e.to_string())?);
+        let security = if self.port == 465 {
+            ClientSecurity::Wrapper(tls)
+        } else {
+            ClientSecurity::Required(tls)
+        };
+
+        let mut client = SmtpClient::new((self.host.as_str(), self.port), security)
+            .map_err(|e| e.to_string())?
+            .timeout(Some(Duration::from_secs(30)));
+        if let Some((user, pass)) = &self.credentials {
+            client = client.credentials(Credentials::new(user.clone(), pass.clone()));
+        }
+
+        let id = format!("{}", chrono::offset::Utc::now().timestamp_nanos());
+        client
+            .transport()
+            .send(SendableEmail::new(
+                envelope,
+                id,
+                email.message().into_bytes(),
+            ))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Writes each email to its own `.eml` file in a directory
+pub struct FileMailer {
+    pub dir: PathBuf,
+    count: AtomicUsize,
+}
+
+impl FileMailer {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            count: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        // The counter keeps emails sent at the same time apart
+        let name = format!(
+            "{}-{}.eml",
+            chrono::offset::Utc::now().format("%Y%m%d%H%M%S"),
+            self.count.fetch_add(1, Ordering::SeqCst)
+        );
+        fs::write(self.dir.join(name), email.message()).map_err(|e| e.to_string())
+    }
+}
+
+/// Prints emails to `stdout`
+pub struct StdoutMailer;
+
+impl Mailer for StdoutMailer {
+    fn send(&self, email: &Email) -> Result<(), String> {
+        println!("{}", email.message());
+        Ok(())
+    }
+}
+
+/// Where handlers send emails from
+pub struct Outbox {
+    from: String,
+    site_url: Option<String>,
+    mailer: Box<dyn Mailer>,
+}
+
+impl Outbox {
+    pub fn new(from: String, site_url: Option<String>, mailer: Box<dyn Mailer>) -> Self {
+        Self {
+            from,
+            site_url,
+            mailer,
+        }
+    }
+
+    /// Create the outbox with the mailer picked by the settings
+    pub fn from_settings(settings: &Settings) -> Self {
+        let mailer: Box<dyn Mailer> = match (settings.mailer.as_str(), &settings.smtp_host) {
+            ("smtp", Some(host)) => Box::new(SmtpMailer {
+                host: host.clone(),
+                port: settings.smtp_port as u16,
+                credentials: settings
+                    .smtp_username
+                    .clone()
+                    .map(|u| (u, settings.smtp_password.clone().unwrap_or_default())),
+            }),
+            ("file", _) => Box::new(FileMailer::new(&settings.mail_dir)),
+            ("stdout", _) => Box::new(StdoutMailer),
+            (m, _) => {
+                warn!("Mailer {} can't be used, printing emails instead", m);
+                Box::new(StdoutMailer)
+            }
+        };
+        Self::new(
+            settings.mail_from.clone(),
+            settings.site_url.clone(),
+            mailer,
+        )
+    }
+
+    /// Send an email
+    ///
+    /// Failures are logged rather than returned since the emails sent are
+    /// links that the user can always ask for again.
+    pub fn send(&self, to: &str, subject: &str, body: String) {
+        let email = Email {
+            from: self.from.clone(),
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body,
+        };
+        if let Err(e) = self.mailer.send(&email) {
+            error!("Failed to send email to {}: {}", to, e);
+        }
+    }
+
+    /// Send an email with links to the site
+    ///
+    /// `body` builds the email from the `site_url` setting. Links in emails
+    /// never use the `Host` of the request like `SiteUrl` can, since anyone
+    /// can forge it to send a working link to another site. Nothing is sent
+    /// if `site_url` isn't set.
+    pub fn send_links<F: FnOnce(&str) -> String>(&self, to: &str, subject: &str, body: F) {
+        match &self.site_url {
+            Some(site) => self.send(to, subject, body(site)),
+            None => error!("Not sending \"{}\" to {}: site_url isn't set", subject, to),
+        }
+    }
+}
+
+/// Keep a header on one line so it can't add headers of its own
+fn header(s: &str) -> String {
+    s.replace(|c| c == '\r' || c == '\n', " ")
+}
+
+/// The address in something like `Observatory <observatory@example.edu>`
+fn bare_address(s: &str) -> String {
+    match (s.find('<'), s.rfind('>')) {
+        (Some(start), Some(end)) if start < end => s[start + 1..end].trim().to_string(),
+        _ => s.trim().to_string(),
+    }
+}
@@ -34,8 +34,6 @@ extern crate serde_derive;
 extern crate diesel_migrations;
 extern crate flexi_logger;
 extern crate log;
-#[macro_use]
-extern crate diesel_derive_newtype;
 
 #[macro_use]
 mod macros {
@@ -46,24 +44,87 @@ mod macros {
             $crate::info!(target: "{Audit}", "{}", format_args!($($arg)*));
         )
     }
+
+    /// Stores an enum in a `TEXT` column by its `key`
+    #[macro_export]
+    macro_rules! text_enum {
+        ($t:ident { $($v:ident = $key:literal,)* }) => {
+            impl $t {
+                /// Every value
+                pub const ALL: &'static [$t] = &[$($t::$v),*];
+
+                /// The name stored in the database
+                pub fn key(self) -> &'static str {
+                    match self {
+                        $($t::$v => $key,)*
+                    }
+                }
+            }
+
+            const _: () = {
+                use diesel::backend::Backend;
+                use diesel::deserialize::{self, FromSql};
+                use diesel::serialize::{self, Output, ToSql};
+                use diesel::sql_types::Text;
+                use diesel::sqlite::Sqlite;
+                use std::io::Write;
+                use std::str::FromStr;
+
+                impl FromStr for $t {
+                    type Err = ();
+
+                    fn from_str(s: &str) -> Result<Self, ()> {
+                        $t::ALL.iter().copied().find(|v| v.key() == s).ok_or(())
+                    }
+                }
+
+                impl ToSql<Text, Sqlite> for $t {
+                    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+                        <str as ToSql<Text, Sqlite>>::to_sql(self.key(), out)
+                    }
+                }
+
+                impl FromSql<Text, Sqlite> for $t {
+                    fn from_sql(bytes: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+                        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+                        s.parse().map_err(|_| format!("Unknown {} {}", stringify!($t), s).into())
+                    }
+                }
+            };
+        };
+    }
 }
 
 // Module files
 mod fairings;
 mod guards;
 mod handlers;
+mod mailer;
+mod roles;
 mod schema;
+mod settings;
 mod templates;
 #[cfg(test)]
 mod tests;
+mod tz;
 
 // Table Modules
+mod api;
 mod attend;
+mod audit;
 mod auth;
 mod calendar;
+mod commits;
+mod feeds;
+mod grades;
 mod groups;
 mod news;
+mod oauth;
 mod projects;
+mod semesters;
+mod sessions;
+mod totp;
+mod trash;
 mod users;
 
 use flexi_logger::{opt_format, writers::FileLogWriter, Logger};
@@ -98,7 +159,10 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
     use handlers::*;
 
     // Load the fairings
-    use fairings::{AdminCheck, ConfigWrite, DatabaseCreate};
+    use fairings::{
+        AdminCheck, CommitSync, ConfigWrite, DatabaseCreate, LoadSettings, MeetingSchedule,
+        TrashPurge,
+    };
 
     let app = if let Some(test_config) = test_config {
         rocket::custom(test_config)
@@ -109,8 +173,12 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
     // Prepare webserver
     app.attach(ConfigWrite)
         // Attach fairings
+        .attach(LoadSettings)
         .attach(DatabaseCreate)
         .attach(AdminCheck)
+        .attach(CommitSync)
+        .attach(TrashPurge)
+        .attach(MeetingSchedule)
         .attach(ObservDbConn::fairing())
         // Register Catchers
         .register(catchers![catch_401, catch_403, catch_404])
@@ -126,6 +194,13 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
                 dashboard,
                 sitemap,
                 log_viewer,
+                // Audit Trail
+                audit,
+                audit_json,
+                // Trash
+                trash,
+                trash_restore,
+                trash_purge,
                 // Calendar
                 calendar,
                 calendar_json,
@@ -133,6 +208,10 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
                 event,
                 event_edit,
                 event_edit_put,
+                event_code_put,
+                event_present,
+                event_rsvp_put,
+                event_rsvps_csv,
                 event_delete,
                 event_new,
                 event_new_post,
@@ -142,9 +221,19 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
                 login,
                 login_post,
                 logout,
+                forgot,
+                forgot_post,
+                reset,
+                reset_post,
+                verify,
+                verify_post,
                 // Attendance
                 attend,
                 attend_post,
+                attend_pending,
+                attend_pending_post,
+                attend_approve,
+                attend_reject,
                 // Users
                 user,
                 user_by_handle,
@@ -152,7 +241,40 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
                 users_json,
                 user_edit,
                 user_edit_put,
+                user_time_zone_put,
                 user_delete,
+                // API Tokens
+                tokens,
+                tokens_post,
+                token_delete,
+                // Calendar Feeds
+                feeds,
+                feeds_post,
+                feed_delete,
+                user_calendar,
+                group_calendar,
+                // Sessions
+                sessions,
+                session_delete,
+                sessions_delete,
+                // Two-Factor Authentication
+                login_totp,
+                login_totp_post,
+                totp,
+                totp_post,
+                totp_recovery_post,
+                totp_delete,
+                oauth_login,
+                oauth_callback,
+                accounts,
+                account_delete,
+                // Commit Identities
+                identities,
+                identities_post,
+                identity_verify,
+                identity_delete,
+                unattributed,
+                unattributed_post,
                 // Projects
                 project,
                 project_by_handle,
@@ -170,6 +292,11 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
                 project_member_add,
                 project_member_add_post,
                 project_member_delete,
+                project_member_role,
+                project_transfer,
+                project_request_answer,
+                milestone_new_post,
+                milestone_delete,
                 // Groups
                 group,
                 groups,
@@ -184,8 +311,25 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
                 meeting_get,
                 meetings_json,
                 meeting_new_post,
+                meeting_code_put,
+                meeting_cancel_put,
+                meeting_present,
                 group_edit,
                 group_edit_put,
+                // Semesters
+                semesters,
+                semester,
+                semester_new_post,
+                semester_edit_put,
+                semester_delete,
+                semester_enroll_post,
+                semester_enroll_put,
+                semester_enroll_delete,
+                semester_rollover,
+                // Grades
+                grade_report,
+                grade_report_csv,
+                rubric_put,
                 // News
                 news,
                 news_json,
@@ -199,6 +343,58 @@ pub fn rocket(test_config: Option<rocket::Config>) -> rocket::Rocket {
                 story_edit_put,
             ],
         )
+        .mount(
+            "/api/v1",
+            routes![
+                api_me,
+                // Users
+                api_users,
+                api_user,
+                api_user_new,
+                api_user_edit,
+                api_user_delete,
+                // Projects
+                api_projects,
+                api_project,
+                api_project_members,
+                api_project_new,
+                api_project_edit,
+                api_project_delete,
+                // Groups
+                api_groups,
+                api_group,
+                api_group_members,
+                api_group_new,
+                api_group_edit,
+                api_group_delete,
+                // Meetings
+                api_meetings,
+                api_meeting,
+                api_meeting_attendees,
+                api_meeting_new,
+                api_meeting_edit,
+                api_meeting_delete,
+                // Events
+                api_events,
+                api_event,
+                api_event_new,
+                api_event_edit,
+                api_event_delete,
+                // News
+                api_news,
+                api_story,
+                api_story_new,
+                api_story_edit,
+                api_story_delete,
+                // Attendances
+                api_attendances,
+                api_attendances_pending,
+                api_attendance,
+                api_attendance_new,
+                api_attendance_approve,
+                api_attendance_reject,
+            ],
+        )
 }
 
 /// The main function that starts the program
@@ -234,12 +430,16 @@ pub mod models {
     use chrono::NaiveDateTime;
     use std::fmt::Debug;
 
+    use crate::tz::Tz;
+
     // Import then re-export all models
     pub use crate::attend::models::*;
     pub use crate::calendar::models::*;
+    pub use crate::grades::models::*;
     pub use crate::groups::models::*;
     pub use crate::news::models::*;
     pub use crate::projects::models::*;
+    pub use crate::semesters::models::*;
     pub use crate::users::models::*;
 
     /// Represents anything that can be attended such as meetings and events.
@@ -249,8 +449,18 @@ pub mod models {
     /// structs that are Attendable.
     pub trait Attendable: Debug {
         fn id(&self) -> i32;
-        fn name(&self) -> String;
+        /// What it is and when, with the time shown in `zone`
+        fn name(&self, zone: Tz) -> String;
         fn time(&self) -> NaiveDateTime;
+        /// The time the attendance code stops being accepted
+        ///
+        /// The code starts being accepted at `time()`.
+        fn closes_at(&self) -> NaiveDateTime;
+        /// Manual override of the attendance code window by the host
+        ///
+        /// `None` means the code follows the window, otherwise the code is
+        /// forced open or closed.
+        fn code_open(&self) -> Option<bool>;
         fn code(&self) -> String;
         fn owner_id(&self) -> i32;
         fn group_id(&self) -> Option<i32> {
@@ -258,5 +468,9 @@ pub mod models {
         }
         fn is_event(&self) -> bool;
         fn url(&self) -> String;
+        /// Cancelled meetings excuse everyone, so they don't count towards grades
+        fn cancelled(&self) -> bool {
+            false
+        }
     }
 }
@@ -1,25 +1,35 @@
 use diesel::prelude::*;
-use diesel::{delete, insert_into, update};
+use diesel::{insert_into, update};
 use rocket::request::Form;
 use rocket::response::Redirect;
+use rocket::State;
 
 use rocket::http::ContentType;
 use rocket::response::Content;
 use rocket_contrib::json::Json;
 
+use crate::api::v1::last_id;
+use crate::audit;
 use crate::guards::*;
+use crate::settings::Settings;
 use crate::templates::{is_reserved, FormError};
+use crate::trash::handlers::move_to_trash;
+use crate::trash::models::Kind;
+use crate::tz;
 use crate::ObservDbConn;
 
 use super::models::*;
 use super::templates::*;
 
 #[get("/news")]
-pub fn news(conn: ObservDbConn, l: MaybeLoggedIn) -> NewsTemplate {
+pub fn news(conn: ObservDbConn, l: MaybeLoggedIn, settings: State<Settings>) -> NewsTemplate {
     use crate::schema::news::dsl::*;
+    let logged_in = l.user();
     NewsTemplate {
-        logged_in: l.user(),
+        zone: tz::of(logged_in.as_ref(), &settings.site_zone()),
+        logged_in,
         stories: news
+            .filter(deleted_at.is_null())
             .order(happened_at.desc())
             .load(&*conn)
             .expect("Failed to get news from database"),
@@ -30,18 +40,22 @@ pub fn news(conn: ObservDbConn, l: MaybeLoggedIn) -> NewsTemplate {
 pub fn news_json(conn: ObservDbConn, _l: MaybeLoggedIn) -> Json<Vec<NewsStory>> {
     use crate::schema::news::dsl::*;
     Json(
-        news.order(happened_at.desc())
+        news.filter(deleted_at.is_null())
+            .order(happened_at.desc())
             .load(&*conn)
             .expect("Failed to get news from database"),
     )
 }
 
 #[get("/news.xml")]
-pub fn news_rss(conn: ObservDbConn) -> Content<String> {
+pub fn news_rss(conn: ObservDbConn, settings: State<Settings>) -> Content<String> {
     use crate::schema::news::dsl::*;
-    use rss;
 
-    let all_news: Vec<NewsStory> = news.load(&*conn).expect("Failed to get news from database");
+    let all_news: Vec<NewsStory> = news
+        .filter(deleted_at.is_null())
+        .load(&*conn)
+        .expect("Failed to get news from database");
+    let site = settings.site_zone();
     let items: Vec<rss::Item> = all_news
         .iter()
         .map(|story| {
@@ -57,7 +71,7 @@ pub fn news_rss(conn: ObservDbConn) -> Content<String> {
                 })
                 .link(link)
                 .guid(guid)
-                .pub_date(story.happened_at.format("%a, %d %b %Y %T EST").to_string())
+                .pub_date(site.to_datetime(&story.happened_at).to_rfc2822())
                 .build()
                 .expect("Failed to build RSS Item")
         })
@@ -77,19 +91,32 @@ pub fn news_rss(conn: ObservDbConn) -> Content<String> {
 }
 
 #[get("/news/<nid>")]
-pub fn story(conn: ObservDbConn, l: MaybeLoggedIn, nid: i32) -> NewsStoryTemplate {
+pub fn story(
+    conn: ObservDbConn,
+    l: MaybeLoggedIn,
+    settings: State<Settings>,
+    nid: i32,
+) -> Option<NewsStoryTemplate> {
     use crate::schema::news::dsl::*;
-    NewsStoryTemplate {
-        logged_in: l.user(),
+    let logged_in = l.user();
+    Some(NewsStoryTemplate {
+        zone: tz::of(logged_in.as_ref(), &settings.site_zone()),
+        logged_in,
         story: news
             .find(nid)
+            .filter(deleted_at.is_null())
             .first(&*conn)
-            .expect("Failed to get news event from database"),
-    }
+            .optional()
+            .expect("Failed to get news event from database")?,
+    })
 }
 
 #[get("/news/new?<e>")]
-pub fn story_new(_conn: ObservDbConn, l: AdminGuard, e: Option<FormError>) -> NewNewsStoryTemplate {
+pub fn story_new(
+    _conn: ObservDbConn,
+    l: Can<can::ManageNews>,
+    e: Option<FormError>,
+) -> NewNewsStoryTemplate {
     NewNewsStoryTemplate {
         logged_in: Some(l.0),
         error: e,
@@ -99,13 +126,17 @@ pub fn story_new(_conn: ObservDbConn, l: AdminGuard, e: Option<FormError>) -> Ne
 #[post("/news/new", data = "<newnewsstory>")]
 pub fn story_new_post(
     conn: ObservDbConn,
-    _l: AdminGuard,
+    l: Can<can::ManageNews>,
+    settings: State<Settings>,
     newnewsstory: Form<NewNewsStory>,
 ) -> Redirect {
     use crate::schema::news::dsl::*;
 
     let mut newnewsstory = newnewsstory.into_inner();
-    if newnewsstory.fix_times().is_none() {
+    if newnewsstory
+        .fix_times(&tz::of(Some(&l.0), &settings.site_zone()))
+        .is_none()
+    {
         return Redirect::to(format!("/news/new?e={}", FormError::InvalidDate));
     }
     if let Err(e) = is_reserved(&newnewsstory.title) {
@@ -117,21 +148,30 @@ pub fn story_new_post(
         .execute(&*conn)
         .expect("Failed to insert news story into database");
 
+    let story: NewsStory = news
+        .find(last_id(&*conn))
+        .first(&*conn)
+        .expect("Failed to get news story from database");
+    audit::created(&*conn, Some(&l.0), "news", story.id, &story);
+
     Redirect::to("/news")
 }
 
 #[get("/news/<nid>/edit?<e>")]
 pub fn story_edit(
     conn: ObservDbConn,
-    l: AdminGuard,
+    l: Can<can::ManageNews>,
+    settings: State<Settings>,
     nid: i32,
     e: Option<FormError>,
 ) -> EditNewsStoryTemplate {
     use crate::schema::news::dsl::*;
     EditNewsStoryTemplate {
+        zone: tz::of(Some(&l.0), &settings.site_zone()),
         logged_in: Some(l.0),
         story: news
             .find(nid)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .expect("Failed to load news story from database"),
         error: e,
@@ -141,34 +181,54 @@ pub fn story_edit(
 #[put("/news/<nid>", data = "<editnewsstory>")]
 pub fn story_edit_put(
     conn: ObservDbConn,
-    _l: AdminGuard,
+    l: Can<can::ManageNews>,
+    settings: State<Settings>,
     editnewsstory: Form<NewNewsStory>,
     nid: i32,
 ) -> Redirect {
     use crate::schema::news::dsl::*;
 
     let mut editnewsstory = editnewsstory.into_inner();
-    if editnewsstory.fix_times().is_none() {
+    if editnewsstory
+        .fix_times(&tz::of(Some(&l.0), &settings.site_zone()))
+        .is_none()
+    {
         return Redirect::to(format!("/news/{}/edit?e={}", nid, FormError::InvalidDate));
     }
     if let Err(e) = is_reserved(&editnewsstory.title) {
         return Redirect::to(format!("/news/{}/edit?e={}", nid, e));
     }
 
+    let old: NewsStory = news
+        .find(nid)
+        .first(&*conn)
+        .expect("Failed to get news story from database");
     update(news.find(nid))
         .set(&editnewsstory)
         .execute(&*conn)
         .expect("Failed to update news story in database");
+    let new: NewsStory = news
+        .find(nid)
+        .first(&*conn)
+        .expect("Failed to get news story from database");
+    audit::updated(&*conn, Some(&l.0), "news", nid, &old, &new);
 
     Redirect::to(format!("/news/{}", nid))
 }
 
 #[delete("/news/<nid>")]
-pub fn story_delete(conn: ObservDbConn, _l: AdminGuard, nid: i32) -> Redirect {
+pub fn story_delete(conn: ObservDbConn, l: Can<can::ManageNews>, nid: i32) -> Redirect {
     use crate::schema::news::dsl::*;
-    delete(news.find(nid))
-        .execute(&*conn)
-        .expect("Failed to delete news story from database");
+    let old: Option<NewsStory> = news
+        .find(nid)
+        .filter(deleted_at.is_null())
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get news story from database");
+    move_to_trash(&*conn, Kind::News, nid);
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "news", nid, &old);
+    }
     Redirect::to("/news")
 }
 
@@ -183,17 +243,18 @@ pub fn news_summary(conn: &SqliteConnection) -> (Vec<Event>, Vec<NewsStory>) {
     (
         {
             use crate::schema::events::dsl::*;
-            let now = chrono::offset::Local::now().format("%F %R").to_string();
+            let now = chrono::offset::Utc::now().naive_utc();
             events
-                .order(start.desc())
-                .filter(start.gt(now))
+                .order(start.asc())
+                .filter(start.gt(now).and(deleted_at.is_null()))
                 .limit(5)
                 .load(&*conn)
                 .expect("Failed to get news from database")
         },
         {
             use crate::schema::news::dsl::*;
-            news.order(happened_at.desc())
+            news.filter(deleted_at.is_null())
+                .order(happened_at.desc())
                 .limit(5)
                 .load(&*conn)
                 .expect("Failed to get news from database")
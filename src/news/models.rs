@@ -6,15 +6,19 @@ use crate::schema::*;
 #[table_name = "news"]
 pub struct NewsStory {
     pub id: i32,
+    #[serde(serialize_with = "crate::tz::serialize_utc")]
     pub happened_at: NaiveDateTime,
     pub title: String,
     pub description: String,
     pub color: Option<String>,
     pub announcement: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset)]
+#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset, Deserialize)]
 #[table_name = "news"]
+#[serde(default)]
 pub struct NewNewsStory {
     pub happened_at: String,
     pub title: String,
@@ -23,10 +27,12 @@ pub struct NewNewsStory {
     pub announcement: bool,
 }
 
-use crate::calendar::models::smart_time_parse;
+use crate::calendar::models::parse_time;
+use crate::tz::Tz;
 impl NewNewsStory {
-    pub fn fix_times(&mut self) -> Option<()> {
-        self.happened_at = smart_time_parse(&self.happened_at)?
+    /// Puts the time in UTC, times without an offset are in the time zone `tz`
+    pub fn fix_times(&mut self, tz: &Tz) -> Option<()> {
+        self.happened_at = parse_time(&self.happened_at, tz)?
             .format("%F %R")
             .to_string();
         Some(())
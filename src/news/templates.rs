@@ -2,11 +2,14 @@ use super::models::*;
 #[allow(unused_imports)]
 use crate::templates::filters;
 use crate::templates::{FormError, OptUser};
+use crate::tz::Tz;
 
 #[derive(Template)]
 #[template(path = "news/news.html")]
 pub struct NewsTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub stories: Vec<NewsStory>,
 }
 
@@ -14,6 +17,8 @@ pub struct NewsTemplate {
 #[template(path = "news/newsstory.html")]
 pub struct NewsStoryTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub story: NewsStory,
 }
 
@@ -28,6 +33,8 @@ pub struct NewNewsStoryTemplate {
 #[template(path = "news/edit-newsstory.html")]
 pub struct EditNewsStoryTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub story: NewsStory,
     pub error: Option<FormError>,
 }
@@ -0,0 +1,373 @@
+//! HTTP handlers for logging in with OAuth providers
+
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use log::warn;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::State;
+
+use crate::audit;
+use crate::auth::handlers::finish_login;
+use crate::guards::*;
+use crate::models::User;
+use crate::roles::Permission;
+use crate::settings::Settings;
+use crate::templates::{is_reserved, FormError};
+use crate::tz;
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::provider::*;
+use super::templates::*;
+
+/// GET handler for `/login/oauth/<pid>`
+///
+/// Sends the user to log in at the provider. If they are already logged
+/// in then the account they log in with is linked to them instead.
+#[get("/login/oauth/<pid>?<to>")]
+pub fn oauth_login(
+    l: MaybeLoggedIn,
+    mut session: SessionCookie,
+    settings: State<Settings>,
+    site: SiteUrl,
+    pid: String,
+    to: Option<String>,
+) -> Option<Redirect> {
+    let provider = find_provider(&settings, &pid)?;
+
+    let state = gen_secret();
+    let verifier = gen_secret();
+    let link = l.user().map(|u| u.id);
+    session.start_oauth(&state, &verifier, link, to.as_deref().unwrap_or("/"));
+
+    Some(Redirect::to(provider.authorize(
+        &callback_url(&site, provider),
+        &state,
+        &verifier,
+    )))
+}
+
+/// GET handler for `/login/oauth/<pid>/callback`
+///
+/// Where the provider sends the user back to. Logs in the user the account
+/// is linked to, linking it to the user with the same verified email the
+/// first time. GitHub accounts also verify the user's handle.
+#[get("/login/oauth/<pid>/callback?<code>&<state>")]
+pub fn oauth_callback(
+    conn: ObservDbConn,
+    mut session: SessionCookie,
+    settings: State<Settings>,
+    site: SiteUrl,
+    pid: String,
+    code: Option<String>,
+    state: Option<String>,
+) -> Option<Redirect> {
+    let provider = find_provider(&settings, &pid)?;
+    let failed = |e: FormError| Some(Redirect::to(format!("/login?e={}", e)));
+
+    // The state is missing or different if the request didn't come from
+    // the login this browser started
+    let (verifier, link, to) = match state.and_then(|s| session.end_oauth(&s)) {
+        Some(s) => s,
+        None => return failed(FormError::OAuth),
+    };
+    // There is no code if the user said no at the provider
+    let code = match code {
+        Some(c) => c,
+        None => return failed(FormError::OAuth),
+    };
+
+    let profile = match provider
+        .exchange(&code, &callback_url(&site, provider), &verifier)
+        .and_then(|token| provider.profile(&token))
+    {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("Failed to log in with {}: {:?}", provider.id, e);
+            return failed(FormError::OAuth);
+        }
+    };
+
+    let linked: Option<OAuthIdentity> = {
+        use crate::schema::oauth_identities::dsl;
+        dsl::oauth_identities
+            .filter(
+                dsl::provider
+                    .eq(&provider.id)
+                    .and(dsl::subject.eq(&profile.subject)),
+            )
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get linked account from database")
+    };
+
+    let uid = match (linked, link) {
+        (Some(i), Some(uid)) if i.user_id != uid => {
+            return Some(Redirect::to(format!(
+                "/users/{}/accounts?e={}",
+                uid,
+                FormError::AccountLinked
+            )));
+        }
+        (Some(i), _) => {
+            use crate::schema::oauth_identities::dsl::*;
+            update(oauth_identities.find(i.id))
+                .set(login.eq(&profile.login))
+                .execute(&*conn)
+                .expect("Failed to update linked account in database");
+            i.user_id
+        }
+        (None, Some(uid)) => {
+            link_account(&*conn, provider, uid, &profile);
+            uid
+        }
+        (None, None) => match email_user(&*conn, &profile) {
+            Some(u) if u.verified => {
+                link_account(&*conn, provider, u.id, &profile);
+                u.id
+            }
+            // Otherwise whoever signed up with the email could take over
+            // the account of the person who owns it
+            Some(_) => return failed(FormError::Unverified),
+            None => return failed(FormError::NoAccount),
+        },
+    };
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get user from database")?
+    };
+    if let (ProviderKind::GitHub, Some(l)) = (provider.kind, &profile.login) {
+        claim_handle(&*conn, &user, l);
+    }
+
+    if link.is_some() {
+        Some(Redirect::to(format!("/users/{}/accounts", uid)))
+    } else {
+        Some(finish_login(&*conn, &mut session, &settings, &user, to))
+    }
+}
+
+/// GET handler for `/users/<uid>/accounts`
+///
+/// Lists the accounts a user can log in with and offers to link more.
+///
+/// Restricted to Admins and the user themselves.
+#[get("/users/<uid>/accounts?<e>")]
+pub fn accounts(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+    e: Option<FormError>,
+) -> Result<AccountsTemplate, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get user from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    let linked: Vec<OAuthIdentity> = {
+        use crate::schema::oauth_identities::dsl::*;
+        OAuthIdentity::belonging_to(&user)
+            .order(provider.asc())
+            .load(&*conn)
+            .expect("Failed to get linked accounts from database")
+    };
+
+    Ok(AccountsTemplate {
+        zone: tz::of(Some(&l.0), &settings.site_zone()),
+        logged_in: Some(l.0),
+        user,
+        accounts: linked
+            .into_iter()
+            .map(|a| {
+                let name = find_provider(&settings, &a.provider)
+                    .map_or_else(|| a.provider.clone(), |p| p.name.clone());
+                (a, name)
+            })
+            .collect(),
+        providers: settings
+            .oauth_providers
+            .iter()
+            .map(|p| (p.id.clone(), p.name.clone()))
+            .collect(),
+        error: e,
+    })
+}
+
+/// DELETE handler for `/users/<uid>/accounts/<aid>`
+///
+/// Unlinks an account. Unlinking a GitHub account unlocks the handle.
+///
+/// Restricted to Admins and the user themselves.
+#[delete("/users/<uid>/accounts/<aid>")]
+pub fn account_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+    aid: i32,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    let account: OAuthIdentity = {
+        use crate::schema::oauth_identities::dsl::*;
+        let a = oauth_identities
+            .filter(id.eq(aid).and(user_id.eq(uid)))
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get linked account from database")
+            .ok_or(Status::NotFound)?;
+        delete(oauth_identities.find(aid))
+            .execute(&*conn)
+            .expect("Failed to delete linked account from database");
+        a
+    };
+
+    let github = find_provider(&settings, &account.provider)
+        .map_or(false, |p| p.kind == ProviderKind::GitHub);
+    if github {
+        use crate::schema::users::dsl::*;
+        update(users.find(uid))
+            .set(handle_verified.eq(false))
+            .execute(&*conn)
+            .expect("Failed to update user in database");
+    }
+
+    audit::deleted(&*conn, Some(&l.0), "account", account.id, &account);
+    audit_log!(
+        "User {} [{}] has unlinked the {} account of User {}",
+        l.0.id,
+        l.0.email,
+        account.provider,
+        uid
+    );
+
+    Ok(Redirect::to(format!("/users/{}/accounts", uid)))
+}
+
+//# Helper Functions
+
+/// Find a provider by its ID
+fn find_provider<'a>(settings: &'a Settings, pid: &str) -> Option<&'a Provider> {
+    settings.oauth_providers.iter().find(|p| p.id == pid)
+}
+
+/// Where the provider sends the user back to
+fn callback_url(site: &SiteUrl, provider: &Provider) -> String {
+    format!("{}/login/oauth/{}/callback", site.0, provider.id)
+}
+
+/// The user whose email is one of the verified emails of an account
+fn email_user(conn: &SqliteConnection, profile: &Profile) -> Option<User> {
+    use crate::schema::users::dsl::*;
+    profile.emails.iter().find_map(|e| {
+        users
+            .filter(email.eq(e).and(deleted_at.is_null()))
+            .first(conn)
+            .optional()
+            .expect("Failed to get user from database")
+    })
+}
+
+/// Link an account to a user, replacing their account at the same provider
+fn link_account(conn: &SqliteConnection, p: &Provider, uid: i32, profile: &Profile) {
+    use crate::schema::oauth_identities::dsl::*;
+
+    delete(oauth_identities.filter(provider.eq(&p.id).and(user_id.eq(uid))))
+        .execute(conn)
+        .expect("Failed to delete linked account from database");
+    insert_into(oauth_identities)
+        .values(&NewOAuthIdentity {
+            user_id: uid,
+            provider: p.id.clone(),
+            subject: profile.subject.clone(),
+            login: profile.login.clone(),
+            created_at: chrono::offset::Utc::now().naive_utc(),
+        })
+        .execute(conn)
+        .expect("Failed to insert linked account into database");
+
+    let account: OAuthIdentity = oauth_identities
+        .filter(provider.eq(&p.id).and(user_id.eq(uid)))
+        .first(conn)
+        .expect("Failed to get linked account from database");
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .first(conn)
+            .expect("Failed to get user from database")
+    };
+    audit::created(conn, Some(&user), "account", account.id, &account);
+    audit_log!(
+        "User {} has linked the {} account {}",
+        uid,
+        p.id,
+        profile.subject
+    );
+}
+
+/// Make a GitHub username the user's verified handle
+///
+/// If another user already has it as their handle it is left for an
+/// Admin to sort out.
+fn claim_handle(conn: &SqliteConnection, user: &User, github: &str) {
+    use crate::schema::users::dsl::*;
+
+    if user.handle_verified && user.handle == github {
+        return;
+    }
+    let other: Option<User> = users
+        .filter(handle.like(github).and(id.ne(user.id)))
+        .first(conn)
+        .optional()
+        .expect("Failed to get user from database");
+    if let Some(other) = other {
+        warn!(
+            "GitHub user {} logged in as User {} but User {} has that handle",
+            github, user.id, other.id
+        );
+        return;
+    }
+    if is_reserved(github).is_err() {
+        return;
+    }
+
+    update(users.find(user.id))
+        .set((handle.eq(github), handle_verified.eq(true)))
+        .execute(conn)
+        .expect("Failed to update user in database");
+    audit::updated(
+        conn,
+        Some(user),
+        "user",
+        user.id,
+        &serde_json::json!({ "handle": user.handle, "handle_verified": user.handle_verified }),
+        &serde_json::json!({ "handle": github, "handle_verified": true }),
+    );
+
+    audit_log!(
+        "User {} [{}] has verified the handle {}",
+        user.id,
+        user.email,
+        github
+    );
+}
@@ -0,0 +1,21 @@
+//! Logging in with GitHub and other OAuth providers
+//!
+//! Besides their password users can log in with an account at one of the
+//! providers in the `oauth_providers` setting, see `provider`. The first
+//! time, the account is linked to the user with the same verified email,
+//! or a logged in user can link it from their Linked Accounts page.
+//!
+//! Logging in with GitHub verifies the user's `handle` and it can't be
+//! changed by hand after that, so nobody can claim someone else's commits.
+//!
+//! ## Routes
+//! - `/login/oauth/<provider>`
+//! - `/login/oauth/<provider>/callback`
+//! - `/users/<uid>/accounts`
+//! - `/users/<uid>/accounts/<aid>`
+
+pub mod handlers;
+pub mod models;
+pub mod provider;
+
+mod templates;
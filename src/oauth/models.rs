@@ -0,0 +1,45 @@
+//! Models for linked accounts
+//!
+//! Each row of the `oauth_identities` table is an account at a provider
+//! that a user can log in with. A user has at most one account at each
+//! provider.
+
+use chrono::NaiveDateTime;
+
+use crate::models::User;
+use crate::schema::*;
+
+/// Models a linked account in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+#[table_name = "oauth_identities"]
+pub struct OAuthIdentity {
+    /// ID of the linked account
+    pub id: i32,
+    /// ID of the user it is linked to
+    pub user_id: i32,
+    /// Name of the provider in the `oauth_providers` setting
+    pub provider: String,
+    /// The provider's ID of the account
+    pub subject: String,
+    /// User name at the provider, if it has them
+    pub login: Option<String>,
+    /// When the account was linked
+    pub created_at: NaiveDateTime,
+}
+
+/// Used to link a new account in the database
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "oauth_identities"]
+pub struct NewOAuthIdentity {
+    /// ID of the user it is linked to
+    pub user_id: i32,
+    /// Name of the provider in the `oauth_providers` setting
+    pub provider: String,
+    /// The provider's ID of the account
+    pub subject: String,
+    /// User name at the provider, if it has them
+    pub login: Option<String>,
+    /// When the account was linked
+    pub created_at: NaiveDateTime,
+}
@@ -0,0 +1,266 @@
+//! OAuth 2.0 and OpenID Connect providers
+//!
+//! Providers are set up in `Rocket.toml` as tables under `oauth_providers`
+//! keyed by the name used in their URLs. Each one has a `kind`:
+//!
+//! - `github` logs in with a GitHub account. The URLs default to github.com
+//!   and the `github_api_url` setting.
+//! - `oidc` logs in with any OpenID Connect provider such as the
+//!   university SSO. Its `authorize_url`, `token_url` and `userinfo_url`
+//!   have to be set. Providers that don't send `email_verified` need
+//!   `trust_email = true` for their emails to be used.
+//!
+//! Both need a `client_id` and `client_secret` and can have a display
+//! `name` and a different `scope`.
+//!
+//! Logging in uses the authorization code flow with PKCE.
+
+use std::time::Duration;
+
+use http_req::request::{Method, Request};
+use http_req::uri::Uri;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use ring::digest;
+use rocket::config::Table;
+use rocket::http::uri::Uri as RocketUri;
+use serde_json::Value;
+
+/// The kinds of provider that are supported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProviderKind {
+    GitHub,
+    Oidc,
+}
+
+/// A provider that users can log in with
+#[derive(Debug, Clone)]
+pub struct Provider {
+    /// Name used in URLs and stored with linked accounts
+    pub id: String,
+    /// Name shown on the login page
+    pub name: String,
+    pub kind: ProviderKind,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where users are sent to log in
+    pub authorize_url: String,
+    /// Where the code is traded for an access token
+    pub token_url: String,
+    /// Where the profile of the account is read
+    pub userinfo_url: String,
+    pub scope: String,
+    /// Use the email even if the provider doesn't say it's verified
+    pub trust_email: bool,
+}
+
+/// The account that logged in at a provider
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// The provider's ID of the account, which never changes
+    pub subject: String,
+    /// User name at the provider such as the GitHub username
+    pub login: Option<String>,
+    /// Verified emails of the account with the primary one first
+    pub emails: Vec<String>,
+}
+
+/// Reasons talking to a provider can fail
+#[derive(Debug)]
+pub enum OAuthError {
+    /// The request could not be made at all
+    Http(http_req::error::Error),
+    /// The provider responded with an unexpected status code
+    Status(u16),
+    /// The provider responded with something that wasn't the expected JSON
+    BadResponse,
+}
+
+impl From<http_req::error::Error> for OAuthError {
+    fn from(e: http_req::error::Error) -> Self {
+        OAuthError::Http(e)
+    }
+}
+
+impl Provider {
+    /// Read a provider from its table in the settings
+    ///
+    /// Returns `None` if the kind is unknown or something required is missing.
+    pub fn from_table(id: &str, t: &Table, github_api_url: &str) -> Option<Self> {
+        let get = |k: &str| t.get(k).and_then(|v| v.as_str()).map(String::from);
+
+        let kind = match get("kind")?.as_str() {
+            "github" => ProviderKind::GitHub,
+            "oidc" => ProviderKind::Oidc,
+            _ => return None,
+        };
+        let (authorize_url, token_url, userinfo_url, scope, name) = match kind {
+            ProviderKind::GitHub => (
+                get("authorize_url")
+                    .unwrap_or_else(|| String::from("https://github.com/login/oauth/authorize")),
+                get("token_url")
+                    .unwrap_or_else(|| String::from("https://github.com/login/oauth/access_token")),
+                get("userinfo_url").unwrap_or_else(|| format!("{}/user", github_api_url)),
+                "read:user user:email",
+                "GitHub",
+            ),
+            ProviderKind::Oidc => (
+                get("authorize_url")?,
+                get("token_url")?,
+                get("userinfo_url")?,
+                "openid email profile",
+                id,
+            ),
+        };
+
+        Some(Self {
+            id: id.to_lowercase(),
+            name: get("name").unwrap_or_else(|| name.to_string()),
+            kind,
+            client_id: get("client_id")?,
+            client_secret: get("client_secret")?,
+            authorize_url,
+            token_url,
+            userinfo_url,
+            scope: get("scope").unwrap_or_else(|| scope.to_string()),
+            trust_email: t
+                .get("trust_email")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        })
+    }
+
+    /// The URL that sends the user to log in at the provider
+    pub fn authorize(&self, redirect_uri: &str, state: &str, verifier: &str) -> String {
+        let sep = if self.authorize_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}\
+             &code_challenge={}&code_challenge_method=S256",
+            self.authorize_url,
+            sep,
+            RocketUri::percent_encode(&self.client_id),
+            RocketUri::percent_encode(redirect_uri),
+            RocketUri::percent_encode(&self.scope),
+            state,
+            challenge(verifier)
+        )
+    }
+
+    /// Trade the code the provider sent back for an access token
+    pub fn exchange(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        verifier: &str,
+    ) -> Result<String, OAuthError> {
+        let form = format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}\
+             &client_id={}&client_secret={}&code_verifier={}",
+            RocketUri::percent_encode(code),
+            RocketUri::percent_encode(redirect_uri),
+            RocketUri::percent_encode(&self.client_id),
+            RocketUri::percent_encode(&self.client_secret),
+            verifier
+        );
+        let json = request(&self.token_url, None, Some(&form))?;
+        json["access_token"]
+            .as_str()
+            .map(String::from)
+            .ok_or(OAuthError::BadResponse)
+    }
+
+    /// Get the profile of the account an access token is for
+    pub fn profile(&self, token: &str) -> Result<Profile, OAuthError> {
+        let auth = format!("Bearer {}", token);
+        let user = request(&self.userinfo_url, Some(&auth), None)?;
+
+        match self.kind {
+            ProviderKind::GitHub => {
+                // The profile only has the public email, if any
+                let emails = request(&format!("{}/emails", self.userinfo_url), Some(&auth), None)?;
+                let mut verified: Vec<&Value> = emails
+                    .as_array()
+                    .ok_or(OAuthError::BadResponse)?
+                    .iter()
+                    .filter(|e| e["verified"].as_bool() == Some(true))
+                    .collect();
+                verified.sort_by_key(|e| e["primary"].as_bool() != Some(true));
+
+                Ok(Profile {
+                    subject: user["id"]
+                        .as_i64()
+                        .ok_or(OAuthError::BadResponse)?
+                        .to_string(),
+                    login: user["login"].as_str().map(String::from),
+                    emails: verified
+                        .iter()
+                        .filter_map(|e| e["email"].as_str())
+                        .map(String::from)
+                        .collect(),
+                })
+            }
+            ProviderKind::Oidc => {
+                let verified = self.trust_email || user["email_verified"].as_bool() == Some(true);
+                Ok(Profile {
+                    subject: user["sub"]
+                        .as_str()
+                        .ok_or(OAuthError::BadResponse)?
+                        .to_string(),
+                    login: user["preferred_username"].as_str().map(String::from),
+                    emails: user["email"]
+                        .as_str()
+                        .filter(|_| verified)
+                        .map(String::from)
+                        .into_iter()
+                        .collect(),
+                })
+            }
+        }
+    }
+}
+
+/// Generate a random value for the `state` or PKCE code verifier
+pub fn gen_secret() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(43)
+        .collect()
+}
+
+/// The PKCE code challenge of a verifier
+fn challenge(verifier: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, verifier.as_bytes());
+    base64::encode_config(hash.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Make a request to a provider and parse the JSON it sends back
+///
+/// The request is a POST of `form` if there is one, otherwise a GET.
+fn request(url: &str, auth: Option<&str>, form: Option<&str>) -> Result<Value, OAuthError> {
+    let uri: Uri = url.parse()?;
+    let mut req = Request::new(&uri);
+    req.header("User-Agent", "Observatory")
+        .header("Accept", "application/json")
+        .timeout(Some(Duration::from_secs(30)));
+    if let Some(a) = auth {
+        req.header("Authorization", a);
+    }
+    if let Some(f) = form {
+        req.method(Method::POST)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Content-Length", &f.len())
+            .body(f.as_bytes());
+    }
+
+    let mut body = Vec::new();
+    let res = req.send(&mut body)?;
+    if !res.status_code().is_success() {
+        return Err(OAuthError::Status(u16::from(res.status_code())));
+    }
+    serde_json::from_slice(&body).map_err(|_| OAuthError::BadResponse)
+}
@@ -0,0 +1,26 @@
+//! HTML templates for linked accounts
+
+use super::models::OAuthIdentity;
+use crate::models::User;
+#[allow(unused_imports)]
+use crate::templates::{filters, FormError, OptUser};
+use crate::tz::Tz;
+
+/// Linked Accounts page template
+///
+/// HTML File: `oauth/accounts.html`
+///
+/// Lists the accounts a user can log in with.
+#[derive(Template)]
+#[template(path = "oauth/accounts.html")]
+pub struct AccountsTemplate {
+    pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
+    pub user: User,
+    /// Each linked account with the name of its provider
+    pub accounts: Vec<(OAuthIdentity, String)>,
+    /// ID and name of each provider
+    pub providers: Vec<(String, String)>,
+    pub error: Option<FormError>,
+}
@@ -3,32 +3,52 @@ use diesel::{delete, insert_into, update};
 use rocket::http::Status;
 use rocket::request::Form;
 use rocket::response::Redirect;
+use rocket::State;
 
 use rocket_contrib::json::Json;
-use serde_json;
 
+use crate::api::v1::last_id;
+use crate::audit;
+use crate::calendar::models::parse_time;
 use crate::guards::*;
+use crate::mailer::Outbox;
+use crate::roles::Permission;
 use crate::ObservDbConn;
 
 use super::models::*;
 use super::templates::*;
+use crate::commits::models::Commit;
+use crate::semesters::handlers::{all_semesters, enroll_in, find_semester, semester_select};
+use crate::semesters::templates::SemesterSelect;
+use crate::settings::Settings;
 use crate::templates::{is_reserved, FormError};
+use crate::trash::handlers::move_to_trash;
+use crate::trash::models::Kind;
+use crate::tz;
 
 /// GET handler for `/projects?s`
 /// Project list page with an optional search string,
+/// showing the projects in the selected semester
 
-#[get("/projects?<s>&<a>")]
+#[get("/projects?<s>&<a>&<sem>")]
 pub fn projects(
     conn: ObservDbConn,
     l: MaybeLoggedIn,
+    settings: State<Settings>,
     s: Option<String>,
     a: Option<bool>,
+    sem: Option<String>,
 ) -> ProjectsListTemplate {
+    let semester = semester_select(&*conn, sem, &settings.site_zone());
     ProjectsListTemplate {
         logged_in: l.user(),
         search_term: s.clone().unwrap_or_else(String::new),
-        projects: filter_projects(&*conn, s, a),
+        projects: filter_projects(&*conn, s, a)
+            .into_iter()
+            .filter(|p| semester.includes(p.semester_id))
+            .collect(),
         inactive: a.unwrap_or(false),
+        semester,
     }
 }
 
@@ -43,66 +63,46 @@ pub fn projects_json(conn: ObservDbConn, s: Option<String>, a: Option<bool>) ->
 /// GET handler for `/projects/id`
 /// Gets an indivual project from the data base by its ID and returns its template
 
-#[get("/projects/<n>")]
-pub fn project(conn: ObservDbConn, l: MaybeLoggedIn, n: i32) -> Option<ProjectTemplate> {
+#[get("/projects/<n>?<e>")]
+pub fn project(
+    conn: ObservDbConn,
+    l: MaybeLoggedIn,
+    settings: State<Settings>,
+    n: i32,
+    e: Option<FormError>,
+) -> Option<ProjectTemplate> {
     use crate::schema::projects::dsl::*;
-    use std::collections::HashMap;
 
     let p: Project = projects
         .find(n)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .optional()
         .expect("Failed to get project from database")?;
 
-    let rc = project_commits(&conn, &p)
-        .unwrap_or(Vec::new())
-        .iter()
-        .enumerate()
-        .map(|(i, repo)| {
-            (
-                project_repos(&p)[i]
-                    .to_owned()
-                    .replace("https://github.com/", ""),
-                repo.as_array()
-                    .unwrap_or(&Vec::new())
-                    .into_iter()
-                    .take(10)
-                    .map(|commit| {
-                        let auth_name = serde_json::to_string(&commit["commit"]["author"]["name"])
-                            .unwrap()
-                            .replace("\"", "");
-                        let auth_email =
-                            serde_json::to_string(&commit["commit"]["author"]["email"])
-                                .unwrap()
-                                .replace("\"", "");
-                        let full_msg = serde_json::to_string(&commit["commit"]["message"])
-                            .unwrap()
-                            .replace("\"", "");
-                        let auth_url = serde_json::to_string(&commit["html_url"])
-                            .unwrap()
-                            .replace("\"", "");
-
-                        let trunc_msg = String::from(
-                            *full_msg
-                                .split("\\n")
-                                .collect::<Vec<&str>>()
-                                .first()
-                                .unwrap(),
-                        );
-
-                        (auth_name, auth_email, trunc_msg, auth_url)
-                    })
-                    .collect::<Vec<(String, String, String, String)>>(),
-            )
-        })
-        .collect::<HashMap<_, _>>();
-
+    let logged_in = l.user();
+    let edits = logged_in
+        .as_ref()
+        .map_or(false, |u| can_edit_project(&*conn, u, &p));
+    let manages = logged_in
+        .as_ref()
+        .map_or(false, |u| can_manage_members(&*conn, u, &p));
     Some(ProjectTemplate {
-        logged_in: l.user(),
+        zone: tz::of(logged_in.as_ref(), &settings.site_zone()),
+        logged_in,
+        requests: if manages {
+            project_requests(&*conn, &p)
+        } else {
+            Vec::new()
+        },
+        edits,
+        manages,
         repos: project_repos(&p),
-        users: project_users(&*conn, &p),
+        members: project_roles(&*conn, &p),
+        recent_commits: project_commits(&*conn, &p, 10),
+        milestones: project_milestones(&*conn, &p),
         project: p,
-        recent_commits: rc,
+        error: e,
     })
 }
 
@@ -113,7 +113,7 @@ pub fn project(conn: ObservDbConn, l: MaybeLoggedIn, n: i32) -> Option<ProjectTe
 pub fn project_by_handle(conn: ObservDbConn, _l: MaybeLoggedIn, n: String) -> Option<Redirect> {
     use crate::schema::projects::dsl::*;
     let p: Project = projects
-        .filter(name.like(n))
+        .filter(name.like(n).and(deleted_at.is_null()))
         .first(&*conn)
         .optional()
         .expect("Failed to get project from database")?;
@@ -125,9 +125,15 @@ pub fn project_by_handle(conn: ObservDbConn, _l: MaybeLoggedIn, n: String) -> Op
 /// Returns the new project template
 
 #[get("/projects/new?<e>")]
-pub fn project_new(l: UserGuard, e: Option<FormError>) -> NewProjectTemplate {
+pub fn project_new(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    e: Option<FormError>,
+) -> NewProjectTemplate {
     NewProjectTemplate {
         logged_in: Some(l.0),
+        semester: semester_select(&*conn, None, &settings.site_zone()),
         error: e,
     }
 }
@@ -141,56 +147,13 @@ pub fn project_new_post(
     l: UserGuard,
     newproject: Form<NewProject>,
 ) -> Redirect {
-    let mut newproject = newproject.into_inner();
-    newproject.name.truncate(50); // sets a character limit for a new project name
-    newproject.description.truncate(500); // sets a character limit for a new project description
-    newproject.repos.truncate(100); // sets a character limit for a new repository URL
-    newproject.owner_id = l.0.id; // set owner to be the person who created the project
-    newproject.active = true;
-
-    if let Err(e) = is_reserved(&newproject.name) {
-        return Redirect::to(format!("/projects/new?e={}", e));
-    }
-
-    // handles the fact that projects can have multiple repos
-    newproject.repos = serde_json::to_string(
-        &serde_json::from_str::<Vec<String>>(&newproject.repos)
-            .unwrap()
-            .iter()
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<&String>>(),
-    )
-    .unwrap();
-
-    // inserts the project into the database
-    use crate::schema::projects::dsl::*;
-    use diesel::result::DatabaseErrorKind;
-    use diesel::result::Error;
-    match insert_into(projects).values(&newproject).execute(&*conn) {
-        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
-            return Redirect::to(format!("/projects/new?e={}", FormError::TakenName))
+    match create_project(&*conn, &l.0, newproject.into_inner()) {
+        Ok(p) => {
+            audit::created(&*conn, Some(&l.0), "project", p.id, &p);
+            Redirect::to(format!("/projects/{}", p.id))
         }
-        Err(_) => return Redirect::to(format!("/projects/new?e={}", FormError::Other)),
-        Ok(_) => (),
+        Err(e) => Redirect::to(format!("/projects/new?e={}", e)),
     }
-
-    // retrieves the object from the database after creating it
-    let p: Project = projects
-        .filter(name.eq(newproject.name)) // CHANGEME switch to id
-        .first(&*conn)
-        .expect("Failed to get project from database");
-
-    //creates the relation for the project owner
-    use crate::schema::relation_project_user::dsl::*;
-    insert_into(relation_project_user)
-        .values(&NewRelationProjectUser {
-            project_id: p.id,
-            user_id: l.0.id,
-        })
-        .execute(&*conn)
-        .expect("Failed to add user to project");
-
-    Redirect::to(format!("/projects/{}", p.id))
 }
 
 /// GET handler for `/projects/edit`
@@ -204,26 +167,27 @@ pub fn project_edit(
     e: Option<FormError>,
 ) -> Result<EditProjectTemplate, Status> {
     use crate::schema::projects::dsl::*;
-    use crate::schema::users::dsl::*;
 
     let p: Project = projects
         .find(h)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get project from database");
 
-    //checks to see what tier logged in user is or if there the owner
-    if l.0.tier > 1 || p.owner_id == l.0.id {
+    if can_edit_project(&*conn, &l.0, &p) {
         Ok(EditProjectTemplate {
             logged_in: Some(l.0),
             repos: project_repos(&p),
+            semester: SemesterSelect {
+                semesters: all_semesters(&*conn),
+                selected: p.semester_id.and_then(|sid| find_semester(&*conn, sid)),
+            },
+            members: project_roles(&*conn, &p),
             project: p,
-            all_users: users
-                .load(&*conn)
-                .expect("Failed to get users from database"),
             error: e,
         })
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -239,44 +203,33 @@ pub fn project_edit_put(
 ) -> Result<Redirect, Status> {
     use crate::schema::projects::dsl::*;
 
-    let mut editproject = editproject.into_inner();
-    editproject.name.truncate(50); // sets a character limit for an edited project name
-    editproject.description.truncate(500); // sets a character limit for a new project name
-    editproject.repos.truncate(100); // sets a character limit for an edited repository URL
-
-    editproject.repos = serde_json::to_string(
-        &serde_json::from_str::<Vec<String>>(&editproject.repos)
-            .unwrap()
-            .iter()
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<&String>>(),
-    )
-    .unwrap();
-
     let p: Project = projects
         .find(h)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get project from database");
 
-    //checks to see what tier logged in user is or if there the owner so no one outside the project messes with it
-    if l.0.tier > 1 || p.owner_id == l.0.id {
-        if let Err(e) = is_reserved(&editproject.name) {
-            return Ok(Redirect::to(format!("/projects/{}/edit?e={}", h, e)));
+    //checks to see if the logged in user can edit any project or leads it so no one outside the project messes with it
+    if can_edit_project(&*conn, &l.0, &p) {
+        match update_project(&*conn, h, editproject.into_inner()) {
+            Ok(()) => {
+                let new: Project = projects
+                    .find(h)
+                    .first(&*conn)
+                    .expect("Failed to get project from database");
+                audit::updated(&*conn, Some(&l.0), "project", h, &p, &new);
+                Ok(Redirect::to(format!("/projects/{}", h)))
+            }
+            Err(e) => Ok(Redirect::to(format!("/projects/{}/edit?e={}", h, e))),
         }
-
-        update(projects.find(h))
-            .set(&editproject)
-            .execute(&*conn)
-            .expect("Failed to update project in database");
-        Ok(Redirect::to(format!("/projects/{}", h)))
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
 /// DELETE handler for `/projects/h`
 ///
-/// Deletes relation from all users tied to the project then deletes the project
+/// Moves the project to the trash, its members are kept in case it is restored
 
 #[delete("/projects/<h>")]
 pub fn project_delete(conn: ObservDbConn, l: UserGuard, h: i32) -> Result<Redirect, Status> {
@@ -284,24 +237,17 @@ pub fn project_delete(conn: ObservDbConn, l: UserGuard, h: i32) -> Result<Redire
     // Find the project
     let p: Project = projects
         .find(h)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get project from database");
 
     // If they are an admin or the project owner
-    if l.0.tier > 1 || p.owner_id == l.0.id {
-        // Delete the relations
-        use crate::schema::relation_project_user::dsl::*;
-        delete(relation_project_user.filter(project_id.eq(h)))
-            .execute(&*conn)
-            .expect("Failed to delete relations from database");
-
-        // Delete the project
-        delete(projects.find(h))
-            .execute(&*conn)
-            .expect("Failed to delete project from database");
+    if l.0.can(Permission::EditAnyProject) || p.owner_id == l.0.id {
+        move_to_trash(&*conn, Kind::Project, h);
+        audit::deleted(&*conn, Some(&l.0), "project", h, &p);
         Ok(Redirect::to("/projects"))
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -322,6 +268,7 @@ pub fn project_members_json(conn: ObservDbConn, h: i32) -> Json<Vec<User>> {
         use crate::schema::projects::dsl::*;
         projects
             .find(h)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .expect("Failed to get project from database")
     }))
@@ -340,6 +287,7 @@ pub fn project_member_add(
         use crate::schema::projects::dsl::*;
         projects
             .find(h)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .expect("Failed to get project from database")
     };
@@ -348,15 +296,14 @@ pub fn project_member_add(
 
     use crate::schema::users::dsl::*;
 
-    //checks to see what tier your logged into
-    if l.0.tier > 0 || l.0.id == p.owner_id {
+    if can_manage_members(&*conn, &l.0, &p) {
         Ok(AddUserTemplate {
             logged_in: Some(l.0),
             project: p,
             all_users: {
                 // gets a list of users not in the project
                 users
-                    .filter(id.ne(0))
+                    .filter(id.ne(0).and(deleted_at.is_null()))
                     .load(&*conn)
                     .expect("Failed to get users from database")
                     .iter()
@@ -366,7 +313,7 @@ pub fn project_member_add(
             },
         })
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
@@ -378,12 +325,14 @@ pub struct UserId {
 }
 
 ///POST handler for `projects/h/members/add`
-/// This adds the user to the project form
+/// Invites the user to the project, they join once they accept.
+/// Users that already asked to join are let in straight away.
 
 #[post("/projects/<h>/members/add", data = "<userid>")]
 pub fn project_member_add_post(
     conn: ObservDbConn,
     l: UserGuard,
+    outbox: State<Outbox>,
     h: i32,
     userid: Form<UserId>,
 ) -> Result<Redirect, Status> {
@@ -391,28 +340,42 @@ pub fn project_member_add_post(
         use crate::schema::projects::dsl::*;
         projects
             .find(h)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .expect("Failed to get project from database")
     };
 
-    //checks to see if your the right tier so you cant jsut send what you want
-    if l.0.tier > 0 || l.0.id == p.owner_id {
-        use crate::schema::relation_project_user::dsl::*;
-        insert_into(relation_project_user)
-            .values(&NewRelationProjectUser {
-                project_id: h,
-                user_id: userid.into_inner().uid,
-            })
-            .execute(&*conn)
-            .expect("Failed to insert relation into database");
+    //checks to see if you can manage the members so you cant jsut send what you want
+    if can_manage_members(&*conn, &l.0, &p) {
+        let uid = userid.into_inner().uid;
+        let user = find_user(&*conn, uid).ok_or(Status::NotFound)?;
+        if project_users(&*conn, &p).contains(&user) {
+            return Ok(Redirect::to(format!("/projects/{}", h)));
+        }
+
+        match pending_request(&*conn, h, uid) {
+            Some(r) if !r.invite => {
+                let new = answer_request(&*conn, &p, &r, true);
+                audit::updated(&*conn, Some(&l.0), "project-request", r.id, &r, &new);
+                audit::member(&*conn, Some(&l.0), "project", h, uid, true);
+                notify_answer(&*conn, &outbox, &p, &new);
+            }
+            Some(_) => (),
+            None => {
+                let r = create_request(&*conn, h, uid, true);
+                audit::created(&*conn, Some(&l.0), "project-request", r.id, &r);
+                notify_request(&*conn, &outbox, &p, &r);
+            }
+        }
         Ok(Redirect::to(format!("/projects/{}", h)))
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
     }
 }
 
 ///DELETE handler for `projects/h/members/uid`
-/// Removes user relation from the project
+/// Removes user relation from the project.
+/// Leads can remove anyone but the owner and members can leave.
 
 #[delete("/projects/<h>/members/<uid>")]
 pub fn project_member_delete(
@@ -421,24 +384,146 @@ pub fn project_member_delete(
     h: i32,
     uid: i32,
 ) -> Result<Redirect, Status> {
-    let owner_id: i32 = {
+    let p: Project = {
         use crate::schema::projects::dsl::*;
         projects
             .find(h)
-            .select(owner_id)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .expect("Failed to get project from database")
     };
 
-    if l.0.tier > 0 || l.0.id == owner_id {
+    // The owner has to hand the project to someone else first
+    if uid == p.owner_id {
+        return Err(Status::Conflict);
+    }
+
+    if can_manage_members(&*conn, &l.0, &p) || l.0.id == uid {
         use crate::schema::relation_project_user::dsl::*;
         delete(relation_project_user.filter(project_id.eq(h).and(user_id.eq(uid))))
             .execute(&*conn)
             .expect("Failed to delete relation from database");
+        audit::member(&*conn, Some(&l.0), "project", h, uid, false);
         Ok(Redirect::to(format!("/projects/{}", h)))
     } else {
-        Err(Status::Unauthorized)
+        Err(DENIED)
+    }
+}
+
+/// Form to change the role of a member
+#[derive(FromForm)]
+pub struct RoleForm {
+    pub role: ProjectRole,
+}
+
+/// PUT handler for `/projects/<h>/members/<uid>`
+///
+/// Changes the role of a member of the project.
+/// The owner is always a lead.
+///
+/// Restricted to those that can manage the members.
+#[put("/projects/<h>/members/<uid>", data = "<form>")]
+pub fn project_member_role(
+    conn: ObservDbConn,
+    l: UserGuard,
+    h: i32,
+    uid: i32,
+    form: Form<RoleForm>,
+) -> Result<Redirect, Status> {
+    let p: Project = {
+        use crate::schema::projects::dsl::*;
+        projects
+            .find(h)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get project from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    if !can_manage_members(&*conn, &l.0, &p) {
+        return Err(DENIED);
+    }
+    let old = member_role(&*conn, h, uid).ok_or(Status::NotFound)?;
+    let new = form.into_inner().role;
+    if uid == p.owner_id && new != ProjectRole::Lead {
+        return Err(Status::Conflict);
+    }
+
+    set_member_role(&*conn, h, uid, new);
+    audit::record(
+        &*conn,
+        Some(&l.0),
+        "change-role",
+        "project",
+        Some(h),
+        Some(serde_json::json!({ "role": old })),
+        Some(serde_json::json!({ "user_id": uid, "role": new })),
+    );
+    Ok(Redirect::to(format!("/projects/{}", h)))
+}
+
+/// POST handler for `/projects/<h>/owner`
+///
+/// Hands the project to another of its members, who becomes a lead.
+/// The old owner stays on as a lead and the new owner is told by email.
+///
+/// Restricted to the owner and those that can edit any project.
+#[post("/projects/<h>/owner", data = "<userid>")]
+pub fn project_transfer(
+    conn: ObservDbConn,
+    l: UserGuard,
+    outbox: State<Outbox>,
+    h: i32,
+    userid: Form<UserId>,
+) -> Result<Redirect, Status> {
+    use crate::schema::projects::dsl::*;
+
+    let p: Project = projects
+        .find(h)
+        .filter(deleted_at.is_null())
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get project from database")
+        .ok_or(Status::NotFound)?;
+
+    if !l.0.can(Permission::EditAnyProject) && l.0.id != p.owner_id {
+        return Err(DENIED);
+    }
+    let uid = userid.into_inner().uid;
+    let user = find_user(&*conn, uid).ok_or(Status::NotFound)?;
+    if member_role(&*conn, h, uid).is_none() {
+        return Err(Status::Conflict);
     }
+
+    update(projects.find(h))
+        .set(owner_id.eq(uid))
+        .execute(&*conn)
+        .expect("Failed to update project in database");
+    set_member_role(&*conn, h, uid, ProjectRole::Lead);
+    let new = Project {
+        owner_id: uid,
+        ..p.clone()
+    };
+    audit::updated(&*conn, Some(&l.0), "project", h, &p, &new);
+    audit_log!(
+        "User {} [{}] has handed project {} to user {}",
+        l.0.id,
+        l.0.email,
+        h,
+        uid
+    );
+
+    outbox.send_links(&user.email, &format!("You now own {}", p.name), |site| {
+        format!(
+            "Hi {},\n\n\
+             {} has handed the project {} on Observatory over to you.\n\n\
+             {}/projects/{}\n",
+            user.real_name, l.0.real_name, p.name, site, h
+        )
+    });
+
+    Ok(Redirect::to(format!("/projects/{}", h)))
 }
 
 ///GET handler for `projects/h/members/join`
@@ -448,44 +533,377 @@ pub fn project_member_delete(
 pub fn project_join(conn: ObservDbConn, l: UserGuard, h: i32) -> JoinTemplate {
     use crate::schema::projects::dsl::*;
     JoinTemplate {
+        pending: pending_request(&*conn, h, l.0.id),
         logged_in: Some(l.0),
         project: projects
             .find(h)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .expect("Failed to get project from database"),
     }
 }
 
 ///POST handler for `projects/h/members/join`
-/// The User confirms they want to join the project user relation added to project database
+/// The User confirms they want to join the project.
+/// Open projects add them straight away, otherwise the owner is asked.
+/// Users that were invited accept the invite.
 
 #[post("/projects/<h>/members/join")]
-pub fn project_join_post(conn: ObservDbConn, l: UserGuard, h: i32) -> Result<Redirect, Status> {
+pub fn project_join_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    outbox: State<Outbox>,
+    h: i32,
+) -> Result<Redirect, Status> {
     use crate::schema::projects::dsl::*;
 
-    let a: bool = projects
-        .select(active)
+    let p: Project = projects
         .find(h)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .expect("Failed to get project from database");
 
-    if a {
-        use crate::schema::relation_project_user::dsl::*;
-        insert_into(relation_project_user)
-            .values(&NewRelationProjectUser {
-                project_id: h,
-                user_id: l.0.id,
-            })
-            .execute(&*conn)
-            .expect("Failed to add relation to database");
-        Ok(Redirect::to(format!("/projects/{}", h)))
+    if !p.active {
+        return Err(Status::Conflict);
+    }
+    if project_users(&*conn, &p).contains(&l.0) {
+        return Ok(Redirect::to(format!("/projects/{}", h)));
+    }
+
+    match (pending_request(&*conn, h, l.0.id), p.joining) {
+        (Some(r), _) if r.invite => {
+            let new = answer_request(&*conn, &p, &r, true);
+            audit::updated(&*conn, Some(&l.0), "project-request", r.id, &r, &new);
+            audit::member(&*conn, Some(&l.0), "project", h, l.0.id, true);
+            notify_answer(&*conn, &outbox, &p, &new);
+        }
+        (Some(_), _) => (),
+        (None, Joining::Open) => {
+            add_member(&*conn, &p, l.0.id, ProjectRole::Developer);
+            audit::member(&*conn, Some(&l.0), "project", h, l.0.id, true);
+        }
+        (None, Joining::Request) => {
+            let r = create_request(&*conn, h, l.0.id, false);
+            audit::created(&*conn, Some(&l.0), "project-request", r.id, &r);
+            notify_request(&*conn, &outbox, &p, &r);
+        }
+        (None, Joining::Closed) => return Err(DENIED),
+    }
+    Ok(Redirect::to(format!("/projects/{}", h)))
+}
+
+/// Form to accept or decline a request or invite
+#[derive(FromForm)]
+pub struct AnswerForm {
+    pub accept: bool,
+}
+
+/// PUT handler for `/projects/<h>/requests/<rid>`
+///
+/// Accepts or declines a request to join the project or an invite to it.
+/// Requests are answered by the owner or those that can manage the members,
+/// invites by the user that was invited.
+/// The other side is told the answer by email.
+#[put("/projects/<h>/requests/<rid>", data = "<form>")]
+pub fn project_request_answer(
+    conn: ObservDbConn,
+    l: UserGuard,
+    outbox: State<Outbox>,
+    h: i32,
+    rid: i32,
+    form: Form<AnswerForm>,
+) -> Result<Redirect, Status> {
+    let p: Project = {
+        use crate::schema::projects::dsl::*;
+        projects
+            .find(h)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get project from database")
+            .ok_or(Status::NotFound)?
+    };
+    let r: ProjectRequest = {
+        use crate::schema::project_requests::dsl::*;
+        project_requests
+            .find(rid)
+            .filter(project_id.eq(h))
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get request from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    let allowed = if r.invite {
+        l.0.id == r.user_id
     } else {
-        Err(Status::Conflict)
+        can_manage_members(&*conn, &l.0, &p)
+    };
+    if !allowed {
+        return Err(DENIED);
+    }
+    if r.status != RequestStatus::Pending {
+        return Err(Status::Conflict);
+    }
+
+    let accept = form.into_inner().accept;
+    let new = answer_request(&*conn, &p, &r, accept);
+    audit::updated(&*conn, Some(&l.0), "project-request", r.id, &r, &new);
+    if accept {
+        audit::member(&*conn, Some(&l.0), "project", h, r.user_id, true);
+    }
+    notify_answer(&*conn, &outbox, &p, &new);
+
+    Ok(Redirect::to(format!("/projects/{}", h)))
+}
+
+/// POST handler for `/projects/<h>/milestones`
+///
+/// Adds a milestone to the project, due at a time in the user's time zone.
+///
+/// Restricted to those that can edit the project.
+#[post("/projects/<h>/milestones", data = "<form>")]
+pub fn milestone_new_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    h: i32,
+    form: Form<MilestoneForm>,
+) -> Result<Redirect, Status> {
+    let p: Project = {
+        use crate::schema::projects::dsl::*;
+        projects
+            .find(h)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get project from database")
+            .ok_or(Status::NotFound)?
+    };
+    if !can_edit_project(&*conn, &l.0, &p) {
+        return Err(DENIED);
+    }
+
+    let form = form.into_inner();
+    let due = match parse_time(&form.due_at, &tz::of(Some(&l.0), &settings.site_zone())) {
+        Some(d) => d,
+        None => {
+            return Ok(Redirect::to(format!(
+                "/projects/{}?e={}",
+                h,
+                FormError::InvalidDate
+            )))
+        }
+    };
+    let mut name = form.title.trim().to_string();
+    name.truncate(100);
+    if name.is_empty() {
+        return Ok(Redirect::to(format!(
+            "/projects/{}?e={}",
+            h,
+            FormError::Other
+        )));
+    }
+
+    use crate::schema::project_milestones::dsl::*;
+    insert_into(project_milestones)
+        .values(&NewMilestone {
+            project_id: h,
+            title: name,
+            description: form.description.filter(|d| !d.trim().is_empty()),
+            due_at: due,
+        })
+        .execute(&*conn)
+        .expect("Failed to add milestone to database");
+    let m: Milestone = project_milestones
+        .find(last_id(&*conn))
+        .first(&*conn)
+        .expect("Failed to get milestone from database");
+    audit::created(&*conn, Some(&l.0), "milestone", m.id, &m);
+
+    audit_log!(
+        "User {} [{}] has added milestone {} to Project {}",
+        l.0.id,
+        l.0.email,
+        m.id,
+        h
+    );
+
+    Ok(Redirect::to(format!("/projects/{}", h)))
+}
+
+/// DELETE handler for `/projects/<h>/milestones/<mid>`
+///
+/// Removes a milestone from the project.
+///
+/// Restricted to those that can edit the project.
+#[delete("/projects/<h>/milestones/<mid>")]
+pub fn milestone_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    h: i32,
+    mid: i32,
+) -> Result<Redirect, Status> {
+    let p: Project = {
+        use crate::schema::projects::dsl::*;
+        projects
+            .find(h)
+            .filter(deleted_at.is_null())
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get project from database")
+            .ok_or(Status::NotFound)?
+    };
+    if !can_edit_project(&*conn, &l.0, &p) {
+        return Err(DENIED);
     }
+
+    use crate::schema::project_milestones::dsl::*;
+    let old: Milestone = project_milestones
+        .find(mid)
+        .filter(project_id.eq(h))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get milestone from database")
+        .ok_or(Status::NotFound)?;
+    delete(project_milestones.find(mid))
+        .execute(&*conn)
+        .expect("Failed to delete milestone from database");
+    audit::deleted(&*conn, Some(&l.0), "milestone", mid, &old);
+
+    audit_log!(
+        "User {} [{}] has removed milestone {} from Project {}",
+        l.0.id,
+        l.0.email,
+        mid,
+        h
+    );
+
+    Ok(Redirect::to(format!("/projects/{}", h)))
 }
 
 //# Helper Functions
 
+/// Creates a new project owned by `owner` and adds them to it
+pub fn create_project(
+    conn: &SqliteConnection,
+    owner: &User,
+    mut newproject: NewProject,
+) -> Result<Project, FormError> {
+    newproject.owner_id = owner.id; // set owner to be the person who created the project
+    newproject.active = true;
+    clean_project(&mut newproject)?;
+
+    // inserts the project into the database
+    use crate::schema::projects::dsl::*;
+    use diesel::result::DatabaseErrorKind;
+    use diesel::result::Error;
+    match insert_into(projects).values(&newproject).execute(conn) {
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Err(FormError::TakenName)
+        }
+        Err(_) => return Err(FormError::Other),
+        Ok(_) => (),
+    }
+
+    // retrieves the object from the database after creating it
+    let p: Project = projects
+        .filter(name.eq(newproject.name)) // CHANGEME switch to id
+        .first(conn)
+        .expect("Failed to get project from database");
+
+    //creates the relation for the project owner
+    add_member(conn, &p, owner.id, ProjectRole::Lead);
+
+    Ok(p)
+}
+
+/// Saves the edits made to a project
+///
+/// The owner is kept, it is changed by handing the project over with
+/// `project_transfer`.
+pub fn update_project(
+    conn: &SqliteConnection,
+    h: i32,
+    mut editproject: NewProject,
+) -> Result<(), FormError> {
+    use crate::schema::projects::dsl::*;
+
+    clean_project(&mut editproject)?;
+    editproject.owner_id = projects
+        .find(h)
+        .select(owner_id)
+        .first(conn)
+        .expect("Failed to get project from database");
+    update(projects.find(h))
+        .set(&editproject)
+        .execute(conn)
+        .expect("Failed to update project in database");
+    // The changeset skips `None` so it is set separately to allow clearing it
+    update(projects.find(h))
+        .set(semester_id.eq(editproject.semester_id))
+        .execute(conn)
+        .expect("Failed to update project in database");
+    Ok(())
+}
+
+/// Deletes a project for good along with the relations to its members
+/// and the requests to join it
+///
+/// Only used to purge the trash, see `crate::trash`.
+pub fn delete_project(conn: &SqliteConnection, h: i32) {
+    // Delete the relations
+    {
+        use crate::schema::relation_project_user::dsl::*;
+        delete(relation_project_user.filter(project_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete relations from database");
+    }
+
+    // Delete the requests and invites
+    {
+        use crate::schema::project_requests::dsl::*;
+        delete(project_requests.filter(project_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete requests from database");
+    }
+
+    // Delete the milestones
+    {
+        use crate::schema::project_milestones::dsl::*;
+        delete(project_milestones.filter(project_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete milestones from database");
+    }
+
+    // Delete the project
+    use crate::schema::projects::dsl::*;
+    delete(projects.find(h))
+        .execute(conn)
+        .expect("Failed to delete project from database");
+}
+
+/// Checks and tidies up a project before it is saved
+fn clean_project(p: &mut NewProject) -> Result<(), FormError> {
+    p.name.truncate(50); // sets a character limit for a project name
+    p.description.truncate(500); // sets a character limit for a project description
+    p.repos.truncate(100); // sets a character limit for the repository URLs
+
+    is_reserved(&p.name)?;
+
+    // handles the fact that projects can have multiple repos
+    p.repos = serde_json::to_string(
+        &serde_json::from_str::<Vec<String>>(&p.repos)
+            .map_err(|_| FormError::Other)?
+            .iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&String>>(),
+    )
+    .unwrap();
+
+    Ok(())
+}
+
 pub fn project_repos(p: &Project) -> Vec<String> {
     serde_json::from_str(&p.repos).unwrap()
 }
@@ -499,7 +917,7 @@ pub fn filter_projects(
 
     if let Some(term) = term {
         let sterm = format!("%{}%", term);
-        let filter = name.like(&sterm);
+        let filter = name.like(&sterm).and(deleted_at.is_null());
 
         match inact {
             Some(true) => projects.filter(filter).load(conn),
@@ -507,8 +925,10 @@ pub fn filter_projects(
         }
     } else {
         match inact {
-            Some(true) => projects.load(conn),
-            Some(false) | None => projects.filter(active.eq(true)).load(conn),
+            Some(true) => projects.filter(deleted_at.is_null()).load(conn),
+            Some(false) | None => projects
+                .filter(active.eq(true).and(deleted_at.is_null()))
+                .load(conn),
         }
     }
     .expect("Failed to get projects")
@@ -521,81 +941,315 @@ pub fn project_users(conn: &SqliteConnection, project: &Project) -> Vec<User> {
         .load::<RelationProjectUser>(conn)
         .expect("Failed to get relations from database")
         .iter()
-        .map(|r| {
+        .filter_map(|r| {
             use crate::schema::users::dsl::*;
             users
                 .find(r.user_id)
+                .filter(deleted_at.is_null())
                 .first(conn)
+                .optional()
                 .expect("Failed to get user from database")
         })
         .collect()
 }
 
-/// Get the commits in the project
-///
-/// This function calls to the GitHub API to get the commits.
+/// The members of a project with their roles, leads first
+pub fn project_roles(conn: &SqliteConnection, project: &Project) -> Vec<(User, ProjectRole)> {
+    let mut members: Vec<(User, ProjectRole)> = RelationProjectUser::belonging_to(project)
+        .load::<RelationProjectUser>(conn)
+        .expect("Failed to get relations from database")
+        .into_iter()
+        .filter_map(|r| find_user(conn, r.user_id).map(|u| (u, r.role)))
+        .collect();
+    members.sort_by_key(|(_, r)| *r != ProjectRole::Lead);
+    members
+}
+
+/// The role of a user in a project, `None` if they aren't a member
+pub fn member_role(conn: &SqliteConnection, pid: i32, uid: i32) -> Option<ProjectRole> {
+    use crate::schema::relation_project_user::dsl::*;
+    relation_project_user
+        .filter(project_id.eq(pid).and(user_id.eq(uid)))
+        .select(role)
+        .first(conn)
+        .optional()
+        .expect("Failed to get relation from database")
+}
+
+/// Changes the role of a member of a project
+fn set_member_role(conn: &SqliteConnection, pid: i32, uid: i32, r: ProjectRole) {
+    use crate::schema::relation_project_user::dsl::*;
+    update(relation_project_user.filter(project_id.eq(pid).and(user_id.eq(uid))))
+        .set(role.eq(r))
+        .execute(conn)
+        .expect("Failed to update relation in database");
+}
+
+/// The milestones of a project, soonest first
+pub fn project_milestones(conn: &SqliteConnection, p: &Project) -> Vec<Milestone> {
+    use crate::schema::project_milestones::dsl::*;
+    Milestone::belonging_to(p)
+        .order(due_at.asc())
+        .load(conn)
+        .expect("Failed to get milestones from database")
+}
+
+/// Can the user edit the project?
 ///
-/// If the project does not use GitHub for it's repo this returns `None`.
-/// Otherwise it returns a vector of JSON values with each repo having an
-/// entry.
+/// Its owner and leads can, as can those that can edit any project.
+pub fn can_edit_project(conn: &SqliteConnection, user: &User, p: &Project) -> bool {
+    user.can(Permission::EditAnyProject)
+        || user.id == p.owner_id
+        || member_role(conn, p.id, user.id) == Some(ProjectRole::Lead)
+}
+
+/// Can the user add and remove members of the project?
 ///
-/// TODO support other services like GitLab.
-pub fn project_commits(conn: &SqliteConnection, proj: &Project) -> Option<Vec<serde_json::Value>> {
-    // Get the repos from the DB
-    let mut repos: Vec<String> = {
-        use crate::schema::projects::dsl::*;
-        serde_json::from_str(
-            &projects
-                .find(proj.id)
-                .select(repos)
-                .first::<String>(conn)
-                .expect("Failed to get repos from the database"),
-        )
-        .unwrap()
-    };
+/// Its owner and leads can, as can those that can manage any project's members.
+pub fn can_manage_members(conn: &SqliteConnection, user: &User, p: &Project) -> bool {
+    user.can(Permission::ManageProjectMembers)
+        || user.id == p.owner_id
+        || member_role(conn, p.id, user.id) == Some(ProjectRole::Lead)
+}
 
-    // No repos at all
-    if repos.is_empty() {
-        return None;
-    }
+/// Get the recent commits in the project
+///
+/// Commits are synced from the forges in the background by
+/// `crate::commits::sync` so this only reads from the database.
+///
+/// Returns each of the project's repos that has commits with its `limit`
+/// most recent commits.
+pub fn project_commits(
+    conn: &SqliteConnection,
+    proj: &Project,
+    limit: i64,
+) -> Vec<(String, Vec<Commit>)> {
+    use crate::commits::forge::Repo;
+    use crate::schema::commits::dsl::*;
 
-    // Use a regex to filter to only GitHub and convert to the API string
-    use regex::Regex;
-    let re = Regex::new(r"^(https?://)?github\.com/(\S+/\S+)/?$")
-        .expect("Failed to build regular expression");
-    repos = repos
+    project_repos(proj)
         .iter()
-        .filter(|s| re.is_match(&s))
-        .map(|s| {
-            String::from(re.replace(s, "https://api.github.com/repos/$2/commits?per_page=100"))
+        .filter_map(|r| Repo::parse(r))
+        .map(|r| {
+            let c = commits
+                .filter(repo.eq(&r.url))
+                .order(committed_at.desc())
+                .limit(limit)
+                .load(conn)
+                .expect("Failed to get commits from database");
+            (r.path, c)
         })
-        .collect();
+        .filter(|(_, c)| !c.is_empty())
+        .collect()
+}
 
-    // If no GitHub repos
-    if repos.is_empty() {
-        return None;
+/// Adds a user to a project and enrolls them in its semester
+pub fn add_member(conn: &SqliteConnection, p: &Project, uid: i32, r: ProjectRole) {
+    use crate::schema::relation_project_user::dsl::*;
+    insert_into(relation_project_user)
+        .values(&NewRelationProjectUser {
+            project_id: p.id,
+            user_id: uid,
+            role: r,
+        })
+        .execute(conn)
+        .expect("Failed to add user to project");
+    enroll_in(conn, p.semester_id, uid);
+}
+
+/// The request or invite of a user to a project that hasn't been answered
+pub fn pending_request(conn: &SqliteConnection, pid: i32, uid: i32) -> Option<ProjectRequest> {
+    use crate::schema::project_requests::dsl::*;
+    project_requests
+        .filter(project_id.eq(pid))
+        .filter(user_id.eq(uid))
+        .filter(status.eq(RequestStatus::Pending))
+        .first(conn)
+        .optional()
+        .expect("Failed to get request from database")
+}
+
+/// Asks for a user to join a project, or invites them if `invite`
+pub fn create_request(
+    conn: &SqliteConnection,
+    pid: i32,
+    uid: i32,
+    invite_user: bool,
+) -> ProjectRequest {
+    use crate::schema::project_requests::dsl::*;
+    insert_into(project_requests)
+        .values(&NewProjectRequest {
+            project_id: pid,
+            user_id: uid,
+            invite: invite_user,
+            created_at: chrono::offset::Utc::now().naive_utc(),
+        })
+        .execute(conn)
+        .expect("Failed to insert request into database");
+    project_requests
+        .order(id.desc())
+        .first(conn)
+        .expect("Failed to get request from database")
+}
+
+/// Accepts or declines a request or invite, adding the user to the project
+/// if it was accepted
+///
+/// Returns the request as it now is.
+pub fn answer_request(
+    conn: &SqliteConnection,
+    p: &Project,
+    r: &ProjectRequest,
+    accept: bool,
+) -> ProjectRequest {
+    use crate::schema::project_requests::dsl::*;
+    let new = ProjectRequest {
+        status: if accept {
+            RequestStatus::Accepted
+        } else {
+            RequestStatus::Declined
+        },
+        answered_at: Some(chrono::offset::Utc::now().naive_utc()),
+        ..r.clone()
+    };
+    update(project_requests.find(r.id))
+        .set((status.eq(new.status), answered_at.eq(new.answered_at)))
+        .execute(conn)
+        .expect("Failed to update request in database");
+    if accept {
+        add_member(conn, p, r.user_id, ProjectRole::Developer);
     }
+    new
+}
 
-    // Get the commits and return them, filtering out errors
-    Some(
-        repos
-            .iter()
-            .filter_map(|s| {
-                let mut body = Vec::new();
-                let res = http_req::request::get(s, &mut body)
-                    .expect("Failed to get response from GitHub");
-                if res.status_code().is_success() {
-                    if let Ok(json) = serde_json::from_str(
-                        &String::from_utf8(body).expect("Response body was not valid UTF-8"),
-                    ) {
-                        Some(json)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .collect(),
-    )
+/// The requests and invites of a project waiting for an answer with the
+/// users they are for
+pub fn project_requests(conn: &SqliteConnection, p: &Project) -> Vec<(ProjectRequest, User)> {
+    use crate::schema::project_requests::dsl::*;
+    use crate::schema::users;
+    ProjectRequest::belonging_to(p)
+        .inner_join(users::table)
+        .filter(status.eq(RequestStatus::Pending))
+        .filter(users::deleted_at.is_null())
+        .order(created_at)
+        .load(conn)
+        .expect("Failed to get requests from database")
+}
+
+/// Requests and invites that a user has to answer with their projects and
+/// the users they are for
+///
+/// These are invites to the user and requests to join the projects they lead.
+pub fn requests_for(conn: &SqliteConnection, user: &User) -> Vec<(ProjectRequest, Project, User)> {
+    use crate::schema::project_requests::dsl::*;
+    use crate::schema::{projects, relation_project_user as rel, users};
+    let led = rel::table
+        .filter(
+            rel::user_id
+                .eq(user.id)
+                .and(rel::role.eq(ProjectRole::Lead)),
+        )
+        .select(rel::project_id);
+    project_requests
+        .inner_join(projects::table)
+        .inner_join(users::table)
+        .filter(status.eq(RequestStatus::Pending))
+        .filter(projects::deleted_at.is_null())
+        .filter(users::deleted_at.is_null())
+        .filter(
+            invite
+                .and(user_id.eq(user.id))
+                .or(invite.eq(false).and(project_id.eq_any(led))),
+        )
+        .order(created_at)
+        .load(conn)
+        .expect("Failed to get requests from database")
+}
+
+/// Emails the side that has to answer a new request or invite
+fn notify_request(conn: &SqliteConnection, outbox: &Outbox, p: &Project, r: &ProjectRequest) {
+    let (user, owner) = match (find_user(conn, r.user_id), find_user(conn, p.owner_id)) {
+        (Some(u), Some(o)) => (u, o),
+        _ => return,
+    };
+    if r.invite {
+        outbox.send_links(
+            &user.email,
+            &format!("You're invited to join {}", p.name),
+            |site| {
+                format!(
+                    "Hi {},\n\n\
+                     {} has invited you to join the project {} on Observatory. \
+                     You can accept or decline the invite here:\n\n\
+                     {}/projects/{}/members/join\n",
+                    user.real_name, owner.real_name, p.name, site, p.id
+                )
+            },
+        );
+    } else {
+        outbox.send_links(
+            &owner.email,
+            &format!("{} asked to join {}", user.real_name, p.name),
+            |site| {
+                format!(
+                    "Hi {},\n\n\
+                     {} ({}) has asked to join your project {} on Observatory. \
+                     You can accept or decline the request here:\n\n\
+                     {}/projects/{}\n",
+                    owner.real_name, user.real_name, user.handle, p.name, site, p.id
+                )
+            },
+        );
+    }
+}
+
+/// Emails the side that made a request or invite once it is answered
+fn notify_answer(conn: &SqliteConnection, outbox: &Outbox, p: &Project, r: &ProjectRequest) {
+    let (user, owner) = match (find_user(conn, r.user_id), find_user(conn, p.owner_id)) {
+        (Some(u), Some(o)) => (u, o),
+        _ => return,
+    };
+    let answer = match r.status {
+        RequestStatus::Accepted => "accepted",
+        RequestStatus::Declined => "declined",
+        RequestStatus::Pending => return,
+    };
+    if r.invite {
+        outbox.send_links(
+            &owner.email,
+            &format!("{} {} your invite to {}", user.real_name, answer, p.name),
+            |site| {
+                format!(
+                    "Hi {},\n\n\
+                     {} ({}) has {} your invite to join {} on Observatory.\n\n\
+                     {}/projects/{}\n",
+                    owner.real_name, user.real_name, user.handle, answer, p.name, site, p.id
+                )
+            },
+        );
+    } else {
+        outbox.send_links(
+            &user.email,
+            &format!("Your request to join {} was {}", p.name, answer),
+            |site| {
+                format!(
+                    "Hi {},\n\n\
+                     Your request to join the project {} on Observatory was {}.\n\n\
+                     {}/projects/{}\n",
+                    user.real_name, p.name, answer, site, p.id
+                )
+            },
+        );
+    }
+}
+
+/// A user that isn't in the trash
+fn find_user(conn: &SqliteConnection, uid: i32) -> Option<User> {
+    use crate::schema::users::dsl::*;
+    users
+        .find(uid)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get user from database")
 }
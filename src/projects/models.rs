@@ -1,3 +1,10 @@
+use std::fmt;
+
+use chrono::NaiveDateTime;
+use diesel::sql_types::Text;
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
+
 use crate::schema::*;
 
 use crate::models::User;
@@ -25,13 +32,21 @@ pub struct Project {
     pub repos: String,
     /// External (Non-RCOS) Project Flag
     pub extrn: bool,
+    /// Semester the project is part of
+    pub semester_id: Option<i32>,
+    /// When the project was moved to the trash, see `crate::trash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<NaiveDateTime>,
+    /// How users can join the project
+    pub joining: Joining,
 }
 
 /// New Project Template
 ///
 /// Represents the template when a new project is created
-#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset)]
+#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset, Deserialize)]
 #[table_name = "projects"]
+#[serde(default)]
 pub struct NewProject {
     /// Name of the Project
     pub name: String,
@@ -49,6 +64,10 @@ pub struct NewProject {
     pub repos: String,
     /// External (Non-RCOS) Project Flag
     pub extrn: bool,
+    /// Semester the project is part of
+    pub semester_id: Option<i32>,
+    /// How users can join the project
+    pub joining: Joining,
 }
 
 /// Student Relation to the Project
@@ -65,6 +84,8 @@ pub struct RelationProjectUser {
     pub project_id: i32,
     /// The ID of the Indivual User
     pub user_id: i32,
+    /// What the user does in the project
+    pub role: ProjectRole,
 }
 
 /// Used to Tie a student to a project
@@ -75,4 +96,190 @@ pub struct NewRelationProjectUser {
     pub project_id: i32,
     /// ID of the student being added to the project
     pub user_id: i32,
+    /// What the student does in the project
+    pub role: ProjectRole,
+}
+
+/// How users can join a project
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum Joining {
+    /// Anyone can join straight away
+    Open,
+    /// Users ask to join and the owner accepts or declines
+    Request,
+    /// Users can only join when the owner invites them
+    Closed,
+}
+
+text_enum!(Joining {
+    Open = "open",
+    Request = "request",
+    Closed = "closed",
+});
+
+impl Default for Joining {
+    fn default() -> Self {
+        Joining::Request
+    }
+}
+
+impl fmt::Display for Joining {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Joining::Open => "Anyone can join",
+                Joining::Request => "Users ask to join",
+                Joining::Closed => "Invite only",
+            }
+        )
+    }
+}
+
+impl<'v> FromFormValue<'v> for Joining {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Joining, &'v RawStr> {
+        form_value.parse().map_err(|_| form_value)
+    }
+
+    /// Forms without it are for projects that users ask to join
+    fn default() -> Option<Joining> {
+        Some(Joining::Request)
+    }
+}
+
+/// What a member does in a project
+///
+/// Leads can edit the project and manage its members like the owner.
+/// A project can have any number of leads, the owner is always one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectRole {
+    Lead,
+    Developer,
+    Mentor,
+}
+
+text_enum!(ProjectRole {
+    Lead = "lead",
+    Developer = "developer",
+    Mentor = "mentor",
+});
+
+impl Default for ProjectRole {
+    fn default() -> Self {
+        ProjectRole::Developer
+    }
+}
+
+impl fmt::Display for ProjectRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ProjectRole::Lead => "Lead",
+                ProjectRole::Developer => "Developer",
+                ProjectRole::Mentor => "Mentor",
+            }
+        )
+    }
+}
+
+impl<'v> FromFormValue<'v> for ProjectRole {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<ProjectRole, &'v RawStr> {
+        form_value.parse().map_err(|_| form_value)
+    }
+}
+
+/// Where a request to join a project or an invite is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum RequestStatus {
+    /// Waiting for an answer
+    Pending,
+    Accepted,
+    Declined,
+}
+
+text_enum!(RequestStatus {
+    Pending = "pending",
+    Accepted = "accepted",
+    Declined = "declined",
+});
+
+/// A user asking to join a project, or a project inviting a user
+///
+/// Requests are answered by the project owner and invites by the user.
+#[derive(Debug, PartialEq, Clone, Queryable, Associations, Identifiable, Serialize)]
+#[table_name = "project_requests"]
+#[belongs_to(Project)]
+#[belongs_to(User)]
+pub struct ProjectRequest {
+    pub id: i32,
+    /// ID of the project
+    pub project_id: i32,
+    /// ID of the user joining it
+    pub user_id: i32,
+    /// Whether the project invited the user rather than the user asking
+    pub invite: bool,
+    pub status: RequestStatus,
+    pub created_at: NaiveDateTime,
+    /// When it was accepted or declined
+    pub answered_at: Option<NaiveDateTime>,
+}
+
+/// Used to ask to join a project or invite a user to one
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "project_requests"]
+pub struct NewProjectRequest {
+    pub project_id: i32,
+    pub user_id: i32,
+    pub invite: bool,
+    pub created_at: NaiveDateTime,
+}
+
+/// A date a project plans to reach a goal by
+///
+/// Shown on the project page and in the calendar feeds of its members.
+#[derive(Debug, PartialEq, Clone, Queryable, Associations, Identifiable, Serialize)]
+#[table_name = "project_milestones"]
+#[belongs_to(Project)]
+pub struct Milestone {
+    pub id: i32,
+    /// ID of the project
+    pub project_id: i32,
+    pub title: String,
+    pub description: Option<String>,
+    /// When it is due in UTC
+    #[serde(serialize_with = "crate::tz::serialize_utc")]
+    pub due_at: NaiveDateTime,
+}
+
+/// Used to add a milestone to a project
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "project_milestones"]
+pub struct NewMilestone {
+    pub project_id: i32,
+    pub title: String,
+    pub description: Option<String>,
+    pub due_at: NaiveDateTime,
+}
+
+/// The form to add a milestone
+///
+/// `due_at` is in the time zone of the user adding it.
+#[derive(Debug, Clone, FromForm)]
+pub struct MilestoneForm {
+    pub title: String,
+    pub description: Option<String>,
+    pub due_at: String,
 }
@@ -2,9 +2,11 @@ use super::models::*;
 #[allow(unused_imports)]
 use crate::templates::filters;
 use crate::templates::{FormError, OptUser};
-use std::collections::HashMap;
 
+use crate::commits::models::Commit;
 use crate::models::User;
+use crate::semesters::templates::SemesterSelect;
+use crate::tz::Tz;
 
 /// Project page template
 ///
@@ -16,10 +18,34 @@ use crate::models::User;
 #[template(path = "project/project.html")]
 pub struct ProjectTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub project: Project,
     pub repos: Vec<String>,
-    pub users: Vec<User>,
-    pub recent_commits: HashMap<String, Vec<(String, String, String, String)>>,
+    /// The members with their roles, leads first
+    pub members: Vec<(User, ProjectRole)>,
+    pub recent_commits: Vec<(String, Vec<Commit>)>,
+    /// Can the logged in user edit the project?
+    pub edits: bool,
+    /// Can the logged in user manage the members?
+    pub manages: bool,
+    /// Requests and invites waiting for an answer,
+    /// only shown to those that can manage the members
+    pub requests: Vec<(ProjectRequest, User)>,
+    pub milestones: Vec<Milestone>,
+    pub error: Option<FormError>,
+}
+
+impl ProjectTemplate {
+    /// Is the user a member of the project?
+    pub fn is_member(&self, user: &User) -> bool {
+        self.members.iter().any(|(u, _)| u.id == user.id)
+    }
+
+    /// Every role a member can have
+    pub fn roles(&self) -> &'static [ProjectRole] {
+        ProjectRole::ALL
+    }
 }
 
 /// Project page template
@@ -32,9 +58,17 @@ pub struct ProjectTemplate {
 #[template(path = "project/new-project.html")]
 pub struct NewProjectTemplate {
     pub logged_in: OptUser,
+    pub semester: SemesterSelect,
     pub error: Option<FormError>,
 }
 
+impl NewProjectTemplate {
+    /// Every way of joining a project
+    pub fn joinings(&self) -> &'static [Joining] {
+        Joining::ALL
+    }
+}
+
 /// Project page template
 ///
 /// HTML File: `project/edit-project.html`
@@ -46,10 +80,19 @@ pub struct EditProjectTemplate {
     pub logged_in: OptUser,
     pub project: Project,
     pub repos: Vec<String>,
-    pub all_users: Vec<User>,
+    /// The members the project can be handed to
+    pub members: Vec<(User, ProjectRole)>,
+    pub semester: SemesterSelect,
     pub error: Option<FormError>,
 }
 
+impl EditProjectTemplate {
+    /// Every way of joining a project
+    pub fn joinings(&self) -> &'static [Joining] {
+        Joining::ALL
+    }
+}
+
 /// List of the Projects
 ///
 /// HTML File: `project/project-list.html`
@@ -62,6 +105,7 @@ pub struct ProjectsListTemplate {
     pub projects: Vec<Project>,
     pub search_term: String,
     pub inactive: bool,
+    pub semester: SemesterSelect,
 }
 
 /// Template shown when a student wants to join a project
@@ -74,6 +118,8 @@ pub struct ProjectsListTemplate {
 pub struct JoinTemplate {
     pub logged_in: OptUser,
     pub project: Project,
+    /// The student's request to join or invite that hasn't been answered
+    pub pending: Option<ProjectRequest>,
 }
 
 /// The Adduser Template
@@ -0,0 +1,185 @@
+//! Roles and permissions
+//!
+//! Every user has a `Role` which decides what they are allowed to do.
+//! What each role can do is the permission matrix in `Role::permissions`.
+//! Handlers check a `Permission` with `User::can` or by taking the
+//! `crate::guards::Can` guard, like `Can<can::ManageNews>`.
+//!
+//! Roles can be given for a semester when enrolling users in it, so
+//! next semester's mentors can be picked ahead of time. The role of an
+//! enrollment becomes the user's role when the semester is current, or
+//! when it is rolled over to.
+
+use std::fmt;
+
+use diesel::sql_types::Text;
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
+
+/// The roles a user can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// A student taking part in RCOS
+    Student,
+    /// Someone taking part who isn't a student
+    External,
+    /// A student who runs a small group
+    Mentor,
+    /// Runs RCOS, which is everything but managing the accounts of others
+    Coordinator,
+    /// The special Admin account
+    Admin,
+}
+
+text_enum!(Role {
+    Student = "student",
+    External = "external",
+    Mentor = "mentor",
+    Coordinator = "coordinator",
+    Admin = "admin",
+});
+
+macro_rules! permissions {
+    ($($(#[$doc:meta])* $p:ident = $key:literal,)*) => {
+        /// The things a role can be allowed to do
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Permission {
+            $($(#[$doc])* $p,)*
+        }
+
+        impl Permission {
+            /// Every permission
+            pub const ALL: &'static [Permission] = &[$(Permission::$p),*];
+
+            /// The name of the permission like `create-event`
+            pub fn key(self) -> &'static str {
+                match self {
+                    $(Permission::$p => $key,)*
+                }
+            }
+        }
+
+        /// Types naming each permission for the `Can` guard
+        pub mod can {
+            $(
+                pub struct $p;
+
+                impl super::RequiredPermission for $p {
+                    const PERMISSION: super::Permission = super::Permission::$p;
+                }
+            )*
+        }
+    };
+}
+
+permissions! {
+    /// See small groups and run their meetings
+    RunMeetings = "run-meetings",
+    /// Create, edit and delete any group and its meetings
+    ManageGroup = "manage-group",
+    /// Add events to the calendar
+    CreateEvent = "create-event",
+    /// Edit and delete any event, not just those they host
+    EditAnyEvent = "edit-any-event",
+    /// Edit and delete any project
+    EditAnyProject = "edit-any-project",
+    /// Add and remove the members of any project
+    ManageProjectMembers = "manage-project-members",
+    /// Approve and reject attendance of the meetings and events they run
+    ReviewAttendance = "review-attendance",
+    /// Approve and reject any attendance
+    ReviewAnyAttendance = "review-any-attendance",
+    /// Verify commit identities and attribute commits
+    VerifyIdentities = "verify-identities",
+    /// See the details other users only show to mentors
+    ViewUsers = "view-users",
+    /// Edit and delete any user and their logins
+    ManageUsers = "manage-users",
+    /// Change the role of users
+    AssignRoles = "assign-roles",
+    /// Post, edit and delete news
+    ManageNews = "manage-news",
+    /// Manage semesters and their grades
+    ManageSemesters = "manage-semesters",
+    /// Read the server logs and the audit trail
+    ViewLogs = "view-logs",
+    /// Restore deleted things from the trash or purge them for good
+    ManageTrash = "manage-trash",
+}
+
+/// Names a permission for the `Can` guard
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+impl Role {
+    /// The permission matrix
+    pub fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Role::Student | Role::External => &[],
+            Role::Mentor => &[
+                RunMeetings,
+                ManageProjectMembers,
+                ReviewAttendance,
+                VerifyIdentities,
+                ViewUsers,
+            ],
+            Role::Coordinator => &[
+                RunMeetings,
+                ManageGroup,
+                CreateEvent,
+                EditAnyEvent,
+                EditAnyProject,
+                ManageProjectMembers,
+                ReviewAttendance,
+                ReviewAnyAttendance,
+                VerifyIdentities,
+                ViewUsers,
+                AssignRoles,
+                ManageNews,
+                ManageSemesters,
+                ViewLogs,
+                ManageTrash,
+            ],
+            Role::Admin => Permission::ALL,
+        }
+    }
+
+    /// Is this role allowed to do something?
+    pub fn can(self, p: Permission) -> bool {
+        self.permissions().contains(&p)
+    }
+}
+
+impl Default for Role {
+    fn default() -> Self {
+        Role::Student
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Role::Student => "Student",
+                Role::External => "External",
+                Role::Mentor => "Mentor",
+                Role::Coordinator => "Coordinator",
+                Role::Admin => "Administrator",
+            }
+        )
+    }
+}
+
+impl<'v> FromFormValue<'v> for Role {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Role, &'v RawStr> {
+        form_value.parse().map_err(|_| form_value)
+    }
+}
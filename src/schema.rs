@@ -1,3 +1,14 @@
+table! {
+    api_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        last_used -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     attendances (id) {
         id -> Integer,
@@ -5,6 +16,75 @@ table! {
         user_id -> Integer,
         meeting_id -> Nullable<Integer>,
         event_id -> Nullable<Integer>,
+        approved -> Bool,
+    }
+}
+
+table! {
+    audit_events (id) {
+        id -> Integer,
+        actor_id -> Nullable<Integer>,
+        action -> Text,
+        target_type -> Text,
+        target_id -> Nullable<Integer>,
+        old_values -> Nullable<Text>,
+        new_values -> Nullable<Text>,
+        happened_at -> Timestamp,
+    }
+}
+
+table! {
+    calendar_feeds (id) {
+        id -> Integer,
+        user_id -> Integer,
+        group_id -> Nullable<Integer>,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        last_used -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    commit_identities (id) {
+        id -> Integer,
+        user_id -> Integer,
+        kind -> Text,
+        value -> Text,
+        verified -> Bool,
+    }
+}
+
+table! {
+    commits (id) {
+        id -> Integer,
+        repo -> Text,
+        sha -> Text,
+        author_name -> Text,
+        author_email -> Text,
+        author_login -> Nullable<Text>,
+        message -> Text,
+        url -> Text,
+        committed_at -> Timestamp,
+    }
+}
+
+table! {
+    enrollments (id) {
+        id -> Integer,
+        semester_id -> Integer,
+        user_id -> Integer,
+        role -> Nullable<Text>,
+    }
+}
+
+table! {
+    event_series (id) {
+        id -> Integer,
+        start -> Timestamp,
+        end -> Timestamp,
+        weekdays -> Text,
+        until -> Date,
+        except_dates -> Text,
     }
 }
 
@@ -19,6 +99,13 @@ table! {
         location -> Nullable<Text>,
         code -> Text,
         color -> Nullable<Text>,
+        code_open -> Nullable<Bool>,
+        semester_id -> Nullable<Integer>,
+        deleted_at -> Nullable<Timestamp>,
+        series_id -> Nullable<Integer>,
+        original_start -> Nullable<Timestamp>,
+        detached -> Bool,
+        capacity -> Nullable<Integer>,
     }
 }
 
@@ -28,6 +115,20 @@ table! {
         name -> Text,
         owner_id -> Integer,
         location -> Nullable<Text>,
+        semester_id -> Nullable<Integer>,
+        archived -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+        meeting_day -> Nullable<Text>,
+        meeting_time -> Nullable<Time>,
+    }
+}
+
+table! {
+    login_attempts (id) {
+        id -> Integer,
+        email -> Text,
+        ip -> Nullable<Text>,
+        attempted_at -> Timestamp,
     }
 }
 
@@ -38,6 +139,11 @@ table! {
         code -> Text,
         group_id -> Integer,
         hosted_by -> Integer,
+        code_expires -> Timestamp,
+        code_open -> Nullable<Bool>,
+        semester_id -> Nullable<Integer>,
+        location -> Nullable<Text>,
+        cancelled -> Bool,
     }
 }
 
@@ -49,6 +155,40 @@ table! {
         description -> Text,
         color -> Nullable<Text>,
         announcement -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    oauth_identities (id) {
+        id -> Integer,
+        user_id -> Integer,
+        provider -> Text,
+        subject -> Text,
+        login -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    project_milestones (id) {
+        id -> Integer,
+        project_id -> Integer,
+        title -> Text,
+        description -> Nullable<Text>,
+        due_at -> Timestamp,
+    }
+}
+
+table! {
+    project_requests (id) {
+        id -> Integer,
+        project_id -> Integer,
+        user_id -> Integer,
+        invite -> Bool,
+        status -> Text,
+        created_at -> Timestamp,
+        answered_at -> Nullable<Timestamp>,
     }
 }
 
@@ -63,6 +203,17 @@ table! {
         active -> Bool,
         repos -> Text,
         extrn -> Bool,
+        semester_id -> Nullable<Integer>,
+        deleted_at -> Nullable<Timestamp>,
+        joining -> Text,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Integer,
+        user_id -> Integer,
+        code_hash -> Text,
     }
 }
 
@@ -79,6 +230,69 @@ table! {
         id -> Integer,
         project_id -> Integer,
         user_id -> Integer,
+        role -> Text,
+    }
+}
+
+table! {
+    repo_syncs (repo) {
+        repo -> Text,
+        etag -> Nullable<Text>,
+        synced_at -> Timestamp,
+        resume_url -> Nullable<Text>,
+    }
+}
+
+table! {
+    rsvps (id) {
+        id -> Integer,
+        event_id -> Integer,
+        user_id -> Integer,
+        status -> Text,
+        responded_at -> Timestamp,
+    }
+}
+
+table! {
+    rubrics (semester_id) {
+        semester_id -> Integer,
+        small_group_weight -> Integer,
+        large_group_weight -> Integer,
+        event_weight -> Integer,
+        commit_weight -> Integer,
+        commits_needed -> Integer,
+        thresholds -> Text,
+    }
+}
+
+table! {
+    semesters (id) {
+        id -> Integer,
+        name -> Text,
+        start -> Date,
+        end -> Date,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        last_seen -> Timestamp,
+        ip -> Nullable<Text>,
+        user_agent -> Text,
+    }
+}
+
+table! {
+    totp_secrets (user_id) {
+        user_id -> Integer,
+        secret -> Text,
+        enabled -> Bool,
+        last_step -> BigInt,
+        created_at -> Timestamp,
     }
 }
 
@@ -89,33 +303,76 @@ table! {
         handle -> Text,
         email -> Text,
         password_hash -> Text,
-        salt -> Text,
         bio -> Text,
         active -> Bool,
         joined_on -> Timestamp,
-        tier -> Integer,
+        role -> Text,
         mmost -> Text,
         former -> Bool,
         extrn -> Bool,
+        verified -> Bool,
+        handle_verified -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+        time_zone -> Nullable<Text>,
     }
 }
 
+joinable!(api_tokens -> users (user_id));
 joinable!(attendances -> events (event_id));
 joinable!(attendances -> meetings (meeting_id));
 joinable!(attendances -> users (user_id));
+joinable!(audit_events -> users (actor_id));
+joinable!(calendar_feeds -> groups (group_id));
+joinable!(calendar_feeds -> users (user_id));
+joinable!(commit_identities -> users (user_id));
+joinable!(enrollments -> semesters (semester_id));
+joinable!(enrollments -> users (user_id));
+joinable!(events -> event_series (series_id));
+joinable!(events -> semesters (semester_id));
+joinable!(groups -> semesters (semester_id));
+joinable!(meetings -> semesters (semester_id));
+joinable!(oauth_identities -> users (user_id));
+joinable!(project_milestones -> projects (project_id));
+joinable!(project_requests -> projects (project_id));
+joinable!(project_requests -> users (user_id));
+joinable!(projects -> semesters (semester_id));
+joinable!(recovery_codes -> users (user_id));
 joinable!(relation_group_user -> groups (group_id));
 joinable!(relation_group_user -> users (user_id));
 joinable!(relation_project_user -> projects (project_id));
 joinable!(relation_project_user -> users (user_id));
+joinable!(rsvps -> events (event_id));
+joinable!(rsvps -> users (user_id));
+joinable!(rubrics -> semesters (semester_id));
+joinable!(sessions -> users (user_id));
+joinable!(totp_secrets -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
     attendances,
+    audit_events,
+    calendar_feeds,
+    commit_identities,
+    commits,
+    enrollments,
+    event_series,
     events,
     groups,
+    login_attempts,
     meetings,
     news,
+    oauth_identities,
+    project_milestones,
+    project_requests,
     projects,
+    recovery_codes,
     relation_group_user,
     relation_project_user,
+    repo_syncs,
+    rsvps,
+    rubrics,
+    semesters,
+    sessions,
+    totp_secrets,
     users,
 );
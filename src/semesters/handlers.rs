@@ -0,0 +1,696 @@
+//! HTTP handlers for semesters
+//!
+//! Projects, groups, meetings and events can belong to a semester, and users
+//! are enrolled in the semesters they take part in. Things without a
+//! semester are from before semesters were added and show up in every one.
+
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::{delete, insert_into, insert_or_ignore_into, update};
+use rocket::http::Status;
+use rocket::request::Form;
+use rocket::response::Redirect;
+use rocket::State;
+
+use crate::api::handlers::revoke_tokens;
+use crate::audit;
+use crate::guards::*;
+use crate::models::User;
+use crate::roles::{Permission, Role};
+use crate::sessions::handlers::revoke_sessions;
+use crate::settings::Settings;
+use crate::templates::FormError;
+use crate::tz::Tz;
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::templates::*;
+
+/// GET handler for `/semesters`
+///
+/// Lists the semesters with the form to add a new one.
+///
+/// Restricted to Admins.
+#[get("/semesters?<e>")]
+pub fn semesters(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    settings: State<Settings>,
+    e: Option<FormError>,
+) -> SemestersTemplate {
+    SemestersTemplate {
+        logged_in: Some(l.0),
+        semesters: all_semesters(&*conn),
+        current: current_semester(&*conn, &settings.site_zone()),
+        error: e,
+    }
+}
+
+/// A new or edited semester
+///
+/// Dates are in the form `2021-01-25`.
+#[derive(FromForm)]
+pub struct SemesterForm {
+    name: String,
+    start: String,
+    end: String,
+    /// Enroll every active user in the new semester
+    enroll_active: bool,
+}
+
+impl SemesterForm {
+    /// Check the form and turn it into a semester
+    fn parse(self) -> Result<NewSemester, FormError> {
+        let date = |s: &str| NaiveDate::parse_from_str(s.trim(), "%F").ok();
+        let (start, end) = match (date(&self.start), date(&self.end)) {
+            (Some(s), Some(e)) if s <= e => (s, e),
+            _ => return Err(FormError::InvalidDate),
+        };
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err(FormError::Other);
+        }
+        Ok(NewSemester { name, start, end })
+    }
+}
+
+/// POST handler for `/semesters`
+///
+/// Adds a semester, optionally enrolling every active user in it.
+///
+/// Restricted to Admins.
+#[post("/semesters", data = "<form>")]
+pub fn semester_new_post(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    form: Form<SemesterForm>,
+) -> Redirect {
+    let form = form.into_inner();
+    let enroll_active = form.enroll_active;
+
+    match create_semester(&*conn, form.parse()) {
+        Ok(s) => {
+            audit_log!(
+                "User {} [{}] has created Semester {} \'{}\'",
+                l.0.id,
+                l.0.email,
+                s.id,
+                s.name
+            );
+            audit::created(&*conn, Some(&l.0), "semester", s.id, &s);
+            if enroll_active {
+                enroll_active_users(&*conn, s.id);
+            }
+            Redirect::to(format!("/semesters/{}", s.id))
+        }
+        Err(e) => Redirect::to(format!("/semesters?e={}", e)),
+    }
+}
+
+/// GET handler for `/semesters/<sid>`
+///
+/// Shows a semester with its enrolled users.
+///
+/// Restricted to Admins.
+#[get("/semesters/<sid>?<e>")]
+pub fn semester(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    sid: i32,
+    e: Option<FormError>,
+) -> Option<SemesterTemplate> {
+    let s = find_semester(&*conn, sid)?;
+    let enrolled = semester_roles(&*conn, &s);
+
+    let all_users: Vec<User> = {
+        use crate::schema::users::dsl::*;
+        users
+            .filter(id.ne(0).and(deleted_at.is_null()))
+            .order(real_name.asc())
+            .load::<User>(&*conn)
+            .expect("Failed to get users from database")
+            .into_iter()
+            .filter(|u| !enrolled.iter().any(|(e, _)| e == u))
+            .collect()
+    };
+
+    let roles = if l.0.can(Permission::AssignRoles) {
+        Role::ALL
+            .iter()
+            .copied()
+            .filter(|r| *r != Role::Admin)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Some(SemesterTemplate {
+        logged_in: Some(l.0),
+        roles,
+        next: next_semester(&*conn, &s),
+        semester: s,
+        enrolled,
+        all_users,
+        error: e,
+    })
+}
+
+/// PUT handler for `/semesters/<sid>`
+///
+/// Renames a semester or changes its dates.
+///
+/// Restricted to Admins.
+#[put("/semesters/<sid>", data = "<form>")]
+pub fn semester_edit_put(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    sid: i32,
+    form: Form<SemesterForm>,
+) -> Result<Redirect, Status> {
+    let old = find_semester(&*conn, sid).ok_or(Status::NotFound)?;
+
+    let edit = match form.into_inner().parse() {
+        Ok(s) => s,
+        Err(e) => return Ok(Redirect::to(format!("/semesters/{}?e={}", sid, e))),
+    };
+
+    use crate::schema::semesters::dsl::*;
+    use diesel::result::{DatabaseErrorKind, Error};
+    match update(semesters.find(sid)).set(&edit).execute(&*conn) {
+        Ok(_) => (),
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Ok(Redirect::to(format!(
+                "/semesters/{}?e={}",
+                sid,
+                FormError::TakenName
+            )))
+        }
+        Err(_) => {
+            return Ok(Redirect::to(format!(
+                "/semesters/{}?e={}",
+                sid,
+                FormError::Other
+            )))
+        }
+    }
+
+    audit_log!(
+        "User {} [{}] has edited Semester {}",
+        l.0.id,
+        l.0.email,
+        sid
+    );
+    if let Some(new) = find_semester(&*conn, sid) {
+        audit::updated(&*conn, Some(&l.0), "semester", sid, &old, &new);
+    }
+
+    Ok(Redirect::to(format!("/semesters/{}", sid)))
+}
+
+/// DELETE handler for `/semesters/<sid>`
+///
+/// Deletes a semester. Everything that was part of it is kept
+/// but no longer has a semester.
+///
+/// Restricted to Admins.
+#[delete("/semesters/<sid>")]
+pub fn semester_delete(conn: ObservDbConn, l: Can<can::ManageSemesters>, sid: i32) -> Redirect {
+    let old = find_semester(&*conn, sid);
+    delete_semester(&*conn, sid);
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "semester", sid, &old);
+    }
+
+    audit_log!(
+        "User {} [{}] has deleted Semester {}",
+        l.0.id,
+        l.0.email,
+        sid
+    );
+
+    Redirect::to("/semesters")
+}
+
+/// A user to enroll
+#[derive(FromForm)]
+pub struct EnrollForm {
+    uid: Option<i32>,
+}
+
+/// POST handler for `/semesters/<sid>/enroll`
+///
+/// Enrolls a user in a semester.
+///
+/// Restricted to Admins.
+#[post("/semesters/<sid>/enroll", data = "<form>")]
+pub fn semester_enroll_post(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    sid: i32,
+    form: Form<EnrollForm>,
+) -> Result<Redirect, Status> {
+    find_semester(&*conn, sid).ok_or(Status::NotFound)?;
+
+    if let Some(uid) = form.into_inner().uid {
+        enroll(&*conn, sid, uid);
+
+        audit_log!(
+            "User {} [{}] has enrolled User {} in Semester {}",
+            l.0.id,
+            l.0.email,
+            uid,
+            sid
+        );
+        audit::member(&*conn, Some(&l.0), "semester", sid, uid, true);
+    }
+
+    Ok(Redirect::to(format!("/semesters/{}", sid)))
+}
+
+/// The role to give an enrolled user, or none to keep the one they have
+#[derive(FromForm)]
+pub struct EnrollRoleForm {
+    role: Option<Role>,
+}
+
+/// PUT handler for `/semesters/<sid>/enroll/<uid>`
+///
+/// Gives an enrolled user a role for the semester, see `set_semester_role`.
+///
+/// Restricted to those who can assign roles.
+#[put("/semesters/<sid>/enroll/<uid>", data = "<form>")]
+pub fn semester_enroll_put(
+    conn: ObservDbConn,
+    l: Can<can::AssignRoles>,
+    settings: State<Settings>,
+    sid: i32,
+    uid: i32,
+    form: Form<EnrollRoleForm>,
+) -> Result<Redirect, Status> {
+    let s = find_semester(&*conn, sid).ok_or(Status::NotFound)?;
+    // Admin is only for the Admin account
+    let r = form.into_inner().role.filter(|r| *r != Role::Admin);
+    let enrollment = |conn: &SqliteConnection| -> Option<Enrollment> {
+        use crate::schema::enrollments::dsl::*;
+        enrollments
+            .filter(semester_id.eq(sid).and(user_id.eq(uid)))
+            .first(conn)
+            .optional()
+            .expect("Failed to get enrollment from database")
+    };
+    let old = enrollment(&*conn).ok_or(Status::NotFound)?;
+    set_semester_role(&*conn, &s, uid, r, &settings.site_zone());
+    if let Some(new) = enrollment(&*conn) {
+        audit::updated(&*conn, Some(&l.0), "enrollment", old.id, &old, &new);
+    }
+
+    audit_log!(
+        "User {} [{}] has given User {} the role {} for Semester {}",
+        l.0.id,
+        l.0.email,
+        uid,
+        r.map_or("they have", Role::key),
+        sid
+    );
+
+    Ok(Redirect::to(format!("/semesters/{}", sid)))
+}
+
+/// DELETE handler for `/semesters/<sid>/enroll/<uid>`
+///
+/// Removes a user from a semester.
+///
+/// Restricted to Admins.
+#[delete("/semesters/<sid>/enroll/<uid>")]
+pub fn semester_enroll_delete(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    sid: i32,
+    uid: i32,
+) -> Redirect {
+    use crate::schema::enrollments::dsl::*;
+    delete(enrollments.filter(semester_id.eq(sid).and(user_id.eq(uid))))
+        .execute(&*conn)
+        .expect("Failed to delete enrollment from database");
+
+    audit_log!(
+        "User {} [{}] has removed User {} from Semester {}",
+        l.0.id,
+        l.0.email,
+        uid,
+        sid
+    );
+    audit::member(&*conn, Some(&l.0), "semester", sid, uid, false);
+
+    Redirect::to(format!("/semesters/{}", sid))
+}
+
+/// POST handler for `/semesters/<sid>/rollover`
+///
+/// Ends a semester, see `rollover`.
+///
+/// Restricted to Admins.
+#[post("/semesters/<sid>/rollover")]
+pub fn semester_rollover(
+    conn: ObservDbConn,
+    l: Can<can::ManageSemesters>,
+    sid: i32,
+) -> Result<Redirect, Status> {
+    let s = find_semester(&*conn, sid).ok_or(Status::NotFound)?;
+
+    match rollover(&*conn, &s) {
+        Ok((next, former)) => {
+            audit_log!(
+                "User {} [{}] has rolled over Semester {} into Semester {}, {} users are now former",
+                l.0.id,
+                l.0.email,
+                s.id,
+                next.id,
+                former
+            );
+            audit::record(
+                &*conn,
+                Some(&l.0),
+                "rollover",
+                "semester",
+                Some(s.id),
+                None,
+                audit::snapshot(&serde_json::json!({ "next": next.id, "former": former })),
+            );
+            Ok(Redirect::to(format!("/semesters/{}", next.id)))
+        }
+        Err(e) => Ok(Redirect::to(format!("/semesters/{}?e={}", sid, e))),
+    }
+}
+
+//# Helper Functions
+
+/// Every semester, newest first
+pub fn all_semesters(conn: &SqliteConnection) -> Vec<Semester> {
+    use crate::schema::semesters::dsl::*;
+    semesters
+        .order(start.desc())
+        .load(conn)
+        .expect("Failed to get semesters from database")
+}
+
+/// Find a semester by its ID
+pub fn find_semester(conn: &SqliteConnection, sid: i32) -> Option<Semester> {
+    use crate::schema::semesters::dsl::*;
+    semesters
+        .find(sid)
+        .first(conn)
+        .optional()
+        .expect("Failed to get semester from database")
+}
+
+/// The semester a time in UTC is in, if any
+///
+/// Semesters start and end on days in the site's time zone `site`.
+pub fn semester_at(conn: &SqliteConnection, t: NaiveDateTime, site: &Tz) -> Option<Semester> {
+    use crate::schema::semesters::dsl::*;
+    let day = site.to_local(&t).date();
+    semesters
+        .filter(start.le(day).and(end.ge(day)))
+        .order(start.desc())
+        .first(conn)
+        .optional()
+        .expect("Failed to get semester from database")
+}
+
+/// The current semester
+///
+/// This is the semester today is in, or between semesters the one that
+/// ended most recently.
+pub fn current_semester(conn: &SqliteConnection, site: &Tz) -> Option<Semester> {
+    let now = Utc::now().naive_utc();
+    semester_at(conn, now, site).or_else(|| {
+        use crate::schema::semesters::dsl::*;
+        let today = site.to_local(&now).date();
+        semesters
+            .filter(start.le(today))
+            .order(start.desc())
+            .first(conn)
+            .optional()
+            .expect("Failed to get semester from database")
+    })
+}
+
+/// The semester that starts after this one, if it has been added
+pub fn next_semester(conn: &SqliteConnection, s: &Semester) -> Option<Semester> {
+    use crate::schema::semesters::dsl::*;
+    semesters
+        .filter(start.gt(s.start))
+        .order(start.asc())
+        .first(conn)
+        .optional()
+        .expect("Failed to get semester from database")
+}
+
+/// The semester selected on a list page by the `sem` parameter
+///
+/// `all` shows every semester, an ID shows that semester and anything else
+/// shows the current semester.
+pub fn semester_select(conn: &SqliteConnection, sem: Option<String>, site: &Tz) -> SemesterSelect {
+    let semesters = all_semesters(conn);
+    let selected = match sem.as_deref() {
+        Some("all") => None,
+        Some(s) => match s.parse::<i32>() {
+            Ok(sid) => semesters.iter().find(|s| s.id == sid).cloned(),
+            Err(_) => current_semester(conn, site),
+        },
+        None => current_semester(conn, site),
+    };
+    SemesterSelect {
+        semesters,
+        selected,
+    }
+}
+
+/// Adds a semester
+pub fn create_semester(
+    conn: &SqliteConnection,
+    newsemester: Result<NewSemester, FormError>,
+) -> Result<Semester, FormError> {
+    let newsemester = newsemester?;
+
+    use crate::schema::semesters::dsl::*;
+    use diesel::result::{DatabaseErrorKind, Error};
+    match insert_into(semesters).values(&newsemester).execute(conn) {
+        Ok(_) => (),
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Err(FormError::TakenName)
+        }
+        Err(_) => return Err(FormError::Other),
+    }
+
+    Ok(semesters
+        .filter(name.eq(&newsemester.name))
+        .first(conn)
+        .expect("Failed to get semester from database"))
+}
+
+/// Deletes a semester and its enrollments
+///
+/// Projects, groups, meetings and events in the semester are kept
+/// without a semester.
+pub fn delete_semester(conn: &SqliteConnection, sid: i32) {
+    {
+        use crate::schema::projects::dsl::*;
+        update(projects.filter(semester_id.eq(sid)))
+            .set(semester_id.eq(Option::<i32>::None))
+            .execute(conn)
+            .expect("Failed to update projects in database");
+    }
+    {
+        use crate::schema::groups::dsl::*;
+        update(groups.filter(semester_id.eq(sid)))
+            .set(semester_id.eq(Option::<i32>::None))
+            .execute(conn)
+            .expect("Failed to update groups in database");
+    }
+    {
+        use crate::schema::meetings::dsl::*;
+        update(meetings.filter(semester_id.eq(sid)))
+            .set(semester_id.eq(Option::<i32>::None))
+            .execute(conn)
+            .expect("Failed to update meetings in database");
+    }
+    {
+        use crate::schema::events::dsl::*;
+        update(events.filter(semester_id.eq(sid)))
+            .set(semester_id.eq(Option::<i32>::None))
+            .execute(conn)
+            .expect("Failed to update events in database");
+    }
+    {
+        use crate::schema::enrollments::dsl::*;
+        delete(enrollments.filter(semester_id.eq(sid)))
+            .execute(conn)
+            .expect("Failed to delete enrollments from database");
+    }
+
+    use crate::schema::semesters::dsl::*;
+    delete(semesters.find(sid))
+        .execute(conn)
+        .expect("Failed to delete semester from database");
+}
+
+/// Enrolls a user in a semester if they aren't already
+pub fn enroll(conn: &SqliteConnection, sid: i32, uid: i32) {
+    use crate::schema::enrollments::dsl::*;
+    insert_or_ignore_into(enrollments)
+        .values(&NewEnrollment {
+            semester_id: sid,
+            user_id: uid,
+        })
+        .execute(conn)
+        .expect("Failed to insert enrollment into database");
+}
+
+/// Enrolls a user in a semester when they join something in it
+///
+/// Does nothing for things without a semester.
+pub fn enroll_in(conn: &SqliteConnection, sid: Option<i32>, uid: i32) {
+    if let Some(sid) = sid {
+        enroll(conn, sid, uid);
+    }
+}
+
+/// Enrolls every active user in a semester
+fn enroll_active_users(conn: &SqliteConnection, sid: i32) {
+    let uids: Vec<i32> = {
+        use crate::schema::users::dsl::*;
+        users
+            .filter(id.ne(0).and(active.eq(true)).and(deleted_at.is_null()))
+            .select(id)
+            .load(conn)
+            .expect("Failed to get users from database")
+    };
+    for uid in uids {
+        enroll(conn, sid, uid);
+    }
+}
+
+/// The users enrolled in a semester
+pub fn semester_users(conn: &SqliteConnection, s: &Semester) -> Vec<User> {
+    use crate::schema::users::dsl::*;
+    users
+        .filter(id.eq_any(Enrollment::belonging_to(s).select(crate::schema::enrollments::user_id)))
+        .filter(deleted_at.is_null())
+        .order(real_name.asc())
+        .load(conn)
+        .expect("Failed to get users from database")
+}
+
+/// The users enrolled in a semester with the role they are given for it
+pub fn semester_roles(conn: &SqliteConnection, s: &Semester) -> Vec<(User, Option<Role>)> {
+    use crate::schema::enrollments::dsl::role;
+    use crate::schema::users::dsl::*;
+    Enrollment::belonging_to(s)
+        .inner_join(users)
+        .filter(deleted_at.is_null())
+        .select((users::all_columns(), role))
+        .order(real_name.asc())
+        .load(conn)
+        .expect("Failed to get users from database")
+}
+
+/// Give an enrolled user a role for a semester, `None` to keep the one they have
+///
+/// If the semester is the current one in the site's time zone `site` it
+/// becomes their role right away, otherwise it does when the semester is
+/// rolled over to.
+/// Returns false if the user isn't enrolled in the semester.
+pub fn set_semester_role(
+    conn: &SqliteConnection,
+    s: &Semester,
+    uid: i32,
+    r: Option<Role>,
+    site: &Tz,
+) -> bool {
+    let found = {
+        use crate::schema::enrollments::dsl::*;
+        update(enrollments.filter(semester_id.eq(s.id).and(user_id.eq(uid))))
+            .set(role.eq(r))
+            .execute(conn)
+            .expect("Failed to update enrollment in database")
+    };
+
+    if let Some(r) = r.filter(|_| current_semester(conn, site).as_ref() == Some(s)) {
+        set_role(conn, uid, r);
+    }
+    found > 0
+}
+
+/// Change the role of a user, logging them out if it is different
+fn set_role(conn: &SqliteConnection, uid: i32, r: Role) {
+    use crate::schema::users::dsl::*;
+    let changed = update(users.filter(id.eq(uid).and(id.ne(0)).and(role.ne(r))))
+        .set(role.eq(r))
+        .execute(conn)
+        .expect("Failed to update user in database");
+    if changed > 0 {
+        revoke_sessions(conn, uid);
+        revoke_tokens(conn, uid);
+    }
+}
+
+/// Ends a semester and moves on to the next one
+///
+/// The groups of the semester are archived and its projects are made
+/// inactive. Users enrolled in this semester but not the next one are
+/// marked as former and inactive, so the next semester has to be added and
+/// its returning users enrolled first. Users given a role for the next
+/// semester get that role.
+///
+/// Returns the next semester and how many users became former.
+pub fn rollover(conn: &SqliteConnection, s: &Semester) -> Result<(Semester, usize), FormError> {
+    let next = next_semester(conn, s).ok_or(FormError::NoNextSemester)?;
+
+    let former = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            {
+                use crate::schema::groups::dsl::*;
+                update(groups.filter(semester_id.eq(s.id)))
+                    .set(archived.eq(true))
+                    .execute(conn)?;
+            }
+            {
+                use crate::schema::projects::dsl::*;
+                update(projects.filter(semester_id.eq(s.id)))
+                    .set(active.eq(false))
+                    .execute(conn)?;
+            }
+
+            use crate::schema::enrollments::dsl::{enrollments, semester_id, user_id};
+            let given: Vec<(i32, Option<Role>)> = enrollments
+                .filter(semester_id.eq(next.id))
+                .select((user_id, crate::schema::enrollments::role))
+                .load(conn)?;
+            let returning: Vec<i32> = enrollments
+                .filter(semester_id.eq(next.id))
+                .select(user_id)
+                .load(conn)?;
+            let leaving: Vec<i32> = enrollments
+                .filter(semester_id.eq(s.id))
+                .filter(user_id.ne_all(returning))
+                .select(user_id)
+                .load(conn)?;
+
+            use crate::schema::users::dsl::*;
+            let n = update(users.filter(id.ne(0).and(id.eq_any(leaving))))
+                .set((active.eq(false), former.eq(true)))
+                .execute(conn)?;
+
+            for (uid, r) in given {
+                if let Some(r) = r {
+                    set_role(conn, uid, r);
+                }
+            }
+            Ok(n)
+        })
+        .expect("Failed to roll over semester in database");
+
+    Ok((next, former))
+}
@@ -0,0 +1,17 @@
+//! Semesters
+//!
+//! Projects, groups, meetings, events and enrollment are scoped to the
+//! semester they happen in. At the end of a semester an Admin rolls it over
+//! which archives its groups and marks the users who aren't returning as
+//! former. Users can be given a role for a semester, see `crate::roles`.
+//!
+//! ## Routes
+//! - `/semesters`
+//! - `/semesters/<sid>`
+//! - `/semesters/<sid>/enroll`
+//! - `/semesters/<sid>/enroll/<uid>`
+//! - `/semesters/<sid>/rollover`
+
+pub mod handlers;
+pub mod models;
+pub mod templates;
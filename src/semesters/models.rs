@@ -0,0 +1,71 @@
+//! Models for semesters
+//!
+//! Semesters are stored in the `semesters` table. The `enrollments` table
+//! records which users take part in each semester.
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::models::User;
+use crate::roles::Role;
+use crate::schema::*;
+use crate::tz::Tz;
+
+/// Models a semester in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Serialize)]
+pub struct Semester {
+    /// ID of the semester
+    pub id: i32,
+    /// Name of the semester like `Spring 2021`
+    pub name: String,
+    /// First day of the semester
+    pub start: NaiveDate,
+    /// Last day of the semester
+    pub end: NaiveDate,
+}
+
+impl Semester {
+    /// Does the semester include this time in UTC?
+    ///
+    /// The days of a semester are in the site's time zone `site`.
+    pub fn contains(&self, t: NaiveDateTime, site: &Tz) -> bool {
+        let day = site.to_local(&t).date();
+        self.start <= day && day <= self.end
+    }
+}
+
+/// Used to create a new semester in the database
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[table_name = "semesters"]
+pub struct NewSemester {
+    /// Name of the semester like `Spring 2021`
+    pub name: String,
+    /// First day of the semester
+    pub start: NaiveDate,
+    /// Last day of the semester
+    pub end: NaiveDate,
+}
+
+/// Models a user taking part in a semester
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(Semester)]
+#[belongs_to(User)]
+pub struct Enrollment {
+    /// ID of the enrollment
+    pub id: i32,
+    /// ID of the semester
+    pub semester_id: i32,
+    /// ID of the user
+    pub user_id: i32,
+    /// Role the user is given for the semester, if any
+    pub role: Option<Role>,
+}
+
+/// Used to enroll a user in a semester
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "enrollments"]
+pub struct NewEnrollment {
+    /// ID of the semester
+    pub semester_id: i32,
+    /// ID of the user
+    pub user_id: i32,
+}
@@ -0,0 +1,81 @@
+//! HTML templates for semesters
+
+use super::models::Semester;
+use crate::models::User;
+use crate::roles::Role;
+#[allow(unused_imports)]
+use crate::templates::{filters, FormError, OptUser};
+
+/// The semester a list page is showing
+///
+/// HTML File: `semester-select.html`
+///
+/// Pages include the select in a `GET` form so it is sent as `sem`.
+/// A `selected` of `None` shows every semester.
+#[derive(Debug, Default)]
+pub struct SemesterSelect {
+    /// Every semester, newest first
+    pub semesters: Vec<Semester>,
+    /// The semester being shown
+    pub selected: Option<Semester>,
+}
+
+impl SemesterSelect {
+    /// Is this semester the one being shown?
+    pub fn is_selected(&self, sid: i32) -> bool {
+        self.selected.as_ref().map_or(false, |s| s.id == sid)
+    }
+
+    /// Should something in this semester be shown?
+    ///
+    /// Things without a semester are always shown.
+    pub fn includes(&self, semester_id: Option<i32>) -> bool {
+        match (&self.selected, semester_id) {
+            (Some(s), Some(sid)) => s.id == sid,
+            _ => true,
+        }
+    }
+}
+
+/// Semesters page template
+///
+/// HTML File: `semester/semesters.html`
+///
+/// Lists the semesters and has the form to add one.
+#[derive(Template)]
+#[template(path = "semester/semesters.html")]
+pub struct SemestersTemplate {
+    pub logged_in: OptUser,
+    pub semesters: Vec<Semester>,
+    pub current: Option<Semester>,
+    pub error: Option<FormError>,
+}
+
+/// Semester page template
+///
+/// HTML File: `semester/semester.html`
+///
+/// Shows a semester with the users enrolled in it and the rollover to the
+/// next semester.
+#[derive(Template)]
+#[template(path = "semester/semester.html")]
+pub struct SemesterTemplate {
+    pub logged_in: OptUser,
+    pub semester: Semester,
+    /// Users enrolled in the semester with the role they are given for it
+    pub enrolled: Vec<(User, Option<Role>)>,
+    /// Users that can be enrolled
+    pub all_users: Vec<User>,
+    /// Roles the logged in user can give for the semester
+    pub roles: Vec<Role>,
+    /// The semester after this one, if it has been added
+    pub next: Option<Semester>,
+    pub error: Option<FormError>,
+}
+
+impl SemesterTemplate {
+    /// Is this the role an enrollment gives?
+    pub fn gives(&self, role: &Option<Role>, r: &Role) -> bool {
+        role.as_ref() == Some(r)
+    }
+}
@@ -0,0 +1,258 @@
+//! HTTP handlers for login sessions
+
+use chrono::Duration;
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use rocket::http::{Cookie, Cookies, Status};
+use rocket::response::Redirect;
+use rocket::State;
+
+use crate::api::handlers::{gen_token, hash_token};
+use crate::audit;
+use crate::guards::*;
+use crate::models::User;
+use crate::roles::Permission;
+use crate::settings::Settings;
+use crate::tz;
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::templates::*;
+
+/// Name of the cookie that holds the session token
+pub const SESSION_COOKIE: &str = "session";
+
+/// GET handler for `/users/<uid>/sessions`
+///
+/// Lists the browsers a user is logged in on.
+///
+/// Restricted to Admins and the user themselves.
+#[get("/users/<uid>/sessions")]
+pub fn sessions(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    mut cookies: Cookies,
+    uid: i32,
+) -> Result<SessionsTemplate, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .first(&*conn)
+            .optional()
+            .expect("Failed to get user from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    use crate::schema::sessions::dsl::*;
+    Ok(SessionsTemplate {
+        zone: tz::of(Some(&l.0), &settings.site_zone()),
+        logged_in: Some(l.0),
+        sessions: Session::belonging_to(&user)
+            .order(last_seen.desc())
+            .load(&*conn)
+            .expect("Failed to get sessions from database"),
+        current: current_hash(&mut cookies),
+        user,
+    })
+}
+
+/// DELETE handler for `/users/<uid>/sessions/<sid>`
+///
+/// Revokes a session, logging that browser out.
+///
+/// Restricted to Admins and the user themselves.
+#[delete("/users/<uid>/sessions/<sid>")]
+pub fn session_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    uid: i32,
+    sid: i32,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    use crate::schema::sessions::dsl::*;
+    let old: Option<Session> = sessions
+        .filter(id.eq(sid).and(user_id.eq(uid)))
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get session from database");
+    delete(sessions.filter(id.eq(sid).and(user_id.eq(uid))))
+        .execute(&*conn)
+        .expect("Failed to delete session from database");
+    if let Some(old) = old {
+        audit::deleted(&*conn, Some(&l.0), "session", sid, &old);
+    }
+
+    audit_log!(
+        "User {} [{}] has revoked session {} of User {}",
+        l.0.id,
+        l.0.email,
+        sid,
+        uid
+    );
+
+    Ok(Redirect::to(format!("/users/{}/sessions", uid)))
+}
+
+/// DELETE handler for `/users/<uid>/sessions`
+///
+/// Revokes every session of the user except the one making the request.
+///
+/// Restricted to Admins and the user themselves.
+#[delete("/users/<uid>/sessions")]
+pub fn sessions_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    mut cookies: Cookies,
+    uid: i32,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::ManageUsers) && l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    use crate::schema::sessions::dsl::*;
+    let keep = current_hash(&mut cookies).unwrap_or_default();
+    delete(sessions.filter(user_id.eq(uid).and(token_hash.ne(keep))))
+        .execute(&*conn)
+        .expect("Failed to delete sessions from database");
+
+    audit_log!(
+        "User {} [{}] has revoked the other sessions of User {}",
+        l.0.id,
+        l.0.email,
+        uid
+    );
+    audit::record(
+        &*conn,
+        Some(&l.0),
+        "revoke-sessions",
+        "user",
+        Some(uid),
+        None,
+        None,
+    );
+
+    Ok(Redirect::to(format!("/users/{}/sessions", uid)))
+}
+
+//# Helper Functions
+
+/// Start a session for a user and set the cookie
+///
+/// Also cleans up the user's expired sessions.
+pub fn start_session(
+    conn: &SqliteConnection,
+    cookies: &mut Cookies,
+    user: &User,
+    client: &ClientInfo,
+    days: i64,
+) {
+    use crate::schema::sessions::dsl::*;
+
+    let now = chrono::offset::Utc::now().naive_utc();
+    delete(
+        sessions.filter(
+            user_id
+                .eq(user.id)
+                .and(last_seen.lt(now - Duration::days(days))),
+        ),
+    )
+    .execute(conn)
+    .expect("Failed to delete sessions from database");
+
+    let token = gen_token();
+    insert_into(sessions)
+        .values(&NewSession {
+            user_id: user.id,
+            token_hash: hash_token(&token),
+            created_at: now,
+            last_seen: now,
+            ip: client.ip.clone(),
+            user_agent: client.user_agent.clone(),
+        })
+        .execute(conn)
+        .expect("Failed to insert session into database");
+
+    cookies.add_private(
+        Cookie::build(SESSION_COOKIE, token)
+            .max_age(Duration::days(days))
+            .finish(),
+    );
+}
+
+/// End the session in the cookie and remove it
+pub fn end_session(conn: &SqliteConnection, cookies: &mut Cookies) {
+    use crate::schema::sessions::dsl::*;
+
+    if let Some(hash) = current_hash(cookies) {
+        delete(sessions.filter(token_hash.eq(hash)))
+            .execute(conn)
+            .expect("Failed to delete session from database");
+    }
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+}
+
+/// Find the user a session token belongs to
+///
+/// Sessions that haven't been used in `days` are expired and deleted.
+/// Otherwise records that the session has been seen.
+pub fn session_user(
+    conn: &SqliteConnection,
+    token: &str,
+    client: &ClientInfo,
+    days: i64,
+) -> Option<User> {
+    use crate::schema::sessions::dsl::*;
+
+    let s: Session = sessions
+        .filter(token_hash.eq(hash_token(token)))
+        .first(conn)
+        .optional()
+        .expect("Failed to get session from database")?;
+
+    let now = chrono::offset::Utc::now().naive_utc();
+    if s.last_seen < now - Duration::days(days) {
+        delete(sessions.find(s.id))
+            .execute(conn)
+            .expect("Failed to delete session from database");
+        return None;
+    }
+    // Don't write to the database on every request
+    if now - s.last_seen > Duration::minutes(1) || s.ip != client.ip {
+        update(sessions.find(s.id))
+            .set((last_seen.eq(now), ip.eq(&client.ip)))
+            .execute(conn)
+            .expect("Failed to update session in database");
+    }
+
+    use crate::schema::users::dsl::*;
+    users
+        .find(s.user_id)
+        .filter(deleted_at.is_null())
+        .first(conn)
+        .optional()
+        .expect("Failed to get user from database")
+}
+
+/// Revoke every session of a user, logging them out everywhere
+pub fn revoke_sessions(conn: &SqliteConnection, uid: i32) {
+    use crate::schema::sessions::dsl::*;
+    delete(sessions.filter(user_id.eq(uid)))
+        .execute(conn)
+        .expect("Failed to delete sessions from database");
+}
+
+/// The hash of the token in the session cookie
+fn current_hash(cookies: &mut Cookies) -> Option<String> {
+    cookies
+        .get_private(SESSION_COOKIE)
+        .map(|c| hash_token(c.value()))
+}
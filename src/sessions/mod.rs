@@ -0,0 +1,15 @@
+//! Login sessions
+//!
+//! Logging in starts a session that is stored in the database, the
+//! `session` cookie only holds its random token. Sessions expire when they
+//! go unused for `session_days` and can be revoked by the user. All of a
+//! user's sessions are revoked when their password or role changes.
+//!
+//! ## Routes
+//! - `/users/<uid>/sessions`
+//! - `/users/<uid>/sessions/<sid>`
+
+pub mod handlers;
+pub mod models;
+
+mod templates;
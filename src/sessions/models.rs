@@ -0,0 +1,48 @@
+//! Models for login sessions
+//!
+//! Sessions are stored in the `sessions` table where each row is a browser
+//! that a user is logged in on. Only a hash of the token is stored.
+
+use chrono::NaiveDateTime;
+
+use crate::models::User;
+use crate::schema::*;
+
+/// Models a session in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations, Serialize)]
+#[belongs_to(User)]
+pub struct Session {
+    /// ID of the session
+    pub id: i32,
+    /// ID of the user that is logged in
+    pub user_id: i32,
+    /// SHA-256 hash of the token in the cookie
+    #[serde(skip)]
+    pub token_hash: String,
+    /// When the user logged in
+    pub created_at: NaiveDateTime,
+    /// When the session was last used
+    pub last_seen: NaiveDateTime,
+    /// IP address of the last request, if it is known
+    pub ip: Option<String>,
+    /// User agent of the browser
+    pub user_agent: String,
+}
+
+/// Used to create a new session in the database
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession {
+    /// ID of the user that is logged in
+    pub user_id: i32,
+    /// SHA-256 hash of the token in the cookie
+    pub token_hash: String,
+    /// When the user logged in
+    pub created_at: NaiveDateTime,
+    /// When the session was last used
+    pub last_seen: NaiveDateTime,
+    /// IP address of the request, if it is known
+    pub ip: Option<String>,
+    /// User agent of the browser
+    pub user_agent: String,
+}
@@ -0,0 +1,31 @@
+//! HTML templates for login sessions
+
+use super::models::Session;
+use crate::models::User;
+#[allow(unused_imports)]
+use crate::templates::{filters, OptUser};
+use crate::tz::Tz;
+
+/// Sessions page template
+///
+/// HTML File: `session/sessions.html`
+///
+/// Lists the browsers a user is logged in on so they can be revoked.
+#[derive(Template)]
+#[template(path = "session/sessions.html")]
+pub struct SessionsTemplate {
+    pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
+    pub user: User,
+    pub sessions: Vec<Session>,
+    /// Token hash of the session making the request
+    pub current: Option<String>,
+}
+
+impl SessionsTemplate {
+    /// Is this the session making the request?
+    pub fn is_current(&self, s: &Session) -> bool {
+        self.current.as_ref() == Some(&s.token_hash)
+    }
+}
@@ -0,0 +1,292 @@
+//! Site settings
+//!
+//! Observatory specific settings are read from the extra values in
+//! `Rocket.toml` when the server is launched. Every setting has a default
+//! so none of them have to be set.
+//!
+//! The settings are managed by Rocket and can be accessed from a handler
+//! using `State<Settings>`.
+
+use std::collections::HashMap;
+
+use rocket::config::{Config, Value};
+
+use crate::oauth::provider::Provider;
+use crate::roles::Role;
+use crate::tz::{self, Tz};
+
+/// Observatory's settings
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// How many minutes after a meeting starts its attendance code is valid
+    ///
+    /// Config key: `meeting_code_minutes`
+    pub meeting_code_minutes: i64,
+    /// How many weeks ahead the meetings on group schedules are created,
+    /// 0 stops creating them
+    ///
+    /// Config key: `meeting_weeks_ahead`
+    pub meeting_weeks_ahead: i64,
+    /// How many seconds each rotating code in presenter mode lasts
+    ///
+    /// While codes rotate only the rotating codes are accepted, 0 turns
+    /// rotation off so the plain codes are accepted instead.
+    ///
+    /// Config key: `code_rotation_seconds`
+    pub code_rotation_seconds: i64,
+    /// The public URL of the site such as `https://rcos.io`
+    ///
+    /// Used for links that leave the site like QR codes and emails. If it
+    /// is not set then QR codes use the `Host` of the request, but emails
+    /// with links aren't sent since that header can be forged.
+    ///
+    /// Config key: `site_url`
+    pub site_url: Option<String>,
+    /// Time zone of the site such as `America/New_York`, see `crate::tz`
+    ///
+    /// Times are shown in it to users who haven't picked their own and
+    /// weekly schedules follow it.
+    ///
+    /// Config key: `time_zone`
+    pub time_zone: String,
+    /// Base URL of the GitHub API
+    ///
+    /// Only needs changing for GitHub Enterprise or to test against a mock.
+    ///
+    /// Config key: `github_api_url`
+    pub github_api_url: String,
+    /// Token used for the GitHub API to get a higher rate limit
+    ///
+    /// Config key: `github_token`
+    pub github_token: Option<String>,
+    /// Hosts running GitLab, including self-hosted instances
+    ///
+    /// Config key: `gitlab_hosts`
+    pub gitlab_hosts: Vec<String>,
+    /// Hosts running Gitea or Forgejo
+    ///
+    /// Config key: `gitea_hosts`
+    pub gitea_hosts: Vec<String>,
+    /// API tokens for forges keyed by host such as `gitlab.com`
+    ///
+    /// Config key: `forge_tokens`
+    pub forge_tokens: HashMap<String, String>,
+    /// How many minutes between syncs of project commits, 0 disables syncing
+    ///
+    /// Config key: `commit_sync_minutes`
+    pub commit_sync_minutes: i64,
+    /// How emails are sent, one of `smtp`, `file` or `stdout`
+    ///
+    /// Config key: `mailer`
+    pub mailer: String,
+    /// The address emails are sent from
+    ///
+    /// Config key: `mail_from`
+    pub mail_from: String,
+    /// Directory the `file` mailer writes emails to
+    ///
+    /// Config key: `mail_dir`
+    pub mail_dir: String,
+    /// SMTP server used by the `smtp` mailer
+    ///
+    /// Config key: `smtp_host`
+    pub smtp_host: Option<String>,
+    /// Port of the SMTP server, 465 for TLS or another port with STARTTLS
+    ///
+    /// Config key: `smtp_port`
+    pub smtp_port: i64,
+    /// User name to log in to the SMTP server with, if it needs one
+    ///
+    /// Config key: `smtp_username`
+    pub smtp_username: Option<String>,
+    /// Password to log in to the SMTP server with
+    ///
+    /// Config key: `smtp_password`
+    pub smtp_password: Option<String>,
+    /// Secret used to sign the tokens in password reset and verification links
+    ///
+    /// If it is not set a random one is made at launch, so links that were
+    /// sent stop working when the server restarts.
+    ///
+    /// Config key: `token_secret`
+    pub token_secret: Option<String>,
+    /// How many days a login session lasts without being used
+    ///
+    /// Config key: `session_days`
+    pub session_days: i64,
+    /// Users with these roles must use two-factor authentication
+    ///
+    /// Such as `["mentor", "coordinator", "admin"]`. If none are set then
+    /// two-factor authentication is optional for everyone.
+    ///
+    /// Config key: `totp_required_roles`
+    pub totp_required_roles: Vec<Role>,
+    /// How many failed logins an email can have, from any IP address,
+    /// before it has to wait
+    ///
+    /// Config key: `login_attempts`
+    pub login_attempts: i64,
+    /// How many failed logins an IP address can have before it has to wait
+    ///
+    /// Config key: `login_ip_attempts`
+    pub login_ip_attempts: i64,
+    /// How many seconds to wait after the first failure over the limit,
+    /// it doubles with every failure after that
+    ///
+    /// Config key: `login_backoff_seconds`
+    pub login_backoff_seconds: i64,
+    /// The longest wait between failed logins
+    ///
+    /// Config key: `login_lockout_minutes`
+    pub login_lockout_minutes: i64,
+    /// Providers that users can log in with, see `crate::oauth::provider`
+    ///
+    /// Config key: `oauth_providers`
+    pub oauth_providers: Vec<Provider>,
+    /// How many days deleted things stay in the trash before they are
+    /// purged for good, 0 keeps them forever
+    ///
+    /// Config key: `trash_days`
+    pub trash_days: i64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            meeting_code_minutes: 120,
+            meeting_weeks_ahead: 4,
+            code_rotation_seconds: 30,
+            site_url: None,
+            time_zone: String::from("UTC"),
+            github_api_url: String::from("https://api.github.com"),
+            github_token: None,
+            gitlab_hosts: vec![String::from("gitlab.com")],
+            gitea_hosts: vec![String::from("codeberg.org")],
+            forge_tokens: HashMap::new(),
+            commit_sync_minutes: 15,
+            mailer: String::from("stdout"),
+            mail_from: String::from("Observatory <observatory@localhost>"),
+            mail_dir: String::from("mail"),
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+            token_secret: None,
+            session_days: 30,
+            totp_required_roles: Vec::new(),
+            login_attempts: 5,
+            login_ip_attempts: 20,
+            login_backoff_seconds: 30,
+            login_lockout_minutes: 15,
+            oauth_providers: Vec::new(),
+            trash_days: 30,
+        }
+    }
+}
+
+impl Settings {
+    /// Read the settings from a Rocket config
+    ///
+    /// Anything that is missing or the wrong type falls back to the default.
+    pub fn from_config(conf: &Config) -> Self {
+        let default = Self::default();
+        let github_api_url = conf
+            .get_string("github_api_url")
+            .map(|s| s.trim_end_matches('/').to_string())
+            .unwrap_or(default.github_api_url);
+        Self {
+            meeting_code_minutes: conf
+                .get_int("meeting_code_minutes")
+                .unwrap_or(default.meeting_code_minutes),
+            meeting_weeks_ahead: conf
+                .get_int("meeting_weeks_ahead")
+                .unwrap_or(default.meeting_weeks_ahead),
+            code_rotation_seconds: conf
+                .get_int("code_rotation_seconds")
+                .unwrap_or(default.code_rotation_seconds),
+            site_url: conf
+                .get_string("site_url")
+                .ok()
+                .map(|s| s.trim_end_matches('/').to_string()),
+            time_zone: conf.get_string("time_zone").unwrap_or(default.time_zone),
+            github_api_url: github_api_url.clone(),
+            github_token: conf.get_string("github_token").ok(),
+            gitlab_hosts: conf
+                .get_slice("gitlab_hosts")
+                .map(|a| hosts(a))
+                .unwrap_or(default.gitlab_hosts),
+            gitea_hosts: conf
+                .get_slice("gitea_hosts")
+                .map(|a| hosts(a))
+                .unwrap_or(default.gitea_hosts),
+            forge_tokens: conf
+                .get_table("forge_tokens")
+                .map(|t| {
+                    t.iter()
+                        .filter_map(|(k, v)| Some((k.to_lowercase(), v.as_str()?.to_string())))
+                        .collect()
+                })
+                .unwrap_or(default.forge_tokens),
+            commit_sync_minutes: conf
+                .get_int("commit_sync_minutes")
+                .unwrap_or(default.commit_sync_minutes),
+            mailer: conf.get_string("mailer").unwrap_or(default.mailer),
+            mail_from: conf.get_string("mail_from").unwrap_or(default.mail_from),
+            mail_dir: conf.get_string("mail_dir").unwrap_or(default.mail_dir),
+            smtp_host: conf.get_string("smtp_host").ok(),
+            smtp_port: conf.get_int("smtp_port").unwrap_or(default.smtp_port),
+            smtp_username: conf.get_string("smtp_username").ok(),
+            smtp_password: conf.get_string("smtp_password").ok(),
+            token_secret: conf.get_string("token_secret").ok(),
+            session_days: conf.get_int("session_days").unwrap_or(default.session_days),
+            totp_required_roles: conf
+                .get_slice("totp_required_roles")
+                .map(|a| a.iter().filter_map(|v| v.as_str()?.parse().ok()).collect())
+                .unwrap_or(default.totp_required_roles),
+            login_attempts: conf
+                .get_int("login_attempts")
+                .unwrap_or(default.login_attempts),
+            login_ip_attempts: conf
+                .get_int("login_ip_attempts")
+                .unwrap_or(default.login_ip_attempts),
+            login_backoff_seconds: conf
+                .get_int("login_backoff_seconds")
+                .unwrap_or(default.login_backoff_seconds),
+            login_lockout_minutes: conf
+                .get_int("login_lockout_minutes")
+                .unwrap_or(default.login_lockout_minutes),
+            oauth_providers: conf
+                .get_table("oauth_providers")
+                .map(|t| {
+                    t.iter()
+                        .filter_map(|(id, v)| {
+                            Provider::from_table(id, v.as_table()?, &github_api_url)
+                        })
+                        .collect()
+                })
+                .unwrap_or(default.oauth_providers),
+            trash_days: conf.get_int("trash_days").unwrap_or(default.trash_days),
+        }
+    }
+
+    /// The site's time zone
+    ///
+    /// Rocket won't launch with a `time_zone` that isn't a zone, see
+    /// `crate::fairings::LoadSettings`, so UTC is never used in its place.
+    pub fn site_zone(&self) -> Tz {
+        tz::zone(&self.time_zone).unwrap_or_else(Tz::utc)
+    }
+
+    /// Do attendance codes rotate, see `code_rotation_seconds`
+    pub fn rotating_codes(&self) -> bool {
+        self.code_rotation_seconds > 0
+    }
+}
+
+/// Read a list of hosts, ignoring anything that isn't a string
+fn hosts(a: &[Value]) -> Vec<String> {
+    a.iter()
+        .filter_map(|v| v.as_str())
+        .map(|s| s.to_lowercase())
+        .collect()
+}
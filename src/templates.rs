@@ -8,6 +8,7 @@
 
 use crate::models::User;
 use crate::news::models::NewsStory;
+use crate::tz::Tz;
 
 /// Companion to `MaybeLoggedIn`
 ///
@@ -31,16 +32,25 @@ pub struct IndexTemplate {
 ///
 /// This is a simple template that just shows the given text large
 /// across the screen. Useful for attendance codes.
+///
+/// In presenter mode it also shows a QR code and reloads itself every
+/// `refresh` seconds so the rotating attendance code stays current.
 #[derive(Template)]
 #[template(path = "big.html")]
 pub struct BigTemplate {
     pub logged_in: OptUser,
     pub text: String,
+    /// A QR code image as a `data:` URI
+    pub qr: Option<String>,
+    /// Seconds until the page reloads
+    pub refresh: Option<i64>,
 }
 
 use crate::models::GradeSummary;
 use crate::models::Group;
 use crate::models::Project;
+use crate::models::ProjectRequest;
+use crate::semesters::templates::SemesterSelect;
 
 /// User Dashboard template
 ///
@@ -52,9 +62,14 @@ use crate::models::Project;
 #[template(path = "dashboard.html")]
 pub struct DashboardTemplate {
     pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
     pub projects: Vec<Project>,
     pub groups: Vec<Group>,
+    /// Requests to join projects and invites the user has to answer
+    pub requests: Vec<(ProjectRequest, Project, User)>,
     pub summary: GradeSummary,
+    pub semester: SemesterSelect,
 }
 
 #[derive(Template)]
@@ -83,6 +98,8 @@ pub struct SitemapTemplate {}
 #[template(path = "catchers/403.html")]
 pub struct Error403Template {
     pub logged_in: OptUser,
+    /// If the page needs two-factor authentication that the user hasn't set up
+    pub totp_missing: bool,
 }
 
 /// 404 error template
@@ -102,6 +119,18 @@ pub struct Error404Template {
 /// Done like this so we can add custom filters later.
 pub mod filters {
     pub use askama_filters::filters::*;
+
+    use chrono::NaiveDateTime;
+
+    use crate::tz::Tz;
+
+    /// Formats a time stored in UTC in a time zone, usually the `zone` of
+    /// the template which is the one of the user looking at it
+    ///
+    /// Used like `{{ event.start|local(zone, "%F %R") }}`.
+    pub fn local(t: &NaiveDateTime, zone: &Tz, fmt: &str) -> askama::Result<String> {
+        Ok(zone.to_datetime(t).format(fmt).to_string())
+    }
 }
 
 /// An error in an HTML form
@@ -130,12 +159,36 @@ pub enum FormError {
     InvalidCode,
     /// An valid attendance code does was already used by a user
     UsedCode,
+    /// An attendance code is no longer, or not yet, being accepted
+    ExpiredCode,
     /// A date field was the wrong format invalid
     InvalidDate,
     /// You used a name that is reserved and can't be used
     ReservedName,
     /// Project name already taken
     TakenName,
+    /// A commit email or alias already belongs to a user
+    IdentityExists,
+    /// A semester can't be rolled over until the next one is added
+    NoNextSemester,
+    /// A reset or verification link is forged, used or expired
+    InvalidToken,
+    /// The user has to verify their email first
+    Unverified,
+    /// A two-factor or recovery code is wrong or was already used
+    TotpCode,
+    /// Logging in with an OAuth provider failed
+    OAuth,
+    /// No user has a verified email of the OAuth account
+    NoAccount,
+    /// The OAuth account is already linked to another user
+    AccountLinked,
+    /// Too many failed logins, try again later
+    Locked,
+    /// The time zone isn't one in the time zone database
+    TimeZone,
+    /// An event has room for less than one person
+    Capacity,
     /// Some other unknown error
     Other,
 }
@@ -158,9 +211,21 @@ impl fmt::Display for FormError {
                 FormError::MmostExists => "mmostExists",
                 FormError::InvalidCode => "code",
                 FormError::UsedCode => "usedCode",
+                FormError::ExpiredCode => "expiredCode",
                 FormError::InvalidDate => "date",
                 FormError::ReservedName => "reserved",
                 FormError::TakenName => "taken",
+                FormError::IdentityExists => "identityExists",
+                FormError::NoNextSemester => "noNextSemester",
+                FormError::InvalidToken => "invalidToken",
+                FormError::Unverified => "unverified",
+                FormError::TotpCode => "totp",
+                FormError::OAuth => "oauth",
+                FormError::NoAccount => "noAccount",
+                FormError::AccountLinked => "accountLinked",
+                FormError::Locked => "locked",
+                FormError::TimeZone => "timeZone",
+                FormError::Capacity => "capacity",
                 FormError::Other => "other",
             }
         )
@@ -180,9 +245,21 @@ impl<T: AsRef<str>> From<T> for FormError {
             "mmostExists" => FormError::MmostExists,
             "code" => FormError::InvalidCode,
             "usedCode" => FormError::UsedCode,
+            "expiredCode" => FormError::ExpiredCode,
             "date" => FormError::InvalidDate,
             "reserved" => FormError::ReservedName,
             "taken" => FormError::TakenName,
+            "identityExists" => FormError::IdentityExists,
+            "noNextSemester" => FormError::NoNextSemester,
+            "invalidToken" => FormError::InvalidToken,
+            "unverified" => FormError::Unverified,
+            "totp" => FormError::TotpCode,
+            "oauth" => FormError::OAuth,
+            "noAccount" => FormError::NoAccount,
+            "accountLinked" => FormError::AccountLinked,
+            "locked" => FormError::Locked,
+            "timeZone" => FormError::TimeZone,
+            "capacity" => FormError::Capacity,
             "other" => FormError::Other,
             _ => FormError::Other,
         }
@@ -1,6 +1,7 @@
 use super::*;
 use crate::auth::crypto::*;
 use crate::models::*;
+use crate::roles::*;
 use diesel::delete;
 use diesel::insert_into;
 use diesel::prelude::*;
@@ -48,7 +49,7 @@ fn create_connection_url(client: &rocket::local::Client) -> String {
 fn setup(test_name: String) -> Option<rocket::Config> {
     let mut db_path = String::from("./");
     db_path.push_str(test_name.as_str());
-    db_path.push_str("/");
+    db_path.push('/');
 
     let db_path_exists = Path::new(db_path.as_str()).is_dir();
 
@@ -77,6 +78,7 @@ fn setup(test_name: String) -> Option<rocket::Config> {
         .port(8000)
         .log_level(LoggingLevel::Normal)
         .extra("databases", databases)
+        .extra("site_url", "http://localhost:8000")
         .finalize()
         .unwrap();
 
@@ -86,7 +88,7 @@ fn setup(test_name: String) -> Option<rocket::Config> {
 fn cleanup(test_name: String) {
     let mut db_path_string = String::from("./");
     db_path_string.push_str(test_name.as_str());
-    db_path_string.push_str("/");
+    db_path_string.push('/');
 
     let mut db_file_string = String::from(db_path_string.as_str());
     db_file_string.push_str(test_name.as_str());
@@ -97,6 +99,36 @@ fn cleanup(test_name: String) {
     fs::remove_dir(db_path_string).expect("Dir Deletion Error");
 }
 
+/// Creates an active user that logs in with `<handle>@test-rcos.io` and
+/// the password `password`
+fn make_user(conn: &SqliteConnection, handle: &str, role: Role) -> User {
+    create_user(
+        conn,
+        NewUser {
+            real_name: String::from(handle),
+            handle: String::from(handle),
+            email: format!("{}@test-rcos.io", handle),
+            mmost: String::from(handle),
+            password_hash: hash_password("password"),
+            active: true,
+            role,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user")
+}
+
+/// Logs the client in as a user from `make_user`, logging out first
+fn login(client: &Client, handle: &str) {
+    use rocket::http::ContentType;
+    client.get("/logout").dispatch();
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body(format!("email={}@test-rcos.io&password=password", handle))
+        .dispatch();
+}
+
 #[test]
 fn launch() {
     let config = setup(String::from("test_launch"));
@@ -151,16 +183,14 @@ fn add_user() {
     use crate::schema::users::dsl::*;
     let pass = String::from("thisisapassword");
 
-    let (phash, psalt) = hash_password(pass);
-
+    let phash = hash_password(pass);
     let nu = NewUser {
         real_name: String::from("John Doe"),
         handle: String::from("JD1"),
         password_hash: phash,
-        salt: psalt,
         bio: String::from("This is a test user. Do not disturb."),
         email: String::from("doej@test-rcos.io"),
-        tier: 0,
+        role: Role::Student,
         active: true,
         mmost: String::from("JDMM"),
         former: false,
@@ -220,6 +250,9 @@ fn add_group() {
         name: String::from("Test Group"),
         owner_id: 0,
         location: Some(String::from("DCC 318")),
+        semester_id: None,
+        meeting_day: None,
+        meeting_time: None,
     };
     insert_into(groups)
         .values(&nu)
@@ -254,3 +287,3317 @@ fn add_group() {
     // Cleanup database
     cleanup(String::from("test_add_group"));
 }
+
+// Tests that late attendance waits in the queue until it is approved or rejected
+#[test]
+fn attendance_approval() {
+    use crate::attend::code::{rotating_code, rotation_step};
+    use chrono::{Duration, Utc};
+    use rocket::http::ContentType;
+
+    let client = Client::new(rocket(setup(String::from("test_attendance_approval")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in AttendanceApprovalTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let mentor = make_user(&conn, "mentor", Role::Mentor);
+    let student = make_user(&conn, "student", Role::Student);
+    {
+        use crate::schema::users::dsl::*;
+        diesel::update(users.find(student.id))
+            .set(verified.eq(true))
+            .execute(&conn)
+            .expect("Failed to verify user");
+    }
+
+    let gid = create_group(
+        &conn,
+        &NewGroup {
+            name: String::from("Approval Group"),
+            owner_id: mentor.id,
+            ..NewGroup::default()
+        },
+    )
+    .expect("Failed to create group");
+    {
+        use crate::schema::relation_group_user::dsl::*;
+        insert_into(relation_group_user)
+            .values(&NewRelationGroupUser {
+                group_id: gid,
+                user_id: student.id,
+            })
+            .execute(&conn)
+            .expect("Failed to add user to group");
+    }
+
+    // Two meetings whose codes were reopened after their window closed
+    let started = Utc::now().naive_utc() - Duration::hours(3);
+    for c in &["lateone", "latetwo"] {
+        use crate::schema::meetings::dsl::*;
+        insert_into(meetings)
+            .values((
+                happened_at.eq(started),
+                code.eq(c),
+                group_id.eq(gid),
+                hosted_by.eq(mentor.id),
+                code_expires.eq(started + Duration::hours(2)),
+                code_open.eq(Some(true)),
+            ))
+            .execute(&conn)
+            .expect("Failed to add meeting to database");
+    }
+
+    // Attendance submitted late has to be approved
+    login(&client, "student");
+    for c in &["lateone", "latetwo"] {
+        let step = rotation_step(Utc::now().naive_utc(), 30);
+        let response = client
+            .post("/attend")
+            .header(ContentType::Form)
+            .body(format!("code={}", rotating_code(c, step)))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Location"), Some("/dashboard"));
+    }
+    let pending: Vec<Attendance> = {
+        use crate::schema::attendances::dsl::*;
+        attendances
+            .order(id.asc())
+            .load(&conn)
+            .expect("Failed to get attendances from database")
+    };
+    assert_eq!(pending.len(), 2);
+    assert!(pending.iter().all(|a| !a.approved));
+    let summary = grade_summary(&conn, &student, None, &crate::tz::Tz::utc());
+    assert_eq!((summary.attendances.len(), summary.pending.len()), (0, 2));
+
+    // The group mentor sees them in the queue
+    login(&client, "mentor");
+    let page = client
+        .get("/attend/pending")
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(page.contains(&format!("value=\"{}\"", pending[0].id)));
+    assert!(page.contains(&format!("value=\"{}\"", pending[1].id)));
+
+    // Other mentors don't, but Admins see everything
+    make_user(&conn, "othermentor", Role::Mentor);
+    make_user(&conn, "manager", Role::Admin);
+    let queue = |h: &str| {
+        login(&client, h);
+        client
+            .get("/attend/pending")
+            .dispatch()
+            .body_string()
+            .unwrap_or_default()
+    };
+    assert!(!queue("othermentor").contains(&format!("value=\"{}\"", pending[0].id)));
+    assert!(queue("manager").contains(&format!("value=\"{}\"", pending[0].id)));
+    login(&client, "mentor");
+
+    // One is approved and counts, the other is rejected and removed
+    let status = client
+        .put(format!("/attend/{}", pending[0].id))
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::SeeOther);
+    let status = client
+        .delete(format!("/attend/{}", pending[1].id))
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::SeeOther);
+    let left: Vec<Attendance> = {
+        use crate::schema::attendances::dsl::*;
+        attendances
+            .load(&conn)
+            .expect("Failed to get attendances from database")
+    };
+    assert_eq!(left.len(), 1);
+    assert!(left[0].approved);
+    let summary = grade_summary(&conn, &student, None, &crate::tz::Tz::utc());
+    assert_eq!((summary.attendances.len(), summary.pending.len()), (1, 0));
+
+    cleanup(String::from("test_attendance_approval"));
+}
+
+// Tests that attendance codes are only accepted during their window
+#[test]
+fn code_window() {
+    use crate::attend::code::{code_is_late, code_is_open};
+    use chrono::{Duration, NaiveDate};
+
+    let start = NaiveDate::from_ymd(2021, 3, 2).and_hms(16, 0, 0);
+    let mut evt = Event {
+        id: 1,
+        start,
+        end: start + Duration::hours(2),
+        title: String::from("Large Group"),
+        description: None,
+        hosted_by: 0,
+        location: None,
+        code: String::from("abcdef"),
+        color: None,
+        code_open: None,
+        semester_id: None,
+        deleted_at: None,
+        series_id: None,
+        original_start: None,
+        detached: false,
+        capacity: None,
+    };
+
+    assert!(!code_is_open(&evt, start - Duration::minutes(1)));
+    assert!(code_is_open(&evt, start + Duration::minutes(30)));
+    assert!(!code_is_open(&evt, start + Duration::hours(3)));
+
+    // The host can override the window either way, attendance taken
+    // after the window is late
+    evt.code_open = Some(true);
+    assert!(code_is_open(&evt, start + Duration::days(7)));
+    assert!(code_is_late(&evt, start + Duration::days(7)));
+    assert!(!code_is_late(&evt, start + Duration::minutes(30)));
+    evt.code_open = Some(false);
+    assert!(!code_is_open(&evt, start + Duration::minutes(30)));
+}
+
+#[test]
+fn rotating_codes() {
+    use crate::attend::code::{rotating_code, rotation_remaining, rotation_step, verify_code};
+    use chrono::{Duration, NaiveDate, Utc};
+
+    let now = NaiveDate::from_ymd(2021, 3, 2).and_hms(16, 0, 10);
+    let step = rotation_step(now, 30);
+    assert_eq!(rotation_remaining(now, 30), 20);
+
+    let code = rotating_code("abcdef", step);
+    assert_eq!(code.len(), 6);
+    assert_eq!(code, rotating_code("abcdef", step));
+    assert_ne!(code, rotating_code("abcdef", step + 1));
+    assert_ne!(code, rotating_code("ghijkl", step));
+
+    let client = Client::new(rocket(setup(String::from("test_rotating_codes")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in RotatingCodesTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let now = Utc::now().naive_utc();
+    {
+        use crate::schema::meetings::dsl::*;
+        insert_into(meetings)
+            .values((
+                happened_at.eq(now - Duration::minutes(5)),
+                code.eq("rotate"),
+                group_id.eq(0),
+                hosted_by.eq(0),
+                code_expires.eq(now + Duration::hours(1)),
+            ))
+            .execute(&conn)
+            .expect("Failed to add meeting to database");
+    }
+
+    // Only the current and previous rotating codes are accepted
+    let step = rotation_step(Utc::now().naive_utc(), 30);
+    assert!(verify_code(&conn, &rotating_code("rotate", step), 30).is_some());
+    assert!(verify_code(&conn, &rotating_code("rotate", step - 1), 30).is_some());
+    assert!(verify_code(&conn, &rotating_code("rotate", step - 2), 30).is_none());
+    assert!(verify_code(&conn, "rotate", 30).is_none());
+
+    // Without rotation the normal code is accepted instead
+    assert!(verify_code(&conn, "rotate", 0).is_some());
+    assert!(verify_code(&conn, &rotating_code("rotate", step), 0).is_none());
+
+    cleanup(String::from("test_rotating_codes"));
+}
+
+// Tests that the API accepts tokens and responds with JSON errors
+#[test]
+fn api_tokens() {
+    use crate::api::handlers::hash_token;
+    use crate::api::models::NewApiToken;
+    use rocket::http::{ContentType, Header};
+
+    let config = setup(String::from("test_api_tokens"));
+
+    let client = Client::new(rocket(config)).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in ApiTokensTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        insert_into(users)
+            .values(&NewUser {
+                real_name: String::from("Api User"),
+                handle: String::from("apiuser"),
+                email: String::from("api@test-rcos.io"),
+                mmost: String::from("apiuser"),
+                active: true,
+                ..NewUser::default()
+            })
+            .execute(&conn)
+            .expect("Failed to add user to database");
+        users
+            .filter(handle.eq("apiuser"))
+            .first(&conn)
+            .expect("Failed to get user from database")
+    };
+
+    {
+        use crate::schema::api_tokens::dsl::*;
+        insert_into(api_tokens)
+            .values(&NewApiToken {
+                user_id: user.id,
+                name: String::from("test"),
+                token_hash: hash_token("obs_test"),
+                created_at: chrono::offset::Utc::now().naive_utc(),
+            })
+            .execute(&conn)
+            .expect("Failed to add API token to database");
+    }
+
+    let auth = || Header::new("Authorization", "Bearer obs_test");
+
+    let mut response = client.get("/api/v1/me").header(auth()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.body_string().unwrap().contains("apiuser"));
+
+    // Errors are JSON instead of redirects
+    let mut response = client.get("/api/v1/me").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(response.body_string().unwrap().contains("\"status\":401"));
+
+    let response = client
+        .get("/api/v1/me")
+        .header(Header::new("Authorization", "Bearer obs_wrong"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client.get("/api/v1/groups").header(auth()).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let mut response = client
+        .post("/api/v1/projects")
+        .header(auth())
+        .header(ContentType::JSON)
+        .body(r#"{"name": "Api Project", "description": "Made by a script", "repos": "[]"}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+    assert!(response.body_string().unwrap().contains("Api Project"));
+
+    let response = client
+        .post("/api/v1/projects")
+        .header(auth())
+        .header(ContentType::JSON)
+        .body("not json")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // Students only see their own email
+    create_user(
+        &conn,
+        NewUser {
+            real_name: String::from("Other User"),
+            handle: String::from("otheruser"),
+            email: String::from("other@test-rcos.io"),
+            mmost: String::from("otheruser"),
+            active: true,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user");
+    let mut response = client.get("/api/v1/users").header(auth()).dispatch();
+    let body = response.body_string().unwrap();
+    assert!(body.contains("api@test-rcos.io"));
+    assert!(body.contains("otheruser"));
+    assert!(!body.contains("other@test-rcos.io"));
+
+    // Admins can see the tokens of others but can't make them
+    make_user(&conn, "manager", Role::Admin);
+    login(&client, "manager");
+    let tokens = format!("/users/{}/tokens", user.id);
+    assert_eq!(client.get(&tokens).dispatch().status(), Status::Ok);
+    let response = client
+        .post(&tokens)
+        .header(ContentType::Form)
+        .body("name=stolen")
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    client.get("/logout").dispatch();
+
+    // Fields left out of an edit aren't changed
+    let mut response = client
+        .put(format!("/api/v1/users/{}", user.id))
+        .header(auth())
+        .header(ContentType::JSON)
+        .body(r#"{"bio": "Edited by a script"}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Edited by a script"));
+    assert!(body.contains("api@test-rcos.io"));
+    assert!(body.contains("\"handle\":\"apiuser\""));
+
+    // Changing the password revokes the tokens too
+    save_user(
+        &conn,
+        &user,
+        user.id,
+        NewUser {
+            real_name: String::from("Api User"),
+            handle: String::from("apiuser"),
+            email: String::from("api@test-rcos.io"),
+            mmost: String::from("apiuser"),
+            password_hash: String::from("newpassword"),
+            active: true,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to save user");
+    let response = client.get("/api/v1/me").header(auth()).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    cleanup(String::from("test_api_tokens"));
+}
+
+// Serves canned HTTP responses in order and sends back each request
+fn mock_server(
+    listener: std::net::TcpListener,
+    responses: Vec<String>,
+) -> std::sync::mpsc::Receiver<String> {
+    use std::io::{Read, Write};
+
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        for res in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = Vec::new();
+            let mut buf = [0u8; 1024];
+            let end = loop {
+                if let Some(i) = req.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break i + 4;
+                }
+                let n = stream.read(&mut buf).unwrap();
+                req.extend_from_slice(&buf[..n]);
+            };
+            // Read the body of a POST too
+            let head = String::from_utf8_lossy(&req[..end]).to_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:"))
+                .map_or(0, |l| l.trim().parse().unwrap());
+            while req.len() < end + length {
+                let n = stream.read(&mut buf).unwrap();
+                req.extend_from_slice(&buf[..n]);
+            }
+            tx.send(String::from_utf8(req).unwrap()).unwrap();
+            stream.write_all(res.as_bytes()).unwrap();
+        }
+    });
+
+    rx
+}
+
+fn mock_response(status: &str, headers: &[&str], body: &str) -> String {
+    let mut res = format!("HTTP/1.1 {}\r\n", status);
+    for h in headers {
+        res += &format!("{}\r\n", h);
+    }
+    res + &format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+// Tests syncing commits with paging, ETags and rate limiting
+#[test]
+fn commit_sync() {
+    use crate::commits::sync::{SyncError, Syncer};
+    use crate::settings::Settings;
+
+    let config = setup(String::from("test_commit_sync"));
+
+    let client = Client::new(rocket(config)).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in CommitSyncTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let project: Project = {
+        use crate::schema::projects::dsl::*;
+        insert_into(projects)
+            .values(&NewProject {
+                name: String::from("Synced"),
+                description: String::from("Has commits"),
+                repos: String::from(r#"["https://github.com/rcos/observatory.git"]"#),
+                owner_id: 0,
+                active: true,
+                ..NewProject::default()
+            })
+            .execute(&conn)
+            .expect("Failed to add project to database");
+        projects
+            .filter(name.eq("Synced"))
+            .first(&conn)
+            .expect("Failed to get project from database")
+    };
+
+    let commit = |sha: &str, msg: &str, hour: u32| {
+        format!(
+            r#"{{"sha": "{}", "html_url": "https://github.com/rcos/observatory/commit/{0}",
+                "author": {{"login": "admin"}},
+                "commit": {{"message": "{}", "author": {{"name": "Admin",
+                    "email": "admin@rcos.io", "date": "2021-03-02T{:02}:00:00Z"}}}}}}"#,
+            sha, msg, hour
+        )
+    };
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    // The next page is somewhere else, which doesn't get the token
+    let elsewhere = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let link = format!(
+        "Link: <http://{}/repos/rcos/observatory/commits?page=2>; rel=\"next\"",
+        elsewhere.local_addr().unwrap()
+    );
+    let elsewhere = mock_server(
+        elsewhere,
+        vec![mock_response(
+            "200 OK",
+            &[],
+            &format!("[{}]", commit("aaa", "First", 16)),
+        )],
+    );
+
+    let requests = mock_server(
+        listener,
+        vec![
+            mock_response(
+                "200 OK",
+                &["ETag: \"v1\"", &link],
+                &format!("[{}]", commit("bbb", "Second\\n\\nDetails", 17)),
+            ),
+            mock_response("304 Not Modified", &[], ""),
+            mock_response(
+                "403 Forbidden",
+                &[
+                    "X-RateLimit-Remaining: 0",
+                    "X-RateLimit-Reset: 99999999999999",
+                ],
+                "{}",
+            ),
+        ],
+    );
+
+    let mut syncer = Syncer::new(&Settings {
+        github_api_url: url,
+        github_token: Some(String::from("secret")),
+        ..Settings::default()
+    });
+
+    // Paging works and both pages are saved
+    assert_eq!(syncer.sync_all(&conn), 2);
+    let first = requests.recv().unwrap();
+    assert!(first.starts_with("GET /repos/rcos/observatory/commits?per_page=100"));
+    assert!(first.contains("User-Agent: Observatory"));
+    assert!(first.contains("Authorization: token secret"));
+    let second = elsewhere.recv().unwrap();
+    assert!(second.contains("page=2"));
+    assert!(!second.contains("Authorization"));
+
+    let recent = project_commits(&conn, &project, 10);
+    assert_eq!(recent.len(), 1);
+    assert_eq!(recent[0].0, "rcos/observatory");
+    assert_eq!(recent[0].1.len(), 2);
+    assert_eq!(recent[0].1[0].summary(), "Second");
+
+    // The ETag is sent back and unchanged repos are skipped
+    assert_eq!(syncer.sync_all(&conn), 0);
+    assert!(requests.recv().unwrap().contains("If-None-Match: \"v1\""));
+
+    // Once rate limited no more requests are made until the reset, even
+    // when the reset time is out of range
+    let repo = "https://github.com/rcos/observatory";
+    assert!(matches!(
+        syncer.sync_repo(&conn, repo),
+        Err(SyncError::RateLimited(_))
+    ));
+    let _ = requests.recv().unwrap();
+    assert!(matches!(
+        syncer.sync_repo(&conn, repo),
+        Err(SyncError::RateLimited(_))
+    ));
+    assert!(requests.try_recv().is_err());
+
+    // A sync that stops at the page limit continues where it left off
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let page = |n: u32, last: bool| {
+        let link = format!(
+            "Link: <{}/repos/rcos/big/commits?page={}>; rel=\"next\"",
+            url,
+            n + 1
+        );
+        let headers = [link.as_str()];
+        mock_response(
+            "200 OK",
+            if last { &[] } else { &headers },
+            &format!("[{}]", commit(&format!("big{}", n), "Older", n)),
+        )
+    };
+    let mut responses: Vec<String> = (1..=10).map(|n| page(n, false)).collect();
+    responses.push(mock_response(
+        "200 OK",
+        &["ETag: \"v2\""],
+        &format!(
+            "[{}, {}]",
+            commit("big0", "Newer", 0),
+            commit("big1", "Older", 1)
+        ),
+    ));
+    responses.push(page(11, true));
+    let requests = mock_server(listener, responses);
+
+    let mut syncer = Syncer::new(&Settings {
+        github_api_url: url,
+        ..Settings::default()
+    });
+    let repo = "https://github.com/rcos/big";
+    assert_eq!(syncer.sync_repo(&conn, repo).unwrap(), 10);
+    let synced = |conn: &SqliteConnection| -> crate::commits::models::RepoSync {
+        use crate::schema::repo_syncs::dsl::*;
+        repo_syncs
+            .find("https://github.com/rcos/big")
+            .first(conn)
+            .expect("Failed to get repo sync")
+    };
+    assert!(synced(&conn).resume_url.unwrap().ends_with("page=11"));
+
+    // New commits are fetched first, then the pages left last time
+    assert_eq!(syncer.sync_repo(&conn, repo).unwrap(), 2);
+    let requests: Vec<String> = requests.iter().take(12).collect();
+    assert!(requests[10].contains("per_page=100"));
+    assert!(requests[11].contains("page=11"));
+    let sync = synced(&conn);
+    assert_eq!(sync.resume_url, None);
+    assert_eq!(sync.etag.as_deref(), Some("\"v2\""));
+
+    cleanup(String::from("test_commit_sync"));
+}
+
+// Tests that repo URLs go to the right forge and their commits are parsed
+#[test]
+fn forges() {
+    use crate::commits::forge::{find_forge, forges, Repo};
+    use crate::settings::Settings;
+
+    let settings = Settings {
+        gitlab_hosts: vec![String::from("gitlab.com"), String::from("git.rcos.io")],
+        ..Settings::default()
+    };
+    let forges = forges(&settings);
+
+    let repo = Repo::parse("http://www.GitHub.com/rcos/observatory.git/").unwrap();
+    assert_eq!(repo.url, "https://github.com/rcos/observatory");
+    assert_eq!(repo.path, "rcos/observatory");
+    assert!(Repo::parse("https://github.com/rcos").is_none());
+
+    let (gitlab, repo) = find_forge(&forges, "https://git.rcos.io/group/sub/project").unwrap();
+    assert_eq!(
+        gitlab.commits_url(&repo),
+        "https://git.rcos.io/api/v4/projects/group%2Fsub%2Fproject/repository/commits?per_page=100"
+    );
+    let body = serde_json::json!([{
+        "id": "abc",
+        "message": "Fix things\n\nAt length",
+        "author_name": "Admin",
+        "author_email": "admin@rcos.io",
+        "authored_date": "2021-03-02T16:00:00.000+00:00",
+        "web_url": "https://git.rcos.io/group/sub/project/-/commit/abc"
+    }]);
+    let link =
+        "<https://git.rcos.io/page2>; rel=\"next\", <https://git.rcos.io/page9>; rel=\"last\"";
+    let (commits, next) = gitlab.parse_page(&repo, &body, Some(link)).unwrap();
+    assert_eq!(commits[0].sha, "abc");
+    assert_eq!(commits[0].repo, "https://git.rcos.io/group/sub/project");
+    assert_eq!(next.as_deref(), Some("https://git.rcos.io/page2"));
+
+    let (gitea, repo) = find_forge(&forges, "https://codeberg.org/rcos/observatory").unwrap();
+    assert_eq!(
+        gitea.commits_url(&repo),
+        "https://codeberg.org/api/v1/repos/rcos/observatory/commits?limit=50&stat=false"
+    );
+
+    let (bitbucket, repo) = find_forge(&forges, "https://bitbucket.org/rcos/observatory").unwrap();
+    let body = serde_json::json!({
+        "values": [{
+            "hash": "def",
+            "message": "Add things",
+            "date": "2021-03-02T16:00:00+00:00",
+            "author": {"raw": "Admin <admin@rcos.io>", "user": {"nickname": "admin"}},
+            "links": {"html": {"href": "https://bitbucket.org/rcos/observatory/commits/def"}}
+        }],
+        "next": "https://api.bitbucket.org/page2"
+    });
+    let (commits, next) = bitbucket.parse_page(&repo, &body, None).unwrap();
+    assert_eq!(commits[0].author_name, "Admin");
+    assert_eq!(commits[0].author_email, "admin@rcos.io");
+    assert_eq!(commits[0].author_login.as_deref(), Some("admin"));
+    assert_eq!(next.as_deref(), Some("https://api.bitbucket.org/page2"));
+
+    assert!(find_forge(&forges, "https://example.com/rcos/observatory").is_none());
+}
+
+// Tests that commits are attributed by verified emails and aliases
+#[test]
+fn commit_attribution() {
+    use crate::commits::handlers::add_identity;
+    use crate::commits::models::{NewCommit, RepoSync};
+
+    let config = setup(String::from("test_commit_attribution"));
+
+    let client = Client::new(rocket(config)).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in CommitAttributionTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let (user, admin): (User, User) = {
+        use crate::schema::users::dsl::*;
+        insert_into(users)
+            .values(&NewUser {
+                real_name: String::from("Committer"),
+                handle: String::from("committer"),
+                email: String::from("committer@test-rcos.io"),
+                mmost: String::from("committer"),
+                active: true,
+                ..NewUser::default()
+            })
+            .execute(&conn)
+            .expect("Failed to add user to database");
+        (
+            users
+                .filter(handle.eq("committer"))
+                .first(&conn)
+                .expect("Failed to get user from database"),
+            users
+                .find(0)
+                .first(&conn)
+                .expect("Failed to get admin from database"),
+        )
+    };
+
+    let repos_json = r#"["https://gitlab.com/rcos/one", "https://github.com/rcos/two"]"#;
+    let project: Project = {
+        use crate::schema::projects::dsl::*;
+        insert_into(projects)
+            .values(&NewProject {
+                name: String::from("Attributed"),
+                description: String::from("Has commits"),
+                repos: String::from(repos_json),
+                owner_id: 0,
+                active: true,
+                ..NewProject::default()
+            })
+            .execute(&conn)
+            .expect("Failed to add project to database");
+        projects
+            .filter(name.eq("Attributed"))
+            .first(&conn)
+            .expect("Failed to get project from database")
+    };
+    {
+        use crate::schema::relation_project_user::dsl::*;
+        insert_into(relation_project_user)
+            .values(&NewRelationProjectUser {
+                project_id: project.id,
+                user_id: user.id,
+                ..NewRelationProjectUser::default()
+            })
+            .execute(&conn)
+            .expect("Failed to add relation to database");
+    }
+
+    assert_eq!(
+        user_commits_count(&conn, &user, None, &crate::tz::Tz::utc()),
+        None
+    );
+
+    let now = chrono::offset::Utc::now().naive_utc();
+    let commit = |r: &str, s: &str, email: &str, login: Option<&str>| NewCommit {
+        repo: String::from(r),
+        sha: String::from(s),
+        author_name: String::from("Someone"),
+        author_email: String::from(email),
+        author_login: login.map(String::from),
+        message: String::from("Change"),
+        url: String::new(),
+        committed_at: now,
+    };
+    {
+        use crate::schema::commits::dsl::*;
+        insert_into(commits)
+            .values(&vec![
+                commit(
+                    "https://gitlab.com/rcos/one",
+                    "a",
+                    "COMMITTER@test-rcos.io",
+                    None,
+                ),
+                commit(
+                    "https://gitlab.com/rcos/one",
+                    "b",
+                    "laptop@example.com",
+                    None,
+                ),
+                commit(
+                    "https://github.com/rcos/two",
+                    "c",
+                    "other@example.com",
+                    Some("Committer"),
+                ),
+                commit(
+                    "https://github.com/rcos/two",
+                    "d",
+                    "stranger@example.com",
+                    None,
+                ),
+            ])
+            .execute(&conn)
+            .expect("Failed to add commits to database");
+    }
+    {
+        use crate::schema::repo_syncs::dsl::*;
+        for r in &["https://gitlab.com/rcos/one", "https://github.com/rcos/two"] {
+            insert_into(repo_syncs)
+                .values(&RepoSync {
+                    repo: String::from(*r),
+                    etag: None,
+                    synced_at: now,
+                    resume_url: None,
+                })
+                .execute(&conn)
+                .expect("Failed to add repo sync to database");
+        }
+    }
+
+    // The account email and handle count in every repo
+    assert_eq!(
+        user_commits_count(&conn, &user, None, &crate::tz::Tz::utc()),
+        Some(2)
+    );
+
+    // Unverified emails don't count until a mentor adds or verifies them
+    add_identity(&conn, &user, user.id, "email", "laptop@example.com").unwrap();
+    assert_eq!(
+        user_commits_count(&conn, &user, None, &crate::tz::Tz::utc()),
+        Some(2)
+    );
+    {
+        use crate::schema::commit_identities::dsl::*;
+        diesel::update(commit_identities)
+            .set(verified.eq(true))
+            .execute(&conn)
+            .expect("Failed to update commit identity in database");
+    }
+    assert_eq!(
+        user_commits_count(&conn, &user, None, &crate::tz::Tz::utc()),
+        Some(3)
+    );
+
+    // Someone else can't claim the same email
+    assert!(add_identity(&conn, &admin, 0, "email", "Laptop@example.com").is_err());
+    assert!(add_identity(&conn, &admin, user.id, "email", "not an email").is_err());
+
+    cleanup(String::from("test_commit_attribution"));
+}
+
+// Tests that ending a semester archives it and marks non-returning users as former
+#[test]
+fn semester_rollover() {
+    use crate::semesters::handlers::*;
+    use crate::semesters::models::NewSemester;
+    use crate::semesters::templates::SemesterSelect;
+    use crate::templates::FormError;
+    use chrono::NaiveDate;
+
+    let config = setup(String::from("test_semester_rollover"));
+
+    let client = Client::new(rocket(config)).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in SemesterRolloverTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let semester = |n: &str, start: (i32, u32, u32), end: (i32, u32, u32)| {
+        create_semester(
+            &conn,
+            Ok(NewSemester {
+                name: String::from(n),
+                start: NaiveDate::from_ymd(start.0, start.1, start.2),
+                end: NaiveDate::from_ymd(end.0, end.1, end.2),
+            }),
+        )
+        .expect("Failed to create semester")
+    };
+    let fall = semester("Fall 2020", (2020, 9, 1), (2020, 12, 20));
+
+    // The next semester has to exist first
+    assert!(matches!(
+        rollover(&conn, &fall),
+        Err(FormError::NoNextSemester)
+    ));
+    let spring = semester("Spring 2021", (2021, 1, 25), (2021, 5, 10));
+    assert!(create_semester(
+        &conn,
+        Ok(NewSemester {
+            name: String::from("Spring 2021"),
+            start: spring.start,
+            end: spring.end,
+        })
+    )
+    .is_err());
+
+    let first_day = NaiveDate::from_ymd(2020, 9, 1).and_hms(12, 0, 0);
+    assert_eq!(
+        semester_at(&conn, first_day, &crate::tz::Tz::utc()),
+        Some(fall.clone())
+    );
+    assert_eq!(
+        semester_at(
+            &conn,
+            first_day - chrono::Duration::days(1),
+            &crate::tz::Tz::utc()
+        ),
+        None
+    );
+
+    let leaving = make_user(&conn, "leaving", Role::Student);
+    let returning = make_user(&conn, "returning", Role::Student);
+
+    // Joining a group or project enrolls the user in its semester
+    let gid = create_group(
+        &conn,
+        &NewGroup {
+            name: String::from("Fall Group"),
+            owner_id: leaving.id,
+            location: None,
+            semester_id: Some(fall.id),
+            ..NewGroup::default()
+        },
+    )
+    .expect("Failed to create group");
+    let project = create_project(
+        &conn,
+        &returning,
+        NewProject {
+            name: String::from("Fall Project"),
+            description: String::from("Only this semester"),
+            repos: String::from("[]"),
+            semester_id: Some(fall.id),
+            ..NewProject::default()
+        },
+    )
+    .expect("Failed to create project");
+    enroll(&conn, spring.id, returning.id);
+    assert_eq!(
+        semester_users(&conn, &fall),
+        vec![leaving.clone(), returning.clone()]
+    );
+
+    assert_eq!(rollover(&conn, &fall).ok(), Some((spring.clone(), 1)));
+
+    let g: Group = {
+        use crate::schema::groups::dsl::*;
+        groups.find(gid).first(&conn).expect("Failed to get group")
+    };
+    assert!(g.archived);
+    let p: Project = {
+        use crate::schema::projects::dsl::*;
+        projects
+            .find(project.id)
+            .first(&conn)
+            .expect("Failed to get project")
+    };
+    assert!(!p.active);
+    {
+        use crate::schema::users::dsl::*;
+        let l: User = users.find(leaving.id).first(&conn).unwrap();
+        assert!(l.former && !l.active);
+        let r: User = users.find(returning.id).first(&conn).unwrap();
+        assert!(!r.former && r.active);
+    }
+
+    // Things without a semester show up in every semester
+    let select = SemesterSelect {
+        semesters: all_semesters(&conn),
+        selected: Some(spring.clone()),
+    };
+    assert_eq!(select.semesters, vec![spring.clone(), fall.clone()]);
+    assert!(select.includes(None));
+    assert!(select.includes(Some(spring.id)));
+    assert!(!select.includes(Some(fall.id)));
+
+    // Deleting a semester keeps what was in it
+    delete_semester(&conn, fall.id);
+    let g: Group = {
+        use crate::schema::groups::dsl::*;
+        groups.find(gid).first(&conn).expect("Failed to get group")
+    };
+    assert_eq!(g.semester_id, None);
+
+    cleanup(String::from("test_semester_rollover"));
+}
+
+// Tests computing grades from a rubric
+#[test]
+fn grading() {
+    use crate::grades::handlers::*;
+    use crate::semesters::handlers::{create_semester, enroll};
+    use chrono::NaiveDate;
+
+    let mut rubric = Rubric::default_for(1);
+    assert_eq!(rubric.letter(95.0), "A");
+    assert_eq!(rubric.letter(80.0), "B");
+    assert_eq!(rubric.letter(10.0), "F");
+    rubric.thresholds = String::from("Fail:0, Pass:70");
+    assert_eq!(rubric.letter(70.0), "Pass");
+    assert_eq!(rubric.letter(69.9), "Fail");
+    rubric.thresholds = String::from("A:90, B");
+    assert!(rubric.thresholds().is_none());
+
+    // Parts with nothing needed don't count against anyone
+    let g = Grade {
+        small_group: Part { done: 3, needed: 4 },
+        large_group: Part { done: 2, needed: 2 },
+        events: Part::default(),
+        commits: Part {
+            done: 20,
+            needed: 10,
+        },
+        ..Grade::default()
+    };
+    rubric.small_group_weight = 2;
+    assert!((rubric.score(&g) - 87.5).abs() < 1e-9);
+
+    let config = setup(String::from("test_grading"));
+
+    let client = Client::new(rocket(config)).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in GradingTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let semester = create_semester(
+        &conn,
+        Ok(NewSemester {
+            name: String::from("Spring 2021"),
+            start: NaiveDate::from_ymd(2021, 1, 25),
+            end: NaiveDate::from_ymd(2021, 5, 10),
+        }),
+    )
+    .expect("Failed to create semester");
+    let user = create_user(
+        &conn,
+        NewUser {
+            real_name: String::from("Graded"),
+            handle: String::from("graded"),
+            email: String::from("graded@test-rcos.io"),
+            mmost: String::from("graded"),
+            active: true,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user");
+    enroll(&conn, semester.id, user.id);
+
+    // Two large group meetings in the semester and one before it
+    let during = NaiveDate::from_ymd(2021, 2, 1).and_hms(16, 0, 0);
+    let before = NaiveDate::from_ymd(2020, 12, 1).and_hms(16, 0, 0);
+    {
+        use crate::schema::meetings::dsl::*;
+        for (i, t) in [during, during, before].iter().enumerate() {
+            insert_into(meetings)
+                .values((
+                    happened_at.eq(t),
+                    code.eq(format!("code{}", i)),
+                    group_id.eq(0),
+                    hosted_by.eq(0),
+                    code_expires.eq(t),
+                ))
+                .execute(&conn)
+                .expect("Failed to add meeting to database");
+        }
+    }
+    let mids: Vec<i32> = {
+        use crate::schema::meetings::dsl::*;
+        meetings
+            .order(id.asc())
+            .select(id)
+            .load(&conn)
+            .expect("Failed to get meetings from database")
+    };
+    {
+        use crate::schema::attendances::dsl::*;
+        for (mid, ok) in &[(mids[0], true), (mids[1], false), (mids[2], true)] {
+            insert_into(attendances)
+                .values(&NewAttendance {
+                    user_id: user.id,
+                    is_event: false,
+                    meeting_id: Some(*mid),
+                    event_id: None,
+                    approved: *ok,
+                })
+                .execute(&conn)
+                .expect("Failed to add attendance to database");
+        }
+    }
+
+    // Only the large group and commits have anything needed
+    let rubric = rubric_for(&conn, semester.id);
+    let grade = compute_grade(&conn, &user, &semester, &rubric, &crate::tz::Tz::utc());
+    assert_eq!(grade.large_group, Part { done: 1, needed: 2 });
+    assert_eq!(grade.small_group, Part::default());
+    assert_eq!(
+        grade.commits,
+        Part {
+            done: 0,
+            needed: 10
+        }
+    );
+    assert!((grade.score - 25.0).abs() < 1e-9);
+    assert_eq!(grade.letter, "F");
+
+    let rows = grade_rows(
+        &conn,
+        &semester,
+        &rubric,
+        &crate::tz::Tz::utc(),
+        "score",
+        true,
+    );
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].grade, grade);
+
+    cleanup(String::from("test_grading"));
+}
+
+// Tests signed tokens and the reset and verification emails
+#[test]
+fn password_reset() {
+    use crate::auth::tokens::*;
+    use crate::mailer::{FileMailer, Outbox};
+    use rocket::http::{ContentType, Header};
+
+    let mut config = setup(String::from("test_password_reset")).unwrap();
+    let mail_dir = "./test_password_reset/mail";
+    config.extras.insert("mailer".into(), Value::from("file"));
+    config
+        .extras
+        .insert("mail_dir".into(), Value::from(mail_dir));
+
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in PasswordResetTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let get_user = || -> User {
+        use crate::schema::users::dsl::*;
+        users
+            .filter(email.eq("reset@test-rcos.io"))
+            .first(&conn)
+            .expect("Failed to get user from database")
+    };
+    // Get the token out of the last email sent with this link
+    let last_token = |link: &str| -> String {
+        let mut mails: Vec<_> = fs::read_dir(mail_dir)
+            .expect("Failed to read mail directory")
+            .map(|f| f.unwrap().path())
+            .collect();
+        mails.sort();
+        let mail = fs::read_to_string(mails.last().unwrap()).unwrap();
+        let start = mail.find(link).expect("Link not in email") + link.len();
+        mail[start..].split_whitespace().next().unwrap().to_string()
+    };
+    let form = |url: &str, body: String| {
+        client
+            .post(url.to_string())
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+    };
+    let location =
+        |r: &rocket::local::LocalResponse| r.headers().get_one("Location").unwrap().to_string();
+
+    form(
+        "/signup",
+        String::from(
+            "email=reset@test-rcos.io&password=first&password_repeat=first\
+             &real_name=Reset+User&handle=resetuser&mmost=resetuser",
+        ),
+    );
+    assert!(!get_user().verified);
+
+    // Unverified users can't submit attendance
+    let response = form("/attend", String::from("code=abcdef"));
+    assert_eq!(location(&response), "/attend?e=unverified");
+
+    let token = last_token("/verify?token=");
+    let mut response = client.get("/verify?token=1.1.bad").dispatch();
+    assert!(response.body_string().unwrap().contains("invalid"));
+    assert!(!get_user().verified);
+    client.get(format!("/verify?token={}", token)).dispatch();
+    assert!(get_user().verified);
+
+    // Resetting asks for the email without saying if it has an account
+    let response = form("/forgot", String::from("email=nobody@test-rcos.io"));
+    assert_eq!(location(&response), "/forgot?sent=true");
+    // The link is on site_url even if the Host is forged
+    client
+        .post("/forgot")
+        .header(ContentType::Form)
+        .header(Header::new("Host", "evil.example"))
+        .body("email=reset@test-rcos.io")
+        .dispatch();
+    let token = last_token("http://localhost:8000/reset?token=");
+
+    assert_eq!(
+        client
+            .get(format!("/reset?token={}", token))
+            .dispatch()
+            .status(),
+        Status::Ok
+    );
+    let response = form(
+        "/reset",
+        format!("token={}&password=second&password_repeat=other", token),
+    );
+    assert!(location(&response).ends_with("e=mismatch"));
+    let response = form(
+        "/reset",
+        format!("token={}&password=second&password_repeat=second", token),
+    );
+    assert_eq!(location(&response), "/login");
+
+    let user = get_user();
+    assert!(verify_password("second", &user.password_hash));
+
+    // A reset token only works once
+    let response = form(
+        "/reset",
+        format!("token={}&password=third&password_repeat=third", token),
+    );
+    assert_eq!(location(&response), "/forgot?e=invalidToken");
+
+    // Tokens expire and only work for what they were made for
+    let key = TokenKey::new(Some("secret"));
+    let now = chrono::offset::Utc::now().naive_utc();
+    let token = key.sign(Purpose::Verify, &user, now);
+    assert_eq!(key.verify(&conn, Purpose::Verify, &token, now), Some(user));
+    assert!(key.verify(&conn, Purpose::Reset, &token, now).is_none());
+    assert!(TokenKey::new(Some("other"))
+        .verify(&conn, Purpose::Verify, &token, now)
+        .is_none());
+    assert!(key
+        .verify(
+            &conn,
+            Purpose::Verify,
+            &token,
+            now + chrono::Duration::days(4)
+        )
+        .is_none());
+
+    // Emails with links aren't sent at all without site_url
+    let outbox = Outbox::new(String::new(), None, Box::new(FileMailer::new(mail_dir)));
+    let sent = fs::read_dir(mail_dir).unwrap().count();
+    outbox.send_links("reset@test-rcos.io", "Link", |site| site.to_string());
+    assert_eq!(fs::read_dir(mail_dir).unwrap().count(), sent);
+
+    fs::remove_dir_all(mail_dir).expect("Failed to remove mail directory");
+    cleanup(String::from("test_password_reset"));
+}
+
+// Tests that sessions are stored, expire and are revoked
+#[test]
+fn sessions() {
+    use crate::api::handlers::hash_token;
+    use crate::sessions::models::*;
+    use rocket::http::{ContentType, Header};
+
+    let config = setup(String::from("test_sessions"));
+
+    let client = Client::new(rocket(config)).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in SessionsTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let phash = hash_password("password");
+    let nu = NewUser {
+        real_name: String::from("Session User"),
+        handle: String::from("sessionuser"),
+        email: String::from("session@test-rcos.io"),
+        mmost: String::from("sessionuser"),
+        password_hash: phash,
+        active: true,
+        ..NewUser::default()
+    };
+    let user = create_user(&conn, nu.clone()).expect("Failed to create user");
+
+    let login = || {
+        client
+            .post("/login")
+            .header(ContentType::Form)
+            .header(Header::new("User-Agent", "TestBrowser"))
+            .body("email=session@test-rcos.io&password=password")
+            .dispatch();
+    };
+    let user_sessions = || -> Vec<Session> {
+        Session::belonging_to(&user)
+            .load(&conn)
+            .expect("Failed to get sessions from database")
+    };
+    let logged_in = || client.get("/dashboard").dispatch().status() == Status::Ok;
+
+    login();
+    let current = user_sessions();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0].user_agent, "TestBrowser");
+    assert!(logged_in());
+
+    // Log out another browser but not this one
+    {
+        use crate::schema::sessions::dsl::*;
+        let now = chrono::offset::Utc::now().naive_utc();
+        insert_into(sessions)
+            .values(&NewSession {
+                user_id: user.id,
+                token_hash: hash_token("other"),
+                created_at: now,
+                last_seen: now,
+                ip: None,
+                user_agent: String::new(),
+            })
+            .execute(&conn)
+            .expect("Failed to add session to database");
+    }
+    assert_eq!(user_sessions().len(), 2);
+    client
+        .delete(format!("/users/{}/sessions", user.id))
+        .dispatch();
+    assert_eq!(user_sessions(), current);
+    assert!(logged_in());
+
+    // Unused sessions expire
+    {
+        use crate::schema::sessions::dsl::*;
+        diesel::update(sessions.find(current[0].id))
+            .set(last_seen.eq(current[0].last_seen - chrono::Duration::days(31)))
+            .execute(&conn)
+            .expect("Failed to update session in database");
+    }
+    assert!(!logged_in());
+    assert!(user_sessions().is_empty());
+
+    // Changing the password logs out everywhere
+    login();
+    assert!(logged_in());
+    save_user(
+        &conn,
+        &user,
+        user.id,
+        NewUser {
+            password_hash: "newpassword".into(),
+            ..nu
+        },
+    )
+    .expect("Failed to save user");
+    assert!(user_sessions().is_empty());
+    assert!(!logged_in());
+
+    cleanup(String::from("test_sessions"));
+}
+
+// Tests TOTP codes and logging in with two-factor authentication
+#[test]
+fn two_factor() {
+    use crate::totp::handlers::totp_secret;
+    use crate::totp::otp::*;
+    use rocket::http::ContentType;
+
+    // Test vectors from RFC 6238
+    let secret = b"12345678901234567890";
+    assert_eq!(totp(secret, step_at(59)), "287082");
+    assert_eq!(totp(secret, step_at(1_111_111_109)), "081804");
+    assert_eq!(base32_encode(secret), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    assert_eq!(
+        base32_decode("gezd gnbv gy3t qojq gezd gnbv gy3t qojq"),
+        Some(secret.to_vec())
+    );
+    let b32 = base32_encode(secret);
+    assert_eq!(verify(&b32, "287082", 59 + STEP_SECONDS), Some(1));
+    assert_eq!(verify(&b32, "287082", 59 + 3 * STEP_SECONDS), None);
+
+    let mut config = setup(String::from("test_two_factor")).unwrap();
+    config.extras.insert(
+        "totp_required_roles".into(),
+        Value::from(vec!["mentor", "coordinator"]),
+    );
+
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in TwoFactorTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let user = make_user(&conn, "totpmentor", Role::Mentor);
+
+    let post = |url: String, body: String| {
+        let r = client
+            .post(url)
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        r.headers().get_one("Location").map(String::from)
+    };
+    let login = || {
+        post(
+            String::from("/login?to=/attend/pending"),
+            String::from("email=totpmentor@test-rcos.io&password=password"),
+        )
+    };
+    let code_now = |secret: &str| {
+        let step = step_at(chrono::offset::Utc::now().timestamp());
+        totp(&base32_decode(secret).unwrap(), step)
+    };
+
+    // Mentors have to set it up before using Mentor pages
+    assert_eq!(login(), Some(format!("/users/{}/totp", user.id)));
+    let mut response = client.get("/attend/pending").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    assert!(response.body_string().unwrap().contains("two-factor"));
+
+    client.get(format!("/users/{}/totp", user.id)).dispatch();
+    let secret = totp_secret(&conn, user.id).unwrap();
+    assert!(!secret.enabled);
+
+    let mut response = client
+        .post(format!("/users/{}/totp", user.id))
+        .header(ContentType::Form)
+        .body(format!("code={}", code_now(&secret.secret)))
+        .dispatch();
+    let body = response.body_string().unwrap();
+    assert!(totp_secret(&conn, user.id).unwrap().enabled);
+    let start = body.find("<li><code>").expect("No recovery codes shown") + 10;
+    let recovery = body[start..start + 9].to_string();
+    assert_eq!(
+        client.get("/attend/pending").dispatch().status(),
+        Status::Ok
+    );
+
+    // Logging in now takes a second step
+    client.get("/logout").dispatch();
+    assert_eq!(
+        login(),
+        Some(String::from("/login/totp?to=%2Fattend%2Fpending"))
+    );
+    assert_ne!(
+        client.get("/attend/pending").dispatch().status(),
+        Status::Ok
+    );
+    let second = |code: &str| {
+        post(
+            String::from("/login/totp?to=/attend/pending"),
+            format!("code={}", code),
+        )
+    };
+    assert!(second("000000").unwrap().contains("e=totp"));
+    // The code used to turn it on can't be used again
+    if totp_secret(&conn, user.id).unwrap().last_step
+        == step_at(chrono::offset::Utc::now().timestamp())
+    {
+        assert!(second(&code_now(&secret.secret))
+            .unwrap()
+            .contains("e=totp"));
+    }
+    assert_eq!(second(&recovery), Some(String::from("/attend/pending")));
+    assert_eq!(
+        client.get("/attend/pending").dispatch().status(),
+        Status::Ok
+    );
+
+    // Recovery codes only work once
+    client.get("/logout").dispatch();
+    login();
+    assert!(second(&recovery).unwrap().contains("e=totp"));
+
+    cleanup(String::from("test_two_factor"));
+}
+
+// Tests logging in with and linking accounts from a mock OAuth provider
+#[test]
+fn oauth_login() {
+    use crate::oauth::models::OAuthIdentity;
+    use rocket::http::ContentType;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let provider = |kind: &str| {
+        let mut p = HashMap::new();
+        p.insert("kind", Value::from(kind));
+        p.insert("client_id", Value::from("observatory"));
+        p.insert("client_secret", Value::from("hunter2"));
+        p.insert("authorize_url", Value::from(format!("{}/authorize", url)));
+        p.insert("token_url", Value::from(format!("{}/token", url)));
+        p.insert("userinfo_url", Value::from(format!("{}/user", url)));
+        Value::from(p)
+    };
+    let mut providers = HashMap::new();
+    providers.insert("github", provider("github"));
+    providers.insert("sso", provider("oidc"));
+
+    let mut config = setup(String::from("test_oauth_login")).unwrap();
+    config
+        .extras
+        .insert("oauth_providers".into(), Value::from(providers));
+
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in OAuthLoginTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let phash = hash_password("password");
+    let user = create_user(
+        &conn,
+        NewUser {
+            real_name: String::from("OAuth User"),
+            handle: String::from("notoctocat"),
+            email: String::from("oauth@test-rcos.io"),
+            mmost: String::from("oauthuser"),
+            password_hash: phash,
+            active: true,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user");
+    let get_user = || -> User {
+        use crate::schema::users::dsl::*;
+        users.find(user.id).first(&conn).unwrap()
+    };
+    {
+        use crate::schema::users::dsl::*;
+        diesel::update(users.find(user.id))
+            .set(verified.eq(true))
+            .execute(&conn)
+            .unwrap();
+    }
+
+    let token = mock_response("200 OK", &[], r#"{"access_token": "mock-token"}"#);
+    let github_user = mock_response("200 OK", &[], r#"{"id": 1234, "login": "octocat"}"#);
+    let github_emails = mock_response(
+        "200 OK",
+        &[],
+        r#"[{"email": "octocat@example.com", "verified": false, "primary": true},
+            {"email": "oauth@test-rcos.io", "verified": true, "primary": false}]"#,
+    );
+    let sso_user = mock_response(
+        "200 OK",
+        &[],
+        r#"{"sub": "s-1", "email": "oauth@test-rcos.io", "email_verified": false}"#,
+    );
+    let requests = mock_server(
+        listener,
+        vec![
+            token.clone(),
+            github_user.clone(),
+            github_emails.clone(),
+            token.clone(),
+            github_user,
+            github_emails,
+            token.clone(),
+            sso_user.clone(),
+            token,
+            sso_user,
+        ],
+    );
+
+    // Starts logging in at a provider and returns the state
+    let start = |p: &str| {
+        let response = client
+            .get(format!("/login/oauth/{}?to=/dashboard", p))
+            .dispatch();
+        let location = response.headers().get_one("Location").unwrap();
+        assert!(location.starts_with(&format!("{}/authorize?response_type=code", url)));
+        assert!(location.contains("code_challenge_method=S256"));
+        let state = location.split("state=").nth(1).unwrap();
+        state.split('&').next().unwrap().to_string()
+    };
+    let callback = |p: &str, state: &str| {
+        let response = client
+            .get(format!(
+                "/login/oauth/{}/callback?code=abc&state={}",
+                p, state
+            ))
+            .dispatch();
+        response.headers().get_one("Location").map(String::from)
+    };
+
+    // The GitHub account is linked by its verified email
+    let state = start("github");
+    assert_eq!(callback("github", &state), Some(String::from("/dashboard")));
+    let request = requests.recv().unwrap();
+    assert!(request.starts_with("POST /token"));
+    assert!(request.contains("code=abc"));
+    assert!(request.contains("client_secret=hunter2"));
+    assert!(request.contains("code_verifier="));
+    let request = requests.recv().unwrap();
+    assert!(request.starts_with("GET /user "));
+    assert!(request.contains("Authorization: Bearer mock-token"));
+    assert!(requests.recv().unwrap().starts_with("GET /user/emails "));
+    assert_eq!(client.get("/dashboard").dispatch().status(), Status::Ok);
+
+    // Which verifies and locks the handle
+    let u = get_user();
+    assert_eq!(u.handle, "octocat");
+    assert!(u.handle_verified);
+    let edit = NewUser {
+        real_name: u.real_name.clone(),
+        handle: String::from("someoneelse"),
+        email: u.email.clone(),
+        mmost: u.mmost.clone(),
+        active: true,
+        ..NewUser::default()
+    };
+    save_user(&conn, &u, u.id, edit).expect("Failed to save user");
+    assert_eq!(get_user().handle, "octocat");
+
+    // The state has to match the one the browser started with
+    client.get("/logout").dispatch();
+    assert!(callback("github", "forged").unwrap().contains("e=oauth"));
+    assert!(requests.try_recv().is_err());
+
+    // The linked account logs in again
+    let state = start("github");
+    assert_eq!(callback("github", &state), Some(String::from("/dashboard")));
+    for _ in 0..3 {
+        requests.recv().unwrap();
+    }
+
+    // Emails the provider hasn't verified don't link accounts
+    client.get("/logout").dispatch();
+    let state = start("sso");
+    assert!(callback("sso", &state).unwrap().contains("e=noAccount"));
+    requests.recv().unwrap();
+    requests.recv().unwrap();
+
+    // But a logged in user can link it
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("email=oauth@test-rcos.io&password=password")
+        .dispatch();
+    let state = start("sso");
+    assert_eq!(
+        callback("sso", &state),
+        Some(format!("/users/{}/accounts", user.id))
+    );
+    let linked: Vec<OAuthIdentity> = OAuthIdentity::belonging_to(&user).load(&conn).unwrap();
+    assert_eq!(linked.len(), 2);
+    let mut response = client
+        .get(format!("/users/{}/accounts", user.id))
+        .dispatch();
+    assert!(response.body_string().unwrap().contains("octocat"));
+
+    // Unlinking GitHub unlocks the handle
+    let github = linked.iter().find(|a| a.provider == "github").unwrap();
+    client
+        .delete(format!("/users/{}/accounts/{}", user.id, github.id))
+        .dispatch();
+    assert!(!get_user().handle_verified);
+
+    cleanup(String::from("test_oauth_login"));
+}
+
+// Tests that failed logins are throttled without giving away who has an account
+#[test]
+fn login_throttling() {
+    use crate::auth::throttle::*;
+    use crate::settings::Settings;
+    use rocket::http::ContentType;
+
+    let mut config = setup(String::from("test_login_throttling")).unwrap();
+    config
+        .extras
+        .insert("login_attempts".into(), Value::from(2));
+    config
+        .extras
+        .insert("login_ip_attempts".into(), Value::from(4));
+
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in LoginThrottlingTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let phash = hash_password("password");
+    create_user(
+        &conn,
+        NewUser {
+            real_name: String::from("Throttled User"),
+            handle: String::from("throttled"),
+            email: String::from("throttled@test-rcos.io"),
+            mmost: String::from("throttled"),
+            password_hash: phash,
+            active: true,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user");
+
+    let login = |email: &str, password: &str, ip: &str| {
+        let response = client
+            .post("/login")
+            .header(ContentType::Form)
+            .remote(format!("{}:4000", ip).parse().unwrap())
+            .body(format!("email={}&password={}", email, password))
+            .dispatch();
+        response.headers().get_one("Location").unwrap().to_string()
+    };
+
+    // A wrong email and a wrong password look the same
+    let wrong_email = login("nobody@test-rcos.io", "password", "10.0.0.1");
+    let wrong_password = login("throttled@test-rcos.io", "wrong", "10.0.0.1");
+    assert!(wrong_email.ends_with("e=credentials"));
+    assert_eq!(wrong_email, wrong_password);
+
+    // The account has to wait, even with the right password
+    assert!(login("throttled@test-rcos.io", "wrong", "10.0.0.1").ends_with("e=credentials"));
+    assert!(login("Throttled@test-rcos.io", "password", "10.0.0.1").ends_with("e=locked"));
+    {
+        use crate::schema::audit_events::dsl::*;
+        let failed: i64 = audit_events
+            .filter(action.eq("failed-login"))
+            .count()
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(failed, 3);
+    }
+
+    // Once the wait is over logging in works and clears the failures
+    {
+        use crate::schema::login_attempts::dsl::*;
+        let earlier = chrono::offset::Utc::now().naive_utc() - chrono::Duration::minutes(1);
+        diesel::update(login_attempts)
+            .set(attempted_at.eq(earlier))
+            .execute(&conn)
+            .unwrap();
+    }
+    assert_eq!(login("throttled@test-rcos.io", "password", "10.0.0.1"), "/");
+    client.get("/logout").dispatch();
+
+    // Failures from every IP address count against the account
+    login("throttled@test-rcos.io", "wrong", "10.0.0.2");
+    login("throttled@test-rcos.io", "wrong", "10.0.0.3");
+    assert!(login("throttled@test-rcos.io", "password", "10.0.0.4").ends_with("e=locked"));
+    {
+        use crate::schema::login_attempts::dsl::*;
+        let earlier = chrono::offset::Utc::now().naive_utc() - chrono::Duration::minutes(1);
+        diesel::update(login_attempts)
+            .set(attempted_at.eq(earlier))
+            .execute(&conn)
+            .unwrap();
+    }
+    assert_eq!(login("throttled@test-rcos.io", "password", "10.0.0.4"), "/");
+    client.get("/logout").dispatch();
+
+    // An IP address guessing many accounts has to wait too
+    for n in 0..3 {
+        login(&format!("guess{}@test-rcos.io", n), "password", "10.0.0.1");
+    }
+    assert!(login("throttled@test-rcos.io", "password", "10.0.0.1").ends_with("e=locked"));
+    assert_eq!(login("throttled@test-rcos.io", "password", "10.0.0.4"), "/");
+
+    // The wait doubles with every failure up to the lockout
+    let settings = Settings::default();
+    let now = chrono::offset::Utc::now().naive_utc();
+    for _ in 0..settings.login_attempts + 10 {
+        record_failure(&conn, "locked@test-rcos.io", None, now);
+    }
+    let until = locked_until(&conn, &settings, "locked@test-rcos.io", None, now).unwrap();
+    assert_eq!(
+        until,
+        now + chrono::Duration::minutes(settings.login_lockout_minutes)
+    );
+    record_failure(&conn, "doubled@test-rcos.io", None, now);
+    let doubled = |n| {
+        for _ in 0..n {
+            record_failure(&conn, "doubled@test-rcos.io", None, now);
+        }
+        locked_until(&conn, &settings, "doubled@test-rcos.io", None, now).map(|u| u - now)
+    };
+    assert_eq!(doubled(settings.login_attempts - 2), None);
+    let first = doubled(1).unwrap();
+    assert_eq!(first.num_seconds(), settings.login_backoff_seconds);
+    assert_eq!(doubled(1), Some(first * 2));
+
+    cleanup(String::from("test_login_throttling"));
+}
+
+#[test]
+fn password_hashing() {
+    use ring::pbkdf2;
+    use rocket::http::ContentType;
+    use std::num::NonZeroU32;
+
+    let hash = hash_password("password");
+    assert!(hash.starts_with("$argon2id$"));
+    assert!(verify_password("password", &hash));
+    assert!(!verify_password("wrong", &hash));
+    assert!(!needs_rehash(&hash));
+    // The Admin can't log in with a password
+    assert!(!verify_password("", ""));
+
+    // A hash from before Argon2id, as moved into the PHC format by the migration
+    let salt = [7u8; 32];
+    let mut out = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA512,
+        NonZeroU32::new(100_000).unwrap(),
+        &salt,
+        b"password",
+        &mut out,
+    );
+    let hex = |b: &[u8]| b.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    let legacy = format!("$pbkdf2-sha512$i=100000${}${}", hex(&salt), hex(&out));
+    assert!(verify_password("password", &legacy));
+    assert!(!verify_password("wrong", &legacy));
+    assert!(needs_rehash(&legacy));
+
+    let client = Client::new(rocket(setup(String::from("test_password_hashing")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in PasswordHashingTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let user = create_user(
+        &conn,
+        NewUser {
+            real_name: String::from("Legacy User"),
+            handle: String::from("legacy"),
+            email: String::from("legacy@test-rcos.io"),
+            mmost: String::from("legacy"),
+            password_hash: legacy.clone(),
+            active: true,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user");
+
+    let login = |password: &str| {
+        let response = client
+            .post("/login")
+            .header(ContentType::Form)
+            .body(format!("email=legacy@test-rcos.io&password={}", password))
+            .dispatch();
+        response.headers().get_one("Location").unwrap().to_string()
+    };
+    let stored = || -> String {
+        use crate::schema::users::dsl::*;
+        users
+            .find(user.id)
+            .select(password_hash)
+            .first(&conn)
+            .unwrap()
+    };
+
+    // A failed login leaves the hash alone
+    assert!(login("wrong").ends_with("e=credentials"));
+    assert_eq!(stored(), legacy);
+
+    // Logging in replaces it with an Argon2id hash
+    assert_eq!(login("password"), "/");
+    let upgraded = stored();
+    assert!(upgraded.starts_with("$argon2id$"));
+    assert!(verify_password("password", &upgraded));
+    client.get("/logout").dispatch();
+    assert_eq!(login("password"), "/");
+    assert_eq!(stored(), upgraded);
+
+    cleanup(String::from("test_password_hashing"));
+}
+
+// Tests the permission matrix and giving roles for a semester
+#[test]
+fn roles() {
+    use crate::semesters::handlers::*;
+    use crate::semesters::models::NewSemester;
+    use chrono::{Duration, Utc};
+    use rocket::http::ContentType;
+
+    assert!(!Role::Student.can(Permission::RunMeetings));
+    assert!(Role::Mentor.can(Permission::RunMeetings));
+    assert!(!Role::Mentor.can(Permission::ManageNews));
+    assert!(Role::Coordinator.can(Permission::AssignRoles));
+    assert!(!Role::Coordinator.can(Permission::ManageUsers));
+    assert!(Permission::ALL.iter().all(|p| Role::Admin.can(*p)));
+    assert_eq!("coordinator".parse(), Ok(Role::Coordinator));
+    assert_eq!(Role::Admin.to_string(), "Administrator");
+
+    let client = Client::new(rocket(setup(String::from("test_roles")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in RolesTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let today = Utc::now().naive_utc().date();
+    let semester = |n: &str, start, end| {
+        create_semester(
+            &conn,
+            Ok(NewSemester {
+                name: String::from(n),
+                start,
+                end,
+            }),
+        )
+        .expect("Failed to create semester")
+    };
+    let current = semester(
+        "Current",
+        today - Duration::days(30),
+        today + Duration::days(30),
+    );
+    let next = semester(
+        "Next",
+        today + Duration::days(60),
+        today + Duration::days(150),
+    );
+
+    let mentor = make_user(&conn, "mentor", Role::Mentor);
+    let student = make_user(&conn, "student", Role::Student);
+    for u in &[&mentor, &student] {
+        enroll(&conn, current.id, u.id);
+        enroll(&conn, next.id, u.id);
+    }
+    let role_of = |uid: i32| -> Role {
+        use crate::schema::users::dsl::*;
+        users.find(uid).select(role).first(&conn).unwrap()
+    };
+
+    login(&client, "mentor");
+    assert_eq!(
+        client.get("/news/new").dispatch().status(),
+        Status::Forbidden
+    );
+
+    // A role for the current semester is given right away and logs them out
+    assert!(set_semester_role(
+        &conn,
+        &current,
+        mentor.id,
+        Some(Role::Coordinator),
+        &crate::tz::Tz::utc()
+    ));
+    assert_eq!(role_of(mentor.id), Role::Coordinator);
+    assert_ne!(client.get("/news/new").dispatch().status(), Status::Ok);
+    login(&client, "mentor");
+    assert_eq!(client.get("/news/new").dispatch().status(), Status::Ok);
+
+    // Unlike Admins, Coordinators can't manage the accounts of others
+    let sessions = format!("/users/{}/sessions", student.id);
+    assert_eq!(client.get(sessions).dispatch().status(), Status::Forbidden);
+
+    // A role for the next semester waits for the rollover
+    assert!(set_semester_role(
+        &conn,
+        &next,
+        student.id,
+        Some(Role::Mentor),
+        &crate::tz::Tz::utc()
+    ));
+    assert!(!set_semester_role(
+        &conn,
+        &next,
+        1000,
+        Some(Role::Mentor),
+        &crate::tz::Tz::utc()
+    ));
+    assert_eq!(role_of(student.id), Role::Student);
+    assert_eq!(
+        semester_roles(&conn, &next)
+            .into_iter()
+            .map(|(u, r)| (u.id, r))
+            .collect::<Vec<_>>(),
+        vec![(mentor.id, None), (student.id, Some(Role::Mentor))]
+    );
+    rollover(&conn, &current).expect("Failed to roll over semester");
+    assert_eq!(role_of(student.id), Role::Mentor);
+    assert_eq!(role_of(mentor.id), Role::Coordinator);
+
+    // Coordinators can't make anyone an Admin
+    let response = client
+        .put(format!("/semesters/{}/enroll/{}", next.id, student.id))
+        .header(ContentType::Form)
+        .body("role=admin")
+        .dispatch();
+    assert_ne!(response.status(), Status::Ok);
+    assert_eq!(role_of(student.id), Role::Mentor);
+
+    cleanup(String::from("test_roles"));
+}
+
+#[test]
+fn audit() {
+    use crate::audit::handlers::find_events;
+    use crate::audit::models::AuditFilter;
+    use rocket::http::ContentType;
+
+    let client = Client::new(rocket(setup(String::from("test_audit")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in AuditTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let coordinator = make_user(&conn, "coordinator", Role::Coordinator);
+    make_user(&conn, "student", Role::Student);
+
+    login(&client, "coordinator");
+    let story = "happened_at=2021-03-01+12:00&description=Hello&announcement=false";
+    client
+        .post("/news/new")
+        .header(ContentType::Form)
+        .body(format!("title=Welcome&{}", story))
+        .dispatch();
+    let nid: i32 = {
+        use crate::schema::news::dsl::*;
+        news.select(id).first(&conn).unwrap()
+    };
+    client
+        .put(format!("/news/{}", nid))
+        .header(ContentType::Form)
+        .body(format!("title=Welcome+Back&{}", story))
+        .dispatch();
+    client.delete(format!("/news/{}", nid)).dispatch();
+
+    let search = |filter: AuditFilter| {
+        find_events(&conn, &filter.clean(), &crate::tz::Tz::utc(), None)
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect::<Vec<_>>()
+    };
+    let events = search(AuditFilter {
+        target: Some(String::from("news")),
+        ..AuditFilter::default()
+    });
+    assert_eq!(
+        events.iter().map(|e| e.action.as_str()).collect::<Vec<_>>(),
+        vec!["delete", "update", "create"]
+    );
+    assert!(events
+        .iter()
+        .all(|e| e.actor_id == Some(coordinator.id) && e.target_id == Some(nid)));
+    // Only the title changed
+    assert_eq!(
+        events[1].changes(),
+        vec![(
+            String::from("title"),
+            String::from("Welcome"),
+            String::from("Welcome Back")
+        )]
+    );
+
+    let filtered = search(AuditFilter {
+        actor: Some(coordinator.id),
+        action: Some(String::from("update")),
+        from: Some(String::new()),
+        ..AuditFilter::default()
+    });
+    assert_eq!(filtered.len(), 1);
+    assert!(search(AuditFilter {
+        to: Some(String::from("2000-01-01")),
+        ..AuditFilter::default()
+    })
+    .is_empty());
+
+    let mut response = client
+        .get("/audit.json?target=news&action=create")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let json: serde_json::Value = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+    assert_eq!(json[0]["new_values"]["title"], "Welcome");
+    assert_eq!(json.as_array().map(|a| a.len()), Some(1));
+    assert_eq!(client.get("/audit").dispatch().status(), Status::Ok);
+
+    client.get("/logout").dispatch();
+    login(&client, "student");
+    assert_eq!(client.get("/audit").dispatch().status(), Status::Forbidden);
+
+    cleanup(String::from("test_audit"));
+}
+
+#[test]
+fn trash() {
+    use crate::projects::handlers::{create_project, project_users};
+    use crate::trash::handlers::{purge_expired, trashed};
+    use crate::trash::models::Kind;
+    use chrono::{Duration, Utc};
+
+    let client = Client::new(rocket(setup(String::from("test_trash")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in TrashTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    make_user(&conn, "manager", Role::Admin);
+    let student = make_user(&conn, "student", Role::Student);
+    let project = create_project(
+        &conn,
+        &student,
+        NewProject {
+            name: String::from("Trashed"),
+            description: String::from("Deleted by mistake"),
+            repos: String::from("[]"),
+            ..NewProject::default()
+        },
+    )
+    .expect("Failed to create project");
+
+    // Students can't see the trash
+    login(&client, "student");
+    assert_eq!(client.get("/trash").dispatch().status(), Status::Forbidden);
+
+    // Deleting hides things but keeps them and their members
+    login(&client, "manager");
+    client.delete(format!("/users/{}", student.id)).dispatch();
+    client
+        .delete(format!("/projects/{}", project.id))
+        .dispatch();
+    assert_eq!(
+        client
+            .get(format!("/users/{}", student.id))
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+    assert_eq!(
+        client
+            .get(format!("/projects/{}", project.id))
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+    assert!(filter_users(&conn, None, Some(true))
+        .iter()
+        .all(|u| u.id != student.id));
+    {
+        use crate::schema::relation_project_user::dsl::*;
+        let members: i64 = relation_project_user
+            .filter(project_id.eq(project.id))
+            .count()
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(members, 1);
+    }
+    assert_eq!(
+        trashed(&conn)
+            .iter()
+            .map(|t| (t.kind, t.id))
+            .collect::<Vec<_>>(),
+        vec![(Kind::Project, project.id), (Kind::User, student.id)]
+    );
+    assert_eq!(client.get("/trash").dispatch().status(), Status::Ok);
+
+    // Users in the trash can't log in
+    client.get("/logout").dispatch();
+    login(&client, "student");
+    assert_ne!(client.get("/dashboard").dispatch().status(), Status::Ok);
+
+    // Restoring brings the user back to the project
+    login(&client, "manager");
+    let response = client.put(format!("/trash/user/{}", student.id)).dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    let response = client.put(format!("/trash/user/{}", student.id)).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    client
+        .put(format!("/trash/project/{}", project.id))
+        .dispatch();
+    assert_eq!(
+        client
+            .get(format!("/projects/{}", project.id))
+            .dispatch()
+            .status(),
+        Status::Ok
+    );
+    assert_eq!(project_users(&conn, &project), vec![student.clone()]);
+
+    // Only what has been in the trash too long is purged
+    client
+        .delete(format!("/projects/{}", project.id))
+        .dispatch();
+    let now = Utc::now().naive_utc();
+    assert_eq!(purge_expired(&conn, 30, now + Duration::days(29)), 0);
+    assert_eq!(purge_expired(&conn, 30, now + Duration::days(31)), 1);
+    assert!(trashed(&conn).is_empty());
+    {
+        use crate::schema::projects::dsl::*;
+        let left: i64 = projects.count().get_result(&conn).unwrap();
+        assert_eq!(left, 0);
+    }
+
+    // Purging a user takes their attendances with them
+    {
+        use crate::schema::meetings::dsl::*;
+        insert_into(meetings)
+            .values((
+                happened_at.eq(now),
+                code.eq("purged"),
+                group_id.eq(0),
+                hosted_by.eq(0),
+                code_expires.eq(now),
+            ))
+            .execute(&conn)
+            .expect("Failed to add meeting to database");
+    }
+    {
+        use crate::attend::models::NewAttendance;
+        use crate::schema::attendances::dsl::*;
+        insert_into(attendances)
+            .values(&NewAttendance {
+                user_id: student.id,
+                is_event: false,
+                meeting_id: Some(crate::api::v1::last_id(&conn)),
+                event_id: None,
+                approved: false,
+            })
+            .execute(&conn)
+            .expect("Failed to add attendance to database");
+    }
+    client.delete(format!("/users/{}", student.id)).dispatch();
+    assert_eq!(purge_expired(&conn, 30, now + Duration::days(31)), 1);
+    {
+        use crate::schema::attendances::dsl::*;
+        let left: i64 = attendances.count().get_result(&conn).unwrap();
+        assert_eq!(left, 0);
+    }
+    assert_eq!(
+        client.get("/attend/pending").dispatch().status(),
+        Status::Ok
+    );
+
+    cleanup(String::from("test_trash"));
+}
+
+// Tests asking to join projects and being invited to them
+#[test]
+fn project_requests() {
+    use crate::projects::handlers::{create_project, pending_request, project_users};
+    use rocket::http::ContentType;
+
+    let mut config = setup(String::from("test_project_requests")).unwrap();
+    let mail_dir = "./test_project_requests/mail";
+    config.extras.insert("mailer".into(), Value::from("file"));
+    config
+        .extras
+        .insert("mail_dir".into(), Value::from(mail_dir));
+
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in ProjectRequestsTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let owner = make_user(&conn, "owner", Role::Student);
+    let joiner = make_user(&conn, "joiner", Role::Student);
+    let invitee = make_user(&conn, "invitee", Role::Student);
+    let project = create_project(
+        &conn,
+        &owner,
+        NewProject {
+            name: String::from("Requested"),
+            description: String::from("Ask first"),
+            repos: String::from("[]"),
+            ..NewProject::default()
+        },
+    )
+    .expect("Failed to create project");
+    assert_eq!(project.joining, Joining::Request);
+
+    let answer = |rid: i32, accept: bool| {
+        client
+            .put(format!("/projects/{}/requests/{}", project.id, rid))
+            .header(ContentType::Form)
+            .body(format!("accept={}", accept))
+            .dispatch()
+            .status()
+    };
+    let join = || {
+        client
+            .post(format!("/projects/{}/members/join", project.id))
+            .dispatch()
+            .status()
+    };
+    // Has an email with this in it been sent to the user?
+    let emailed = |u: &User, text: &str| {
+        fs::read_dir(mail_dir)
+            .expect("Failed to read mail directory")
+            .map(|f| fs::read_to_string(f.unwrap().path()).unwrap())
+            .any(|m| m.contains(&format!("To: {}", u.email)) && m.contains(text))
+    };
+    let members = || project_users(&conn, &project);
+
+    // Asking to join waits for the owner
+    login(&client, "joiner");
+    assert_eq!(join(), Status::SeeOther);
+    assert_eq!(join(), Status::SeeOther);
+    let request = pending_request(&conn, project.id, joiner.id).expect("No request was made");
+    assert!(!request.invite);
+    assert!(!members().contains(&joiner));
+    assert!(emailed(&owner, "joiner asked to join Requested"));
+    {
+        use crate::schema::project_requests::dsl::*;
+        let count: i64 = project_requests.count().get_result(&conn).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    // Only the owner can answer it
+    assert_eq!(answer(request.id, true), Status::Forbidden);
+    login(&client, "owner");
+    let dashboard = client.get("/dashboard").dispatch().body_string().unwrap();
+    assert!(dashboard.contains("asked to join"));
+    assert_eq!(answer(request.id, true), Status::SeeOther);
+    assert_eq!(answer(request.id, false), Status::Conflict);
+    assert!(members().contains(&joiner));
+    assert!(emailed(
+        &joiner,
+        "Your request to join Requested was accepted"
+    ));
+
+    // Invites wait for the user
+    client
+        .post(format!("/projects/{}/members/add", project.id))
+        .header(ContentType::Form)
+        .body(format!("uid={}", invitee.id))
+        .dispatch();
+    let invite = pending_request(&conn, project.id, invitee.id).expect("No invite was made");
+    assert!(invite.invite);
+    assert!(!members().contains(&invitee));
+    assert!(emailed(&invitee, "You're invited to join Requested"));
+    assert_eq!(answer(invite.id, true), Status::Forbidden);
+
+    login(&client, "invitee");
+    // Someone outside the project is denied, not sent to log in again
+    let edit = format!("/projects/{}/edit", project.id);
+    assert_eq!(client.get(edit).dispatch().status(), Status::Forbidden);
+    assert_eq!(answer(invite.id, false), Status::SeeOther);
+    assert!(!members().contains(&invitee));
+    assert!(pending_request(&conn, project.id, invitee.id).is_none());
+    assert!(emailed(&owner, "invitee declined your invite to Requested"));
+
+    // Closed projects can't be asked to join and open ones are joined at once
+    let set_joining = |j: Joining| {
+        use crate::schema::projects::dsl::*;
+        diesel::update(projects.find(project.id))
+            .set(joining.eq(j))
+            .execute(&conn)
+            .unwrap();
+    };
+    set_joining(Joining::Closed);
+    assert_eq!(join(), Status::Forbidden);
+    set_joining(Joining::Open);
+    assert_eq!(join(), Status::SeeOther);
+    assert!(members().contains(&invitee));
+
+    fs::remove_dir_all(mail_dir).expect("Failed to remove mail directory");
+    cleanup(String::from("test_project_requests"));
+}
+
+// Tests project roles and handing projects over
+#[test]
+fn project_roles() {
+    use crate::projects::handlers::{add_member, create_project, member_role};
+    use rocket::http::ContentType;
+
+    let client = Client::new(rocket(setup(String::from("test_project_roles")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in ProjectRolesTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let owner = make_user(&conn, "owner", Role::Student);
+    let dev = make_user(&conn, "dev", Role::Student);
+    let mentor = make_user(&conn, "mentor", Role::Student);
+    let project = create_project(
+        &conn,
+        &owner,
+        NewProject {
+            name: String::from("Roles"),
+            description: String::from("Who does what"),
+            repos: String::from("[]"),
+            ..NewProject::default()
+        },
+    )
+    .expect("Failed to create project");
+    add_member(&conn, &project, dev.id, ProjectRole::Developer);
+    add_member(&conn, &project, mentor.id, ProjectRole::Mentor);
+    assert_eq!(
+        member_role(&conn, project.id, owner.id),
+        Some(ProjectRole::Lead)
+    );
+
+    let form = |put: bool, url: String, body: String| {
+        if put {
+            client.put(url)
+        } else {
+            client.post(url)
+        }
+        .header(ContentType::Form)
+        .body(body)
+        .dispatch()
+        .status()
+    };
+    let edit_page = || {
+        client
+            .get(format!("/projects/{}/edit", project.id))
+            .dispatch()
+            .status()
+    };
+    let get_project = || -> Project {
+        use crate::schema::projects::dsl::*;
+        projects.find(project.id).first(&conn).unwrap()
+    };
+
+    // Developers can't edit the project
+    login(&client, "dev");
+    assert_ne!(edit_page(), Status::Ok);
+    let role_url = |u: &User| format!("/projects/{}/members/{}", project.id, u.id);
+    assert_eq!(
+        form(true, role_url(&dev), String::from("role=lead")),
+        Status::Forbidden
+    );
+
+    // The owner makes them a lead who then can
+    login(&client, "owner");
+    assert_eq!(
+        form(true, role_url(&dev), String::from("role=lead")),
+        Status::SeeOther
+    );
+    assert_eq!(
+        form(true, role_url(&owner), String::from("role=developer")),
+        Status::Conflict
+    );
+    login(&client, "dev");
+    assert_eq!(edit_page(), Status::Ok);
+    form(
+        true,
+        format!("/projects/{}", project.id),
+        format!(
+            "name=Roles&description=Changed&repos=%5B%5D&owner_id={}",
+            dev.id
+        ),
+    );
+    assert_eq!(get_project().description, "Changed");
+    assert_eq!(get_project().owner_id, owner.id);
+
+    // Leads can't remove the owner or hand the project over
+    assert_eq!(
+        client.delete(role_url(&owner)).dispatch().status(),
+        Status::Conflict
+    );
+    let owner_url = format!("/projects/{}/owner", project.id);
+    assert_eq!(
+        form(false, owner_url.clone(), format!("uid={}", dev.id)),
+        Status::Forbidden
+    );
+
+    // The owner can, and only to members
+    login(&client, "owner");
+    let outsider = make_user(&conn, "outsider", Role::Student);
+    assert_eq!(
+        form(false, owner_url.clone(), format!("uid={}", outsider.id)),
+        Status::Conflict
+    );
+    assert_eq!(
+        form(false, owner_url, format!("uid={}", dev.id)),
+        Status::SeeOther
+    );
+    assert_eq!(get_project().owner_id, dev.id);
+    assert_eq!(
+        member_role(&conn, project.id, owner.id),
+        Some(ProjectRole::Lead)
+    );
+
+    // Members can leave
+    login(&client, "mentor");
+    assert_eq!(
+        client.delete(role_url(&mentor)).dispatch().status(),
+        Status::SeeOther
+    );
+    assert_eq!(member_role(&conn, project.id, mentor.id), None);
+
+    cleanup(String::from("test_project_roles"));
+}
+
+#[test]
+fn recurring_events() {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use rocket::http::ContentType;
+
+    let client = Client::new(rocket(setup(String::from("test_recurring_events")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in RecurringEventsTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let host = create_user(
+        &conn,
+        NewUser {
+            real_name: String::from("coordinator"),
+            handle: String::from("coordinator"),
+            email: String::from("coordinator@test-rcos.io"),
+            mmost: String::from("coordinator"),
+            password_hash: hash_password("password"),
+            active: true,
+            role: Role::Coordinator,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user");
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("email=coordinator@test-rcos.io&password=password")
+        .dispatch();
+
+    let occurrences = || -> Vec<Event> {
+        use crate::schema::events::dsl::*;
+        events
+            .filter(series_id.is_not_null())
+            .filter(deleted_at.is_null())
+            .order(start.asc())
+            .load(&conn)
+            .expect("Failed to get events")
+    };
+    let at = |d: u32, h: u32| -> NaiveDateTime { NaiveDate::from_ymd(2021, 3, d).and_hms(h, 0, 0) };
+    let form = |url: String, body: String| {
+        client
+            .put(url)
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+            .status()
+    };
+
+    // Mondays and Thursdays for three weeks, skipping the 11th
+    let status = client
+        .post("/calendar/new")
+        .header(ContentType::Form)
+        .body(format!(
+            "title=Large+Group&start=2021-03-01T16:00&end=2021-03-01T18:00&hosted_by={}\
+             &code=&color=red&mo=on&th=on&until=2021-03-18&except=2021-03-11",
+            host.id
+        ))
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::SeeOther);
+    let evts = occurrences();
+    assert_eq!(
+        evts.iter().map(|e| e.start).collect::<Vec<_>>(),
+        vec![at(1, 16), at(4, 16), at(8, 16), at(15, 16), at(18, 16)]
+    );
+    assert!(evts.iter().all(|e| e.original_start == Some(e.start)));
+    let mut codes: Vec<&String> = evts.iter().map(|e| &e.code).collect();
+    codes.sort();
+    codes.dedup();
+    assert_eq!(codes.len(), 5);
+
+    // Changing one occurrence leaves the rest of the series alone
+    let moved = &evts[2];
+    assert_eq!(
+        form(
+            format!("/calendar/{}", moved.id),
+            format!(
+                "title=Moved&start=2021-03-09T17:00&end=2021-03-09T19:00&hosted_by={}&code=",
+                host.id
+            ),
+        ),
+        Status::SeeOther
+    );
+    let evts = occurrences();
+    assert_eq!(evts[2].start, at(9, 17));
+    assert_eq!(evts[2].original_start, Some(at(8, 16)));
+    assert!(evts[2].detached);
+    assert!(evts
+        .iter()
+        .filter(|e| e.id != moved.id)
+        .all(|e| e.title == "Large Group" && !e.detached));
+
+    // Deleting one occurrence only deletes that one
+    client
+        .delete(format!("/calendar/{}", evts[4].id))
+        .dispatch();
+    assert_eq!(occurrences().len(), 4);
+
+    let ics = client
+        .get("/calendar.ics")
+        .dispatch()
+        .body_string()
+        .expect("Failed to get calendar");
+    assert_eq!(ics.matches("RRULE:FREQ=WEEKLY").count(), 1);
+    assert_eq!(ics.matches("EXDATE").count(), 2);
+    assert!(ics.contains("RECURRENCE-ID"));
+    assert!(ics.contains("SUMMARY:Moved"));
+
+    // Changing the whole series changes every occurrence
+    assert_eq!(
+        form(
+            format!("/calendar/{}", evts[1].id),
+            format!(
+                "title=Renamed&start=2021-03-04T15:00&end=2021-03-04T16:00&hosted_by={}\
+                 &code=&th=on&until=2021-03-18&whole_series=on",
+                host.id
+            ),
+        ),
+        Status::SeeOther
+    );
+    let evts = occurrences();
+    assert_eq!(
+        evts.iter().map(|e| e.start).collect::<Vec<_>>(),
+        vec![at(4, 15), at(11, 15)]
+    );
+    assert!(evts.iter().all(|e| e.title == "Renamed" && !e.detached));
+    assert_eq!(evts[1].end, at(11, 16));
+
+    cleanup(String::from("test_recurring_events"));
+}
+
+#[test]
+fn group_schedules() {
+    use crate::groups::handlers::schedule_meetings;
+    use crate::settings::Settings;
+    use chrono::{Datelike, NaiveDate, Utc, Weekday};
+    use rocket::http::ContentType;
+
+    let client = Client::new(rocket(setup(String::from("test_group_schedules")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in GroupSchedulesTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let mentor = make_user(&conn, "mentor", Role::Mentor);
+    let student = make_user(&conn, "student", Role::Student);
+    let settings = Settings::default();
+
+    // Bad schedules are turned away
+    assert!(create_group(
+        &conn,
+        &NewGroup {
+            name: String::from("Unscheduled"),
+            owner_id: mentor.id,
+            meeting_day: Some(String::from("someday")),
+            meeting_time: Some(String::from("16:00")),
+            ..NewGroup::default()
+        },
+    )
+    .is_err());
+
+    let gid = create_group(
+        &conn,
+        &NewGroup {
+            name: String::from("Thursday Group"),
+            owner_id: mentor.id,
+            location: Some(String::from("DCC 318")),
+            meeting_day: Some(String::from("th")),
+            meeting_time: Some(String::from("16:00")),
+            ..NewGroup::default()
+        },
+    )
+    .expect("Failed to create group");
+    {
+        use crate::schema::relation_group_user::dsl::*;
+        insert_into(relation_group_user)
+            .values(&NewRelationGroupUser {
+                group_id: gid,
+                user_id: student.id,
+            })
+            .execute(&conn)
+            .expect("Failed to add user to group");
+    }
+    let get_group = || -> Group {
+        use crate::schema::groups::dsl::*;
+        groups.find(gid).first(&conn).expect("Failed to get group")
+    };
+    let group_meetings = || -> Vec<Meeting> {
+        use crate::schema::meetings::dsl::*;
+        meetings
+            .filter(group_id.eq(gid))
+            .order(happened_at.asc())
+            .load(&conn)
+            .expect("Failed to get meetings")
+    };
+    let needed = || grade_summary(&conn, &student, None, &settings.site_zone()).needed_attendances;
+
+    // Meetings are created for the coming weeks, once
+    let monday = NaiveDate::from_ymd(2021, 3, 1).and_hms(12, 0, 0);
+    let created = schedule_meetings(&conn, &get_group(), &settings, monday);
+    assert_eq!(
+        created.iter().map(|m| m.happened_at).collect::<Vec<_>>(),
+        vec![4, 11, 18, 25]
+            .into_iter()
+            .map(|d| NaiveDate::from_ymd(2021, 3, d).and_hms(16, 0, 0))
+            .collect::<Vec<_>>()
+    );
+    assert!(created
+        .iter()
+        .all(|m| m.hosted_by == mentor.id && m.location.as_deref() == Some("DCC 318")));
+    assert!(schedule_meetings(&conn, &get_group(), &settings, monday).is_empty());
+    assert_eq!(needed(), 4);
+    {
+        use crate::schema::meetings::dsl::*;
+        delete(meetings.filter(group_id.eq(gid)))
+            .execute(&conn)
+            .expect("Failed to delete meetings");
+    }
+
+    // Meetings that haven't happened yet aren't needed
+    let now = Utc::now().naive_utc();
+    assert_eq!(
+        schedule_meetings(&conn, &get_group(), &settings, now).len(),
+        4
+    );
+    assert_eq!(needed(), 0);
+
+    // Changing the schedule moves the meetings that haven't happened yet
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("email=mentor@test-rcos.io&password=password")
+        .dispatch();
+    let status = client
+        .put(format!("/groups/{}", gid))
+        .header(ContentType::Form)
+        .body(format!(
+            "name=Monday+Group&owner_id={}&location=DCC+324&meeting_day=mo&meeting_time=10:00",
+            mentor.id
+        ))
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::SeeOther);
+    let upcoming = group_meetings();
+    assert_eq!(upcoming.len(), 4);
+    assert!(upcoming.iter().all(
+        |m| m.happened_at.weekday() == Weekday::Mon && m.location.as_deref() == Some("DCC 324")
+    ));
+
+    // Fields left out of an API edit aren't changed
+    let status = client
+        .put(format!("/api/v1/groups/{}", gid))
+        .header(ContentType::JSON)
+        .body(r#"{"location": "DCC 308"}"#)
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::Ok);
+    let edited = get_group();
+    assert_eq!(edited.name, "Monday Group");
+    assert_eq!(edited.owner_id, mentor.id);
+    assert_eq!(edited.location.as_deref(), Some("DCC 308"));
+    assert_eq!(
+        edited.schedule_name().as_deref(),
+        Some("Mondays at 10:00 AM")
+    );
+
+    // Extra meetings are hosted by whoever started them and are needed
+    client
+        .post(format!("/groups/{}/meetings/new", gid))
+        .dispatch();
+    let extra = group_meetings()
+        .into_iter()
+        .find(|m| m.happened_at <= Utc::now().naive_utc())
+        .expect("Failed to start a meeting");
+    assert_eq!(extra.hosted_by, mentor.id);
+    assert_eq!(needed(), 1);
+
+    // Cancelling a meeting excuses everyone and closes its code
+    let status = client
+        .put(format!("/groups/{}/meetings/{}/cancel", gid, extra.id))
+        .header(ContentType::Form)
+        .body("cancelled=true")
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::SeeOther);
+    assert_eq!(needed(), 0);
+    let cancelled = group_meetings()
+        .into_iter()
+        .find(|m| m.id == extra.id)
+        .expect("Failed to get meeting");
+    assert!(!crate::attend::code::code_is_open(
+        &cancelled,
+        Utc::now().naive_utc()
+    ));
+
+    // Students can't cancel meetings
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("email=student@test-rcos.io&password=password")
+        .dispatch();
+    let status = client
+        .put(format!("/groups/{}/meetings/{}/cancel", gid, extra.id))
+        .header(ContentType::Form)
+        .body("cancelled=false")
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::Forbidden);
+
+    cleanup(String::from("test_group_schedules"));
+}
+
+#[test]
+fn time_zones() {
+    use crate::tz::{self, Tz};
+    use chrono::{NaiveDate, NaiveDateTime};
+    use rocket::http::ContentType;
+
+    // Daylight saving time in New York, from the transitions in the
+    // database and from the rule after them
+    let ny = tz::zone("America/New_York").expect("Failed to load time zone");
+    let at = |y: i32, m: u32, d: u32, h: u32, min: u32| -> NaiveDateTime {
+        NaiveDate::from_ymd(y, m, d).and_hms(h, min, 0)
+    };
+    for y in &[2021, 2040] {
+        assert_eq!(
+            ny.offset(&at(*y, 1, 15, 12, 0)).local_minus_utc(),
+            -5 * 3600
+        );
+        assert_eq!(
+            ny.offset(&at(*y, 7, 15, 12, 0)).local_minus_utc(),
+            -4 * 3600
+        );
+    }
+    assert_eq!(ny.to_local(&at(2021, 3, 14, 6, 59)), at(2021, 3, 14, 1, 59));
+    assert_eq!(ny.to_local(&at(2021, 3, 14, 7, 0)), at(2021, 3, 14, 3, 0));
+    assert_eq!(ny.to_utc(&at(2021, 7, 4, 12, 0)), at(2021, 7, 4, 16, 0));
+    // Skipped times move forward and repeated times are the first of the two
+    assert_eq!(ny.to_utc(&at(2021, 3, 14, 2, 30)), at(2021, 3, 14, 7, 30));
+    assert_eq!(ny.to_utc(&at(2021, 11, 7, 1, 30)), at(2021, 11, 7, 5, 30));
+    assert_eq!(ny.to_utc(&at(2040, 11, 4, 1, 30)), at(2040, 11, 4, 5, 30));
+    // The southern hemisphere has daylight saving time over new year
+    let sydney = tz::zone("Australia/Sydney").expect("Failed to load time zone");
+    assert_eq!(
+        sydney.offset(&at(2040, 1, 15, 0, 0)).local_minus_utc(),
+        11 * 3600
+    );
+    assert_eq!(
+        sydney.offset(&at(2040, 7, 15, 0, 0)).local_minus_utc(),
+        10 * 3600
+    );
+    assert!(tz::zone("../etc/passwd").is_none());
+    assert!(tz::zone("Nowhere/Special").is_none());
+    assert_eq!(
+        crate::calendar::models::parse_time("2021-03-01T12:00:00-05:00", &Tz::utc()),
+        Some(at(2021, 3, 1, 17, 0))
+    );
+
+    let client = Client::new(rocket(setup(String::from("test_time_zones")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in TimeZonesTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let user = create_user(
+        &conn,
+        NewUser {
+            real_name: String::from("coordinator"),
+            handle: String::from("coordinator"),
+            email: String::from("coordinator@test-rcos.io"),
+            mmost: String::from("coordinator"),
+            password_hash: hash_password("password"),
+            active: true,
+            role: Role::Coordinator,
+            ..NewUser::default()
+        },
+    )
+    .expect("Failed to create user");
+    assert!(user.time_zone.is_none());
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("email=coordinator@test-rcos.io&password=password")
+        .dispatch();
+
+    let set_zone = |z: &str| {
+        client
+            .put(format!("/users/{}/time-zone", user.id))
+            .header(ContentType::Form)
+            .body(format!("time_zone={}", z))
+            .dispatch()
+            .headers()
+            .get_one("Location")
+            .unwrap_or_default()
+            .to_string()
+    };
+    assert!(set_zone("Nowhere%2FSpecial").ends_with("e=timeZone"));
+    set_zone("America%2FNew_York");
+    let get_user = || -> User {
+        use crate::schema::users::dsl::*;
+        users
+            .find(user.id)
+            .first(&conn)
+            .expect("Failed to get user")
+    };
+    assert_eq!(get_user().time_zone.as_deref(), Some("America/New_York"));
+
+    // Times typed in are in the user's time zone and stored in UTC
+    let new_event = |day: u32| {
+        client
+            .post("/calendar/new")
+            .header(ContentType::Form)
+            .body(format!(
+                "title=Event&start=2021-03-{0}T12:00&end=2021-03-{0}T13:00&hosted_by={1}&code=",
+                day, user.id
+            ))
+            .dispatch();
+        use crate::schema::events::dsl::*;
+        events
+            .order(id.desc())
+            .first::<Event>(&conn)
+            .expect("Failed to get event")
+    };
+    let before = new_event(13);
+    let after = new_event(15);
+    assert_eq!(before.start, at(2021, 3, 13, 17, 0));
+    assert_eq!(after.start, at(2021, 3, 15, 16, 0));
+
+    // and shown in it
+    let page = client
+        .get(format!("/calendar/{}", after.id))
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(page.contains("Mar. 15, from 12:00 PM"));
+
+    let json = client
+        .get("/calendar.json")
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(json.contains("\"start\":\"2021-03-15T16:00:00Z\""));
+    let ics = client
+        .get("/calendar.ics")
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(ics.contains("DTSTART:20210315T160000Z"));
+
+    // Clearing it goes back to the site's time zone, which is UTC here
+    set_zone("");
+    assert!(get_user().time_zone.is_none());
+    let page = client
+        .get(format!("/calendar/{}", after.id))
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(page.contains("Mar. 15, from  4:00 PM"));
+
+    // RSS has the offset of the site's time zone
+    client
+        .post("/news/new")
+        .header(ContentType::Form)
+        .body("happened_at=2021-03-15T12:00&title=Story&description=News&color=")
+        .dispatch();
+    let rss = client
+        .get("/news.xml")
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(rss.contains("<pubDate>Mon, 15 Mar 2021 12:00:00 +0000</pubDate>"));
+
+    // Each Rocket has its own site time zone
+    let site_config = |zone: &str| {
+        let mut config = setup(String::from("test_time_zones")).unwrap();
+        config
+            .extras
+            .insert(String::from("time_zone"), Value::from(zone));
+        config
+    };
+    let ny_client = Client::new(rocket(Some(site_config("America/New_York")))).unwrap();
+    let event_page = |c: &Client| {
+        c.get(format!("/calendar/{}", after.id))
+            .dispatch()
+            .body_string()
+            .unwrap_or_default()
+    };
+    assert!(event_page(&ny_client).contains("Mar. 15, from 12:00 PM"));
+    assert!(event_page(&client).contains("Mar. 15, from  4:00 PM"));
+
+    // Names of what can be attended are in the zone they are shown in
+    {
+        use crate::models::Attendable;
+        assert_eq!(after.name(ny), "Event (Event) on: Mar. 15, at 12:00 PM");
+        assert_eq!(
+            after.name(Tz::utc()),
+            "Event (Event) on: Mar. 15, at  4:00 PM"
+        );
+    }
+
+    // and won't launch with one that doesn't exist
+    let error = Client::new(rocket(Some(site_config("Nowhere/Special"))))
+        .err()
+        .expect("Launched with an unknown time zone");
+    assert!(matches!(
+        error.kind(),
+        rocket::error::LaunchErrorKind::FailedFairings(_)
+    ));
+
+    cleanup(String::from("test_time_zones"));
+}
+
+#[test]
+fn calendar_feeds() {
+    use crate::settings::Settings;
+    use chrono::Utc;
+    use rocket::http::ContentType;
+
+    let client = Client::new(rocket(setup(String::from("test_calendar_feeds")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in CalendarFeedsTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let mentor = make_user(&conn, "mentor", Role::Mentor);
+    let student = make_user(&conn, "student", Role::Student);
+
+    let gid = create_group(
+        &conn,
+        &NewGroup {
+            name: String::from("Feed Group"),
+            owner_id: mentor.id,
+            location: Some(String::from("DCC 318")),
+            meeting_day: Some(String::from("we")),
+            meeting_time: Some(String::from("16:00")),
+            ..NewGroup::default()
+        },
+    )
+    .expect("Failed to create group");
+    {
+        use crate::schema::relation_group_user::dsl::*;
+        insert_into(relation_group_user)
+            .values(&NewRelationGroupUser {
+                group_id: gid,
+                user_id: student.id,
+            })
+            .execute(&conn)
+            .expect("Failed to add user to group");
+    }
+    let other_gid = create_group(
+        &conn,
+        &NewGroup {
+            name: String::from("Other Group"),
+            owner_id: mentor.id,
+            ..NewGroup::default()
+        },
+    )
+    .expect("Failed to create group");
+    let g: Group = {
+        use crate::schema::groups::dsl::*;
+        groups.find(gid).first(&conn).expect("Failed to get group")
+    };
+    schedule_meetings(&conn, &g, &Settings::default(), Utc::now().naive_utc());
+
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("email=student@test-rcos.io&password=password")
+        .dispatch();
+
+    // Milestones of the student's projects are in their feed
+    let project = create_project(
+        &conn,
+        &student,
+        NewProject {
+            name: String::from("Feed Project"),
+            description: String::from("Has milestones"),
+            repos: String::from("[]"),
+            ..NewProject::default()
+        },
+    )
+    .expect("Failed to create project");
+    let status = client
+        .post(format!("/projects/{}/milestones", project.id))
+        .header(ContentType::Form)
+        .body("title=Demo+Day&due_at=2021-04-20T16:00&description=")
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::SeeOther);
+    let page = client
+        .get(format!("/projects/{}", project.id))
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(page.contains("Demo Day"));
+
+    // The link is only shown when the feed is created
+    let new_feed = |group: &str| -> String {
+        let page = client
+            .post(format!("/users/{}/feeds", student.id))
+            .header(ContentType::Form)
+            .body(format!("group_id={}", group))
+            .dispatch()
+            .body_string()
+            .unwrap_or_default();
+        let start = page.find("<code>").expect("Failed to find feed link") + 6;
+        let url = page[start..start + page[start..].find("</code>").unwrap()]
+            .replace("&#x2f;", "/")
+            .replace("&#x3d;", "=");
+        let path = url
+            .find("/users/")
+            .or_else(|| url.find("/groups/"))
+            .unwrap();
+        url[path..].to_string()
+    };
+    let mine = new_feed("");
+    let group = new_feed(&gid.to_string());
+    assert!(mine.starts_with(&format!("/users/{}/calendar.ics?token=", student.id)));
+    assert!(group.starts_with(&format!("/groups/{}/calendar.ics?token=", gid)));
+
+    // Students can't follow groups they aren't in or see other users' feeds
+    let status = client
+        .post(format!("/users/{}/feeds", student.id))
+        .header(ContentType::Form)
+        .body(format!("group_id={}", other_gid))
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::Forbidden);
+    let status = client
+        .get(format!("/users/{}/feeds", mentor.id))
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::Forbidden);
+
+    // The feeds work without logging in
+    client.get("/logout").dispatch();
+    let fetch = |path: &str| {
+        let mut response = client.get(path).dispatch();
+        (
+            response.status(),
+            response.body_string().unwrap_or_default(),
+        )
+    };
+    let (status, ics) = fetch(&mine);
+    assert_eq!(status, Status::Ok);
+    assert!(ics.contains("SUMMARY:Feed Group Meeting"));
+    assert!(ics.contains("LOCATION:DCC 318"));
+    assert!(ics.contains("SUMMARY:Feed Project: Demo Day"));
+    assert!(ics.contains("DTSTART:20210420T160000Z"));
+    let (status, ics) = fetch(&group);
+    assert_eq!(status, Status::Ok);
+    assert!(ics.contains("SUMMARY:Feed Group Meeting"));
+
+    // but not with a bad token, or one for another feed
+    let bad = format!("/users/{}/calendar.ics?token=obs_bad", student.id);
+    assert_eq!(fetch(&bad).0, Status::NotFound);
+    let token = &mine[mine.find("token=").unwrap() + 6..];
+    let other = format!("/users/{}/calendar.ics?token={}", mentor.id, token);
+    assert_eq!(fetch(&other).0, Status::NotFound);
+    let other = format!("/groups/{}/calendar.ics?token={}", gid, token);
+    assert_eq!(fetch(&other).0, Status::NotFound);
+
+    // Leaving the group stops its feed
+    {
+        use crate::schema::relation_group_user::dsl::*;
+        delete(relation_group_user.filter(user_id.eq(student.id)))
+            .execute(&conn)
+            .expect("Failed to remove user from group");
+    }
+    assert_eq!(fetch(&group).0, Status::NotFound);
+    let (_, ics) = fetch(&mine);
+    assert!(!ics.contains("SUMMARY:Feed Group Meeting"));
+    assert!(ics.contains("SUMMARY:Feed Project: Demo Day"));
+
+    // Revoking a feed stops it
+    client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("email=student@test-rcos.io&password=password")
+        .dispatch();
+    let f: crate::feeds::models::CalendarFeed = {
+        use crate::schema::calendar_feeds::dsl::*;
+        calendar_feeds
+            .filter(group_id.is_null())
+            .first(&conn)
+            .expect("Failed to get calendar feed")
+    };
+    assert!(f.last_used.is_some());
+    client
+        .delete(format!("/users/{}/feeds/{}", student.id, f.id))
+        .dispatch();
+    assert_eq!(fetch(&mine).0, Status::NotFound);
+
+    cleanup(String::from("test_calendar_feeds"));
+}
+
+#[test]
+fn event_rsvps() {
+    use chrono::{Duration, Utc};
+    use diesel::update;
+    use rocket::http::ContentType;
+
+    let client = Client::new(rocket(setup(String::from("test_event_rsvps")))).unwrap();
+    let conn_url = create_connection_url(&client);
+
+    let conn = SqliteConnection::establish(conn_url.as_str())
+        .expect("Failed to connect to database in EventRsvpsTest");
+    embedded_migrations::run(&conn).expect("Failed to run embedded migrations");
+
+    let host = make_user(&conn, "host", Role::Coordinator);
+    let first = make_user(&conn, "first", Role::Student);
+    let second = make_user(&conn, "second", Role::Student);
+    let third = make_user(&conn, "third", Role::Student);
+
+    // Events need room for at least one person
+    login(&client, "host");
+    let start = Utc::now().naive_utc() + Duration::days(7);
+    let new_event = |capacity: &str| {
+        client
+            .post("/calendar/new")
+            .header(ContentType::Form)
+            .body(format!(
+                "title=Workshop&start={}&end={}&hosted_by={}&code=&capacity={}",
+                start.format("%FT%R"),
+                (start + Duration::hours(1)).format("%FT%R"),
+                host.id,
+                capacity
+            ))
+            .dispatch()
+            .headers()
+            .get_one("Location")
+            .unwrap_or_default()
+            .to_string()
+    };
+    assert!(new_event("0").ends_with("e=capacity"));
+    new_event("2");
+    let evt: Event = {
+        use crate::schema::events::dsl::*;
+        events.first(&conn).expect("Failed to get event")
+    };
+    assert_eq!(evt.capacity, Some(2));
+
+    let rsvp = |status: &str| {
+        client
+            .put(format!("/calendar/{}/rsvp", evt.id))
+            .header(ContentType::Form)
+            .body(format!("status={}", status))
+            .dispatch()
+            .status()
+    };
+    let guests = || guest_list(&conn, &evt);
+    let ids =
+        |g: &[crate::calendar::models::Guest]| g.iter().map(|g| g.user.id).collect::<Vec<_>>();
+
+    // The first to say they are going get the spots and the rest wait
+    for h in &["first", "second", "third"] {
+        login(&client, h);
+        assert_eq!(rsvp("going"), Status::SeeOther);
+    }
+    assert_eq!(ids(&guests().going), vec![first.id, second.id]);
+    assert_eq!(ids(&guests().waitlist), vec![third.id]);
+    let page = client
+        .get(format!("/calendar/{}", evt.id))
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(page.contains("number 1 on the waitlist"));
+
+    // Answering the same again keeps their place, giving up a spot moves
+    // the waitlist up
+    assert_eq!(rsvp("going"), Status::SeeOther);
+    login(&client, "first");
+    assert_eq!(rsvp("going"), Status::SeeOther);
+    assert_eq!(ids(&guests().going), vec![first.id, second.id]);
+    assert_eq!(rsvp("not_going"), Status::SeeOther);
+    assert_eq!(ids(&guests().going), vec![second.id, third.id]);
+    assert_eq!(ids(&guests().not_going), vec![first.id]);
+    assert_eq!(rsvp("maybe"), Status::SeeOther);
+    assert_eq!(ids(&guests().maybe), vec![first.id]);
+
+    // Only the host can export the guest list
+    let status = client
+        .get(format!("/calendar/{}/rsvps.csv", evt.id))
+        .dispatch()
+        .status();
+    assert_eq!(status, Status::Forbidden);
+
+    // Once the event is over the RSVP closes and no-shows are shown
+    {
+        use crate::schema::events::dsl::*;
+        update(events.find(evt.id))
+            .set((
+                start.eq(Utc::now().naive_utc() - Duration::hours(2)),
+                end.eq(Utc::now().naive_utc() - Duration::hours(1)),
+            ))
+            .execute(&conn)
+            .expect("Failed to update event");
+    }
+    {
+        use crate::schema::attendances::dsl::*;
+        insert_into(attendances)
+            .values(&NewAttendance {
+                user_id: second.id,
+                is_event: true,
+                meeting_id: None,
+                event_id: Some(evt.id),
+                approved: true,
+            })
+            .execute(&conn)
+            .expect("Failed to add attendance");
+    }
+    assert_eq!(rsvp("going"), Status::Forbidden);
+    let no_shows: Vec<i32> = guests().no_shows().iter().map(|g| g.user.id).collect();
+    assert_eq!(no_shows, vec![third.id]);
+
+    login(&client, "host");
+    let page = client
+        .get(format!("/calendar/{}", evt.id))
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    assert!(page.contains("No-Shows"));
+    let csv = client
+        .get(format!("/calendar/{}/rsvps.csv", evt.id))
+        .dispatch()
+        .body_string()
+        .unwrap_or_default();
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows.len(), 4);
+    assert!(rows[0].starts_with("handle,name,email,rsvp,waitlist"));
+    assert!(rows[1].starts_with("second,second,second@test-rcos.io,going,,"));
+    assert!(rows[1].ends_with(",true"));
+    assert!(rows[2].starts_with("third,third,third@test-rcos.io,going,,"));
+    assert!(rows[2].ends_with(",false"));
+    assert!(rows[3].starts_with("first,first,first@test-rcos.io,maybe,,"));
+
+    cleanup(String::from("test_event_rsvps"));
+}
@@ -0,0 +1,427 @@
+//! HTTP handlers for two-factor authentication
+
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use rocket::http::uri::Uri;
+use rocket::http::Status;
+use rocket::request::Form;
+use rocket::response::Redirect;
+use rocket::State;
+
+use crate::api::handlers::hash_token;
+use crate::audit;
+use crate::auth::handlers::now;
+use crate::auth::throttle::*;
+use crate::guards::*;
+use crate::models::User;
+use crate::roles::Permission;
+use crate::settings::Settings;
+use crate::templates::FormError;
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::otp;
+use super::templates::*;
+
+/// How many recovery codes a user gets
+const RECOVERY_CODES: usize = 10;
+
+/// GET handler for `/login/totp`
+///
+/// The second step of logging in for users with two-factor authentication.
+/// Only reachable after getting the password right.
+#[get("/login/totp?<to>&<e>")]
+pub fn login_totp(
+    mut session: SessionCookie,
+    to: Option<String>,
+    e: Option<FormError>,
+) -> Result<LoginTotpTemplate, Redirect> {
+    if session.second_step_user().is_none() {
+        return Err(Redirect::to(login_url(to)));
+    }
+    Ok(LoginTotpTemplate {
+        logged_in: None,
+        error: e,
+    })
+}
+
+/// A code from an authenticator app or a recovery code
+///
+/// Used to parse the incoming form in `login_totp_post` and `totp_post`
+#[derive(FromForm)]
+pub struct TotpForm {
+    code: String,
+}
+
+/// POST handler for `/login/totp`
+///
+/// Finishes logging in if the code is right. Wrong codes count as failed
+/// logins of the user's email, see `crate::auth::throttle`.
+#[post("/login/totp?<to>", data = "<form>")]
+pub fn login_totp_post(
+    conn: ObservDbConn,
+    mut session: SessionCookie,
+    settings: State<Settings>,
+    client: ClientInfo,
+    to: Option<String>,
+    form: Form<TotpForm>,
+) -> Redirect {
+    let uid = match session.second_step_user() {
+        Some(uid) => uid,
+        None => return Redirect::to(login_url(to)),
+    };
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .first(&*conn)
+            .expect("Failed to get user from database")
+    };
+
+    let back = Uri::percent_encode(to.as_deref().unwrap_or("/"));
+    let ip = client.ip.as_deref();
+    if locked_until(&*conn, &settings, &user.email, ip, now()).is_some() {
+        return Redirect::to(format!("/login/totp?to={}&e={}", back, FormError::Locked));
+    }
+    if !check_code(&*conn, uid, &form.code) {
+        record_failure(&*conn, &user.email, ip, now());
+        return Redirect::to(format!("/login/totp?to={}&e={}", back, FormError::TotpCode));
+    }
+
+    clear_failures(&*conn, &user.email);
+    session.end_second_step();
+    session.start(&*conn, &user);
+
+    Redirect::to(to.unwrap_or_else(|| String::from("/")))
+}
+
+/// GET handler for `/users/<uid>/totp`
+///
+/// Shows if a user has two-factor authentication. If they don't then
+/// it shows them the QR code to set it up.
+///
+/// Restricted to Admins and the user themselves.
+#[get("/users/<uid>/totp?<e>")]
+pub fn totp(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+    e: Option<FormError>,
+) -> Result<TotpTemplate, Status> {
+    totp_page(&*conn, l.0, &settings, uid, Vec::new(), e)
+}
+
+/// POST handler for `/users/<uid>/totp`
+///
+/// Turns on two-factor authentication once a code from the new secret is
+/// confirmed, then shows the recovery codes.
+///
+/// Restricted to the user themselves.
+#[post("/users/<uid>/totp", data = "<form>")]
+pub fn totp_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+    form: Form<TotpForm>,
+) -> Result<TotpTemplate, Status> {
+    if l.0.id != uid {
+        return Err(DENIED);
+    }
+
+    let s = totp_secret(&*conn, uid).ok_or(Status::NotFound)?;
+    if s.enabled {
+        return totp_page(&*conn, l.0, &settings, uid, Vec::new(), None);
+    }
+    let step = match otp::verify(&s.secret, &form.code, unix_now()) {
+        Some(step) => step,
+        None => {
+            let e = Some(FormError::TotpCode);
+            return totp_page(&*conn, l.0, &settings, uid, Vec::new(), e);
+        }
+    };
+
+    {
+        use crate::schema::totp_secrets::dsl::*;
+        update(totp_secrets.find(uid))
+            .set((enabled.eq(true), last_step.eq(step)))
+            .execute(&*conn)
+            .expect("Failed to update TOTP secret in database");
+    }
+    let codes = new_recovery_codes(&*conn, uid);
+
+    audit::record(
+        &*conn,
+        Some(&l.0),
+        "enable-totp",
+        "user",
+        Some(uid),
+        None,
+        None,
+    );
+    audit_log!(
+        "User {} [{}] has turned on two-factor authentication",
+        l.0.id,
+        l.0.email
+    );
+
+    totp_page(&*conn, l.0, &settings, uid, codes, None)
+}
+
+/// POST handler for `/users/<uid>/totp/recovery`
+///
+/// Replaces the user's recovery codes with new ones and shows them.
+///
+/// Restricted to the user themselves.
+#[post("/users/<uid>/totp/recovery")]
+pub fn totp_recovery_post(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+) -> Result<TotpTemplate, Status> {
+    if l.0.id != uid {
+        return Err(DENIED);
+    }
+    if !totp_enabled(&*conn, uid) {
+        return Err(Status::NotFound);
+    }
+
+    let codes = new_recovery_codes(&*conn, uid);
+
+    audit::record(
+        &*conn,
+        Some(&l.0),
+        "new-recovery-codes",
+        "user",
+        Some(uid),
+        None,
+        None,
+    );
+    audit_log!(
+        "User {} [{}] has made new recovery codes",
+        l.0.id,
+        l.0.email
+    );
+
+    totp_page(&*conn, l.0, &settings, uid, codes, None)
+}
+
+/// DELETE handler for `/users/<uid>/totp`
+///
+/// Turns off two-factor authentication. Admins use this when a user has
+/// lost their authenticator and their recovery codes.
+///
+/// Restricted to Admins and the user themselves, if their role doesn't
+/// require it.
+#[delete("/users/<uid>/totp")]
+pub fn totp_delete(
+    conn: ObservDbConn,
+    l: UserGuard,
+    settings: State<Settings>,
+    uid: i32,
+) -> Result<Redirect, Status> {
+    if !l.0.can(Permission::ManageUsers) && (l.0.id != uid || totp_required(&settings, &l.0)) {
+        return Err(DENIED);
+    }
+
+    remove_totp(&*conn, uid);
+
+    audit::record(
+        &*conn,
+        Some(&l.0),
+        "disable-totp",
+        "user",
+        Some(uid),
+        None,
+        None,
+    );
+    audit_log!(
+        "User {} [{}] has turned off two-factor authentication of User {}",
+        l.0.id,
+        l.0.email,
+        uid
+    );
+
+    Ok(Redirect::to(format!("/users/{}/totp", uid)))
+}
+
+fn totp_page(
+    conn: &SqliteConnection,
+    l: User,
+    settings: &Settings,
+    uid: i32,
+    new_codes: Vec<String>,
+    e: Option<FormError>,
+) -> Result<TotpTemplate, Status> {
+    if !l.can(Permission::ManageUsers) && l.id != uid {
+        return Err(DENIED);
+    }
+
+    let user: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(uid)
+            .first(conn)
+            .optional()
+            .expect("Failed to get user from database")
+            .ok_or(Status::NotFound)?
+    };
+
+    let secret = totp_secret(conn, uid);
+    let enabled = secret.as_ref().map_or(false, |s| s.enabled);
+
+    // Only the user can see the secret and only until it is turned on
+    let setup = if !enabled && l.id == uid {
+        let s = secret.unwrap_or_else(|| new_secret(conn, uid));
+        let uri = otp::otpauth_uri(&s.secret, "Observatory", &user.email);
+        Some(TotpSetup {
+            qr: crate::attend::handlers::qr_data_uri(&uri),
+            secret: s.secret,
+        })
+    } else {
+        None
+    };
+
+    Ok(TotpTemplate {
+        required: totp_required(settings, &user),
+        recovery_left: RecoveryCode::belonging_to(&user)
+            .count()
+            .get_result::<i64>(conn)
+            .expect("Failed to count recovery codes in database"),
+        logged_in: Some(l),
+        user,
+        enabled,
+        setup,
+        new_codes,
+        error: e,
+    })
+}
+
+//# Helper Functions
+
+/// The TOTP secret of a user, if they have one
+pub fn totp_secret(conn: &SqliteConnection, uid: i32) -> Option<TotpSecret> {
+    use crate::schema::totp_secrets::dsl::*;
+    totp_secrets
+        .find(uid)
+        .first(conn)
+        .optional()
+        .expect("Failed to get TOTP secret from database")
+}
+
+/// Does the user have two-factor authentication turned on?
+pub fn totp_enabled(conn: &SqliteConnection, uid: i32) -> bool {
+    totp_secret(conn, uid).map_or(false, |s| s.enabled)
+}
+
+/// Does the user's role require two-factor authentication?
+pub fn totp_required(settings: &Settings, user: &User) -> bool {
+    settings.totp_required_roles.contains(&user.role)
+}
+
+/// Does the user's role require two-factor authentication
+/// that they haven't set up yet?
+pub fn totp_missing(conn: &SqliteConnection, settings: &Settings, user: &User) -> bool {
+    totp_required(settings, user) && !totp_enabled(conn, user.id)
+}
+
+/// Check a code from the user's authenticator or one of their recovery codes
+///
+/// The time step of an accepted code is recorded and a recovery code is
+/// deleted, so no code works twice.
+pub fn check_code(conn: &SqliteConnection, uid: i32, code: &str) -> bool {
+    let s = match totp_secret(conn, uid) {
+        Some(s) if s.enabled => s,
+        _ => return false,
+    };
+
+    if let Some(step) = otp::verify(&s.secret, code, unix_now()) {
+        if step <= s.last_step {
+            return false;
+        }
+        use crate::schema::totp_secrets::dsl::*;
+        update(totp_secrets.find(uid))
+            .set(last_step.eq(step))
+            .execute(conn)
+            .expect("Failed to update TOTP secret in database");
+        return true;
+    }
+
+    use crate::schema::recovery_codes::dsl::*;
+    let hash = hash_token(&code.trim().to_lowercase());
+    let used = delete(recovery_codes.filter(user_id.eq(uid).and(code_hash.eq(hash))))
+        .execute(conn)
+        .expect("Failed to delete recovery code from database");
+    if used > 0 {
+        audit_log!("User {} has logged in with a recovery code", uid);
+    }
+    used > 0
+}
+
+/// Turn off two-factor authentication for a user
+pub fn remove_totp(conn: &SqliteConnection, uid: i32) {
+    {
+        use crate::schema::totp_secrets::dsl::*;
+        delete(totp_secrets.find(uid))
+            .execute(conn)
+            .expect("Failed to delete TOTP secret from database");
+    }
+    use crate::schema::recovery_codes::dsl::*;
+    delete(recovery_codes.filter(user_id.eq(uid)))
+        .execute(conn)
+        .expect("Failed to delete recovery codes from database");
+}
+
+/// Make a new secret for the user that isn't turned on yet
+fn new_secret(conn: &SqliteConnection, uid: i32) -> TotpSecret {
+    use crate::schema::totp_secrets::dsl::*;
+    insert_into(totp_secrets)
+        .values(&NewTotpSecret {
+            user_id: uid,
+            secret: otp::gen_secret(),
+            created_at: chrono::offset::Utc::now().naive_utc(),
+        })
+        .execute(conn)
+        .expect("Failed to insert TOTP secret into database");
+    totp_secret(conn, uid).expect("Failed to get TOTP secret from database")
+}
+
+/// Replace a user's recovery codes, returning the new ones
+fn new_recovery_codes(conn: &SqliteConnection, uid: i32) -> Vec<String> {
+    use crate::schema::recovery_codes::dsl::*;
+    delete(recovery_codes.filter(user_id.eq(uid)))
+        .execute(conn)
+        .expect("Failed to delete recovery codes from database");
+
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| otp::gen_recovery_code())
+        .collect();
+    let rows: Vec<NewRecoveryCode> = codes
+        .iter()
+        .map(|c| NewRecoveryCode {
+            user_id: uid,
+            code_hash: hash_token(c),
+        })
+        .collect();
+    insert_into(recovery_codes)
+        .values(&rows)
+        .execute(conn)
+        .expect("Failed to insert recovery codes into database");
+    codes
+}
+
+/// The current Unix time that codes are checked against
+fn unix_now() -> i64 {
+    chrono::offset::Utc::now().timestamp()
+}
+
+/// Back to the first step of logging in
+fn login_url(to: Option<String>) -> String {
+    match to {
+        Some(to) => format!("/login?to={}", Uri::percent_encode(&to)),
+        None => String::from("/login"),
+    }
+}
@@ -0,0 +1,18 @@
+//! Two-factor authentication
+//!
+//! Users can add a TOTP authenticator app to their account, after which
+//! logging in takes a code from it or one of their recovery codes as a
+//! second step. The `totp_required_roles` setting makes it required for
+//! everyone with those roles, they can't use pages that need a permission
+//! until they have set it up.
+//!
+//! ## Routes
+//! - `/login/totp`
+//! - `/users/<uid>/totp`
+//! - `/users/<uid>/totp/recovery`
+
+pub mod handlers;
+pub mod models;
+pub mod otp;
+
+mod templates;
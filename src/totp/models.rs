@@ -0,0 +1,61 @@
+//! Models for two-factor authentication
+//!
+//! Each user has at most one TOTP secret in the `totp_secrets` table and
+//! their unused recovery codes in the `recovery_codes` table. Only a hash
+//! of each recovery code is stored.
+
+use chrono::NaiveDateTime;
+
+use crate::models::User;
+use crate::schema::*;
+
+/// Models a user's TOTP secret in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
+#[belongs_to(User)]
+#[primary_key(user_id)]
+pub struct TotpSecret {
+    /// ID of the user
+    pub user_id: i32,
+    /// Base32 secret shared with the authenticator app
+    pub secret: String,
+    /// If a code has been confirmed and the secret is in use
+    pub enabled: bool,
+    /// The last time step a code was accepted for
+    pub last_step: i64,
+    /// When the secret was made
+    pub created_at: NaiveDateTime,
+}
+
+/// Used to create a new TOTP secret in the database
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "totp_secrets"]
+pub struct NewTotpSecret {
+    /// ID of the user
+    pub user_id: i32,
+    /// Base32 secret shared with the authenticator app
+    pub secret: String,
+    /// When the secret was made
+    pub created_at: NaiveDateTime,
+}
+
+/// Models an unused recovery code in the database
+#[derive(Debug, PartialEq, Clone, Queryable, Identifiable, Associations)]
+#[belongs_to(User)]
+pub struct RecoveryCode {
+    /// ID of the code
+    pub id: i32,
+    /// ID of the user
+    pub user_id: i32,
+    /// SHA-256 hash of the code
+    pub code_hash: String,
+}
+
+/// Used to create a new recovery code in the database
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "recovery_codes"]
+pub struct NewRecoveryCode {
+    /// ID of the user
+    pub user_id: i32,
+    /// SHA-256 hash of the code
+    pub code_hash: String,
+}
@@ -0,0 +1,116 @@
+//! Time-based one-time passwords
+//!
+//! Implements [RFC 6238](https://tools.ietf.org/html/rfc6238) TOTP with the
+//! defaults authenticator apps expect: HMAC-SHA1, 6 digits and 30 second
+//! steps. Secrets are shared with the app in base32.
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// How many seconds each code lasts
+pub const STEP_SECONDS: i64 = 30;
+
+/// How many steps before and after now are accepted for clock drift
+const DRIFT_STEPS: i64 = 1;
+
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Generate a new random base32 secret
+pub fn gen_secret() -> String {
+    let mut bytes = [0u8; 20];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate TOTP secret");
+    base32_encode(&bytes)
+}
+
+/// Generate a recovery code like `ab12-cd34`
+pub fn gen_recovery_code() -> String {
+    let s: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(8)
+        .collect::<String>()
+        .to_lowercase();
+    format!("{}-{}", &s[..4], &s[4..])
+}
+
+/// The code for a secret at a time step
+pub fn totp(secret: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let h = tag.as_ref();
+
+    // Dynamic truncation from the RFC
+    let offset = (h[h.len() - 1] & 0xf) as usize;
+    let bin = (u32::from(h[offset]) & 0x7f) << 24
+        | u32::from(h[offset + 1]) << 16
+        | u32::from(h[offset + 2]) << 8
+        | u32::from(h[offset + 3]);
+    format!("{:06}", bin % 1_000_000)
+}
+
+/// The time step of a Unix timestamp
+pub fn step_at(timestamp: i64) -> i64 {
+    timestamp.div_euclid(STEP_SECONDS)
+}
+
+/// Check a code from a base32 secret at a Unix timestamp
+///
+/// Returns the time step the code is for so that it can't be used again.
+pub fn verify(secret: &str, code: &str, timestamp: i64) -> Option<i64> {
+    let secret = base32_decode(secret)?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let now = step_at(timestamp);
+    (now - DRIFT_STEPS..=now + DRIFT_STEPS).find(|&step| totp(&secret, step) == code)
+}
+
+/// The `otpauth://` URI that authenticator apps read from the QR code
+pub fn otpauth_uri(secret: &str, issuer: &str, account: &str) -> String {
+    use rocket::http::uri::Uri;
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}",
+        Uri::percent_encode(issuer),
+        Uri::percent_encode(account),
+        secret,
+        Uri::percent_encode(issuer)
+    )
+}
+
+/// Encode bytes as base32 without padding
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &b in bytes {
+        buffer = buffer << 8 | u32::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    out
+}
+
+/// Decode base32, ignoring case, spaces and padding
+pub fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let v = BASE32
+            .iter()
+            .position(|&b| b as char == c.to_ascii_uppercase())?;
+        buffer = buffer << 5 | v as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits & 0xff) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,50 @@
+//! HTML templates for two-factor authentication
+
+use crate::models::User;
+#[allow(unused_imports)]
+use crate::templates::{filters, FormError, OptUser};
+
+/// Log In code page template
+///
+/// HTML File: `totp/login.html`
+///
+/// The second step of logging in that asks for a code
+#[derive(Template)]
+#[template(path = "totp/login.html")]
+pub struct LoginTotpTemplate {
+    pub logged_in: OptUser,
+    pub error: Option<FormError>,
+}
+
+/// A new secret for the user to add to their authenticator
+pub struct TotpSetup {
+    /// Base32 secret for typing in by hand
+    pub secret: String,
+    /// QR code of the `otpauth://` URI as a `data:` URI
+    pub qr: String,
+}
+
+/// Two-Factor page template
+///
+/// HTML File: `totp/totp.html`
+///
+/// Shows if a user has two-factor authentication and sets it up.
+#[derive(Template)]
+#[template(path = "totp/totp.html")]
+pub struct TotpTemplate {
+    pub logged_in: OptUser,
+    pub user: User,
+    /// If two-factor authentication is turned on
+    pub enabled: bool,
+    /// If the user's role requires two-factor authentication
+    pub required: bool,
+    /// The secret to set up, only shown to the user before it's turned on
+    pub setup: Option<TotpSetup>,
+    /// How many unused recovery codes the user has
+    pub recovery_left: i64,
+    /// Recovery codes that were just made
+    ///
+    /// This is the only time the codes are ever shown.
+    pub new_codes: Vec<String>,
+    pub error: Option<FormError>,
+}
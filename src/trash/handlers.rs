@@ -0,0 +1,274 @@
+//! HTTP handlers for the trash
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::{delete, update};
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::State;
+
+use crate::audit;
+use crate::guards::*;
+use crate::settings::Settings;
+use crate::tz;
+use crate::ObservDbConn;
+
+use super::models::*;
+use super::templates::*;
+
+/// Runs the same code for the table of each kind
+///
+/// The table's columns are imported so `id` and `deleted_at` can be used.
+macro_rules! for_kind {
+    ($kind:expr, |$table:ident| $body:expr) => {
+        match $kind {
+            Kind::User => {
+                use crate::schema::users::{dsl::*, table as $table};
+                $body
+            }
+            Kind::Project => {
+                use crate::schema::projects::{dsl::*, table as $table};
+                $body
+            }
+            Kind::Group => {
+                use crate::schema::groups::{dsl::*, table as $table};
+                $body
+            }
+            Kind::Event => {
+                use crate::schema::events::{dsl::*, table as $table};
+                $body
+            }
+            Kind::News => {
+                use crate::schema::news::{dsl::*, table as $table};
+                $body
+            }
+        }
+    };
+}
+
+/// GET handler for `/trash`
+///
+/// Lists everything in the trash.
+///
+/// Restricted to those that can manage the trash.
+#[get("/trash")]
+pub fn trash(
+    conn: ObservDbConn,
+    l: Can<can::ManageTrash>,
+    settings: State<Settings>,
+) -> TrashTemplate {
+    TrashTemplate {
+        zone: tz::of(Some(&l.0), &settings.site_zone()),
+        logged_in: Some(l.0),
+        items: trashed(&*conn),
+        days: settings.trash_days,
+    }
+}
+
+/// PUT handler for `/trash/<kind>/<tid>`
+///
+/// Takes something out of the trash.
+///
+/// Restricted to those that can manage the trash.
+#[put("/trash/<kind>/<tid>")]
+pub fn trash_restore(
+    conn: ObservDbConn,
+    l: Can<can::ManageTrash>,
+    kind: Kind,
+    tid: i32,
+) -> Result<Redirect, Status> {
+    if !restore(&*conn, kind, tid) {
+        return Err(Status::NotFound);
+    }
+    audit::record(
+        &*conn,
+        Some(&l.0),
+        "restore",
+        kind.key(),
+        Some(tid),
+        None,
+        None,
+    );
+    audit_log!(
+        "User {} [{}] has restored {} {} from the trash",
+        l.0.id,
+        l.0.email,
+        kind.key(),
+        tid
+    );
+    Ok(Redirect::to("/trash"))
+}
+
+/// DELETE handler for `/trash/<kind>/<tid>`
+///
+/// Purges something in the trash for good.
+///
+/// Restricted to those that can manage the trash.
+#[delete("/trash/<kind>/<tid>")]
+pub fn trash_purge(
+    conn: ObservDbConn,
+    l: Can<can::ManageTrash>,
+    kind: Kind,
+    tid: i32,
+) -> Result<Redirect, Status> {
+    if !purge(&*conn, kind, tid) {
+        return Err(Status::NotFound);
+    }
+    audit::record(
+        &*conn,
+        Some(&l.0),
+        "purge",
+        kind.key(),
+        Some(tid),
+        None,
+        None,
+    );
+    audit_log!(
+        "User {} [{}] has purged {} {} from the trash",
+        l.0.id,
+        l.0.email,
+        kind.key(),
+        tid
+    );
+    Ok(Redirect::to("/trash"))
+}
+
+//# Helper Functions
+
+/// Move something to the trash, returning if it was there to move
+///
+/// Users in the trash are logged out.
+pub fn move_to_trash(conn: &SqliteConnection, kind: Kind, tid: i32) -> bool {
+    let now = chrono::offset::Utc::now().naive_utc();
+    let moved = for_kind!(kind, |table| update(
+        table.filter(id.eq(tid).and(deleted_at.is_null()))
+    )
+    .set(deleted_at.eq(now))
+    .execute(conn))
+    .expect("Failed to move to the trash in database")
+        > 0;
+
+    if moved && kind == Kind::User {
+        crate::sessions::handlers::revoke_sessions(conn, tid);
+        crate::api::handlers::revoke_tokens(conn, tid);
+    }
+    moved
+}
+
+/// Take something out of the trash, returning if it was in it
+pub fn restore(conn: &SqliteConnection, kind: Kind, tid: i32) -> bool {
+    for_kind!(kind, |table| update(
+        table.filter(id.eq(tid).and(deleted_at.is_not_null()))
+    )
+    .set(deleted_at.eq(None::<NaiveDateTime>))
+    .execute(conn))
+    .expect("Failed to restore from the trash in database")
+        > 0
+}
+
+/// Delete something in the trash for good, returning if it was in it
+///
+/// This is the only place things are really deleted, along with their
+/// members, meetings and attendances.
+pub fn purge(conn: &SqliteConnection, kind: Kind, tid: i32) -> bool {
+    let trashed: Option<i32> = for_kind!(kind, |table| table
+        .filter(id.eq(tid).and(deleted_at.is_not_null()))
+        .select(id)
+        .first(conn)
+        .optional())
+    .expect("Failed to get trash from database");
+    if trashed.is_none() {
+        return false;
+    }
+
+    match kind {
+        Kind::User => crate::users::handlers::delete_user(conn, tid),
+        Kind::Project => crate::projects::handlers::delete_project(conn, tid),
+        Kind::Group => crate::groups::handlers::delete_group(conn, tid),
+        Kind::Event => crate::calendar::handlers::delete_event(conn, tid),
+        Kind::News => {
+            use crate::schema::news::dsl::*;
+            delete(news.find(tid))
+                .execute(conn)
+                .expect("Failed to delete news story from database");
+        }
+    }
+    true
+}
+
+/// Purge everything that has been in the trash for more than `days`,
+/// returning how many were purged
+pub fn purge_expired(conn: &SqliteConnection, days: i64, now: NaiveDateTime) -> usize {
+    let cutoff = now - Duration::days(days);
+    let mut purged = 0;
+    for &kind in Kind::ALL {
+        let expired: Vec<i32> = for_kind!(kind, |table| table
+            .filter(deleted_at.lt(cutoff))
+            .select(id)
+            .load(conn))
+        .expect("Failed to get trash from database");
+        for tid in expired {
+            purge(conn, kind, tid);
+            audit::record(conn, None, "purge", kind.key(), Some(tid), None, None);
+            purged += 1;
+        }
+    }
+    purged
+}
+
+/// Everything in the trash, most recently deleted first
+pub fn trashed(conn: &SqliteConnection) -> Vec<TrashItem> {
+    let mut items: Vec<TrashItem> = Kind::ALL
+        .iter()
+        .flat_map(|&kind| {
+            let rows: Vec<(i32, String, Option<NaiveDateTime>)> = match kind {
+                Kind::User => {
+                    use crate::schema::users::dsl::*;
+                    users
+                        .filter(deleted_at.is_not_null())
+                        .select((id, real_name, deleted_at))
+                        .load(conn)
+                }
+                Kind::Project => {
+                    use crate::schema::projects::dsl::*;
+                    projects
+                        .filter(deleted_at.is_not_null())
+                        .select((id, name, deleted_at))
+                        .load(conn)
+                }
+                Kind::Group => {
+                    use crate::schema::groups::dsl::*;
+                    groups
+                        .filter(deleted_at.is_not_null())
+                        .select((id, name, deleted_at))
+                        .load(conn)
+                }
+                Kind::Event => {
+                    use crate::schema::events::dsl::*;
+                    events
+                        .filter(deleted_at.is_not_null())
+                        .select((id, title, deleted_at))
+                        .load(conn)
+                }
+                Kind::News => {
+                    use crate::schema::news::dsl::*;
+                    news.filter(deleted_at.is_not_null())
+                        .select((id, title, deleted_at))
+                        .load(conn)
+                }
+            }
+            .expect("Failed to get trash from database");
+
+            rows.into_iter().filter_map(move |(tid, tname, at)| {
+                Some(TrashItem {
+                    kind,
+                    id: tid,
+                    name: tname,
+                    deleted_at: at?,
+                })
+            })
+        })
+        .collect();
+    items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    items
+}
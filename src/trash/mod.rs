@@ -0,0 +1,18 @@
+//! Trash for deleted users, projects, groups, events and news
+//!
+//! Deleting one of these only sets its `deleted_at` so it is hidden from
+//! the rest of the site while its attendance history is kept. Those that
+//! can manage the trash can restore it or purge it for good. Anything
+//! that has been in the trash longer than the `trash_days` setting is
+//! purged by the `crate::fairings::TrashPurge` job.
+//!
+//! ## Routes
+//! - `/trash`
+//! - `/trash/<kind>/<tid>`
+
+pub mod handlers;
+pub mod models;
+
+mod templates;
+
+pub use handlers::{move_to_trash, restore};
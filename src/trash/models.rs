@@ -0,0 +1,77 @@
+//! Models for the trash
+
+use std::fmt;
+
+use chrono::NaiveDateTime;
+use rocket::http::RawStr;
+use rocket::request::FromParam;
+
+/// The kinds of things that can be put in the trash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    User,
+    Project,
+    Group,
+    Event,
+    News,
+}
+
+impl Kind {
+    /// Every kind
+    pub const ALL: &'static [Kind] = &[
+        Kind::User,
+        Kind::Project,
+        Kind::Group,
+        Kind::Event,
+        Kind::News,
+    ];
+
+    /// The name used in URLs and the audit trail like `project`
+    pub fn key(self) -> &'static str {
+        match self {
+            Kind::User => "user",
+            Kind::Project => "project",
+            Kind::Group => "group",
+            Kind::Event => "event",
+            Kind::News => "news",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Kind::User => "User",
+                Kind::Project => "Project",
+                Kind::Group => "Group",
+                Kind::Event => "Event",
+                Kind::News => "News Story",
+            }
+        )
+    }
+}
+
+impl<'a> FromParam<'a> for Kind {
+    type Error = &'a RawStr;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+        Kind::ALL
+            .iter()
+            .copied()
+            .find(|k| k.key() == param.as_str())
+            .ok_or(param)
+    }
+}
+
+/// Something in the trash
+#[derive(Debug, Clone)]
+pub struct TrashItem {
+    pub kind: Kind,
+    pub id: i32,
+    /// Its name, handle or title
+    pub name: String,
+    pub deleted_at: NaiveDateTime,
+}
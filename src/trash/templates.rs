@@ -0,0 +1,35 @@
+//! HTML templates for the trash
+
+use chrono::{Duration, NaiveDateTime};
+
+use super::models::TrashItem;
+#[allow(unused_imports)]
+use crate::templates::{filters, OptUser};
+use crate::tz::Tz;
+
+/// Trash page template
+///
+/// HTML File: `trash/trash.html`
+///
+/// Lists everything in the trash, most recently deleted first.
+#[derive(Template)]
+#[template(path = "trash/trash.html")]
+pub struct TrashTemplate {
+    pub logged_in: OptUser,
+    /// The time zone times are shown in
+    pub zone: Tz,
+    pub items: Vec<TrashItem>,
+    /// The `trash_days` setting
+    pub days: i64,
+}
+
+impl TrashTemplate {
+    /// When something in the trash will be purged, if it ever will be
+    pub fn purged_at(&self, item: &TrashItem) -> Option<NaiveDateTime> {
+        if self.days > 0 {
+            Some(item.deleted_at + Duration::days(self.days))
+        } else {
+            None
+        }
+    }
+}
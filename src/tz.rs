@@ -0,0 +1,131 @@
+//! Time zones
+//!
+//! Times are stored in the database in UTC. They are shown in the time zone
+//! of the user looking at them if they picked one, otherwise in the site's
+//! time zone from the `time_zone` setting, and times typed into forms are
+//! read in the same zone. Weekly schedules and the days of semesters are
+//! in the site's time zone.
+//!
+//! The zones come from `chrono-tz`, which builds the time zone database
+//! into the binary so the server doesn't need one installed.
+//!
+//! The site's zone comes from the Rocket instance's `Settings`, see
+//! `crate::settings::Settings::site_zone`, and is passed to whatever needs
+//! it. Pages show times in the zone from `of`, which they have as `zone`
+//! for the `local` filter.
+
+use chrono::{
+    DateTime, Duration, FixedOffset, LocalResult, NaiveDateTime, Offset, SecondsFormat, TimeZone,
+    Utc,
+};
+use serde::Serializer;
+
+use crate::models::User;
+
+/// A time zone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tz(chrono_tz::Tz);
+
+impl Tz {
+    /// Coordinated Universal Time
+    pub fn utc() -> Self {
+        Tz(chrono_tz::UTC)
+    }
+
+    /// Name of the zone like `America/New_York`
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    /// The offset from UTC at a time in UTC
+    pub fn offset(&self, utc: &NaiveDateTime) -> FixedOffset {
+        self.0.offset_from_utc_datetime(utc).fix()
+    }
+
+    /// A time in UTC as it is in this zone
+    pub fn to_local(&self, utc: &NaiveDateTime) -> NaiveDateTime {
+        self.to_datetime(utc).naive_local()
+    }
+
+    /// A time in UTC as it is in this zone, with its offset
+    pub fn to_datetime(&self, utc: &NaiveDateTime) -> DateTime<FixedOffset> {
+        self.offset(utc).from_utc_datetime(utc)
+    }
+
+    /// A time in this zone in UTC
+    ///
+    /// Times that happen twice when the clocks go back are the first of the
+    /// two. Times skipped when the clocks go forward are moved forward by
+    /// the change, so 2:30 becomes 3:30.
+    pub fn to_utc(&self, local: &NaiveDateTime) -> NaiveDateTime {
+        match self.0.from_local_datetime(local) {
+            LocalResult::Single(t) => t.naive_utc(),
+            LocalResult::Ambiguous(first, _) => first.naive_utc(),
+            // Read it with the offset from before the clocks changed
+            LocalResult::None => {
+                let before = self.offset(&(*local - Duration::days(1)));
+                *local - Duration::seconds(i64::from(before.local_minus_utc()))
+            }
+        }
+    }
+}
+
+/// A zone by its name like `America/New_York`, if it exists
+pub fn zone(name: &str) -> Option<Tz> {
+    name.parse().ok().map(Tz)
+}
+
+/// The time zone times are shown to a user in
+///
+/// This is the zone they picked, or the site's if they didn't or aren't
+/// logged in.
+pub fn of(user: Option<&User>, site: &Tz) -> Tz {
+    user.and_then(|u| u.time_zone.as_deref())
+        .and_then(zone)
+        .unwrap_or(*site)
+}
+
+/// The names of the zones that can be picked, sorted
+///
+/// Leaves out the old names kept for compatibility like `US/Eastern`.
+pub fn names() -> Vec<String> {
+    const REGIONS: &[&str] = &[
+        "Africa/",
+        "America/",
+        "Antarctica/",
+        "Asia/",
+        "Atlantic/",
+        "Australia/",
+        "Europe/",
+        "Indian/",
+        "Pacific/",
+    ];
+    let mut names: Vec<String> = chrono_tz::TZ_VARIANTS
+        .iter()
+        .map(|z| z.name())
+        .filter(|n| REGIONS.iter().any(|r| n.starts_with(r)))
+        .map(String::from)
+        .chain(std::iter::once(String::from("UTC")))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Serializes a time stored in UTC like `2021-03-01T21:00:00Z`
+pub fn serialize_utc<S: Serializer>(t: &NaiveDateTime, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(
+        &Utc.from_utc_datetime(t)
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+}
+
+/// Serializes an optional time stored in UTC like `serialize_utc`
+pub fn serialize_utc_opt<S: Serializer>(
+    t: &Option<NaiveDateTime>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match t {
+        Some(t) => serialize_utc(t, s),
+        None => s.serialize_none(),
+    }
+}
@@ -1,40 +1,61 @@
 //! The handler for the user page this code handles the function of searching for users, creating users, deleting users
 //! Checking users relation to a project, number of commits made, return a list of users, and the user's grade summary
 
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use diesel::{delete, update};
+use diesel::{delete, insert_into, update};
 use rocket::http::Status;
 use rocket::request::Form;
 use rocket::response::Redirect;
+use rocket::State;
 
 use rocket_contrib::json::Json;
 
+use crate::api::handlers::revoke_tokens;
+use crate::audit;
 use crate::auth::crypto::*;
 use crate::guards::*;
+use crate::roles::{Permission, Role};
+use crate::semesters::handlers::{semester_select, semester_users};
+use crate::semesters::models::Semester;
+use crate::sessions::handlers::revoke_sessions;
+use crate::settings::Settings;
 use crate::ObservDbConn;
 
 use super::models::*;
 use super::templates::*;
 use crate::templates::{is_reserved, FormError};
+use crate::trash::handlers::move_to_trash;
+use crate::trash::models::Kind;
+use crate::tz::{self, Tz};
 
 /// GET handler for '/users/<h>'
 /// Gets an Indivual user by their ID and returns it to the template
 
 #[get("/users/<h>")]
-pub fn user(conn: ObservDbConn, l: MaybeLoggedIn, h: i32) -> Option<UserTemplate> {
+pub fn user(
+    conn: ObservDbConn,
+    l: MaybeLoggedIn,
+    settings: State<Settings>,
+    h: i32,
+) -> Option<UserTemplate> {
     use crate::schema::users::dsl::*;
 
     let u = users
         .find(h)
+        .filter(deleted_at.is_null())
         .first(&*conn)
         .optional()
         .expect("Failed to get user from database")?;
 
+    let site = settings.site_zone();
+    let logged_in = l.user();
     Some(UserTemplate {
-        logged_in: l.user(),
+        zone: tz::of(logged_in.as_ref(), &site),
+        logged_in,
         projects: user_projects(&*conn, &u),
         groups: user_groups(&*conn, &u),
-        summary: grade_summary(&*conn, &u),
+        summary: grade_summary(&*conn, &u, None, &site),
         user: u,
     })
 }
@@ -47,7 +68,7 @@ pub fn user_by_handle(conn: ObservDbConn, _l: MaybeLoggedIn, h: String) -> Optio
     use crate::schema::users::dsl::*;
 
     let u: User = users
-        .filter(handle.like(h))
+        .filter(handle.like(h).and(deleted_at.is_null()))
         .first(&*conn)
         .optional()
         .expect("Failed to get user from database")?;
@@ -62,6 +83,7 @@ pub fn user_by_handle(conn: ObservDbConn, _l: MaybeLoggedIn, h: String) -> Optio
 pub fn user_edit(
     conn: ObservDbConn,
     l: UserGuard,
+    settings: State<Settings>,
     h: i32,
     e: Option<FormError>,
 ) -> Option<EditUserTemplate> {
@@ -71,127 +93,314 @@ pub fn user_edit(
         logged_in: Some(l.0),
         user: users
             .find(h)
+            .filter(deleted_at.is_null())
             .first(&*conn)
             .optional()
             .expect("Failed to get user from database")?,
         error: e,
+        time_zones: tz::names(),
+        site_zone: settings.site_zone(),
     })
 }
 
 /// PUT handler for '/users/<h>'
 /// Puts up the new changes made in the user edit and changes the users data
+/// Users that change their own password stay logged in on this browser
 
 #[put("/users/<h>", data = "<edituser>")]
 pub fn user_edit_put(
     conn: ObservDbConn,
     l: UserGuard,
+    mut session: SessionCookie,
     h: i32,
     edituser: Form<NewUser>,
 ) -> Result<Redirect, Status> {
-    let l = l.0;
-    let mut edituser = edituser.into_inner();
+    if l.0.can(Permission::ManageUsers) || l.0.id == h {
+        let new_password = !edituser.password_hash.is_empty();
+        match save_user(&*conn, &l.0, h, edituser.into_inner()) {
+            Ok(edituser) => {
+                if new_password && l.0.id == h {
+                    session.start(&*conn, &l.0);
+                }
+                Ok(Redirect::to(format!("/users/{}", edituser.handle)))
+            }
+            Err(e) => Ok(Redirect::to(format!("/users/{}/edit?e={}", h, e))),
+        }
+    } else {
+        Err(DENIED)
+    }
+}
+
+/// The time zone a user picked, empty to use the site's
+#[derive(FromForm)]
+pub struct TimeZoneForm {
+    pub time_zone: String,
+}
 
+/// PUT handler for '/users/<h>/time-zone'
+/// Sets the time zone times are shown to the user in, see `crate::tz`
+/// Restricted to the user and those who can manage users
+
+#[put("/users/<h>/time-zone", data = "<form>")]
+pub fn user_time_zone_put(
+    conn: ObservDbConn,
+    l: UserGuard,
+    h: i32,
+    form: Form<TimeZoneForm>,
+) -> Result<Redirect, Status> {
     use crate::schema::users::dsl::*;
-    // Get some more info about the edited user
-    let (esalt, phash, etier) = users
+
+    if !(l.0.can(Permission::ManageUsers) || l.0.id == h) {
+        return Err(DENIED);
+    }
+    let old: User = users
+        .find(h)
+        .filter(deleted_at.is_null())
+        .first(&*conn)
+        .optional()
+        .expect("Failed to get user from database")
+        .ok_or(Status::NotFound)?;
+
+    let name = form.time_zone.trim();
+    let zone = if name.is_empty() {
+        None
+    } else if let Some(z) = tz::zone(name) {
+        Some(z.name().to_string())
+    } else {
+        return Ok(Redirect::to(format!(
+            "/users/{}/edit?e={}",
+            h,
+            FormError::TimeZone
+        )));
+    };
+
+    update(users.find(h))
+        .set(time_zone.eq(&zone))
+        .execute(&*conn)
+        .expect("Failed to update user in database");
+    let new: User = users
         .find(h)
-        .select((salt, password_hash, tier))
         .first(&*conn)
         .expect("Failed to get user from database");
+    audit::updated(&*conn, Some(&l.0), "user", h, &old, &new);
 
-    if l.tier > 1 || l.id == h {
-        if let Err(e) = is_reserved(&*edituser.handle) {
-            return Ok(Redirect::to(format!("/users/{}/edit?e={}", h, e)));
-        }
+    Ok(Redirect::to(format!("/users/{}", new.handle)))
+}
 
-        // Check if user's email is already signed up
-        if users
-            .filter(&email.eq(&edituser.email).and(id.ne(h)))
-            .first::<User>(&*conn)
-            .optional()
-            .expect("Failed to get user from database")
-            .is_some()
-        {
-            return Ok(Redirect::to(format!(
-                "/users/{}/edit?e={}",
-                h,
-                FormError::EmailExists
-            )));
-        }
+/// DELETE handler for '/users/<h>'
+/// Moves the user to the trash and logs them out
 
-        // Check if user's github is already signed up
-        if users
-            .filter(&handle.eq(&edituser.handle).and(id.ne(h)))
-            .first::<User>(&*conn)
+#[delete("/users/<h>")]
+pub fn user_delete(conn: ObservDbConn, l: Can<can::ManageUsers>, h: i32) -> Redirect {
+    let old: Option<User> = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(h)
+            .first(&*conn)
             .optional()
             .expect("Failed to get user from database")
-            .is_some()
-        {
-            return Ok(Redirect::to(format!(
-                "/users/{}/edit?e={}",
-                h,
-                FormError::GitExists
-            )));
+    };
+    // The admin user can never be deleted
+    if h != 0 && move_to_trash(&*conn, Kind::User, h) {
+        if let Some(old) = old {
+            audit::deleted(&*conn, Some(&l.0), "user", h, &old);
         }
+    }
+    Redirect::to("/users")
+}
 
-        // Check if user's mattermost is already signed up
-        if users
-            .filter(&mmost.eq(&edituser.mmost).and(id.ne(h)))
-            .first::<User>(&*conn)
-            .optional()
-            .expect("Failed to get user from database")
-            .is_some()
-        {
-            return Ok(Redirect::to(format!(
-                "/users/{}/edit?e={}",
-                h,
-                FormError::MmostExists
-            )));
-        }
+/// Creates a new user and adds them to the Large Group
+///
+/// The password of `newuser` must already be hashed.
+pub fn create_user(conn: &SqliteConnection, newuser: NewUser) -> Result<User, FormError> {
+    use crate::schema::users::dsl::*;
 
-        if edituser.password_hash.is_empty() {
-            edituser.salt = esalt;
-            edituser.password_hash = phash;
-        } else {
-            let (phash, psalt) = hash_password(edituser.password_hash);
-            edituser.password_hash = phash;
-            edituser.salt = psalt;
-        }
+    is_reserved(&*newuser.handle)?;
+    check_user_unique(conn, &newuser, None)?;
+
+    // Insert the new user into the database
+    insert_into(users)
+        .values((
+            &newuser,
+            joined_on.eq(chrono::offset::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .expect("Failed to add user to database");
+
+    let user: User = users
+        .filter(&email.eq(&*newuser.email))
+        .first(conn)
+        .expect("Failed to get user from database");
+    {
+        use crate::models::NewRelationGroupUser;
+        use crate::schema::relation_group_user::dsl::*;
+        insert_into(relation_group_user)
+            .values(&NewRelationGroupUser {
+                group_id: 0,
+                user_id: user.id,
+            })
+            .execute(conn)
+            .expect("Failed to insert new relation into database");
+    }
 
-        // if the logged in user can't change tiers
-        // of if it's the admin user
-        // don't change tiers
-        if l.tier <= 1 || h == 0 {
-            edituser.tier = etier;
-        }
+    Ok(user)
+}
+
+/// Saves the edits made to a user by `editor`
+///
+/// An empty password keeps the old one, only those who can assign roles
+/// can change the role, and nobody can change a handle verified through GitHub.
+/// Nobody can be made an Admin since that role is for the Admin account.
+/// Changing the password or role logs the user out of all their sessions,
+/// and changing the email means it has to be verified again.
+/// Returns the user as it was saved.
+pub fn save_user(
+    conn: &SqliteConnection,
+    editor: &User,
+    h: i32,
+    mut edituser: NewUser,
+) -> Result<NewUser, FormError> {
+    use crate::schema::users::dsl::*;
+    // Get the edited user as they were
+    let old: User = users
+        .find(h)
+        .first(conn)
+        .expect("Failed to get user from database");
+    let (phash, erole, eemail, ehandle, everified) = (
+        old.password_hash.clone(),
+        old.role,
+        old.email.clone(),
+        old.handle.clone(),
+        old.handle_verified,
+    );
+
+    // A handle verified through GitHub can't be changed by hand
+    if everified {
+        edituser.handle = ehandle;
+    }
+
+    is_reserved(&*edituser.handle)?;
+    check_user_unique(conn, &edituser, Some(h))?;
+
+    let new_password = !edituser.password_hash.is_empty();
+    if !new_password {
+        edituser.password_hash = phash;
+    } else {
+        edituser.password_hash = hash_password(edituser.password_hash);
+    }
 
+    // if the logged in user can't change roles
+    // of if it's the admin user
+    // don't change roles
+    if !editor.can(Permission::AssignRoles) || h == 0 || edituser.role == Role::Admin {
+        edituser.role = erole;
+    }
+
+    update(users.find(h))
+        .set(&edituser)
+        .execute(conn)
+        .expect("Failed to update user in database");
+
+    if edituser.email != eemail {
         update(users.find(h))
-            .set(&edituser)
-            .execute(&*conn)
+            .set(verified.eq(false))
+            .execute(conn)
             .expect("Failed to update user in database");
+    }
+    if new_password || edituser.role != erole {
+        revoke_sessions(conn, h);
+        revoke_tokens(conn, h);
+    }
 
-        Ok(Redirect::to(format!("/users/{}", edituser.handle)))
-    } else {
-        Err(Status::Unauthorized)
+    let new: User = users
+        .find(h)
+        .first(conn)
+        .expect("Failed to get user from database");
+    audit::updated(conn, Some(editor), "user", h, &old, &new);
+    if new_password {
+        audit::record(
+            conn,
+            Some(editor),
+            "change-password",
+            "user",
+            Some(h),
+            None,
+            None,
+        );
+    }
+    if edituser.role != erole {
+        audit_log!(
+            "User {} [{}] has changed the role of User {} to {}",
+            editor.id,
+            editor.email,
+            h,
+            edituser.role
+        );
     }
-}
 
-/// DELETE handler for '/users/<h>'
-/// delets all user data from the database
+    Ok(edituser)
+}
 
-#[delete("/users/<h>")]
-pub fn user_delete(conn: ObservDbConn, _l: AdminGuard, h: i32) -> Redirect {
-    // Delete the user
+/// Checks that no other user has the same email, GitHub or Mattermost
+///
+/// `h` is the ID of the user being edited, or `None` for a new user.
+pub fn check_user_unique(
+    conn: &SqliteConnection,
+    u: &NewUser,
+    h: Option<i32>,
+) -> Result<(), FormError> {
     use crate::schema::users::dsl::*;
-    delete(users.find(h))
-        .execute(&*conn)
-        .expect("Failed to delete user from database");
+    // There is never a user with a negative ID
+    let other = id.ne(h.unwrap_or(-1));
+
+    // Check if user's email is already signed up
+    if users
+        .filter(email.eq(&u.email).and(other))
+        .first::<User>(conn)
+        .optional()
+        .expect("Failed to get user from database")
+        .is_some()
+    {
+        return Err(FormError::EmailExists);
+    }
+
+    // Check if user's github is already signed up
+    if users
+        .filter(handle.eq(&u.handle).and(other))
+        .first::<User>(conn)
+        .optional()
+        .expect("Failed to get user from database")
+        .is_some()
+    {
+        return Err(FormError::GitExists);
+    }
+
+    // Check if user's mattermost is already signed up
+    if users
+        .filter(mmost.eq(&u.mmost).and(other))
+        .first::<User>(conn)
+        .optional()
+        .expect("Failed to get user from database")
+        .is_some()
+    {
+        return Err(FormError::MmostExists);
+    }
+
+    Ok(())
+}
+
+/// Deletes a user for good along with their project and group memberships,
+/// attendances and everything else that belongs to them
+///
+/// Only used to purge the trash, see `crate::trash`.
+pub fn delete_user(conn: &SqliteConnection, h: i32) {
+    // Everything referencing the user goes first, the user is deleted last
 
     // Delete the relations to projects
     {
         use crate::schema::relation_project_user::dsl::*;
         delete(relation_project_user.filter(user_id.eq(h)))
-            .execute(&*conn)
+            .execute(conn)
             .expect("Failed to delete relation from database");
     }
 
@@ -199,28 +408,118 @@ pub fn user_delete(conn: ObservDbConn, _l: AdminGuard, h: i32) -> Redirect {
     {
         use crate::schema::relation_group_user::dsl::*;
         delete(relation_group_user.filter(user_id.eq(h)))
-            .execute(&*conn)
+            .execute(conn)
             .expect("Failed to delete relation from database");
     }
 
-    Redirect::to("/users")
+    // Delete their requests to join projects
+    {
+        use crate::schema::project_requests::dsl::*;
+        delete(project_requests.filter(user_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete requests from database");
+    }
+
+    // Delete their attendances
+    {
+        use crate::schema::attendances::dsl::*;
+        delete(attendances.filter(user_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete attendances from database");
+    }
+
+    // Delete their RSVPs
+    {
+        use crate::schema::rsvps::dsl::*;
+        delete(rsvps.filter(user_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete RSVPs from database");
+    }
+
+    // Log them out
+    revoke_sessions(conn, h);
+    crate::totp::handlers::remove_totp(conn, h);
+
+    // Revoke their API tokens
+    revoke_tokens(conn, h);
+
+    // Revoke their calendar feeds
+    {
+        use crate::schema::calendar_feeds::dsl::*;
+        delete(calendar_feeds.filter(user_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete calendar feeds from database");
+    }
+
+    // Unlink their accounts
+    {
+        use crate::schema::oauth_identities::dsl::*;
+        delete(oauth_identities.filter(user_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete linked accounts from database");
+    }
+
+    // Their commits become unattributed
+    {
+        use crate::schema::commit_identities::dsl::*;
+        delete(commit_identities.filter(user_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete commit identities from database");
+    }
+
+    // Remove them from every semester
+    {
+        use crate::schema::enrollments::dsl::*;
+        delete(enrollments.filter(user_id.eq(h)))
+            .execute(conn)
+            .expect("Failed to delete enrollments from database");
+    }
+
+    // Their changes stay in the audit log without saying who made them
+    {
+        use crate::schema::audit_events::dsl::*;
+        update(audit_events.filter(actor_id.eq(h)))
+            .set(actor_id.eq(None::<i32>))
+            .execute(conn)
+            .expect("Failed to update audit events in database");
+    }
+
+    // Delete the user
+    use crate::schema::users::dsl::*;
+    delete(users.find(h))
+        .execute(conn)
+        .expect("Failed to delete user from database");
 }
 
 /// GET handler for '/users?<s>'
 /// Return a list of users form a search string
+/// who are enrolled in the selected semester
 
-#[get("/users?<s>&<a>")]
+#[get("/users?<s>&<a>&<sem>")]
 pub fn users(
     conn: ObservDbConn,
     l: MaybeLoggedIn,
+    settings: State<Settings>,
     s: Option<String>,
     a: Option<bool>,
+    sem: Option<String>,
 ) -> UsersListTemplate {
+    let semester = semester_select(&*conn, sem, &settings.site_zone());
+    let mut found = filter_users(&*conn, s.clone(), a);
+    if let Some(enrolled) = semester
+        .selected
+        .as_ref()
+        .map(|sel| semester_users(&*conn, sel))
+    {
+        found.retain(|u| enrolled.contains(u));
+    }
+
     UsersListTemplate {
         logged_in: l.user(),
-        search_term: s.clone().unwrap_or_else(String::new),
-        users: filter_users(&*conn, s, a),
+        search_term: s.unwrap_or_else(String::new),
+        users: found,
         inactive: a.unwrap_or(false),
+        semester,
     }
 }
 
@@ -239,7 +538,7 @@ pub fn filter_users(
 ) -> Vec<User> {
     use crate::schema::users::dsl::*;
 
-    let default_filter = id.ne(0);
+    let default_filter = id.ne(0).and(deleted_at.is_null());
     let afilter = active.eq(true).and(former.eq(false));
 
     if let Some(term) = term {
@@ -273,11 +572,13 @@ pub fn user_projects(conn: &SqliteConnection, user: &User) -> Vec<Project> {
         .load::<RelationProjectUser>(conn)
         .expect("Failed to load relations from database")
         .iter()
-        .map(|r| {
+        .filter_map(|r| {
             use crate::schema::projects::dsl::*;
             projects
                 .find(r.project_id)
+                .filter(deleted_at.is_null())
                 .first(conn)
+                .optional()
                 .expect("Failed to load project from database")
         })
         .collect()
@@ -290,84 +591,125 @@ pub fn user_groups(conn: &SqliteConnection, user: &User) -> Vec<Group> {
         .load::<RelationGroupUser>(conn)
         .expect("Failed to get relations from database")
         .iter()
-        .map(|r| {
+        .filter_map(|r| {
             use crate::schema::groups::dsl::*;
             groups
                 .find(r.group_id)
+                .filter(deleted_at.is_null())
                 .first(conn)
+                .optional()
                 .expect("Failed to get group from database")
         })
         .collect()
 }
 
 ///Calculates a users grade bassed on attendence and total commits
+///
+/// Only counts what happened during the semester if one is given, with its
+/// days in the site's time zone `site`.
+/// Meetings are only needed once they have happened, and cancelled
+/// meetings aren't needed at all.
 
-pub fn grade_summary(conn: &SqliteConnection, user: &User) -> GradeSummary {
-    use crate::models::Attendable;
+pub fn grade_summary(
+    conn: &SqliteConnection,
+    user: &User,
+    semester: Option<&Semester>,
+    site: &Tz,
+) -> GradeSummary {
+    use crate::attend::handlers::attended;
+    use crate::grades::handlers::{compute_grade, rubric_for};
     use crate::models::Attendance;
 
-    let at = Attendance::belonging_to(user)
+    let during = |t: NaiveDateTime| semester.map_or(true, |s| s.contains(t, site));
+    let now = chrono::offset::Utc::now().naive_utc();
+
+    let (approved, pending): (Vec<Attendance>, Vec<Attendance>) = Attendance::belonging_to(user)
         .load::<Attendance>(conn)
         .expect("Failed to load attendance from database")
-        .iter()
-        .map(|a| {
-            if a.is_event {
-                use crate::models::Event;
-                use crate::schema::events::dsl::*;
-                Box::new(
-                    events
-                        .find(a.event_id.unwrap())
-                        .first::<Event>(conn)
-                        .expect("Failed to load event from database"),
-                ) as Box<dyn Attendable>
-            } else {
-                use crate::models::Meeting;
-                use crate::schema::meetings::dsl::*;
-                Box::new(
-                    meetings
-                        .find(a.meeting_id.unwrap())
-                        .first::<Meeting>(conn)
-                        .expect("Failed to load meeting from database"),
-                ) as Box<dyn Attendable>
-            }
-        })
-        .collect();
+        .into_iter()
+        .partition(|a| a.approved);
 
     let nat: usize = user_groups(conn, user).iter().fold(0, |a, g| {
         use crate::schema::meetings::dsl::*;
         a + meetings
             .filter(group_id.eq(g.id))
+            .filter(happened_at.le(now).and(cancelled.eq(false)))
+            .select(happened_at)
+            .load::<NaiveDateTime>(conn)
+            .expect("Failed to get meetings from database")
+            .into_iter()
+            .filter(|&t| during(t))
             .count()
-            .get_result::<i64>(conn)
-            .expect("Failed to get a count of meetings") as usize
     });
 
     GradeSummary {
-        attendances: at,
+        attendances: approved
+            .iter()
+            .map(|a| attended(conn, a))
+            .filter(|a| during(a.time()) && !a.cancelled())
+            .collect(),
+        pending: pending
+            .iter()
+            .map(|a| attended(conn, a))
+            .filter(|a| during(a.time()))
+            .collect(),
         needed_attendances: nat,
-        commit_count: user_commits_count(conn, user),
+        commit_count: user_commits_count(conn, user, semester, site),
+        grade: semester.map(|s| compute_grade(conn, user, s, &rubric_for(conn, s.id), site)),
     }
 }
 
 /// Counts the number of total commits user has made
-use crate::handlers::project_commits;
-pub fn user_commits_count(conn: &SqliteConnection, user: &User) -> Option<usize> {
+///
+/// Counts the cached commits in the repos of all the user's projects that
+/// are attributed to the user by their emails and aliases.
+/// See `crate::commits::handlers::Identities`.
+///
+/// Only counts commits made during the semester if one is given, with its
+/// days in the site's time zone `site`.
+///
+/// Returns `None` if none of the user's projects have repos being synced.
+pub fn user_commits_count(
+    conn: &SqliteConnection,
+    user: &User,
+    semester: Option<&Semester>,
+    site: &Tz,
+) -> Option<usize> {
+    use crate::commits::forge::Repo;
+    use crate::commits::handlers::Identities;
+    use crate::commits::models::Commit;
+    use crate::projects::handlers::project_repos;
+
+    let repos: Vec<String> = user_projects(conn, user)
+        .iter()
+        .flat_map(project_repos)
+        .filter_map(|r| Repo::parse(&r))
+        .map(|r| r.url)
+        .collect();
+
+    let synced: Vec<String> = {
+        use crate::schema::repo_syncs::dsl::*;
+        repo_syncs
+            .filter(repo.eq_any(repos))
+            .select(repo)
+            .load(conn)
+            .expect("Failed to get repo syncs from database")
+    };
+
+    if synced.is_empty() {
+        return None;
+    }
+
+    let ids = Identities::of(conn, user);
+    use crate::schema::commits::dsl::*;
     Some(
-        user_projects(conn, &user)
-            .iter()
-            .filter_map(|p| project_commits(conn, p))
-            .flatten()
-            .collect::<Vec<serde_json::Value>>()
-            .first()?
-            .as_array()?
+        commits
+            .filter(repo.eq_any(synced))
+            .load::<Commit>(conn)
+            .expect("Failed to get commits from database")
             .iter()
-            .filter_map(|c| {
-                if c.get("author")?.get("login")?.as_str()? == user.handle {
-                    Some(c)
-                } else {
-                    None
-                }
-            })
+            .filter(|c| semester.map_or(true, |s| s.contains(c.committed_at, site)))
+            .filter(|c| ids.matches(c))
             .count(),
     )
 }
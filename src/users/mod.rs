@@ -8,6 +8,7 @@
 //! - `/users/<h>/edit`
 //! - `/users?<s>`
 //! - `/users.json?<s>`
+//! - `/users/<h>/tokens`
 
 pub mod handlers;
 pub mod models;
@@ -1,4 +1,4 @@
-use crate::auth::crypto::UnsafeBinaryString;
+use crate::roles::{Permission, Role};
 use crate::schema::*;
 use chrono::NaiveDateTime;
 
@@ -13,50 +13,68 @@ pub struct User {
     pub real_name: String,
     /// Github user name of the user
     pub handle: String,
-    /// Email of the user
+    /// Email of the user, left out when it is hidden
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub email: String,
-    /// The encrypeted version of the user password
+    /// The hashed user password as a PHC string, see `auth::crypto`
     #[serde(skip)]
-    pub password_hash: UnsafeBinaryString,
-    /// Randomly generated bianry number use to encrypt the password
-    #[serde(skip)]
-    pub salt: UnsafeBinaryString,
+    pub password_hash: String,
     /// The bio gives a brief summary of the user
     pub bio: String,
     /// active is a flag to let us know if a user
     pub active: bool,
     /// the date when they signed up for rcos
     pub joined_on: NaiveDateTime,
-    /// Tier system lets us know if a user is a mentor, course coordinator, or admin
-    pub tier: i32,
+    /// Role of the user that decides what they can do, see `crate::roles`
+    pub role: Role,
     /// Mattermost user name of the user
     pub mmost: String,
     /// flag used to mark if user that were students
     pub former: bool,
     /// flag used to mark if the user is not a student
     pub extrn: bool,
+    /// flag used to mark if the user has verified their email
+    pub verified: bool,
+    /// flag used to mark if the handle was verified through GitHub,
+    /// if so it can't be changed by hand
+    pub handle_verified: bool,
+    /// When the user was moved to the trash, see `crate::trash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<NaiveDateTime>,
+    /// Time zone times are shown to the user in, the site's if `None`,
+    /// see `crate::tz`
+    pub time_zone: Option<String>,
+}
+
+impl User {
+    /// Is the user allowed to do something?
+    pub fn can(&self, p: Permission) -> bool {
+        self.role.can(p)
+    }
 }
 
 /// New User template
 ///
 /// Represents the template for a when a new user is created
-#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset)]
+///
+/// In the API the plain text password is sent as `password`.
+#[derive(Debug, Default, Clone, FromForm, Insertable, AsChangeset, Deserialize)]
 #[table_name = "users"]
+#[serde(default)]
 pub struct NewUser {
     /// Real name of the user
     pub real_name: String,
     /// Github user name of the user
     pub handle: String,
-    /// The encryptd version of the user password
-    pub password_hash: UnsafeBinaryString,
-    /// randomly generated binary string
-    pub salt: UnsafeBinaryString,
+    /// The hashed user password, the plain text one when it comes from a form
+    #[serde(rename = "password")]
+    pub password_hash: String,
     /// Bio gives a brief summary of the user
     pub bio: String,
     /// Gives email of the user
     pub email: String,
-    /// Tier system lets us know if a user is a mentor, course coordinator, or admin
-    pub tier: i32,
+    /// Role of the user that decides what they can do, see `crate::roles`
+    pub role: Role,
     /// active is a flag to let us know if a user
     pub active: bool,
     /// The Mattermost handle of the user
@@ -67,7 +85,7 @@ pub struct NewUser {
     pub extrn: bool,
 }
 
-use crate::models::Attendable;
+use crate::models::{Attendable, Grade};
 /// GradeSummary template
 ///
 /// Represents the template for a user's GradeSummary based on attendances and commits
@@ -75,8 +93,12 @@ use crate::models::Attendable;
 pub struct GradeSummary {
     /// Tracks the users number of successfulo attendances
     pub attendances: Vec<Box<dyn Attendable>>,
+    /// Attendances that are still waiting to be approved
+    pub pending: Vec<Box<dyn Attendable>>,
     /// Tracks the number of total attendences including the missed ones
     pub needed_attendances: usize,
     /// Number of commits the user has made to a project
     pub commit_count: Option<usize>,
+    /// Grade from the rubric when the summary is for a semester
+    pub grade: Option<Grade>,
 }
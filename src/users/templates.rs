@@ -8,7 +8,9 @@ use crate::models::Attendable;
 #[allow(unused_imports)]
 use crate::templates::filters;
 
+use crate::semesters::templates::SemesterSelect;
 use crate::templates::{FormError, OptUser};
+use crate::tz::Tz;
 
 /// User page template
 ///
@@ -24,6 +26,8 @@ pub struct UserTemplate {
     pub projects: Vec<Project>,
     pub summary: GradeSummary,
     pub groups: Vec<Group>,
+    /// The time zone times are shown in
+    pub zone: Tz,
 }
 
 /// Edit-User page template
@@ -38,6 +42,22 @@ pub struct EditUserTemplate {
     pub logged_in: OptUser,
     pub user: User,
     pub error: Option<FormError>,
+    /// Names of the time zones that can be picked
+    pub time_zones: Vec<String>,
+    /// The site's time zone, used for users that don't pick one
+    pub site_zone: Tz,
+}
+
+impl EditUserTemplate {
+    /// The time zone the user picked, empty if they use the site's
+    pub fn time_zone(&self) -> &str {
+        self.user.time_zone.as_deref().unwrap_or_default()
+    }
+
+    /// The name of the site's time zone
+    pub fn site_zone(&self) -> &'static str {
+        self.site_zone.name()
+    }
 }
 
 /// UsersListTemplate page template
@@ -53,4 +73,5 @@ pub struct UsersListTemplate {
     pub users: Vec<User>,
     pub search_term: String,
     pub inactive: bool,
+    pub semester: SemesterSelect,
 }
@@ -26,6 +26,13 @@ document.addEventListener('DOMContentLoaded', () => {
         });
     });
 
+    // Selects with the class 'autosubmit' submit their form when changed
+    document.querySelectorAll("select.autosubmit").forEach(sel => {
+        sel.addEventListener('change', (e) => {
+            sel.form.submit();
+        });
+    });
+
     // HTML forms cannot handle PUT requests
     // So this adds the functionality
     document.querySelectorAll("[method='PUT']")